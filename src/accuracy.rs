@@ -0,0 +1,418 @@
+//
+// Accuracy dashboard: runs a list of test ROMs headless, writes an HTML report of
+// what passes per category and appends the totals to a JSON history file, so
+// progress can be compared release to release.
+//
+// The manifest lists one ROM per line as `category path`, paths relative to the
+// manifest, `#` starting a comment:
+//
+//   cpu     instr_test-v5/rom_singles/01-basics.nes
+//   ppu     ppu_vbl_nmi/rom_singles/02-vbl_set_time.nes
+//   apu     apu_test/rom_singles/1-len_ctr.nes
+//   mapper  mmc3_test_2/rom_singles/4-scanline_timing.nes
+//
+// Results are read the way blargg's test ROMs report them: $6001-$6003 hold the
+// signature DE B0 61 once the test is running, $6000 is $80 while it runs, $81
+// when it wants the console reset, and the result code afterwards (0 = pass),
+// with a message as a zero-terminated string from $6004.
+//
+
+use crate::bus::Bus;
+use crate::cartridge::Rom;
+use crate::cpu::{Mem, CPU};
+use crate::input::ControllerPorts;
+use crate::json::Value;
+use crate::ppu::NesPPU;
+use crate::region::Region;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A test that has not reported a result after this many frames is a timeout.
+pub const MAX_FRAMES: usize = 60 * 60;
+
+/// Frames to wait before honoring a reset request, like a person pressing the button.
+const RESET_DELAY: usize = 6;
+
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    Cpu,
+    PpuTiming,
+    Apu,
+    Mappers,
+}
+
+pub const CATEGORIES: [Category; 4] = [
+    Category::Cpu,
+    Category::PpuTiming,
+    Category::Apu,
+    Category::Mappers,
+];
+
+impl Category {
+    fn parse(name: &str) -> Option<Category> {
+        match name {
+            "cpu" => Some(Category::Cpu),
+            "ppu" => Some(Category::PpuTiming),
+            "apu" => Some(Category::Apu),
+            "mapper" => Some(Category::Mappers),
+            _ => None,
+        }
+    }
+
+    /// Key used in the history file.
+    fn key(self) -> &'static str {
+        match self {
+            Category::Cpu => "cpu",
+            Category::PpuTiming => "ppu",
+            Category::Apu => "apu",
+            Category::Mappers => "mapper",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Category::Cpu => "CPU",
+            Category::PpuTiming => "PPU timing",
+            Category::Apu => "APU",
+            Category::Mappers => "Mappers",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// Result code and message reported by the ROM
+    Failed(u8, String),
+    Timeout,
+    /// The ROM could not be loaded
+    Error(String),
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        *self == Outcome::Passed
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "passed"),
+            Outcome::Failed(code, message) => write!(f, "failed ({}): {}", code, message),
+            Outcome::Timeout => write!(f, "no result after {} frames", MAX_FRAMES),
+            Outcome::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub category: Category,
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+/// Reads a manifest, returning `(category, rom path)` pairs.
+pub fn parse_manifest(text: &str, base: &Path) -> Result<Vec<(Category, PathBuf)>, String> {
+    let mut tests = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (category, path) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("line {}: expected 'category path'", number + 1))?;
+        let category = Category::parse(category).ok_or_else(|| {
+            format!(
+                "line {}: unknown category '{}' (cpu, ppu, apu or mapper)",
+                number + 1,
+                category
+            )
+        })?;
+        tests.push((category, base.join(path.trim())));
+    }
+    Ok(tests)
+}
+
+/// Runs one test ROM from power-on until it reports a result.
+///
+/// Frames are counted in CPU cycles rather than vblanks: many tests never enable NMI.
+pub fn run_test(rom: Rom, max_frames: usize) -> Outcome {
+    let bus = Bus::new(
+        rom,
+        crate::AUDIO_SAMPLE_RATE,
+        |_: &NesPPU, _: &mut ControllerPorts| {},
+    );
+    let mut cpu = CPU::new(bus);
+    cpu.power_on();
+
+    // Tests run as NTSC, like games
+    let cycles_per_frame = Region::Ntsc.cycles_per_frame();
+    let mut cycles = 0;
+    let mut reset_at = None;
+    for frame in 1..=max_frames {
+        while cycles < cycles_per_frame {
+            cycles += cpu.step().cycles as usize;
+            cpu.collect_audio_sample();
+        }
+        cycles -= cycles_per_frame;
+
+        // Results are checked once per frame; the ROM writes them long before then
+        if reset_at == Some(frame) {
+            reset_at = None;
+            cpu.reset();
+            continue;
+        }
+        let signature = [
+            cpu.mem_read(0x6001),
+            cpu.mem_read(0x6002),
+            cpu.mem_read(0x6003),
+        ];
+        if signature != SIGNATURE || reset_at.is_some() {
+            continue;
+        }
+        match cpu.mem_read(0x6000) {
+            STATUS_RUNNING => {}
+            STATUS_NEEDS_RESET => reset_at = Some(frame + RESET_DELAY),
+            0 => return Outcome::Passed,
+            code => return Outcome::Failed(code, read_message(&mut cpu)),
+        }
+    }
+    Outcome::Timeout
+}
+
+fn read_message(cpu: &mut CPU<Bus>) -> String {
+    let bytes: Vec<u8> = (0x6004..0x7000)
+        .map(|addr| cpu.mem_read(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+pub fn run_manifest(tests: &[(Category, PathBuf)]) -> Vec<TestResult> {
+    tests
+        .iter()
+        .map(|(category, path)| {
+            let outcome = match Rom::from_path(path) {
+                Ok(rom) => run_test(rom, MAX_FRAMES),
+                Err(e) => Outcome::Error(e.to_string()),
+            };
+            TestResult {
+                category: *category,
+                name: path.display().to_string(),
+                outcome,
+            }
+        })
+        .collect()
+}
+
+/// `(passed, total)` for each category that has tests.
+pub fn totals(results: &[TestResult]) -> Vec<(Category, usize, usize)> {
+    CATEGORIES
+        .iter()
+        .filter_map(|&category| {
+            let tests: Vec<_> = results.iter().filter(|r| r.category == category).collect();
+            let passed = tests.iter().filter(|r| r.outcome.passed()).count();
+            (!tests.is_empty()).then_some((category, passed, tests.len()))
+        })
+        .collect()
+}
+
+/// Appends this run to the history, a JSON object `{"runs": [...]}`.
+pub fn add_to_history(history: Option<Value>, results: &[TestResult], time: u64) -> Value {
+    let mut runs = history
+        .as_ref()
+        .and_then(|history| history.get("runs"))
+        .and_then(Value::as_array)
+        .map(<[Value]>::to_vec)
+        .unwrap_or_default();
+    let categories = totals(results)
+        .into_iter()
+        .map(|(category, passed, total)| {
+            (
+                category.key(),
+                Value::object(vec![("passed", passed.into()), ("total", total.into())]),
+            )
+        })
+        .collect();
+    runs.push(Value::object(vec![
+        ("time", time.into()),
+        ("version", env!("CARGO_PKG_VERSION").into()),
+        ("categories", Value::object(categories)),
+    ]));
+    Value::object(vec![("runs", Value::Array(runs))])
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The HTML dashboard: a summary per category, every test, then the history.
+pub fn html_report(results: &[TestResult], history: &Value) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>NES Emulator accuracy</title>\n<style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; margin-bottom: 2em; }\n\
+         td, th { border: 1px solid #ccc; padding: 4px 10px; text-align: left; }\n\
+         .pass { background: #d4f7d4; }\n.fail { background: #f7d4d4; }\n\
+         </style>\n</head>\n<body>\n<h1>Accuracy tests</h1>\n",
+    );
+
+    html.push_str("<table>\n<tr><th>Category</th><th>Passed</th></tr>\n");
+    for (category, passed, total) in totals(results) {
+        let class = if passed == total { "pass" } else { "fail" };
+        html.push_str(&format!(
+            "<tr class=\"{}\"><td>{}</td><td>{} / {}</td></tr>\n",
+            class, category, passed, total
+        ));
+    }
+    html.push_str("</table>\n");
+
+    for (category, _, _) in totals(results) {
+        html.push_str(&format!(
+            "<h2>{}</h2>\n<table>\n<tr><th>Test</th><th>Result</th></tr>\n",
+            category
+        ));
+        for result in results.iter().filter(|r| r.category == category) {
+            let class = if result.outcome.passed() {
+                "pass"
+            } else {
+                "fail"
+            };
+            html.push_str(&format!(
+                "<tr class=\"{}\"><td>{}</td><td>{}</td></tr>\n",
+                class,
+                escape(&result.name),
+                escape(&result.outcome.to_string())
+            ));
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("<h2>History</h2>\n<table>\n<tr><th>Run</th><th>Version</th>");
+    for category in CATEGORIES {
+        html.push_str(&format!("<th>{}</th>", category));
+    }
+    html.push_str("</tr>\n");
+    let runs = history
+        .get("runs")
+        .and_then(Value::as_array)
+        .unwrap_or_default();
+    for run in runs.iter().rev() {
+        let time = run.get("time").and_then(Value::as_u64).unwrap_or_default();
+        let version = run.get("version").and_then(Value::as_str).unwrap_or("?");
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td>",
+            time,
+            escape(version)
+        ));
+        for category in CATEGORIES {
+            let counts = run.get("categories").and_then(|c| c.get(category.key()));
+            let count = |key| counts.and_then(|c| c.get(key)).and_then(Value::as_u64);
+            match (count("passed"), count("total")) {
+                (Some(passed), Some(total)) => {
+                    html.push_str(&format!("<td>{} / {}</td>", passed, total))
+                }
+                _ => html.push_str("<td>-</td>"),
+            }
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::program_rom;
+    use crate::mapper::PRG_RAM_SIZE;
+
+    /// Writes the signature, then `status` to $6000 and "bad" as the message.
+    fn reporting_rom(status: u8) -> Rom {
+        let mut program = vec![];
+        for (addr, value) in [
+            (0x6001u16, 0xDE),
+            (0x6002, 0xB0),
+            (0x6003, 0x61),
+            (0x6004, b'b'),
+            (0x6005, b'a'),
+            (0x6006, b'd'),
+            (0x6000, status),
+        ] {
+            let [lo, hi] = addr.to_le_bytes();
+            program.extend([0xA9, value, 0x8D, lo, hi]); // LDA #value; STA addr
+        }
+        let spin = 0x8000 + program.len() as u16;
+        program.push(0x4C); // JMP spin
+        program.extend(spin.to_le_bytes());
+        // Results need the PRG-RAM of an NROM board
+        let mut rom = program_rom(program, [0x8000; 3]);
+        rom.mapper = 0;
+        rom.prg_ram_size = PRG_RAM_SIZE;
+        rom
+    }
+
+    #[test]
+    fn test_result_protocol() {
+        assert_eq!(run_test(reporting_rom(0), 10), Outcome::Passed);
+        assert_eq!(
+            run_test(reporting_rom(3), 10),
+            Outcome::Failed(3, "bad".to_string())
+        );
+        assert_eq!(
+            run_test(reporting_rom(STATUS_RUNNING), 10),
+            Outcome::Timeout
+        );
+    }
+
+    #[test]
+    fn test_manifest_history_and_report() {
+        let tests =
+            parse_manifest("# suite\ncpu a.nes\nmapper  b/c.nes\n", Path::new("t")).unwrap();
+        assert_eq!(
+            tests[1],
+            (Category::Mappers, Path::new("t").join("b/c.nes"))
+        );
+        assert!(parse_manifest("gpu x.nes", Path::new(".")).is_err());
+
+        let results = vec![
+            TestResult {
+                category: Category::Cpu,
+                name: "a.nes".to_string(),
+                outcome: Outcome::Passed,
+            },
+            TestResult {
+                category: Category::Cpu,
+                name: "<b>.nes".to_string(),
+                outcome: Outcome::Timeout,
+            },
+        ];
+        assert_eq!(totals(&results), vec![(Category::Cpu, 1, 2)]);
+
+        let history = add_to_history(None, &results, 100);
+        let history = add_to_history(Value::parse(&history.to_string()).ok(), &results, 200);
+        assert_eq!(
+            history.get("runs").and_then(Value::as_array).unwrap().len(),
+            2
+        );
+
+        let html = html_report(&results, &history);
+        assert!(html.contains("<td>CPU</td><td>1 / 2</td>"));
+        assert!(html.contains("&lt;b&gt;.nes"));
+        assert!(html.contains("<tr><td>200</td>"));
+    }
+}
@@ -5,6 +5,11 @@
 // This file models the APU and its components.
 //
 
+use crate::blip::Blip;
+use crate::expansion::{ExpansionMixer, ExpansionRef};
+use crate::json::Value;
+use crate::region::Region;
+
 // --- Constants ---
 
 /// Duty cycle sequences for the pulse channels.
@@ -24,17 +29,6 @@ const TRIANGLE_SEQUENCE: [u8; 32] = [
     13, 14, 15,
 ];
 
-/// Timer periods for the noise channel, specific to the NTSC video standard.
-const NOISE_TIMER_PERIODS_NTSC: [u16; 16] = [
-    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
-];
-
-/// Rate table for the DMC, specific to the NTSC video standard.
-/// These values determine the playback frequency of samples.
-const DMC_RATE_TABLE_NTSC: [u16; 16] = [
-    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
-];
-
 /// Lookup table for the length counter.
 /// When a value is written to a channel's length counter register,
 /// this table is used to determine the actual length.
@@ -204,8 +198,9 @@ impl PulseChannel {
 #[derive(Default, Copy, Clone)]
 pub struct TriangleChannel {
     enabled: bool,
-    length_counter_halt: bool, // Also the control flag.
-    control_flag: bool,
+    control_flag: bool, // Also halts the length counter.
+    /// Set by $400B writes: the linear counter reloads on its next clock
+    linear_counter_reload: bool,
     linear_counter_load: u8,
     linear_counter_value: u8,
     timer_period: u16,
@@ -228,20 +223,21 @@ impl TriangleChannel {
     }
 
     /// Clocks the linear counter, which controls volume.
+    /// The reload flag stays up for as long as the control flag is set.
     fn clock_linear_counter(&mut self) {
-        if self.control_flag {
+        if self.linear_counter_reload {
             self.linear_counter_value = self.linear_counter_load;
         } else if self.linear_counter_value > 0 {
             self.linear_counter_value -= 1;
         }
-        if !self.length_counter_halt {
-            self.control_flag = false;
+        if !self.control_flag {
+            self.linear_counter_reload = false;
         }
     }
 
     /// Clocks the channel's length counter.
     fn clock_length_counter(&mut self) {
-        if !self.length_counter_halt && self.length_counter > 0 {
+        if !self.control_flag && self.length_counter > 0 {
             self.length_counter -= 1;
         }
     }
@@ -423,13 +419,46 @@ pub struct FrameCounter {
     mode: FrameCounterMode,
     interrupt_inhibit: bool, // Disables frame counter interrupts when set.
     interrupt_flag: bool,    // Set when a frame interrupt occurs.
+    /// Mode last written to $4017 and the CPU cycles after the write's own until
+    /// the sequence restarts in it
+    pending_mode: Option<(FrameCounterMode, u8)>,
+}
+
+/// What one channel is doing, as a status panel shows it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelStatus {
+    /// Bit of the channel in $4015
+    pub enabled: bool,
+    /// Value the channel's timer reloads with
+    pub period: u16,
+    /// Length counter; bytes left to play for the DMC
+    pub length_counter: u16,
+    /// Current envelope volume, for the pulses and the noise only
+    pub envelope: Option<u8>,
+    /// Level the channel outputs right now
+    pub output: u8,
+}
+
+/// State of the whole APU, see [`Apu::status`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ApuStatus {
+    pub pulse1: ChannelStatus,
+    pub pulse2: ChannelStatus,
+    pub triangle: ChannelStatus,
+    pub noise: ChannelStatus,
+    pub dmc: ChannelStatus,
+    pub five_step: bool,
+    pub frame_interrupt: bool,
+    pub dmc_interrupt: bool,
 }
 
 // --- APU ---
 
 /// The main APU structure. It contains all five sound channels and manages their state.
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Apu {
+    /// Console the timer tables and the clock rate are those of
+    region: Region,
     pulse1: PulseChannel,
     pulse2: PulseChannel,
     triangle: TriangleChannel,
@@ -441,14 +470,24 @@ pub struct Apu {
     cycles: u64, // Total APU cycles.
     dmc_read_request: Option<u16>,
 
-    // For audio sampling
-    time_counter: u32,
-    cycles_per_sample: u32,
+    // For audio sampling: a sample is due each time `time_counter` reaches the
+    // CPU clock rate, adding the sample rate every cycle. The remainder carries
+    // over, so a second of emulation yields exactly `sample_rate` samples and the
+    // count per frame only depends on the cycles run, without drift.
+    time_counter: u64,
+    sample_rate: u64,
+    /// Turns the mix's changes of level into samples without aliasing
+    blip: Blip,
+    /// The mix as of the last cycle, to find its changes
+    output: f32,
+    /// Sound chips of the cartridge, mixed in with the channels
+    expansion: ExpansionMixer,
 }
 
 impl Default for Apu {
     fn default() -> Self {
         Apu {
+            region: Region::default(),
             pulse1: PulseChannel::new(false),
             pulse2: PulseChannel::new(true),
             triangle: TriangleChannel::default(),
@@ -459,21 +498,42 @@ impl Default for Apu {
             cycles: 0,
             dmc_read_request: None,
             time_counter: 0,
-            cycles_per_sample: 0,
+            sample_rate: 0,
+            blip: Blip::default(),
+            output: 0.0,
+            expansion: ExpansionMixer::default(),
         }
     }
 }
 
 impl Apu {
-    /// Creates a new APU instance.
-    pub fn new(sample_rate: f64) -> Self {
-        let cpu_clock_rate = 1_789_773.0; // NTSC CPU clock rate
-        let cycles_per_sample = (cpu_clock_rate / sample_rate) as u32;
+    /// Creates a new APU instance, timed for a `region` console.
+    pub fn new(region: Region, sample_rate: f64) -> Self {
+        let mut apu = Apu {
+            region,
+            ..Apu::default()
+        };
+        apu.set_sample_rate(sample_rate);
+        apu
+    }
 
-        Apu {
-            cycles_per_sample,
-            ..Default::default()
-        }
+    /// Changes the output rate on the fly, e.g. after the audio device was reopened.
+    /// Channel state is untouched, so the sound just carries on at the new rate.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate.round() as u64;
+        // Don't emit a burst of samples when the period got shorter
+        self.time_counter = self.time_counter.min(self.region.cpu_clock_rate());
+    }
+
+    /// Plays a sound chip of the cartridge along with the channels, `volume`
+    /// times as loud as it outputs.
+    pub fn register_expansion(&mut self, chip: ExpansionRef, volume: f32) {
+        self.expansion.register(chip, volume);
+    }
+
+    /// The cartridge's sound chips, to change their volume or swap them.
+    pub fn expansion_mut(&mut self) -> &mut ExpansionMixer {
+        &mut self.expansion
     }
 
     /// Clocks the envelopes and the triangle channel's linear counter.
@@ -498,60 +558,213 @@ impl Apu {
 
     /// Main clock cycle for the APU. This is called for every CPU cycle.
     pub fn clock(&mut self) {
-        self.time_counter += 1;
+        self.time_counter += self.sample_rate;
         self.triangle.clock_timer(); // Triangle timer is clocked at CPU speed.
 
         self.cycles += 1;
+        self.apply_frame_counter_write();
+        self.clock_frame_counter();
         // Other channels are clocked at half the CPU speed.
-        if self.cycles % 2 != 0 {
-            self.dmc.clock_timer();
-            self.check_dmc_read_request();
-            return;
+        if self.cycles.is_multiple_of(2) {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+            self.noise.clock_timer();
         }
-
-        self.pulse1.clock_timer();
-        self.pulse2.clock_timer();
-        self.noise.clock_timer();
         self.dmc.clock_timer();
 
         self.check_dmc_read_request();
+        self.expansion.clock();
+        self.add_output_change();
+    }
+
+    /// Hands a change of the mix since the last cycle to the band-limited
+    /// synthesis, at its position between output samples.
+    fn add_output_change(&mut self) {
+        let output = self.get_output_sample() + self.expansion.output();
+        if output != self.output {
+            let position = self.time_counter as f32 / self.region.cpu_clock_rate() as f32;
+            self.blip.add_delta(position, output - self.output);
+            self.output = output;
+        }
+    }
+
+    /// Restarts the frame sequence once a $4017 write takes effect: 3 CPU cycles
+    /// after a write on an APU cycle, 4 after one between two. The sequence runs
+    /// on in the old mode until then. Entering 5-step mode clocks the quarter
+    /// and half frame units right away.
+    fn apply_frame_counter_write(&mut self) {
+        let Some((mode, delay)) = self.frame_counter.pending_mode else {
+            return;
+        };
+        if delay > 0 {
+            self.frame_counter.pending_mode = Some((mode, delay - 1));
+            return;
+        }
+        self.frame_counter.pending_mode = None;
+        self.frame_counter.mode = mode;
+        self.frame_cycle = 0;
+        if matches!(mode, FrameCounterMode::FiveStep) {
+            self.clock_envelopes_and_linear_counter();
+            self.clock_length_counters_and_sweep_units();
+        }
+    }
 
-        // Frame counter logic
+    /// Steps the frame sequencer by one CPU cycle. Its steps fall between two APU
+    /// cycles, so it counts CPU cycles: on NTSC, quarter/half frame clocks at 7457,
+    /// 14913, 22371 and 29829 (4-step) or 37281 (5-step).
+    ///
+    /// In 4-step mode the interrupt flag is raised on each of the last three cycles
+    /// (29828-29830 on NTSC): acknowledging it on the first two does not stop it
+    /// from coming right back.
+    fn clock_frame_counter(&mut self) {
         self.frame_cycle += 1;
-        match self.frame_counter.mode {
-            FrameCounterMode::FourStep => match self.frame_cycle {
-                3729 => self.clock_envelopes_and_linear_counter(),
-                7457 => {
-                    self.clock_envelopes_and_linear_counter();
-                    self.clock_length_counters_and_sweep_units();
-                }
-                11186 => self.clock_envelopes_and_linear_counter(),
-                14915 => {
-                    self.clock_envelopes_and_linear_counter();
-                    self.clock_length_counters_and_sweep_units();
-                    if !self.frame_counter.interrupt_inhibit {
-                        self.frame_counter.interrupt_flag = true;
-                    }
-                    self.frame_cycle = 0;
-                }
-                _ => {}
+        let [quarter, half, three_quarters, end, fifth] = self.region.frame_sequence();
+        match (self.frame_counter.mode, self.frame_cycle) {
+            (_, cycle) if cycle == quarter || cycle == three_quarters => {
+                self.clock_envelopes_and_linear_counter()
+            }
+            (_, cycle) if cycle == half => {
+                self.clock_envelopes_and_linear_counter();
+                self.clock_length_counters_and_sweep_units();
+            }
+            (FrameCounterMode::FourStep, cycle) if cycle == end - 1 => self.raise_frame_interrupt(),
+            (FrameCounterMode::FourStep, cycle) if cycle == end => {
+                self.clock_envelopes_and_linear_counter();
+                self.clock_length_counters_and_sweep_units();
+                self.raise_frame_interrupt();
+            }
+            // Also the first cycle of the next sequence
+            (FrameCounterMode::FourStep, cycle) if cycle == end + 1 => {
+                self.raise_frame_interrupt();
+                self.frame_cycle = 0;
+            }
+            // The fifth step, no interrupt.
+            (FrameCounterMode::FiveStep, cycle) if cycle == fifth => {
+                self.clock_envelopes_and_linear_counter();
+                self.clock_length_counters_and_sweep_units();
+            }
+            (FrameCounterMode::FiveStep, cycle) if cycle == fifth + 1 => self.frame_cycle = 0,
+            _ => {}
+        }
+    }
+
+    fn raise_frame_interrupt(&mut self) {
+        if !self.frame_counter.interrupt_inhibit {
+            self.frame_counter.interrupt_flag = true;
+        }
+    }
+
+    /// Frame interrupt flag, the level the frame counter drives the IRQ line with.
+    pub fn frame_interrupt(&self) -> bool {
+        self.frame_counter.interrupt_flag
+    }
+
+    /// DMC interrupt flag, raised when a sample without loop ends with the IRQ
+    /// enabled. Unlike the frame interrupt, reading $4015 leaves it up: writing
+    /// $4015 or disabling the IRQ in $4010 takes it down.
+    pub fn dmc_interrupt(&self) -> bool {
+        self.dmc.irq_pending
+    }
+
+    /// Per-channel periods, counters and volumes, for a status panel or tests.
+    pub fn status(&self) -> ApuStatus {
+        let pulse = |pulse: &PulseChannel| ChannelStatus {
+            enabled: pulse.enabled,
+            period: pulse.timer_period,
+            length_counter: pulse.length_counter as u16,
+            envelope: Some(pulse.envelope.output()),
+            output: pulse.output(),
+        };
+        ApuStatus {
+            pulse1: pulse(&self.pulse1),
+            pulse2: pulse(&self.pulse2),
+            triangle: ChannelStatus {
+                enabled: self.triangle.enabled,
+                period: self.triangle.timer_period,
+                length_counter: self.triangle.length_counter as u16,
+                envelope: None,
+                output: self.triangle.output(),
             },
-            FrameCounterMode::FiveStep => match self.frame_cycle {
-                3729 => self.clock_envelopes_and_linear_counter(),
-                7457 => {
-                    self.clock_envelopes_and_linear_counter();
-                    self.clock_length_counters_and_sweep_units();
-                }
-                11186 => self.clock_envelopes_and_linear_counter(),
-                18641 => {
-                    // The fifth step, no interrupt.
-                    self.clock_envelopes_and_linear_counter();
-                    self.clock_length_counters_and_sweep_units();
-                    self.frame_cycle = 0;
-                }
-                _ => {}
+            noise: ChannelStatus {
+                enabled: self.noise.enabled,
+                period: self.noise.timer_period,
+                length_counter: self.noise.length_counter as u16,
+                envelope: Some(self.noise.envelope.output()),
+                output: self.noise.output(),
+            },
+            dmc: ChannelStatus {
+                enabled: self.dmc.enabled,
+                period: self.dmc.timer_period,
+                length_counter: self.dmc.current_length,
+                envelope: None,
+                output: self.dmc.output(),
             },
+            five_step: matches!(self.frame_counter.mode, FrameCounterMode::FiveStep),
+            frame_interrupt: self.frame_counter.interrupt_flag,
+            dmc_interrupt: self.dmc.irq_pending,
+        }
+    }
+
+    /// Frame counter and per-channel state for debug dumps. Envelopes, sweeps and
+    /// the noise shift register are left out: they churn every frame and bury the
+    /// fields that matter when comparing two dumps.
+    pub fn debug_state(&self) -> Value {
+        let pulse = |pulse: &PulseChannel| {
+            Value::object(vec![
+                ("enabled", Value::from(pulse.enabled)),
+                ("duty", Value::from(pulse.duty_cycle)),
+                ("timer_period", Value::from(pulse.timer_period)),
+                ("length_counter", Value::from(pulse.length_counter)),
+            ])
+        };
+        let mode = match self.frame_counter.mode {
+            FrameCounterMode::FourStep => 4u8,
+            FrameCounterMode::FiveStep => 5,
         };
+        Value::object(vec![
+            ("frame_mode", Value::from(mode)),
+            ("frame_cycle", Value::from(self.frame_cycle)),
+            (
+                "frame_irq_inhibit",
+                Value::from(self.frame_counter.interrupt_inhibit),
+            ),
+            ("frame_irq", Value::from(self.frame_counter.interrupt_flag)),
+            ("pulse1", pulse(&self.pulse1)),
+            ("pulse2", pulse(&self.pulse2)),
+            (
+                "triangle",
+                Value::object(vec![
+                    ("enabled", Value::from(self.triangle.enabled)),
+                    ("timer_period", Value::from(self.triangle.timer_period)),
+                    ("length_counter", Value::from(self.triangle.length_counter)),
+                    (
+                        "linear_counter",
+                        Value::from(self.triangle.linear_counter_value),
+                    ),
+                ]),
+            ),
+            (
+                "noise",
+                Value::object(vec![
+                    ("enabled", Value::from(self.noise.enabled)),
+                    ("periodic", Value::from(self.noise.mode)),
+                    ("timer_period", Value::from(self.noise.timer_period)),
+                    ("length_counter", Value::from(self.noise.length_counter)),
+                ]),
+            ),
+            (
+                "dmc",
+                Value::object(vec![
+                    ("enabled", Value::from(self.dmc.enabled)),
+                    ("irq_enabled", Value::from(self.dmc.irq_enabled)),
+                    ("irq_pending", Value::from(self.dmc.irq_pending)),
+                    ("loop", Value::from(self.dmc.loop_flag)),
+                    ("current_address", Value::from(self.dmc.current_address)),
+                    ("bytes_remaining", Value::from(self.dmc.current_length)),
+                    ("output_level", Value::from(self.dmc.output_level)),
+                ]),
+            ),
+        ])
     }
 
     /// Checks if the DMC needs to read a new sample byte from memory.
@@ -586,14 +799,43 @@ impl Apu {
         }
     }
 
+    /// What RESET does to the APU: $4015 is written with 0, silencing every
+    /// channel, and $4017 again with its value, restarting the frame sequence.
+    /// The triangle goes back to the start of its waveform and the DMC output
+    /// keeps only its lowest bit.
+    pub fn reset(&mut self) {
+        self.cpu_write(0x4015, 0);
+        let mode = match self.frame_counter.pending_mode {
+            Some((mode, _)) => mode,
+            None => self.frame_counter.mode,
+        };
+        let mode = match mode {
+            FrameCounterMode::FourStep => 0x00,
+            FrameCounterMode::FiveStep => 0x80,
+        };
+        let inhibit = if self.frame_counter.interrupt_inhibit {
+            0x40
+        } else {
+            0
+        };
+        self.frame_counter.interrupt_flag = false;
+        self.cpu_write(0x4017, mode | inhibit);
+        self.triangle.sequencer_step = 0;
+        self.dmc.output_level &= 1;
+    }
+
     /// Handles CPU writes to APU registers.
     pub fn cpu_write(&mut self, addr: u16, data: u8) {
         match addr {
             0x4000..=0x4003 => Self::write_pulse_register(&mut self.pulse1, addr, data),
             0x4004..=0x4007 => Self::write_pulse_register(&mut self.pulse2, addr, data),
             0x4008..=0x400B => Self::write_triangle_register(&mut self.triangle, addr, data),
-            0x400C..=0x400F => Self::write_noise_register(&mut self.noise, addr, data),
-            0x4010..=0x4013 => Self::write_dmc_register(&mut self.dmc, addr, data),
+            0x400C..=0x400F => {
+                Self::write_noise_register(&mut self.noise, self.region.noise_periods(), addr, data)
+            }
+            0x4010..=0x4013 => {
+                Self::write_dmc_register(&mut self.dmc, self.region.dmc_rates(), addr, data)
+            }
             0x4015 => {
                 // Status register write
                 self.pulse1.enabled = (data & 0x01) != 0;
@@ -627,17 +869,23 @@ impl Apu {
             }
             0x4017 => {
                 // Frame counter control
-                self.frame_counter.mode = if data & 0x80 == 0 {
+                let mode = if data & 0x80 == 0 {
                     FrameCounterMode::FourStep
                 } else {
                     FrameCounterMode::FiveStep
                 };
+                // The write lands on the next cycle; APU cycles are the even ones
+                let delay = if (self.cycles + 1).is_multiple_of(2) {
+                    3
+                } else {
+                    4
+                };
+                self.frame_counter.pending_mode = Some((mode, delay));
                 self.frame_counter.interrupt_inhibit = (data & 0x40) != 0;
-                self.frame_cycle = 0;
-                // 5-step mode gets an immediate clocking of half- and quarter-frame units.
-                if matches!(self.frame_counter.mode, FrameCounterMode::FiveStep) {
-                    self.clock_envelopes_and_linear_counter();
-                    self.clock_length_counters_and_sweep_units();
+                // Inhibiting drops a pending interrupt at once; it does not come back
+                // when the inhibit is lifted, only at the next end of sequence
+                if self.frame_counter.interrupt_inhibit {
+                    self.frame_counter.interrupt_flag = false;
                 }
             }
             _ => {}
@@ -682,9 +930,8 @@ impl Apu {
     fn write_triangle_register(t: &mut TriangleChannel, addr: u16, data: u8) {
         match addr & 0x03 {
             0 => {
-                // Control, linear counter load
-                t.length_counter_halt = (data >> 7) & 1 == 1;
-                t.control_flag = t.length_counter_halt;
+                // Control, linear counter load. The reload flag is left as is.
+                t.control_flag = (data >> 7) & 1 == 1;
                 t.linear_counter_load = data & 0x7F;
             }
             1 => {} // Unused
@@ -698,13 +945,13 @@ impl Apu {
                     t.length_counter = LENGTH_COUNTER_TABLE[(data >> 3) as usize];
                 }
                 t.timer_period = (t.timer_period & 0x00FF) | (((data & 0x07) as u16) << 8);
-                t.control_flag = true;
+                t.linear_counter_reload = true;
             }
             _ => unreachable!(),
         }
     }
 
-    fn write_noise_register(n: &mut NoiseChannel, addr: u16, data: u8) {
+    fn write_noise_register(n: &mut NoiseChannel, periods: &[u16; 16], addr: u16, data: u8) {
         match addr {
             0x400C => {
                 // Envelope
@@ -716,7 +963,7 @@ impl Apu {
             0x400E => {
                 // Mode and period
                 n.mode = (data >> 7) & 1 == 1;
-                n.timer_period = NOISE_TIMER_PERIODS_NTSC[(data & 0x0F) as usize];
+                n.timer_period = periods[(data & 0x0F) as usize];
             }
             0x400F => {
                 // Length counter
@@ -729,7 +976,7 @@ impl Apu {
         }
     }
 
-    fn write_dmc_register(dmc: &mut DmcChannel, addr: u16, data: u8) {
+    fn write_dmc_register(dmc: &mut DmcChannel, rates: &[u16; 16], addr: u16, data: u8) {
         match addr {
             0x4010 => {
                 // IRQ, loop, frequency
@@ -738,7 +985,7 @@ impl Apu {
                     dmc.irq_pending = false;
                 }
                 dmc.loop_flag = (data >> 6) & 1 == 1;
-                dmc.timer_period = DMC_RATE_TABLE_NTSC[(data & 0x0F) as usize] / 2;
+                dmc.timer_period = rates[(data & 0x0F) as usize] / 2;
             }
             0x4011 => {
                 // Output level
@@ -756,8 +1003,22 @@ impl Apu {
         }
     }
 
-    /// Handles CPU reads from APU registers.
+    /// Handles CPU reads from APU registers. $4015 reports the length counters,
+    /// a DMC sample still playing and both interrupt flags; bit 5 is not driven
+    /// and reads 0 here, the bus fills it in with open bus.
     pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        let data = self.peek(addr);
+        // Reading acknowledges the frame interrupt (but not the DMC's). A flag
+        // raised on the cycle of the read is seen and stays up: the frame counter
+        // raises it again on the following cycles
+        if addr == 0x4015 {
+            self.frame_counter.interrupt_flag = false;
+        }
+        data
+    }
+
+    /// What `cpu_read` would return, without acknowledging anything.
+    pub fn peek(&self, addr: u16) -> u8 {
         match addr {
             0x4015 => {
                 // Status register
@@ -777,11 +1038,12 @@ impl Apu {
                 if self.dmc.current_length > 0 {
                     status |= 0x10;
                 }
+                if self.frame_counter.interrupt_flag {
+                    status |= 0x40;
+                }
                 if self.dmc.irq_pending {
                     status |= 0x80;
                 }
-
-                self.frame_counter.interrupt_flag = false;
                 status
             }
             _ => 0,
@@ -801,11 +1063,284 @@ impl Apu {
 
     /// Called by the audio system to get a new sample when ready.
     pub fn collect_audio_sample(&mut self) -> Option<f32> {
-        if self.time_counter >= self.cycles_per_sample {
-            self.time_counter -= self.cycles_per_sample;
-            Some(self.get_output_sample())
+        let clock_rate = self.region.cpu_clock_rate();
+        if self.time_counter >= clock_rate {
+            self.time_counter -= clock_rate;
+            Some(self.blip.read())
         } else {
             None
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn samples_per_second(apu: &mut Apu) -> usize {
+        (0..Region::Ntsc.cpu_clock_rate())
+            .filter(|_| {
+                apu.clock();
+                apu.collect_audio_sample().is_some()
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_set_sample_rate() {
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        let before = samples_per_second(&mut apu);
+        assert!((44099..=44100).contains(&before));
+
+        apu.set_sample_rate(22050.0);
+        let after = samples_per_second(&mut apu);
+        assert!((22049..=22051).contains(&after));
+    }
+
+    #[test]
+    fn test_samples_per_frame_do_not_drift() {
+        // NTSC frames alternate 29781 and 29780 CPU cycles (341 * 262 dots, minus
+        // the odd frame skip): 733 or 734 samples each, and over ten seconds
+        // exactly the rate's share of the cycles run
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        let mut total = 0;
+        for frame in 0..600u64 {
+            let cycles = 29781 - frame % 2;
+            let samples = (0..cycles)
+                .filter(|_| {
+                    apu.clock();
+                    apu.collect_audio_sample().is_some()
+                })
+                .count();
+            assert!((733..=734).contains(&samples), "{}", samples);
+            total += samples as u64;
+        }
+        let cycles: u64 = 300 * (29781 + 29780);
+        assert_eq!(total, cycles * 44100 / Region::Ntsc.cpu_clock_rate());
+    }
+
+    /// CPU cycles until the frame interrupt flag goes up, at most `limit`.
+    fn cycles_to_frame_interrupt(apu: &mut Apu, limit: u32) -> Option<u32> {
+        (1..=limit).find(|_| {
+            apu.clock();
+            apu.frame_interrupt()
+        })
+    }
+
+    #[test]
+    fn test_frame_interrupt_timing() {
+        // The sequence restarts 4 cycles after the write's own, which falls
+        // between two APU cycles here, then takes 29828 cycles to the flag
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        apu.cpu_write(0x4017, 0x00);
+        assert_eq!(
+            cycles_to_frame_interrupt(&mut apu, 40000),
+            Some(1 + 3 + 29828)
+        );
+
+        // Set again on the following two cycles after an acknowledge, then not
+        // before the end of the next sequence, 29830 cycles after the first
+        assert_eq!(apu.cpu_read(0x4015) & 0x40, 0x40);
+        apu.clock();
+        assert!(apu.frame_interrupt());
+        apu.cpu_read(0x4015);
+        apu.clock();
+        assert!(apu.frame_interrupt());
+        apu.cpu_read(0x4015);
+        assert_eq!(apu.cpu_read(0x4015) & 0x40, 0);
+        assert_eq!(cycles_to_frame_interrupt(&mut apu, 40000), Some(29828));
+
+        // 3 cycles after a write on an APU cycle
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        apu.clock();
+        apu.cpu_write(0x4017, 0x00);
+        assert_eq!(
+            cycles_to_frame_interrupt(&mut apu, 40000),
+            Some(1 + 2 + 29828)
+        );
+
+        // The 5-step sequence never raises it
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        apu.cpu_write(0x4017, 0x80);
+        assert_eq!(cycles_to_frame_interrupt(&mut apu, 100000), None);
+    }
+
+    #[test]
+    fn test_frame_counter_write_delay() {
+        // The old sequence runs on until the write takes effect: its last step
+        // still raises the interrupt
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        apu.cpu_write(0x4017, 0x00);
+        cycles_to_frame_interrupt(&mut apu, 40000).unwrap();
+        apu.cpu_read(0x4015);
+        apu.cpu_write(0x4017, 0x80);
+        assert_eq!(cycles_to_frame_interrupt(&mut apu, 3), Some(1));
+
+        // Entering 5-step mode clocks the length counters when the sequence
+        // restarts, not at the write
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        apu.cpu_write(0x4015, 0x01);
+        apu.cpu_write(0x4000, 0x00);
+        apu.cpu_write(0x4003, 0x08);
+        assert_eq!(apu.pulse1.length_counter, 254);
+        apu.cpu_write(0x4017, 0x80);
+        assert_eq!(apu.pulse1.length_counter, 254);
+        (0..4).for_each(|_| apu.clock());
+        assert_eq!(apu.pulse1.length_counter, 254);
+        apu.clock();
+        assert_eq!(apu.pulse1.length_counter, 253);
+    }
+
+    #[test]
+    fn test_inhibit_clears_frame_interrupt() {
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        apu.cpu_write(0x4017, 0x00);
+        cycles_to_frame_interrupt(&mut apu, 40000).unwrap();
+        apu.cpu_write(0x4017, 0x40);
+        assert!(!apu.frame_interrupt());
+
+        // Lifting the inhibit does not bring back the interrupt it dropped
+        (0..4).for_each(|_| apu.clock());
+        apu.cpu_write(0x4017, 0x00);
+        assert!(!apu.frame_interrupt());
+        apu.clock();
+        assert!(!apu.frame_interrupt());
+
+        // Inhibited sequences never raise it
+        apu.cpu_write(0x4017, 0x40);
+        assert_eq!(cycles_to_frame_interrupt(&mut apu, 100000), None);
+    }
+
+    /// Plays a one-byte sample to its end, once the buffer has room for it.
+    fn end_dmc_sample(apu: &mut Apu, control: u8) {
+        apu.cpu_write(0x4010, control);
+        apu.cpu_write(0x4013, 0x00);
+        apu.cpu_write(0x4015, 0x10);
+        (0..1000)
+            .find(|_| {
+                apu.clock();
+                apu.dmc_peek_read_request().is_some()
+            })
+            .unwrap();
+        apu.dmc_provide_data(0xAA);
+    }
+
+    #[test]
+    fn test_dmc_interrupt() {
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        end_dmc_sample(&mut apu, 0x8F);
+        assert!(apu.dmc_interrupt());
+        // Reading $4015 shows it without acknowledging it
+        assert_eq!(apu.cpu_read(0x4015) & 0x80, 0x80);
+        assert!(apu.dmc_interrupt());
+        apu.cpu_write(0x4015, 0x00);
+        assert!(!apu.dmc_interrupt());
+
+        end_dmc_sample(&mut apu, 0x8F);
+        apu.cpu_write(0x4010, 0x0F);
+        assert!(!apu.dmc_interrupt());
+
+        // Looping samples never end
+        end_dmc_sample(&mut apu, 0xCF);
+        assert!(!apu.dmc_interrupt());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        apu.cpu_write(0x4015, 0x0F);
+        apu.cpu_write(0x4003, 0x08);
+        apu.cpu_write(0x4011, 0x55);
+        apu.cpu_write(0x4017, 0x00);
+        cycles_to_frame_interrupt(&mut apu, 40000).unwrap();
+        // Past the end of the sequence, which would raise the flag again
+        (0..10).for_each(|_| apu.clock());
+
+        apu.reset();
+        assert_eq!(apu.cpu_read(0x4015), 0);
+        assert_eq!(apu.dmc.output_level, 1);
+        // The frame sequence starts over, in the mode last written
+        assert_eq!(
+            cycles_to_frame_interrupt(&mut apu, 40000),
+            Some(1 + 3 + 29828)
+        );
+    }
+
+    #[test]
+    fn test_samples_are_band_limited() {
+        // A jump of the DMC level reaches the output as a smooth step that
+        // settles on the new mix
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        apu.cpu_write(0x4011, 0x40);
+        let samples: Vec<f32> = (0..2000)
+            .filter_map(|_| {
+                apu.clock();
+                apu.collect_audio_sample()
+            })
+            .collect();
+        let level = apu.get_output_sample();
+        assert!(samples.windows(2).any(|w| w[0] > 0.0 && w[1] < level));
+        assert!((samples[samples.len() - 1] - level).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_triangle_linear_counter_reload() {
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        apu.cpu_write(0x4015, 0x04);
+        apu.cpu_write(0x4008, 0x05);
+        apu.cpu_write(0x400B, 0x08);
+        apu.clock_envelopes_and_linear_counter();
+        assert_eq!(apu.triangle.linear_counter_value, 5);
+        // Control clear: the reload flag went down with that clock
+        apu.clock_envelopes_and_linear_counter();
+        assert_eq!(apu.triangle.linear_counter_value, 4);
+
+        // Setting the control flag alone does not request a reload
+        apu.cpu_write(0x4008, 0x85);
+        apu.clock_envelopes_and_linear_counter();
+        assert_eq!(apu.triangle.linear_counter_value, 3);
+
+        // With the control flag set, a reload repeats on every clock
+        apu.cpu_write(0x400B, 0x08);
+        (0..3).for_each(|_| apu.clock_envelopes_and_linear_counter());
+        assert_eq!(apu.triangle.linear_counter_value, 5);
+        // Clearing it lets the counter run down after one more reload
+        apu.cpu_write(0x4008, 0x05);
+        apu.clock_envelopes_and_linear_counter();
+        assert_eq!(apu.triangle.linear_counter_value, 5);
+        (0..5).for_each(|_| apu.clock_envelopes_and_linear_counter());
+        assert_eq!(apu.triangle.linear_counter_value, 0);
+        assert_eq!(apu.triangle.output(), 0);
+    }
+
+    #[test]
+    fn test_status() {
+        let mut apu = Apu::new(Region::Ntsc, 44100.0);
+        apu.cpu_write(0x4015, 0x09);
+        apu.cpu_write(0x4000, 0x3A);
+        apu.cpu_write(0x4002, 0x34);
+        apu.cpu_write(0x4003, 0x0A);
+        apu.cpu_write(0x400E, 0x03);
+        apu.cpu_write(0x400F, 0x18);
+        let status = apu.status();
+        assert_eq!(
+            status.pulse1,
+            ChannelStatus {
+                enabled: true,
+                period: 0x234,
+                length_counter: 254,
+                envelope: Some(10),
+                output: apu.pulse1.output(),
+            }
+        );
+        assert_eq!(status.noise.length_counter, 2);
+        assert_eq!(status.noise.period, Region::Ntsc.noise_periods()[3]);
+        // Decaying from 15 once the envelope is clocked
+        assert_eq!(status.noise.envelope, Some(0));
+        apu.clock_envelopes_and_linear_counter();
+        assert_eq!(apu.status().noise.envelope, Some(15));
+        assert!(!status.triangle.enabled);
+        assert_eq!(status.triangle.envelope, None);
+        assert!(!status.five_step && !status.frame_interrupt);
+    }
+}
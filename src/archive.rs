@@ -0,0 +1,598 @@
+//
+// Reading ROMs out of .zip archives, the way most collections are distributed.
+// Entries are either stored or compressed with Deflate (RFC 1951), which is
+// decoded here as well. 7z archives are recognized but not read: LZMA is a much
+// bigger job than Deflate.
+//
+// A small Deflate compressor lives here too, for the image files the emulator
+// writes (PNG screenshots).
+//
+
+use crate::hash;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4B50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4B50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4B50;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+pub const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+pub const SEVEN_ZIP_MAGIC: [u8; 6] = [0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArchiveError {
+    /// Not a zip file, or a damaged one
+    Corrupt(String),
+    /// No entry ends in .nes
+    NoRom,
+    /// Several .nes entries, we won't guess which one is meant
+    MultipleRoms(Vec<String>),
+    /// Compression method other than stored and Deflate
+    UnsupportedMethod(u16),
+    /// Archive format we can't read
+    Unsupported(&'static str),
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ArchiveError::Corrupt(what) => write!(f, "Corrupt zip archive: {}", what),
+            ArchiveError::NoRom => write!(f, "Archive contains no .nes file"),
+            ArchiveError::MultipleRoms(names) => {
+                write!(
+                    f,
+                    "Archive contains several .nes files: {}",
+                    names.join(", ")
+                )
+            }
+            ArchiveError::UnsupportedMethod(method) => {
+                write!(f, "Unsupported zip compression method {}", method)
+            }
+            ArchiveError::Unsupported(format) => write!(f, "{} archives are not supported", format),
+        }
+    }
+}
+
+fn corrupt(what: &str) -> ArchiveError {
+    ArchiveError::Corrupt(what.to_string())
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16, ArchiveError> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| corrupt("unexpected end of file"))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32, ArchiveError> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| corrupt("unexpected end of file"))
+}
+
+struct Entry {
+    name: String,
+    method: u16,
+    crc32: u32,
+    compressed_size: usize,
+    size: usize,
+    local_header: usize,
+}
+
+fn entries(zip: &[u8]) -> Result<Vec<Entry>, ArchiveError> {
+    // The end of central directory record sits at the very end, before an optional comment
+    let search_start = zip.len().saturating_sub(22 + 0xFFFF);
+    let eocd = (search_start..zip.len().saturating_sub(21))
+        .rev()
+        .find(|&pos| u32_at(zip, pos) == Ok(END_OF_CENTRAL_DIRECTORY_SIGNATURE))
+        .ok_or_else(|| corrupt("missing central directory"))?;
+
+    let count = u16_at(zip, eocd + 10)? as usize;
+    let mut pos = u32_at(zip, eocd + 16)? as usize;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if u32_at(zip, pos)? != CENTRAL_HEADER_SIGNATURE {
+            return Err(corrupt("bad central directory entry"));
+        }
+        let name_len = u16_at(zip, pos + 28)? as usize;
+        let extra_len = u16_at(zip, pos + 30)? as usize;
+        let comment_len = u16_at(zip, pos + 32)? as usize;
+        let name = zip
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or_else(|| corrupt("unexpected end of file"))?;
+        entries.push(Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: u16_at(zip, pos + 10)?,
+            crc32: u32_at(zip, pos + 16)?,
+            compressed_size: u32_at(zip, pos + 20)? as usize,
+            size: u32_at(zip, pos + 24)? as usize,
+            local_header: u32_at(zip, pos + 42)? as usize,
+        });
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+fn extract(zip: &[u8], entry: &Entry) -> Result<Vec<u8>, ArchiveError> {
+    let pos = entry.local_header;
+    if u32_at(zip, pos)? != LOCAL_HEADER_SIGNATURE {
+        return Err(corrupt("bad local header"));
+    }
+    let start = pos + 30 + u16_at(zip, pos + 26)? as usize + u16_at(zip, pos + 28)? as usize;
+    let compressed = zip
+        .get(start..start + entry.compressed_size)
+        .ok_or_else(|| corrupt("unexpected end of file"))?;
+
+    let data = match entry.method {
+        METHOD_STORED => compressed.to_vec(),
+        METHOD_DEFLATE => inflate(compressed, entry.size).map_err(|e| corrupt(&e))?,
+        method => return Err(ArchiveError::UnsupportedMethod(method)),
+    };
+    if data.len() != entry.size || hash::crc32(&data) != entry.crc32 {
+        return Err(corrupt(&format!("checksum mismatch in {}", entry.name)));
+    }
+    Ok(data)
+}
+
+/// Returns the single .nes file of a zip archive.
+pub fn extract_rom(zip: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let entries = entries(zip)?;
+    let mut roms = entries
+        .iter()
+        .filter(|entry| entry.name.to_ascii_lowercase().ends_with(".nes"));
+    match (roms.next(), roms.next()) {
+        (None, _) => Err(ArchiveError::NoRom),
+        (Some(rom), None) => extract(zip, rom),
+        (Some(_), Some(_)) => Err(ArchiveError::MultipleRoms(
+            entries
+                .iter()
+                .filter(|entry| entry.name.to_ascii_lowercase().ends_with(".nes"))
+                .map(|entry| entry.name.clone())
+                .collect(),
+        )),
+    }
+}
+
+// --- Deflate ---
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are sent in a dynamic block.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or("deflate stream ends early")?;
+            self.pos += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_length_code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or("repeat with no previous length")?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err("code lengths overflow".to_string());
+    }
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+/// Decompresses a raw Deflate stream, `size_hint` being the expected output size.
+pub fn inflate(data: &[u8], size_hint: usize) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader {
+        data,
+        pos: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut out = Vec::with_capacity(size_hint);
+
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let pos = reader.pos;
+                let header = data.get(pos..pos + 4).ok_or("stored block ends early")?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let stored = data
+                    .get(pos + 4..pos + 4 + len)
+                    .ok_or("stored block ends early")?;
+                out.extend_from_slice(stored);
+                reader.pos = pos + 4 + len;
+            }
+            block_type @ (1 | 2) => {
+                let (literals, distances) = if block_type == 1 {
+                    fixed_codes()
+                } else {
+                    dynamic_codes(&mut reader)?
+                };
+                loop {
+                    let symbol = literals.decode(&mut reader)? as usize;
+                    match symbol {
+                        0..=255 => out.push(symbol as u8),
+                        256 => break,
+                        _ => {
+                            let index = symbol - 257;
+                            if index >= LENGTH_BASE.len() {
+                                return Err("invalid length code".to_string());
+                            }
+                            let length = LENGTH_BASE[index] as usize
+                                + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                            let index = distances.decode(&mut reader)? as usize;
+                            if index >= DISTANCE_BASE.len() {
+                                return Err("invalid distance code".to_string());
+                            }
+                            let distance = DISTANCE_BASE[index] as usize
+                                + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+                            if distance > out.len() {
+                                return Err("distance too far back".to_string());
+                            }
+                            let start = out.len() - distance;
+                            for i in 0..length {
+                                out.push(out[start + i]);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => return Err("invalid block type".to_string()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Appends the low `n` bits of `value`, least significant first.
+    fn bits(&mut self, value: u32, n: u32) {
+        self.buffer |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Appends a Huffman code, which Deflate packs most significant bit first.
+    fn code(&mut self, code: u32, len: u32) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+/// Writes a literal/length symbol with the fixed Huffman code.
+fn write_fixed_literal(writer: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => writer.code(0x30 + symbol, 8),
+        144..=255 => writer.code(0x190 + symbol - 144, 9),
+        256..=279 => writer.code(symbol - 256, 7),
+        _ => writer.code(0xC0 + symbol - 280, 8),
+    }
+}
+
+/// Index of the largest table entry not above `value`.
+fn base_index(table: &[u16], value: usize) -> usize {
+    table
+        .iter()
+        .rposition(|&base| base as usize <= value)
+        .unwrap()
+}
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Candidates looked at per position; more compresses better, slower.
+const MAX_CHAIN: usize = 32;
+
+fn hash_at(data: &[u8], pos: usize) -> usize {
+    ((data[pos] as usize) << 10 ^ (data[pos + 1] as usize) << 5 ^ data[pos + 2] as usize) & 0x7FFF
+}
+
+/// Adds `pos` to the chain of earlier positions starting with the same 3 bytes.
+fn insert(data: &[u8], pos: usize, head: &mut [usize], previous: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let hash = hash_at(data, pos);
+        previous[pos] = head[hash];
+        head[hash] = pos;
+    }
+}
+
+/// Compresses `data` into a raw Deflate stream: one fixed-Huffman block, with
+/// matches found through hash chains over the last 32KB.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter {
+        out: Vec::with_capacity(data.len() / 4),
+        buffer: 0,
+        count: 0,
+    };
+    writer.bits(1, 1); // last block
+    writer.bits(1, 2); // fixed codes
+
+    // Most recent position for each hash, and the one before it for each position
+    let mut head = vec![usize::MAX; 0x8000];
+    let mut previous = vec![usize::MAX; data.len()];
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut best_len, mut best_distance) = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash_at(data, pos)];
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || pos - candidate > WINDOW_SIZE {
+                    break;
+                }
+                let len = (0..max_len)
+                    .take_while(|&i| data[candidate + i] == data[pos + i])
+                    .count();
+                if len > best_len {
+                    (best_len, best_distance) = (len, pos - candidate);
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            let index = base_index(&LENGTH_BASE, best_len);
+            write_fixed_literal(&mut writer, 257 + index as u16);
+            writer.bits(
+                (best_len - LENGTH_BASE[index] as usize) as u32,
+                LENGTH_EXTRA[index] as u32,
+            );
+            let index = base_index(&DISTANCE_BASE, best_distance);
+            writer.code(index as u32, 5);
+            writer.bits(
+                (best_distance - DISTANCE_BASE[index] as usize) as u32,
+                DISTANCE_EXTRA[index] as u32,
+            );
+            for i in pos..pos + best_len {
+                insert(data, i, &mut head, &mut previous);
+            }
+            pos += best_len;
+        } else {
+            write_fixed_literal(&mut writer, data[pos] as u16);
+            insert(data, pos, &mut head, &mut previous);
+            pos += 1;
+        }
+    }
+    write_fixed_literal(&mut writer, 256);
+    writer.finish()
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    /// Builds a zip archive of stored entries.
+    pub fn stored_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = vec![];
+        let mut central = vec![];
+        for (name, data) in files {
+            let offset = zip.len() as u32;
+            let crc = hash::crc32(data);
+            let mut common = vec![];
+            common.extend_from_slice(&20u16.to_le_bytes()); // version needed
+            common.extend_from_slice(&0u16.to_le_bytes()); // flags
+            common.extend_from_slice(&METHOD_STORED.to_le_bytes());
+            common.extend_from_slice(&[0; 4]); // time, date
+            common.extend_from_slice(&crc.to_le_bytes());
+            common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            common.extend_from_slice(&0u16.to_le_bytes()); // extra length
+
+            zip.extend_from_slice(&LOCAL_HEADER_SIGNATURE.to_le_bytes());
+            zip.extend_from_slice(&common);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(data);
+
+            central.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+            central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+            central.extend_from_slice(&common);
+            central.extend_from_slice(&[0; 6]); // comment length, disk, internal attributes
+            central.extend_from_slice(&[0; 4]); // external attributes
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+        let central_offset = zip.len() as u32;
+        zip.extend_from_slice(&central);
+        zip.extend_from_slice(&END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        zip.extend_from_slice(&[0; 4]); // disk numbers
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&central_offset.to_le_bytes());
+        zip.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        zip
+    }
+
+    #[test]
+    fn test_inflate() {
+        // Python: zlib.compressobj(9, zlib.DEFLATED, -15), fixed and dynamic Huffman blocks
+        let fixed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00];
+        assert_eq!(inflate(&fixed, 0).unwrap(), b"hello hello hello");
+
+        let alphabet = b"etaoinshrdlu ";
+        let text: Vec<u8> = (0..600)
+            .map(|i| alphabet[(i * i * 7 + i / 3) % 13])
+            .collect();
+        let dynamic = [
+            0xed, 0xca, 0xc1, 0x09, 0x00, 0x30, 0x08, 0x04, 0xb0, 0x55, 0x5c, 0x4d, 0xa8, 0x70,
+            0x42, 0x51, 0x50, 0x6f, 0xff, 0xce, 0xd0, 0xbf, 0x79, 0xc7, 0xa0, 0x72, 0x00, 0x14,
+            0x27, 0xe8, 0x44, 0x9a, 0x90, 0xa6, 0x71, 0xb5, 0x92, 0x88, 0xd4, 0xf4, 0xbe, 0xd3,
+            0xb6, 0x6d, 0xdb, 0x4f, 0x7b,
+        ];
+        assert_eq!(inflate(&dynamic, 0).unwrap(), text);
+
+        // A stored block, as produced at compression level 0
+        let stored = [0x01, 0x03, 0x00, 0xfc, 0xff, 0x61, 0x62, 0x63];
+        assert_eq!(inflate(&stored, 0).unwrap(), b"abc");
+        assert!(inflate(&fixed[..4], 0).is_err());
+    }
+
+    #[test]
+    fn test_deflate_round_trip() {
+        let text: Vec<u8> = (0..5000u32)
+            .map(|i| b"etaoinshrdlu "[((i * i * 7 + i / 3) % 13) as usize])
+            .collect();
+        let frame: Vec<u8> = (0..256 * 240 * 3).map(|i| ((i / 3000) % 4) as u8).collect();
+        for data in [&b""[..], b"a", b"abcabcabcabcabc", &text, &frame] {
+            let compressed = deflate(data);
+            assert_eq!(inflate(&compressed, data.len()).unwrap(), data);
+        }
+        assert!(deflate(&frame).len() < frame.len() / 50);
+    }
+
+    #[test]
+    fn test_extract_rom() {
+        let zip = stored_zip(&[("readme.txt", b"hi"), ("Game.NES", b"NES\x1arom")]);
+        assert_eq!(extract_rom(&zip).unwrap(), b"NES\x1arom");
+
+        let zip = stored_zip(&[("a.nes", b"1"), ("b.nes", b"2")]);
+        assert_eq!(
+            extract_rom(&zip),
+            Err(ArchiveError::MultipleRoms(vec![
+                "a.nes".to_string(),
+                "b.nes".to_string()
+            ]))
+        );
+        assert_eq!(
+            extract_rom(&stored_zip(&[("x.txt", b"")])),
+            Err(ArchiveError::NoRom)
+        );
+
+        let mut zip = stored_zip(&[("a.nes", b"1234")]);
+        zip[30 + 5] ^= 0xFF;
+        assert!(matches!(extract_rom(&zip), Err(ArchiveError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,127 @@
+//
+// Band-limited synthesis, in the manner of blargg's blip_buf. The APU's output
+// is a staircase that changes level on exact CPU cycles, far more often than
+// the sound card samples it. Picking the level at each sample time folds every
+// harmonic above half the output rate back down as aliasing: high notes get
+// out of tune whistles under them.
+//
+// Instead, each change of level is added to the output as a band-limited step:
+// a windowed sinc impulse placed at the change's exact position between two
+// output samples, then integrated back into a level. Nothing above the output
+// rate's Nyquist frequency survives.
+//
+// Steps are centered HALF_WIDTH samples ahead of the sample being read, which
+// delays the sound by that much (a fraction of a millisecond).
+//
+
+use std::f32::consts::PI;
+use std::sync::OnceLock;
+
+/// Taps on each side of an impulse
+const HALF_WIDTH: usize = 8;
+const WIDTH: usize = HALF_WIDTH * 2;
+/// Positions between two samples the impulses are computed for
+const PHASES: usize = 64;
+/// Samples the ring of pending changes holds: the APU can run through an OAM
+/// DMA, over 500 cycles, before the next sample is read
+const BUFFER: usize = 64;
+/// Passband, in fractions of the output rate: a little under Nyquist, so the
+/// short kernel still stops everything above it
+const CUTOFF: f32 = 0.45;
+
+/// One impulse per phase, each summing to 1
+fn kernel() -> &'static [[f32; WIDTH]; PHASES] {
+    static KERNEL: OnceLock<[[f32; WIDTH]; PHASES]> = OnceLock::new();
+    KERNEL.get_or_init(|| {
+        std::array::from_fn(|phase| {
+            let offset = phase as f32 / PHASES as f32;
+            let mut taps: [f32; WIDTH] = std::array::from_fn(|k| {
+                let x = k as f32 - (HALF_WIDTH - 1) as f32 - offset;
+                let sinc = match x {
+                    0.0 => 1.0,
+                    _ => (PI * 2.0 * CUTOFF * x).sin() / (PI * 2.0 * CUTOFF * x),
+                };
+                let w = PI * x / HALF_WIDTH as f32;
+                let blackman = 0.42 + 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+                sinc * blackman
+            });
+            let sum: f32 = taps.iter().sum();
+            taps.iter_mut().for_each(|tap| *tap /= sum);
+            taps
+        })
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Blip {
+    /// Level changes waiting to be integrated, from the next sample on
+    pending: [f32; BUFFER],
+    /// Where `pending` starts
+    start: usize,
+    level: f32,
+}
+
+impl Default for Blip {
+    fn default() -> Self {
+        Blip {
+            pending: [0.0; BUFFER],
+            start: 0,
+            level: 0.0,
+        }
+    }
+}
+
+impl Blip {
+    /// Adds a change of level at `position` samples past the last one read.
+    /// That is usually 0 to a little over 1, more while reads lag behind.
+    pub fn add_delta(&mut self, position: f32, delta: f32) {
+        // Past the end of the ring the change would wrap onto samples due sooner
+        let last = (BUFFER - WIDTH - 1) as f32;
+        let position = position.clamp(0.0, last) + (HALF_WIDTH - 1) as f32;
+        let base = position as usize;
+        let phase = ((position - base as f32) * PHASES as f32) as usize;
+        let first = base + 1 - HALF_WIDTH;
+        for (k, tap) in kernel()[phase.min(PHASES - 1)].iter().enumerate() {
+            let slot = (self.start + first + k) % self.pending.len();
+            self.pending[slot] += delta * tap;
+        }
+    }
+
+    /// The next output sample
+    pub fn read(&mut self) -> f32 {
+        self.level += std::mem::take(&mut self.pending[self.start]);
+        self.start = (self.start + 1) % self.pending.len();
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_band_limited_step() {
+        let mut blip = Blip::default();
+        blip.add_delta(0.5, 1.0);
+        let samples: Vec<f32> = (0..24).map(|_| blip.read()).collect();
+        // Settles on the new level, halfway there HALF_WIDTH samples later
+        assert!(samples[..3].iter().all(|s| s.abs() < 0.01));
+        assert!(samples[20..].iter().all(|s| (s - 1.0).abs() < 1e-5));
+        assert!((samples[HALF_WIDTH - 1] - 0.5).abs() < 0.01);
+
+        // A square wave at twice the output rate, high 60% of the time. Picked
+        // at sample times it would read as a constant 0 or 1; band-limited it
+        // is only its average
+        let mut blip = Blip::default();
+        for n in 0..100 {
+            blip.add_delta(0.1, 1.0);
+            blip.add_delta(0.4, -1.0);
+            blip.add_delta(0.5, 1.0);
+            blip.add_delta(0.8, -1.0);
+            let sample = blip.read();
+            if n > WIDTH {
+                assert!((sample - 0.6).abs() < 0.01);
+            }
+        }
+    }
+}
@@ -1,54 +1,193 @@
 use crate::apu::Apu;
 use crate::cartridge::Rom;
-use crate::cpu::Mem;
-use crate::joypad::Joypad;
-use crate::ppu::NesPPU;
+use crate::cpu::{CpuBus, Mem};
+use crate::filter::FilterChain;
+use crate::input::ControllerPorts;
+use crate::mapper::{self, MapperRef};
 use crate::ppu::PPU;
+use crate::ppu::{NesPPU, PpuOptions, PpuPosition};
+use crate::region::Region;
+use crate::watch::WriteLog;
+use std::path::Path;
 
 const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+/// CPU cycles a DMC sample fetch halts the CPU for: the DMA unit has to take the
+/// bus, wait for an aligned cycle and then read. It is 3 when the fetch starts
+/// during a CPU write; the common read case is the one modelled.
+const DMC_DMA_CYCLES: u8 = 4;
+/// Expansion audio level for games the ROM database has no recommendation for.
+const DEFAULT_EXPANSION_GAIN: f32 = 1.0;
+
+type GameloopCallback<'call> = Box<dyn FnMut(&NesPPU, &mut ControllerPorts) + 'call>;
 
 pub struct Bus<'call> {
     cpu_vram: [u8; 2048],
-    prg_rom: Vec<u8>,
+    mapper: MapperRef,
     ppu: NesPPU,
     apu: Apu,
+    /// From the cartridge header, or the settings' override
+    region: Region,
+    /// `[console] region`, which wins over the header for every game
+    region_override: Option<Region>,
+    sample_rate: f64,
+    /// The board's audio filters, after the APU and the cartridge are mixed
+    filters: FilterChain,
 
     cycles: usize,
-    gameloop_callback: Box<dyn FnMut(&NesPPU, &mut Joypad) + 'call>,
-    joypad1: Joypad,
+    /// PPU dots owed to the CPU cycles so far, in fifths on PAL
+    ppu_dot_remainder: u32,
+    /// Cycles DMC fetches took from the CPU, not yet spent by it
+    dma_stall: u8,
+    write_log: Option<WriteLog>,
+    /// Last value on the CPU data bus, what reads of unmapped addresses return
+    open_bus: u8,
+    /// Level of the cartridge's expansion audio in the mix
+    expansion_gain: f32,
+    /// `[audio] expansion_gain`, which wins over the ROM database's level
+    expansion_gain_override: Option<f32>,
+    /// `[video]` settings, kept for the PPU of the next cartridge
+    ppu_options: PpuOptions,
+    gameloop_callback: GameloopCallback<'call>,
+    ports: ControllerPorts,
 }
 
 impl<'a> Bus<'a> {
     pub fn new<'call, F>(rom: Rom, sample_rate: f64, gameloop_callback: F) -> Bus<'call>
     where
-        F: FnMut(&NesPPU, &mut Joypad) + 'call,
+        F: FnMut(&NesPPU, &mut ControllerPorts) + 'call,
     {
-        let ppu = NesPPU::new(rom.chr_rom, rom.screen_mirroring);
-        let apu = Apu::new(sample_rate);
+        let expansion_gain = rom.info.expansion_gain;
+        let region = Region::for_timing(rom.timing);
+        let mut bus = Bus::with_mapper(
+            mapper::from_rom(rom),
+            region,
+            sample_rate,
+            gameloop_callback,
+        );
+        bus.set_expansion_gain(expansion_gain.unwrap_or(DEFAULT_EXPANSION_GAIN));
+        bus
+    }
+
+    /// Bus around a board that does not come from an iNES image (NSF player).
+    pub fn with_mapper<'call, F>(
+        mapper: MapperRef,
+        region: Region,
+        sample_rate: f64,
+        gameloop_callback: F,
+    ) -> Bus<'call>
+    where
+        F: FnMut(&NesPPU, &mut ControllerPorts) + 'call,
+    {
+        let mut ppu = NesPPU::with_mapper(mapper.clone());
+        ppu.region = region;
+        let mut apu = Apu::new(region, sample_rate);
+        for chip in mapper.borrow().expansion_audio() {
+            apu.register_expansion(chip, DEFAULT_EXPANSION_GAIN);
+        }
 
         Bus {
+            region,
+            region_override: None,
             cpu_vram: [0; 2048],
-            prg_rom: rom.prg_rom,
+            mapper,
             ppu,
             apu,
+            sample_rate,
+            filters: FilterChain::nes(sample_rate),
             cycles: 0,
+            ppu_dot_remainder: 0,
+            dma_stall: 0,
+            write_log: None,
+            open_bus: 0,
+            expansion_gain: DEFAULT_EXPANSION_GAIN,
+            expansion_gain_override: None,
+            ppu_options: PpuOptions::default(),
             gameloop_callback: Box::from(gameloop_callback),
-            joypad1: Joypad::new(),
+            ports: ControllerPorts::new(),
         }
     }
 
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-        if self.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            addr %= 0x4000;
+    /// Swaps the cartridge as if the console had been switched off and on with a
+    /// different game. The controller ports and the frame callback stay in place;
+    /// the CPU still has to be reset.
+    pub fn insert_cartridge(&mut self, rom: Rom) {
+        self.expansion_gain = self
+            .expansion_gain_override
+            .or(rom.info.expansion_gain)
+            .unwrap_or(DEFAULT_EXPANSION_GAIN);
+        self.region = self
+            .region_override
+            .unwrap_or(Region::for_timing(rom.timing));
+        self.mapper = mapper::from_rom(rom);
+        self.ppu = NesPPU::with_mapper(self.mapper.clone());
+        self.ppu.options = self.ppu_options;
+        self.ppu.region = self.region;
+        self.apu = self.new_apu();
+        self.cpu_vram = [0; 2048];
+        self.cycles = 0;
+        self.ppu_dot_remainder = 0;
+    }
+
+    /// Runs this game and the next on `region`'s timing, whatever their header
+    /// says. Meant for power-on: the APU starts over.
+    pub fn override_region(&mut self, region: Region) {
+        self.region_override = Some(region);
+        self.region = region;
+        self.ppu.region = region;
+        self.apu = self.new_apu();
+    }
+
+    /// A powered-on APU for the current region, playing the cartridge's sound chips.
+    fn new_apu(&self) -> Apu {
+        let mut apu = Apu::new(self.region, self.sample_rate);
+        for chip in self.mapper.borrow().expansion_audio() {
+            apu.register_expansion(chip, self.expansion_gain);
         }
-        self.prg_rom[addr as usize]
+        apu
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Mixes expansion audio at `gain` whatever the game, instead of the level the
+    /// ROM database recommends for it.
+    pub fn override_expansion_gain(&mut self, gain: f32) {
+        self.expansion_gain_override = Some(gain);
+        self.set_expansion_gain(gain);
+    }
+
+    fn set_expansion_gain(&mut self, gain: f32) {
+        self.expansion_gain = gain;
+        self.apu.expansion_mut().set_all_volumes(gain);
+    }
+
+    /// PPU settings for this game and the next.
+    pub fn set_ppu_options(&mut self, options: PpuOptions) {
+        self.ppu_options = options;
+        self.ppu.options = options;
+    }
+
+    pub fn expansion_gain(&self) -> f32 {
+        self.expansion_gain
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Follows a change of the audio output rate without touching emulation state.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.apu.set_sample_rate(sample_rate);
+        self.filters = FilterChain::nes(sample_rate);
     }
 
     pub fn tick(&mut self, cycles: u8) {
         self.cycles += cycles as usize;
+        self.mapper.borrow_mut().cpu_cycles(cycles);
 
         for _ in 0..cycles {
             self.apu.clock();
@@ -59,55 +198,225 @@ impl<'a> Bus<'a> {
                         let mirror_down_addr = addr & 0b0000_0111_1111_1111;
                         self.cpu_vram[mirror_down_addr as usize]
                     }
-                    0x8000..=0xFFFF => self.read_prg_rom(addr),
+                    0x8000..=0xFFFF => self.mapper.borrow_mut().cpu_read(addr),
                     _ => 0,
                 };
                 self.apu.dmc_provide_data(data);
+                self.open_bus = data;
+                self.dma_stall = self.dma_stall.saturating_add(DMC_DMA_CYCLES);
             }
         }
 
+        let (dots, per_cycles) = self.region.ppu_dots_per_cpu_cycle();
+        self.ppu_dot_remainder += cycles as u32 * dots;
+        let ppu_dots = self.ppu_dot_remainder / per_cycles;
+        self.ppu_dot_remainder %= per_cycles;
+
         let nmi_before = self.ppu.nmi_interrupt.is_some();
-        self.ppu.tick(cycles * 3);
+        self.ppu.tick(ppu_dots as usize);
         let nmi_after = self.ppu.nmi_interrupt.is_some();
 
         if !nmi_before && nmi_after {
-            (self.gameloop_callback)(&self.ppu, &mut self.joypad1);
+            (self.gameloop_callback)(&self.ppu, &mut self.ports);
         }
     }
 
+    /// The RESET line: the APU goes quiet. RAM, the PPU and the cartridge are left
+    /// as they are.
+    pub fn reset(&mut self) {
+        self.apu.reset();
+    }
+
+    /// Cycles the CPU owes to DMC sample fetches since the last call. The CPU
+    /// spends them halted, with the rest of the console running.
+    pub fn take_dma_stall(&mut self) -> u8 {
+        std::mem::take(&mut self.dma_stall)
+    }
+
     pub fn poll_nmi_status(&mut self) -> Option<u8> {
         self.ppu.poll_nmi_interrupt()
     }
 
+    /// Level of the IRQ line as seen by the CPU: the cartridge, the APU frame
+    /// counter and the DMC all pull it, until the game acknowledges them.
+    pub fn poll_irq_status(&self) -> bool {
+        self.mapper.borrow().irq_pending() || self.apu.frame_interrupt() || self.apu.dmc_interrupt()
+    }
+
     pub fn collect_audio_sample(&mut self) -> Option<f32> {
-        self.apu.collect_audio_sample()
+        let sample = self.apu.collect_audio_sample()?;
+        Some(self.filters.process(sample))
+    }
+
+    /// Starts logging CPU writes to the log's addresses (`--log-writes`).
+    pub fn set_write_log(&mut self, log: WriteLog) {
+        self.write_log = Some(log);
+    }
+
+    pub fn take_write_log(&mut self) -> Option<WriteLog> {
+        self.write_log.take()
+    }
+
+    /// Battery-backed cartridge RAM, empty if the cartridge has no battery.
+    pub fn battery_ram(&self) -> Vec<u8> {
+        self.mapper.borrow().battery_ram()
+    }
+
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        self.mapper.borrow_mut().load_battery_ram(data);
+    }
+
+    /// Writes battery-backed RAM to a save file; does nothing without a battery.
+    pub fn flush_battery_ram(&self, path: &Path) -> std::io::Result<()> {
+        let data = self.battery_ram();
+        if data.is_empty() {
+            return Ok(());
+        }
+        std::fs::write(path, data)
+    }
+
+    /// Restores battery-backed RAM from a save file, if there is one.
+    pub fn restore_battery_ram(&mut self, path: &Path) -> std::io::Result<()> {
+        match std::fs::read(path) {
+            Ok(data) => {
+                self.load_battery_ram(&data);
+                Ok(())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Devices plugged into the controller ports.
+    pub fn ports_mut(&mut self) -> &mut ControllerPorts {
+        &mut self.ports
+    }
+
+    /// The 2KB of internal CPU RAM, without mirrors.
+    pub fn ram(&self) -> &[u8; 2048] {
+        &self.cpu_vram
+    }
+
+    pub fn ppu(&self) -> &NesPPU {
+        &self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    /// The cartridge board in the slot.
+    pub fn mapper(&self) -> &MapperRef {
+        &self.mapper
     }
 }
 
-impl Mem for Bus<'_> {
-    fn mem_read(&mut self, addr: u16) -> u8 {
+impl Bus<'_> {
+    fn read_device(&mut self, addr: u16) -> u8 {
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b0000_0111_1111_1111;
                 self.cpu_vram[mirror_down_addr as usize]
             }
-            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => 0,
-            0x2002 => self.ppu.read_status(),
-            0x2004 => self.ppu.read_oam_data(),
-            0x2007 => self.ppu.read_data(),
-            0x4015 => self.apu.cpu_read(addr),
-            0x4016 => self.joypad1.read(),
-            0x4017 => 0,
+            // The PPU answers from its own latch where it does not drive the bus
+            0x2000..=0x2007 => self.ppu.read_register(addr),
+            // Write-only: nothing drives the bus
+            0x4014 => self.open_bus,
+            // Bit 5 is not connected
+            0x4015 => self.apu.cpu_read(addr) & !0x20 | self.open_bus & 0x20,
+            // The ports only drive D0-D4
+            0x4016 | 0x4017 => {
+                self.ports.scanline = self.ppu.scanline as usize;
+                self.ports.read((addr - 0x4016) as usize) & 0x1F | self.open_bus & 0xE0
+            }
+            0x2008..=PPU_REGISTERS_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b_0010_0000_0000_0111;
+                self.read_device(mirror_down_addr)
+            }
+            0x4020..=0xFFFF => self.mapper.borrow_mut().cpu_read(addr),
+            _ => self.open_bus,
+        }
+    }
+
+    /// What a CPU read of `addr` would return, without its side effects: $2002
+    /// keeps the VBlank flag, $2007 and the controllers do not advance, and the
+    /// open bus keeps its value. For debuggers and the trace log.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                self.cpu_vram[mirror_down_addr as usize]
+            }
+            0x2000..=0x2007 => self.ppu.peek_register(addr),
+            0x4014 => self.open_bus,
+            0x4015 => self.apu.peek(addr) & !0x20 | self.open_bus & 0x20,
+            0x4016 | 0x4017 => {
+                self.ports.peek((addr - 0x4016) as usize) & 0x1F | self.open_bus & 0xE0
+            }
             0x2008..=PPU_REGISTERS_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b_0010_0000_0000_0111;
-                self.mem_read(mirror_down_addr)
+                self.peek(mirror_down_addr)
             }
-            0x8000..=0xFFFF => self.read_prg_rom(addr),
-            _ => 0,
+            0x4020..=0xFFFF => self.mapper.borrow_mut().cpu_peek(addr),
+            _ => self.open_bus,
         }
     }
+}
+
+impl CpuBus for Bus<'_> {
+    fn tick(&mut self, cycles: u8) {
+        Bus::tick(self, cycles)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        Bus::peek(self, addr)
+    }
+
+    fn poll_nmi_status(&mut self) -> Option<u8> {
+        Bus::poll_nmi_status(self)
+    }
+
+    fn poll_irq_status(&self) -> bool {
+        Bus::poll_irq_status(self)
+    }
+
+    fn take_dma_stall(&mut self) -> u8 {
+        Bus::take_dma_stall(self)
+    }
+
+    fn reset(&mut self) {
+        Bus::reset(self)
+    }
+
+    fn ppu_position(&self) -> Option<PpuPosition> {
+        Some(self.ppu.position())
+    }
+}
+
+impl Mem for Bus<'_> {
+    /// Reads go through the data bus, where the value stays until the next access.
+    /// $4015 is the exception: the APU sits inside the CPU chip and answers on
+    /// the internal bus, the data bus outside keeps what it had.
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = self.read_device(addr);
+        if addr != 0x4015 {
+            self.open_bus = data;
+        }
+        data
+    }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
+        let addr = match addr {
+            0x2008..=PPU_REGISTERS_MIRRORS_END => addr & 0b_0010_0000_0000_0111,
+            _ => addr,
+        };
+        self.open_bus = data;
+        if (0x2000..=0x2007).contains(&addr) {
+            self.ppu.refresh_open_bus(data, 0xFF);
+        }
+        if let Some(log) = &mut self.write_log {
+            log.record(self.ppu.get_frame_count(), self.cycles, addr, data);
+        }
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b0000_0111_1111_1111;
@@ -129,12 +438,263 @@ impl Mem for Bus<'_> {
                 }
                 self.ppu.write_oam_dma(&buffer);
             }
-            0x4016 => self.joypad1.write(data),
-            0x2008..=PPU_REGISTERS_MIRRORS_END => {
-                let mirror_down_addr = addr & 0b_0010_0000_0000_0111;
-                self.mem_write(mirror_down_addr, data);
+            0x4016 => self.ports.write(data),
+            0x4020..=0xFFFF => {
+                self.mapper.borrow_mut().cpu_write(addr, data);
+                // Boards such as MMC3 rewire the nametables from a register, even mid-frame
+                let mirroring = self.mapper.borrow().mirroring();
+                self.ppu.set_mirroring(mirroring);
             }
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::{Mirroring, TimingMode};
+    use crate::joypad::JoypadButton;
+
+    fn battery_bus<'a>() -> Bus<'a> {
+        let mut rom = test_rom();
+        rom.mapper = 0;
+        rom.battery = true;
+        rom.prg_ram_size = 0;
+        rom.prg_nvram_size = 0x2000;
+        Bus::new(rom, 44100.0, |_: &NesPPU, _: &mut ControllerPorts| {})
+    }
+
+    #[test]
+    fn test_battery_ram_survives_restart() {
+        let path = std::env::temp_dir().join(format!("nes_bus_test_{}.sav", std::process::id()));
+
+        let mut bus = battery_bus();
+        bus.mem_write(0x6000, 0x42);
+        bus.mem_write(0x7FFF, 0x24);
+        bus.flush_battery_ram(&path).unwrap();
+
+        let mut bus = battery_bus();
+        bus.restore_battery_ram(&path).unwrap();
+        assert_eq!(bus.mem_read(0x6000), 0x42);
+        assert_eq!(bus.mem_read(0x7FFF), 0x24);
+
+        std::fs::remove_file(&path).unwrap();
+        // A missing save file is not an error: the game just starts fresh
+        battery_bus().restore_battery_ram(&path).unwrap();
+    }
+
+    #[test]
+    fn test_no_save_file_without_battery() {
+        let path =
+            std::env::temp_dir().join(format!("nes_bus_test_{}_none.sav", std::process::id()));
+        let bus = Bus::new(
+            test_rom(),
+            44100.0,
+            |_: &NesPPU, _: &mut ControllerPorts| {},
+        );
+        bus.flush_battery_ram(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_mapper_mirroring_reaches_ppu() {
+        let mut rom = test_rom();
+        rom.mapper = 4;
+        let mut bus = Bus::new(rom, 44100.0, |_: &NesPPU, _: &mut ControllerPorts| {});
+        assert_eq!(bus.ppu.mirroring(), Mirroring::Vertical);
+        bus.mem_write(0xA000, 1);
+        assert_eq!(bus.ppu.mirroring(), Mirroring::Horizontal);
+        assert_eq!(bus.ppu.mirror_vram_addr(0x2400), 0x0000);
+    }
+
+    #[test]
+    fn test_long_tick_reaches_ppu() {
+        // 255 CPU cycles are 765 dots, two scanlines and then some
+        let mut bus = battery_bus();
+        bus.tick(255);
+        assert_eq!(bus.ppu.scanline, 2);
+    }
+
+    #[test]
+    fn test_mirrored_ppu_write_logged_once() {
+        let path = std::env::temp_dir().join(format!("nes_bus_test_{}.csv", std::process::id()));
+        let addresses = crate::watch::parse_addresses("2000-2008").unwrap();
+        let mut bus = battery_bus();
+        bus.set_write_log(WriteLog::create(&path, addresses).unwrap());
+        bus.mem_write(0x2008, 0x80);
+        bus.take_write_log().unwrap().finish().unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(csv, "frame,cycle,address,value\n0,0,2000,128\n");
+    }
+
+    #[test]
+    fn test_insert_cartridge() {
+        let mut bus = battery_bus();
+        bus.mem_write(0x0000, 0x11);
+        bus.mem_write(0x6000, 0x42);
+
+        bus.insert_cartridge(crate::cartridge::test::test_rom_containing(vec![0xEA]));
+        assert_eq!(bus.mem_read(0x8000), 0xEA);
+        assert_eq!(bus.mem_read(0x0000), 0);
+        assert!(bus.battery_ram().is_empty());
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = battery_bus();
+        bus.mem_write(0x0010, 0xA5);
+        assert_eq!(bus.mem_read(0x0010), 0xA5);
+        // Unmapped, then write-only: the value read last is still on the bus
+        assert_eq!(bus.mem_read(0x4018), 0xA5);
+        assert_eq!(bus.mem_read(0x4014), 0xA5);
+        // The PPU's registers answer from its own latch, last written through $2003
+        bus.mem_write(0x2003, 0x5A);
+        bus.mem_read(0x0010);
+        assert_eq!(bus.mem_read(0x2000), 0x5A);
+        assert_eq!(bus.mem_read(0x3FF6), 0x5A);
+        assert_eq!(bus.mem_read(0x2002), 0x1A);
+
+        // A pad's report sits under the high byte of the address, as after LDA $4016
+        bus.mem_write(0x0010, 0x40);
+        bus.mem_read(0x0010);
+        assert_eq!(bus.mem_read(0x4016), 0x40);
+        assert_eq!(bus.mem_read(0x4017), 0x40);
+
+        // Player 2 holding A, read with LDA $4017
+        bus.ports_mut().set_buttons(1, JoypadButton::BUTTON_A);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        bus.mem_read(0x0010);
+        assert_eq!(bus.mem_read(0x4017), 0x41);
+        // B is not held; the top bits are still the last value on the bus
+        assert_eq!(bus.mem_read(0x4017), 0x40);
+    }
+
+    #[test]
+    fn test_status_read() {
+        let mut bus = battery_bus();
+        // A one-byte DMC sample ends with its interrupt, then the frame interrupt
+        bus.mem_write(0x4010, 0x8F);
+        bus.mem_write(0x4013, 0x00);
+        bus.mem_write(0x4015, 0x10);
+        while !bus.apu.frame_interrupt() {
+            bus.tick(1);
+        }
+        bus.mem_write(0x0010, 0x20);
+        bus.mem_read(0x0010);
+
+        // Bit 5 comes from the bus, which the read leaves alone
+        assert_eq!(bus.mem_read(0x4015) & 0xF0, 0x80 | 0x40 | 0x20);
+        assert_eq!(bus.mem_read(0x4018), 0x20);
+        // Only the frame interrupt was acknowledged
+        assert_eq!(bus.mem_read(0x4015) & 0xC0, 0x80);
+    }
+
+    #[test]
+    fn test_peek_has_no_side_effects() {
+        let mut bus = battery_bus();
+        bus.ppu.status.set_vblank_status(true);
+        bus.mem_write(0x2006, 0x3F);
+        bus.mem_write(0x2006, 0x01);
+        bus.mem_write(0x2007, 0x2A);
+        bus.mem_write(0x2006, 0x3F);
+        bus.mem_write(0x2006, 0x01);
+        bus.ports_mut().set_buttons(0, JoypadButton::BUTTON_B);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        bus.mem_write(0x0010, 0x40);
+        bus.mem_read(0x0010);
+
+        for _ in 0..3 {
+            assert_eq!(bus.peek(0x2002) & 0x80, 0x80);
+            assert_eq!(bus.peek(0x3FFF), 0x2A);
+            assert_eq!(bus.peek(0x4016), 0x40);
+        }
+        assert_eq!(bus.open_bus, 0x40);
+        // The game still sees everything the peeks left in place
+        assert_eq!(bus.mem_read(0x2002) & 0x80, 0x80);
+        // The palette drives 6 bits, the rest is what $2002 left in the latch
+        assert_eq!(bus.mem_read(0x2007), 0xAA);
+        assert_eq!(bus.mem_read(0x4016) & 1, 0);
+        assert_eq!(bus.mem_read(0x4016) & 1, 1);
+    }
+
+    #[test]
+    fn test_dmc_irq_reaches_cpu() {
+        let mut bus = Bus::new(
+            test_rom(),
+            44100.0,
+            |_: &NesPPU, _: &mut ControllerPorts| {},
+        );
+        bus.mem_write(0x4017, 0x40);
+        bus.mem_write(0x4010, 0x8F);
+        bus.mem_write(0x4013, 0x00);
+        bus.mem_write(0x4015, 0x10);
+        bus.tick(2);
+        assert!(bus.poll_irq_status());
+        bus.mem_read(0x4015);
+        assert!(bus.poll_irq_status());
+        bus.mem_write(0x4015, 0x10);
+        assert!(!bus.poll_irq_status());
+    }
+
+    #[test]
+    fn test_expansion_gain_follows_database_unless_overridden() {
+        let mut rom = test_rom();
+        rom.info.expansion_gain = Some(1.5);
+        let mut bus = Bus::new(rom, 44100.0, |_: &NesPPU, _: &mut ControllerPorts| {});
+        assert_eq!(bus.expansion_gain(), 1.5);
+
+        bus.insert_cartridge(test_rom());
+        assert_eq!(bus.expansion_gain(), DEFAULT_EXPANSION_GAIN);
+
+        bus.override_expansion_gain(0.5);
+        let mut rom = test_rom();
+        rom.info.expansion_gain = Some(1.5);
+        bus.insert_cartridge(rom);
+        assert_eq!(bus.expansion_gain(), 0.5);
+    }
+
+    #[test]
+    fn test_expansion_audio_is_registered_with_the_apu() {
+        let mut bus = Bus::new(
+            test_rom(),
+            44100.0,
+            |_: &NesPPU, _: &mut ControllerPorts| {},
+        );
+        assert!(bus.apu.expansion_mut().is_empty());
+
+        let mut rom = test_rom();
+        rom.mapper = 5;
+        bus.insert_cartridge(rom);
+        assert!(bus.apu.expansion_mut().set_volume("mmc5", 1.0));
+        // The chip follows the cartridge into a new APU
+        bus.override_region(Region::Pal);
+        assert!(!bus.apu.expansion_mut().is_empty());
+    }
+
+    #[test]
+    fn test_region_follows_header_unless_overridden() {
+        let mut rom = test_rom();
+        rom.timing = TimingMode::Pal;
+        let mut bus = Bus::new(rom, 44100.0, |_: &NesPPU, _: &mut ControllerPorts| {});
+        assert_eq!(bus.region(), Region::Pal);
+        // 3.2 dots per CPU cycle: 16 every 5 cycles
+        for _ in 0..5 {
+            bus.tick(1);
+        }
+        assert_eq!(bus.ppu_position().unwrap().dot, 16);
+        bus.tick(1);
+        assert_eq!(bus.ppu_position().unwrap().dot, 19);
+
+        bus.insert_cartridge(test_rom());
+        assert_eq!(bus.region(), Region::Ntsc);
+        bus.override_region(Region::Pal);
+        bus.insert_cartridge(test_rom());
+        assert_eq!(bus.region(), Region::Pal);
+    }
+}
@@ -1,3 +1,10 @@
+use crate::archive::{self, ArchiveError};
+use crate::hash;
+use crate::mapper;
+use crate::romdb::{self, GameEntry};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
@@ -9,54 +16,383 @@ pub enum Mirroring {
     FourScreen,
 }
 
+/// Console timing a dump was made for, as the header gives it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TimingMode {
+    Ntsc,
+    Pal,
+    /// Runs on both NTSC and PAL consoles
+    MultiRegion,
+    Dendy,
+}
+
+/// What the 16-byte header says about an image, read without copying or hashing
+/// the ROM data. Cheap enough to run over a whole game folder.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RomHeader {
+    /// NES 2.0 header, as opposed to iNES 1.0
+    pub nes2: bool,
+    /// Mapper number, up to 12 bits with NES 2.0
+    pub mapper: u16,
+    /// NES 2.0 board variant, 0 for iNES 1.0
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    /// A 512-byte trainer sits between the header and PRG-ROM
+    pub trainer: bool,
+    pub timing: TimingMode,
+}
+
+impl RomHeader {
+    /// Whether this emulator has a board for the header's mapper. The ROM database
+    /// may still correct the mapper of a known dump when it is loaded.
+    pub fn is_supported(&self) -> bool {
+        u8::try_from(self.mapper).is_ok_and(mapper::is_supported)
+    }
+}
+
+/// Reads the header of an iNES or NES 2.0 image. Only the first 16 bytes are
+/// needed: the declared ROM sizes are not checked against the data.
+pub fn inspect(raw: &[u8]) -> Result<RomHeader, RomError> {
+    if raw.len() < 4 || raw[0..4] != NES_TAG {
+        return Err(RomError::BadMagic);
+    }
+    if raw.len() < 16 {
+        return Err(RomError::TruncatedFile);
+    }
+
+    let nes2 = match (raw[7] >> 2) & 0b11 {
+        0 => false,
+        2 => true,
+        _ => return Err(RomError::UnsupportedVersion),
+    };
+
+    let mut mapper = ((raw[7] & 0b1111_0000) | (raw[6] >> 4)) as u16;
+    if nes2 {
+        mapper |= (raw[8] as u16 & 0x0F) << 8;
+    }
+
+    let four_screen = raw[6] & 0b1000 != 0;
+    let vertical_mirroring = raw[6] & 0b1 != 0;
+    let mirroring = match (four_screen, vertical_mirroring) {
+        (true, _) => Mirroring::FourScreen,
+        (false, true) => Mirroring::Vertical,
+        (false, false) => Mirroring::Horizontal,
+    };
+
+    let (prg_rom_size, chr_rom_size) = if nes2 {
+        (
+            nes2_rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_SIZE)
+                .ok_or(RomError::TruncatedFile)?,
+            nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE).ok_or(RomError::TruncatedFile)?,
+        )
+    } else {
+        (
+            raw[4] as usize * PRG_ROM_PAGE_SIZE,
+            raw[5] as usize * CHR_ROM_PAGE_SIZE,
+        )
+    };
+
+    // iNES 1.0 only has a PAL bit, which few dumps set
+    let timing = match (nes2, raw[12] & 0b11) {
+        (true, 1) => TimingMode::Pal,
+        (true, 2) => TimingMode::MultiRegion,
+        (true, 3) => TimingMode::Dendy,
+        (false, _) if raw[9] & 1 != 0 => TimingMode::Pal,
+        _ => TimingMode::Ntsc,
+    };
+
+    Ok(RomHeader {
+        nes2,
+        mapper,
+        submapper: if nes2 { raw[8] >> 4 } else { 0 },
+        prg_rom_size,
+        chr_rom_size,
+        mirroring,
+        battery: raw[6] & 0b10 != 0,
+        trainer: raw[6] & 0b100 != 0,
+        timing,
+    })
+}
+
+/// Why `Rom::new` refused an image.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RomError {
+    /// The file does not start with "NES\x1A"
+    BadMagic,
+    /// No board implementation for this mapper number (12 bits with NES 2.0)
+    UnsupportedMapper(u16),
+    /// The file ends before the header, trainer or ROM data it declares
+    TruncatedFile,
+    /// Header revision other than iNES 1.0 and NES 2.0
+    UnsupportedVersion,
+    /// The file could not be read
+    Io(String),
+    /// The ROM could not be taken out of its archive
+    Archive(ArchiveError),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::BadMagic => write!(f, "File is not in iNES file format"),
+            RomError::UnsupportedMapper(mapper) => write!(f, "Mapper {} is not supported", mapper),
+            RomError::TruncatedFile => write!(f, "File is shorter than its header says"),
+            RomError::UnsupportedVersion => write!(f, "Unknown iNES header version"),
+            RomError::Io(e) => write!(f, "{}", e),
+            RomError::Archive(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
 pub struct Rom {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
     pub mapper: u8,
     pub screen_mirroring: Mirroring,
+    /// Battery flag of the header: some cartridge RAM survives power-off
+    pub battery: bool,
+    /// Volatile PRG-RAM at $6000-$7FFF (and banked on larger boards)
+    pub prg_ram_size: usize,
+    /// Battery-backed PRG-RAM
+    pub prg_nvram_size: usize,
+    /// Volatile CHR-RAM, used instead of CHR-ROM
+    pub chr_ram_size: usize,
+    /// Battery-backed CHR-RAM
+    pub chr_nvram_size: usize,
+    /// Console the dump was made for
+    pub timing: TimingMode,
+    /// Hashes and database match
+    pub info: RomInfo,
 }
 
-impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        if &raw[0..4] != NES_TAG {
-            return Err("File is not in iNES file format".to_string());
+/// Identification of a ROM image: hashes of its data and what the ROM database
+/// says about it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RomInfo {
+    /// CRC32 of PRG-ROM followed by CHR-ROM, without the header
+    pub crc32: u32,
+    /// SHA-1 over the same data
+    pub sha1: [u8; 20],
+    /// Title from the database, `None` for unknown dumps
+    pub title: Option<String>,
+    /// Header fields the database overrode, e.g. "mapper 4 -> 0"
+    pub corrections: Vec<String>,
+    /// Expansion audio level the database recommends for this game
+    pub expansion_gain: Option<f32>,
+}
+
+impl RomInfo {
+    /// Hashes of the ROM data, not yet looked up.
+    pub fn for_data(prg_rom: &[u8], chr_rom: &[u8]) -> RomInfo {
+        let data = [prg_rom, chr_rom].concat();
+        RomInfo {
+            crc32: hash::crc32(&data),
+            sha1: hash::sha1(&data),
+            title: None,
+            corrections: vec![],
+            expansion_gain: None,
         }
+    }
+}
 
-        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}, CRC32 {:08X}, SHA-1 {}",
+            self.title.as_deref().unwrap_or("Unknown game"),
+            self.crc32,
+            hash::to_hex(&self.sha1)
+        )?;
+        if !self.corrections.is_empty() {
+            write!(f, " (header fixed: {})", self.corrections.join(", "))?;
+        }
+        Ok(())
+    }
+}
 
-        let ines_ver = (raw[7] >> 2) & 0b11;
-        if ines_ver != 0 {
-            return Err("NES2.0 format is not supported".to_string());
+impl Rom {
+    /// Loads a `.nes` file, or the single `.nes` file inside a `.zip` archive.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Rom, RomError> {
+        let raw = std::fs::read(path).map_err(|e| RomError::Io(e.to_string()))?;
+        if raw.starts_with(&archive::ZIP_MAGIC) {
+            let rom = archive::extract_rom(&raw).map_err(RomError::Archive)?;
+            Rom::new(&rom)
+        } else if raw.starts_with(&archive::SEVEN_ZIP_MAGIC) {
+            Err(RomError::Archive(ArchiveError::Unsupported("7z")))
+        } else {
+            Rom::new(&raw)
         }
+    }
 
-        let four_screen = raw[6] & 0b1000 != 0;
-        let vertical_mirroring = raw[6] & 0b1 != 0;
-        let screen_mirroring = match (four_screen, vertical_mirroring) {
-            (true, _) => Mirroring::FourScreen,
-            (false, true) => Mirroring::Vertical,
-            (false, false) => Mirroring::Horizontal,
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        let header = inspect(raw)?;
+        let nes2 = header.nes2;
+        let Ok(mut mapper) = u8::try_from(header.mapper) else {
+            return Err(RomError::UnsupportedMapper(header.mapper));
         };
+        let mut screen_mirroring = header.mirroring;
+        let mut battery = header.battery;
+        let (prg_rom_size, chr_rom_size) = (header.prg_rom_size, header.chr_rom_size);
 
-        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        let prg_rom_start: usize = 16 + if header.trainer { 512 } else { 0 };
+        let chr_rom_start = prg_rom_start
+            .checked_add(prg_rom_size)
+            .ok_or(RomError::TruncatedFile)?;
+        let chr_rom_end = chr_rom_start
+            .checked_add(chr_rom_size)
+            .ok_or(RomError::TruncatedFile)?;
+        if raw.len() < chr_rom_end {
+            return Err(RomError::TruncatedFile);
+        }
+        let prg_rom = raw[prg_rom_start..chr_rom_start].to_vec();
+        let chr_rom = raw[chr_rom_start..chr_rom_end].to_vec();
 
-        let skip_trainer = raw[6] & 0b100 != 0;
+        // Identify the dump. Only iNES 1.0 headers get corrected: NES 2.0 ones are
+        // written on purpose and trusted as they are.
+        let mut info = RomInfo::for_data(&prg_rom, &chr_rom);
+        if let Some(entry) = romdb::lookup(info.crc32) {
+            info.title = Some(entry.title.clone());
+            info.expansion_gain = entry.expansion_gain;
+            if !nes2 {
+                info.corrections =
+                    correct_header(entry, &mut mapper, &mut screen_mirroring, &mut battery);
+            }
+        }
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
-        let chr_rom_start = prg_rom_start + prg_rom_size;
+        if !mapper::is_supported(mapper) {
+            return Err(RomError::UnsupportedMapper(mapper as u16));
+        }
+
+        let (prg_ram_size, prg_nvram_size, chr_ram_size, chr_nvram_size) = if nes2 {
+            (
+                nes2_ram_size(raw[10] & 0x0F),
+                nes2_ram_size(raw[10] >> 4),
+                nes2_ram_size(raw[11] & 0x0F),
+                nes2_ram_size(raw[11] >> 4),
+            )
+        } else {
+            // iNES 1.0 has no reliable RAM size field, so use what the board usually carries
+            let prg_ram = mapper::default_prg_ram_size(mapper);
+            let chr_ram = if chr_rom_size == 0 {
+                mapper::CHR_RAM_SIZE
+            } else {
+                0
+            };
+            if battery {
+                (0, prg_ram, chr_ram, 0)
+            } else {
+                (prg_ram, 0, chr_ram, 0)
+            }
+        };
 
         Ok(Rom {
-            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
-            mapper: mapper,
-            screen_mirroring: screen_mirroring,
+            prg_rom,
+            chr_rom,
+            mapper,
+            screen_mirroring,
+            battery,
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            chr_nvram_size,
+            timing: header.timing,
+            info,
         })
     }
 }
 
+/// Overwrites the header fields that disagree with the database entry and
+/// describes what changed.
+fn correct_header(
+    entry: &GameEntry,
+    mapper: &mut u8,
+    mirroring: &mut Mirroring,
+    battery: &mut bool,
+) -> Vec<String> {
+    let mut corrections = vec![];
+    if entry.mapper != *mapper {
+        corrections.push(format!("mapper {} -> {}", mapper, entry.mapper));
+        *mapper = entry.mapper;
+    }
+    if entry.mirroring != *mirroring {
+        corrections.push(format!(
+            "mirroring {:?} -> {:?}",
+            mirroring, entry.mirroring
+        ));
+        *mirroring = entry.mirroring.clone();
+    }
+    if entry.battery != *battery {
+        corrections.push(format!("battery {} -> {}", battery, entry.battery));
+        *battery = entry.battery;
+    }
+    corrections
+}
+
+/// NES 2.0 ROM size: the MSB nibble extends the page count, unless it is $F, in which
+/// case the LSB byte holds an exponent-multiplier pair (2^E * (MM*2+1) bytes).
+/// None if the size does not fit in memory, which no real file can have.
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> Option<usize> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize.checked_shl(exponent)?.checked_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize).checked_mul(page_size)
+    }
+}
+
+/// NES 2.0 RAM size: 0 means none, otherwise 64 << shift bytes.
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+/// Save file for battery-backed RAM: the ROM path with a `.sav` extension.
+pub fn save_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+fn format_size(bytes: usize) -> String {
+    if bytes.is_multiple_of(1024) {
+        format!("{}KB", bytes / 1024)
+    } else {
+        format!("{}B", bytes)
+    }
+}
+
+impl fmt::Display for Rom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Mapper {}, PRG-ROM {}, CHR-ROM {}, PRG-RAM {}, PRG-NVRAM {}, CHR-RAM {}, CHR-NVRAM {}, {:?} mirroring",
+            self.mapper,
+            format_size(self.prg_rom.len()),
+            format_size(self.chr_rom.len()),
+            format_size(self.prg_ram_size),
+            format_size(self.prg_nvram_size),
+            format_size(self.chr_ram_size),
+            format_size(self.chr_nvram_size),
+            self.screen_mirroring,
+        )
+    }
+}
+
 pub mod test {
 
     use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::CPU;
+    use crate::input::ControllerPorts;
+    use crate::ppu::NesPPU;
 
     struct TestRom {
         header: Vec<u8>,
@@ -97,12 +433,57 @@ pub mod test {
             ],
             trainer: None,
             pgp_rom: pgp_rom_contents,
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
 
         Rom::new(&test_rom).unwrap()
     }
 
+    /// `test_rom_containing` with `program` at $8000 and the NMI, RESET and IRQ
+    /// vectors pointing at `vectors`.
+    pub fn program_rom(mut program: Vec<u8>, vectors: [u16; 3]) -> Rom {
+        program.resize(2 * PRG_ROM_PAGE_SIZE - 6, 0);
+        for vector in vectors {
+            program.extend(vector.to_le_bytes());
+        }
+        test_rom_containing(program)
+    }
+
+    /// A board `mapper` with `prg_kb` KB of PRG-ROM and `chr_kb` KB of CHR-ROM
+    /// (none means CHR-RAM), every 8KB PRG bank and 1KB CHR bank filled with its
+    /// own number, so whatever is read tells which bank is mapped there. RAM sizes
+    /// are the board's usual ones.
+    pub fn banked_rom(mapper: u8, prg_kb: usize, chr_kb: usize, mirroring: Mirroring) -> Rom {
+        let prg_rom = (0..prg_kb / 8)
+            .flat_map(|bank| vec![bank as u8; 0x2000])
+            .collect();
+        let chr_rom = (0..chr_kb)
+            .flat_map(|bank| vec![bank as u8; 0x400])
+            .collect();
+        Rom {
+            prg_rom,
+            chr_rom,
+            mapper,
+            screen_mirroring: mirroring,
+            battery: false,
+            prg_ram_size: mapper::default_prg_ram_size(mapper),
+            prg_nvram_size: 0,
+            chr_ram_size: if chr_kb == 0 { mapper::CHR_RAM_SIZE } else { 0 },
+            chr_nvram_size: 0,
+            timing: TimingMode::Ntsc,
+            info: Default::default(),
+        }
+    }
+
+    /// A CPU on a bus holding `test_rom`, with nothing to do at the end of a frame
+    pub fn test_cpu<'a>() -> CPU<Bus<'a>> {
+        CPU::new(Bus::new(
+            test_rom(),
+            44100.0,
+            |_: &NesPPU, _: &mut ControllerPorts| {},
+        ))
+    }
+
     #[test]
     fn test() {
         let test_rom = create_rom(TestRom {
@@ -111,12 +492,12 @@ pub mod test {
             ],
             trainer: None,
             pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
 
         let rom: Rom = Rom::new(&test_rom).unwrap();
 
-        assert_eq!(rom.chr_rom, vec!(2; 1 * CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
         assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
@@ -145,31 +526,234 @@ pub mod test {
             ],
             trainer: Some(vec![0; 512]),
             pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
 
         let rom: Rom = Rom::new(&test_rom).unwrap();
 
-        assert_eq!(rom.chr_rom, vec!(2; 1 * CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
         assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
     }
 
     #[test]
-    fn test_nes2_is_not_supported() {
+    fn test_nes2_ram_sizes() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x52, 0x8, 00, 00, 0x99, 0x07, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.mapper, 5);
+        assert!(rom.battery);
+        assert_eq!(rom.prg_ram_size, 32 * 1024);
+        assert_eq!(rom.prg_nvram_size, 32 * 1024);
+        assert_eq!(rom.chr_ram_size, 8 * 1024);
+        assert_eq!(rom.chr_nvram_size, 0);
+    }
+
+    #[test]
+    fn test_ines_ram_defaults() {
+        let rom = test_rom();
+        assert_eq!(rom.prg_ram_size, 8 * 1024);
+        assert_eq!(rom.chr_ram_size, 0);
+
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x52, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+        let rom = Rom::new(&test_rom).unwrap();
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 64 * 1024);
+        assert_eq!(rom.chr_ram_size, 8 * 1024);
+        assert_eq!(
+            rom.to_string(),
+            "Mapper 5, PRG-ROM 16KB, CHR-ROM 0KB, PRG-RAM 0KB, PRG-NVRAM 64KB, CHR-RAM 8KB, CHR-NVRAM 0KB, Horizontal mirroring"
+        );
+    }
+
+    #[test]
+    fn test_inspect_header() {
+        let header = inspect(&[
+            0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x52, 0x8, 0x21, 00, 0x99, 0x07, 0x01, 00, 00, 00,
+        ])
+        .unwrap();
+        assert_eq!(
+            header,
+            RomHeader {
+                nes2: true,
+                mapper: 0x105,
+                submapper: 2,
+                prg_rom_size: PRG_ROM_PAGE_SIZE,
+                chr_rom_size: 0,
+                mirroring: Mirroring::Horizontal,
+                battery: true,
+                trainer: false,
+                timing: TimingMode::Pal,
+            }
+        );
+        assert!(!header.is_supported());
+
+        // The test ROM's header alone is enough
+        let mut raw = vec![
+            0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+        ];
+        let header = inspect(&raw).unwrap();
+        assert_eq!(header.mapper, 3);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert_eq!(header.prg_rom_size, 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(header.timing, TimingMode::Ntsc);
+        assert!(header.is_supported());
+        raw[7] = 0x04;
+        assert_eq!(inspect(&raw), Err(RomError::UnsupportedVersion));
+    }
+
+    #[test]
+    fn test_save_path() {
+        assert_eq!(
+            save_path(Path::new("roms/zelda.nes")),
+            PathBuf::from("roms/zelda.sav")
+        );
+        assert_eq!(save_path(Path::new("game")), PathBuf::from("game.sav"));
+    }
+
+    #[test]
+    fn test_unknown_header_version() {
         let test_rom = create_rom(TestRom {
             header: vec![
-                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x8, 00, 00, 00, 00, 00, 00, 00, 00,
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x4, 00, 00, 00, 00, 00, 00, 00, 00,
             ],
             trainer: None,
-            pgp_rom: vec![1; 1 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
-        let rom = Rom::new(&test_rom);
-        match rom {
-            Result::Ok(_) => assert!(false, "should not load rom"),
-            Result::Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
+        match Rom::new(&test_rom) {
+            Result::Ok(_) => panic!("should not load rom"),
+            Result::Err(e) => assert_eq!(e, RomError::UnsupportedVersion),
         }
     }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0xF1, 0xF0, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        match Rom::new(&test_rom) {
+            Result::Ok(_) => panic!("should not load rom"),
+            Result::Err(e) => {
+                assert_eq!(e, RomError::UnsupportedMapper(255));
+                assert_eq!(e.to_string(), "Mapper 255 is not supported");
+            }
+        }
+    }
+
+    #[test]
+    fn test_bad_magic_and_truncated_file() {
+        let mut test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        assert!(matches!(Rom::new(b"NES"), Err(RomError::BadMagic)));
+        assert!(matches!(Rom::new(b"PK\x03\x04"), Err(RomError::BadMagic)));
+        assert!(matches!(
+            Rom::new(&test_rom[..10]),
+            Err(RomError::TruncatedFile)
+        ));
+
+        test_rom.pop();
+        assert!(matches!(Rom::new(&test_rom), Err(RomError::TruncatedFile)));
+
+        // NES 2.0 exponent-multiplier sizes too large for any file: 2^63 * 7 bytes
+        let mut header = test_rom[..16].to_vec();
+        header[4] = 0xFF;
+        header[7] = 0x08;
+        header[9] = 0x0F;
+        assert_eq!(inspect(&header), Err(RomError::TruncatedFile));
+        assert!(matches!(Rom::new(&header), Err(RomError::TruncatedFile)));
+        // 2^62 * 3 fits, but not once added to the CHR-ROM's size
+        header[4] = 0xF9;
+        header[5] = 0xF9;
+        header[9] = 0xFF;
+        assert!(matches!(Rom::new(&header), Err(RomError::TruncatedFile)));
+    }
+
+    #[test]
+    fn test_rom_info() {
+        let rom = test_rom();
+        let data = [rom.prg_rom.clone(), rom.chr_rom.clone()].concat();
+        assert_eq!(rom.info.crc32, hash::crc32(&data));
+        assert_eq!(rom.info.sha1, hash::sha1(&data));
+        assert_eq!(rom.info.title, None);
+        assert!(rom.info.to_string().starts_with("Unknown game, CRC32 "));
+    }
+
+    #[test]
+    fn test_database_corrects_header() {
+        let entry = GameEntry {
+            crc32: 0,
+            mapper: 0,
+            mirroring: Mirroring::Vertical,
+            battery: false,
+            expansion_gain: None,
+            title: "Game".to_string(),
+        };
+        let (mut mapper, mut mirroring, mut battery) = (4, Mirroring::Horizontal, false);
+        let corrections = correct_header(&entry, &mut mapper, &mut mirroring, &mut battery);
+        assert_eq!(
+            corrections,
+            vec!["mapper 4 -> 0", "mirroring Horizontal -> Vertical"]
+        );
+        assert_eq!(
+            (mapper, mirroring, battery),
+            (0, Mirroring::Vertical, false)
+        );
+    }
+
+    #[test]
+    fn test_from_path_opens_zip() {
+        let dir = std::env::temp_dir();
+        let rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let path = dir.join(format!("nes_zip_test_{}.zip", std::process::id()));
+        std::fs::write(&path, archive::test::stored_zip(&[("game.nes", &rom)])).unwrap();
+        assert_eq!(
+            Rom::from_path(&path).unwrap().prg_rom,
+            vec![1; PRG_ROM_PAGE_SIZE]
+        );
+
+        let two = archive::test::stored_zip(&[("a.nes", &rom), ("b.nes", &rom)]);
+        std::fs::write(&path, two).unwrap();
+        assert!(matches!(
+            Rom::from_path(&path),
+            Err(RomError::Archive(ArchiveError::MultipleRoms(_)))
+        ));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(Rom::from_path(&path), Err(RomError::Io(_))));
+    }
 }
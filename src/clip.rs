@@ -0,0 +1,273 @@
+//
+// Clips: the last few seconds of video kept in memory so they can be saved as an
+// animated GIF or APNG after the fact. Frames are stored as indices into one color
+// table shared by the whole buffer (a NES picture rarely uses more than a couple
+// dozen colors), which makes a frame 60KB instead of 180KB and gives both formats
+// their palette for free.
+//
+// Export keeps only the rectangle that changed since the previous frame, with the
+// pixels inside it that did not change made transparent, and folds identical
+// frames into the previous one's delay.
+//
+
+use crate::render::frame::Frame;
+use crate::{gif, png};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+pub const DEFAULT_SECONDS: u32 = 6;
+const FRAMES_PER_SECOND: u32 = 60;
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+/// Colors kept in the table; the last index is left free for transparency
+const MAX_COLORS: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClipFormat {
+    Gif,
+    Apng,
+}
+
+impl ClipFormat {
+    pub fn from_name(name: &str) -> Option<ClipFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "gif" => Some(ClipFormat::Gif),
+            "apng" | "png" => Some(ClipFormat::Apng),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ClipFormat::Gif => "gif",
+            ClipFormat::Apng => "png",
+        }
+    }
+}
+
+/// The part of a frame that changed, ready for an animation encoder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Patch {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Color indices, `Clip::transparent()` where the previous frame shows through
+    pub pixels: Vec<u8>,
+    /// Frame number it appears at, and how many frames (1/60s) it stays
+    pub start: u32,
+    pub duration: u32,
+}
+
+/// A copy of the buffer taken for export.
+pub struct Clip {
+    pub width: usize,
+    pub height: usize,
+    pub colors: Vec<[u8; 3]>,
+    pub frames: Vec<Vec<u8>>,
+}
+
+impl Clip {
+    /// The color index no frame uses, shown as transparent.
+    pub fn transparent(&self) -> u8 {
+        self.colors.len() as u8
+    }
+
+    /// The frames as patches over their predecessor, keeping one frame out of
+    /// `step` (GIF players slow down delays under 2/100s, so GIFs use every other
+    /// frame).
+    pub fn patches(&self, step: usize) -> Vec<Patch> {
+        let mut patches: Vec<Patch> = vec![];
+        let mut previous: Option<&Vec<u8>> = None;
+        for (number, frame) in self.frames.iter().enumerate().step_by(step.max(1)) {
+            let rect = match previous {
+                None => Some((0, 0, self.width, self.height)),
+                Some(previous) => self.changed_rect(previous, frame),
+            };
+            match (rect, patches.last_mut()) {
+                (None, Some(last)) => last.duration += step as u32,
+                (Some((x, y, width, height)), _) => {
+                    let mut pixels = Vec::with_capacity(width * height);
+                    for row in y..y + height {
+                        for column in x..x + width {
+                            let i = row * self.width + column;
+                            pixels.push(match previous {
+                                Some(previous) if previous[i] == frame[i] => self.transparent(),
+                                _ => frame[i],
+                            });
+                        }
+                    }
+                    patches.push(Patch {
+                        x,
+                        y,
+                        width,
+                        height,
+                        pixels,
+                        start: number as u32,
+                        duration: step as u32,
+                    });
+                }
+                (None, None) => {}
+            }
+            previous = Some(frame);
+        }
+        patches
+    }
+
+    /// Encodes the clip and writes it to `path`.
+    pub fn save(&self, format: ClipFormat, path: &Path) -> std::io::Result<()> {
+        let data = match format {
+            ClipFormat::Gif => gif::encode(self),
+            ClipFormat::Apng => png::encode_apng(self),
+        };
+        std::fs::write(path, data)
+    }
+
+    /// Bounding box (x, y, width, height) of the pixels that differ.
+    fn changed_rect(&self, previous: &[u8], frame: &[u8]) -> Option<(usize, usize, usize, usize)> {
+        let (mut left, mut top, mut right, mut bottom) = (self.width, self.height, 0, 0);
+        for (row, (old, new)) in previous
+            .chunks(self.width)
+            .zip(frame.chunks(self.width))
+            .enumerate()
+        {
+            let Some(first) = (0..self.width).find(|&i| old[i] != new[i]) else {
+                continue;
+            };
+            let last = (0..self.width).rfind(|&i| old[i] != new[i]).unwrap();
+            left = left.min(first);
+            right = right.max(last + 1);
+            top = top.min(row);
+            bottom = row + 1;
+        }
+        (right > left).then(|| (left, top, right - left, bottom - top))
+    }
+}
+
+/// Ring buffer of the most recent frames.
+pub struct ClipBuffer {
+    capacity: usize,
+    frames: VecDeque<Vec<u8>>,
+    colors: Vec<[u8; 3]>,
+    indices: HashMap<[u8; 3], u8>,
+}
+
+impl ClipBuffer {
+    pub fn new(seconds: u32) -> Self {
+        let capacity = (seconds.max(1) * FRAMES_PER_SECOND) as usize;
+        ClipBuffer {
+            capacity,
+            frames: VecDeque::with_capacity(capacity),
+            colors: vec![],
+            indices: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Records a finished frame, dropping the oldest one when full.
+    pub fn push(&mut self, frame: &Frame) {
+        let mut indexed = if self.frames.len() == self.capacity {
+            self.frames.pop_front().unwrap()
+        } else {
+            vec![0; WIDTH * HEIGHT]
+        };
+        // Frames are mostly long runs of one color; skip the lookup within a run
+        let mut last: Option<([u8; 3], u8)> = None;
+        for (index, rgb) in indexed.iter_mut().zip(frame.data.chunks_exact(3)) {
+            let rgb = [rgb[0], rgb[1], rgb[2]];
+            *index = match last {
+                Some((color, index)) if color == rgb => index,
+                _ => self.color_index(rgb),
+            };
+            last = Some((rgb, *index));
+        }
+        self.frames.push_back(indexed);
+    }
+
+    fn color_index(&mut self, rgb: [u8; 3]) -> u8 {
+        if let Some(&index) = self.indices.get(&rgb) {
+            return index;
+        }
+        if self.colors.len() < MAX_COLORS {
+            let index = self.colors.len() as u8;
+            self.colors.push(rgb);
+            self.indices.insert(rgb, index);
+            return index;
+        }
+        // Table full (filtered pictures): fall back to the closest color
+        let distance = |color: &[u8; 3]| -> u32 {
+            (0..3)
+                .map(|i| (color[i] as i32 - rgb[i] as i32).pow(2) as u32)
+                .sum()
+        };
+        let (index, _) = self
+            .colors
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, color)| distance(color))
+            .unwrap();
+        index as u8
+    }
+
+    /// Copies the buffered frames, oldest first.
+    pub fn snapshot(&self) -> Clip {
+        Clip {
+            width: WIDTH,
+            height: HEIGHT,
+            colors: self.colors.clone(),
+            frames: self.frames.iter().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame_with(pixels: &[(usize, usize, (u8, u8, u8))]) -> Frame {
+        let mut frame = Frame::new();
+        for &(x, y, rgb) in pixels {
+            frame.set_pixel(x, y, rgb);
+        }
+        frame
+    }
+
+    #[test]
+    fn test_ring_buffer_and_patches() {
+        let mut buffer = ClipBuffer::new(1);
+        for _ in 0..FRAMES_PER_SECOND + 5 {
+            buffer.push(&Frame::new());
+        }
+        assert_eq!(buffer.len(), FRAMES_PER_SECOND as usize);
+
+        let mut buffer = ClipBuffer::new(1);
+        buffer.push(&Frame::new());
+        buffer.push(&Frame::new());
+        buffer.push(&frame_with(&[(10, 20, (255, 0, 0)), (12, 21, (0, 0, 255))]));
+        let clip = buffer.snapshot();
+        assert_eq!(clip.colors, vec![[0, 0, 0], [255, 0, 0], [0, 0, 255]]);
+
+        let patches = clip.patches(1);
+        assert_eq!(patches.len(), 2);
+        assert_eq!((patches[0].width, patches[0].height), (256, 240));
+        // The repeated first frame stays on screen twice as long
+        assert_eq!((patches[0].start, patches[0].duration), (0, 2));
+        let patch = &patches[1];
+        assert_eq!(
+            (patch.x, patch.y, patch.width, patch.height),
+            (10, 20, 3, 2)
+        );
+        let t = clip.transparent();
+        assert_eq!(patch.pixels, vec![1, t, t, t, t, 2]);
+        assert_eq!((patch.start, patch.duration), (2, 1));
+
+        assert_eq!(clip.patches(2).len(), 2);
+    }
+}
@@ -0,0 +1,191 @@
+//
+// User settings file (nes_emulator.cfg by default): `key = value` lines grouped
+// in `[section]`s, `#` starting a comment.
+//
+//   [joystick.1]
+//   dead_zone = 0.3
+//   diagonal_bias = -0.5
+//
+// Settings changed from inside the emulator (input remapping) are written back
+// into the file line by line, so the user's comments and layout survive.
+//
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const DEFAULT_PATH: &str = "nes_emulator.cfg";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Config {
+    sections: HashMap<String, HashMap<String, String>>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut section = String::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected 'key = value'", number + 1))?;
+            config
+                .sections
+                .entry(section.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+        Ok(config)
+    }
+
+    /// Reads a settings file; a missing file gives the defaults.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, String> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// Changes settings and writes them to the file at `path`, keeping the rest
+    /// of its text as it is. `values` are `(section, key, value)` triples.
+    pub fn store<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        values: &[(String, String, String)],
+    ) -> Result<(), String> {
+        let path = path.as_ref();
+        let mut text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        for (section, key, value) in values {
+            text = set_in_text(&text, section, key, value);
+            self.sections
+                .entry(section.clone())
+                .or_default()
+                .insert(key.clone(), value.clone());
+        }
+        std::fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.sections.get(section)?.get(key).map(String::as_str)
+    }
+
+    /// A number setting, `default` if it is missing or does not parse.
+    pub fn get_f32(&self, section: &str, key: &str, default: f32) -> f32 {
+        self.get_number(section, key, default)
+    }
+
+    pub fn get_u32(&self, section: &str, key: &str, default: u32) -> u32 {
+        self.get_number(section, key, default)
+    }
+
+    fn get_number<T: FromStr + Display>(&self, section: &str, key: &str, default: T) -> T {
+        match self.get(section, key).map(str::parse) {
+            Some(Ok(value)) => value,
+            Some(Err(_)) => {
+                eprintln!("[{}] {}: not a number, using {}", section, key, default);
+                default
+            }
+            None => default,
+        }
+    }
+}
+
+/// `text` with `key` of `[section]` set to `value`. An existing line is rewritten
+/// in place, keeping its comment; otherwise the line is added after the last
+/// setting of the section, which is appended if the file does not have it.
+fn set_in_text(text: &str, section: &str, key: &str, value: &str) -> String {
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let mut current = "";
+    // Where the new line goes if the key is not there: after the section's last setting
+    let mut insert_at = (section.is_empty()).then_some(0);
+    for (index, line) in text.lines().enumerate() {
+        let (content, comment) = match line.find('#') {
+            Some(start) => line.split_at(start),
+            None => (line, ""),
+        };
+        let content = content.trim();
+        if let Some(name) = content.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = name.trim();
+            if current == section {
+                insert_at = Some(index + 1);
+            }
+            continue;
+        }
+        if current != section || content.is_empty() {
+            continue;
+        }
+        insert_at = Some(index + 1);
+        if content.split('=').next().map(str::trim) == Some(key) {
+            let comment = if comment.is_empty() {
+                String::new()
+            } else {
+                format!(" {}", comment)
+            };
+            lines[index] = format!("{} = {}{}", key, value, comment);
+            return lines.join("\n") + "\n";
+        }
+    }
+    let line = format!("{} = {}", key, value);
+    match insert_at {
+        Some(index) => lines.insert(index, line),
+        None => {
+            if lines.last().is_some_and(|last| !last.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{}]", section));
+            lines.push(line);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_sections() {
+        let config = Config::parse(
+            "top = 1\n# comment\n[joystick.1]\n dead_zone = 0.3 # loose stick\n\n[joystick.2]\ndead_zone=x\n",
+        )
+        .unwrap();
+        assert_eq!(config.get("", "top"), Some("1"));
+        assert_eq!(config.get_f32("joystick.1", "dead_zone", 0.5), 0.3);
+        assert_eq!(config.get_f32("joystick.2", "dead_zone", 0.5), 0.5);
+        assert_eq!(config.get_f32("joystick.3", "dead_zone", 0.5), 0.5);
+        assert_eq!(config.get_u32("", "top", 7), 1);
+
+        assert!(Config::parse("[a]\nnonsense\n").is_err());
+    }
+
+    #[test]
+    fn test_set_in_text_keeps_layout() {
+        let text = "language = fr\n\n[keyboard]\n# arrows on the left hand\nup = W  # was Up\n\n[clips]\nseconds = 4\n";
+        let text = set_in_text(text, "keyboard", "up", "I");
+        let text = set_in_text(&text, "keyboard", "a", "K");
+        let text = set_in_text(&text, "joystick", "a", "2");
+        let text = set_in_text(&text, "", "top", "1");
+        assert_eq!(
+            text,
+            "language = fr\ntop = 1\n\n[keyboard]\n# arrows on the left hand\nup = I # was Up\na = K\n\n[clips]\nseconds = 4\n\n[joystick]\na = 2\n"
+        );
+        let config = Config::parse(&text).unwrap();
+        assert_eq!(config.get("keyboard", "up"), Some("I"));
+        assert_eq!(config.get("joystick", "a"), Some("2"));
+    }
+}
@@ -0,0 +1,146 @@
+//
+// The console as a library: one call to `run_frame` emulates up to the next
+// vblank and hands back the picture, the audio samples produced on the way and
+// what happened to the input, all belonging to the same frame. Recorders and
+// frontends (a libretro core, tests) get aligned audio and video without having
+// to follow the frame callback and the audio queue separately.
+//
+// A frame ends where the window's frame callback runs, at the start of vblank,
+// whether or not the game has the NMI enabled.
+//
+
+use crate::bus::Bus;
+use crate::cartridge::Rom;
+use crate::cpu::CPU;
+use crate::input::ControllerPorts;
+use crate::joypad::JoypadButton;
+use crate::ppu::NesPPU;
+use crate::render;
+use crate::render::frame::Frame;
+
+/// What the game did with its input during a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputLatch {
+    /// Buttons held on the pads of both ports for the whole frame
+    pub buttons: [JoypadButton; 2],
+    /// Times the game latched the controllers
+    pub latches: u32,
+}
+
+impl InputLatch {
+    /// The game never read its input, so the buttons of this frame were lost.
+    pub fn is_lag_frame(&self) -> bool {
+        self.latches == 0
+    }
+}
+
+/// One emulated frame, borrowed from the console until the next `run_frame`.
+pub struct FrameOutput<'a> {
+    pub frame: &'a Frame,
+    /// Mono samples at the console's sample rate, in the order they were produced
+    pub samples: &'a [f32],
+    pub input: InputLatch,
+}
+
+pub struct Console {
+    pub cpu: CPU<Bus<'static>>,
+    frame: Frame,
+    samples: Vec<f32>,
+    buttons: [JoypadButton; 2],
+}
+
+impl Console {
+    /// Powers the console on with `rom` inserted.
+    pub fn new(rom: Rom, sample_rate: f64) -> Self {
+        let bus = Bus::new(rom, sample_rate, |_: &NesPPU, _: &mut ControllerPorts| {});
+        let mut cpu = CPU::new(bus);
+        cpu.power_on();
+        Console {
+            cpu,
+            frame: Frame::new(),
+            samples: Vec::new(),
+            buttons: [JoypadButton::empty(); 2],
+        }
+    }
+
+    /// Buttons held on the pad of `port` from the next frame on.
+    pub fn set_buttons(&mut self, port: usize, buttons: JoypadButton) {
+        self.buttons[port] = buttons;
+    }
+
+    pub fn ports_mut(&mut self) -> &mut ControllerPorts {
+        self.cpu.bus.ports_mut()
+    }
+
+    /// Runs until the next vblank. Without the NMI, the game draws nothing the
+    /// emulator can show, and the previous picture is handed back again.
+    pub fn run_frame(&mut self) -> FrameOutput<'_> {
+        for port in 0..2 {
+            self.cpu
+                .bus
+                .ports_mut()
+                .set_buttons(port, self.buttons[port]);
+        }
+        self.cpu.bus.ports_mut().take_latches();
+        self.samples.clear();
+
+        let vblank = self.cpu.bus.region().vblank_line();
+        let mut scanline = self.cpu.bus.ppu().scanline;
+        loop {
+            self.cpu.step();
+            if let Some(sample) = self.cpu.collect_audio_sample() {
+                self.samples.push(sample);
+            }
+            let now = self.cpu.bus.ppu().scanline;
+            if now == vblank && scanline != vblank {
+                break;
+            }
+            scanline = now;
+        }
+
+        if self.cpu.bus.ppu().ctrl.generate_vblank_nmi() {
+            render::render(self.cpu.bus.ppu(), &mut self.frame);
+        }
+        FrameOutput {
+            frame: &self.frame,
+            samples: &self.samples,
+            input: InputLatch {
+                buttons: self.buttons,
+                latches: self.cpu.bus.ports_mut().take_latches(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom_containing;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_run_frame() {
+        // LDA #$80; STA $2000 (NMI on); then read the pad forever:
+        // LDA #1; STA $4016; LDA #0; STA $4016; LDA $4016; STA $10; JMP $8005
+        let mut prg = vec![
+            0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16,
+            0x40, 0xAD, 0x16, 0x40, 0x85, 0x10, 0x4C, 0x05, 0x80,
+        ];
+        prg.resize(0x8000, 0);
+        // NMI handler: RTI
+        prg[0x7000] = 0x40;
+        prg[0x7FFA..].copy_from_slice(&[0x00, 0xF0, 0x00, 0x80, 0x00, 0x80]);
+        let mut console = Console::new(test_rom_containing(prg), 44100.0);
+
+        console.run_frame();
+        console.set_buttons(0, JoypadButton::BUTTON_A);
+        let output = console.run_frame();
+        // 44100Hz at 60.1 frames a second
+        assert!((730..=737).contains(&output.samples.len()));
+        assert_eq!(output.input.buttons[0], JoypadButton::BUTTON_A);
+        assert!(output.input.latches > 1000);
+        assert!(!output.input.is_lag_frame());
+        assert_eq!(output.frame.data.len(), 256 * 240 * 3);
+        assert_eq!(console.cpu.mem_read(0x10) & 1, 1);
+    }
+}
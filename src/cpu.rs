@@ -1,6 +1,7 @@
 use crate::bus::Bus;
 use crate::opcodes;
-use std::collections::HashMap;
+use crate::ppu::PpuPosition;
+use crate::savestate::{StateError, StateReader, StateWriter};
 
 bitflags! {
     #[derive(Clone, Copy)]
@@ -19,24 +20,102 @@ bitflags! {
 const STACK_BASE: u16 = 0x0100;
 const STACK_RESET: u8 = 0xFD;
 
-const RESET_VECTOR: u16 = 0xFFFC;
-
-pub struct CPU<'a> {
+pub struct CPU<B> {
     pub register_a: u8,
     pub register_x: u8,
     pub register_y: u8,
     pub status: CpuFlags,
     pub program_counter: u16,
     pub stack_pointer: u8,
-    pub bus: Bus<'a>,
+    pub bus: B,
 
     pub nmi_pending: bool,
     pub irq_pending: bool,
 
     pub cycles: u64,
+    /// Bus cycles spent so far by the instruction being executed
+    instruction_cycles: u8,
+    /// Cycles the instruction spent halted by DMC sample fetches
+    stall_cycles: u8,
+    /// Address the instruction being executed works on, once known
+    operand_address: Option<u16>,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Everything of the CPU a save state needs: registers, pending interrupts and
+/// the cycle counter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuState {
+    pub register_a: u8,
+    pub register_x: u8,
+    pub register_y: u8,
+    pub status: u8,
+    pub program_counter: u16,
+    pub stack_pointer: u8,
+    pub nmi_pending: bool,
+    pub irq_pending: bool,
+    pub cycles: u64,
+}
+
+impl CpuState {
+    const TAG: &'static [u8; 4] = b"CPU ";
+
+    pub fn write(&self, out: &mut StateWriter) {
+        out.section(Self::TAG);
+        out.u8(self.register_a);
+        out.u8(self.register_x);
+        out.u8(self.register_y);
+        out.u8(self.status);
+        out.u16(self.program_counter);
+        out.u8(self.stack_pointer);
+        out.bool(self.nmi_pending);
+        out.bool(self.irq_pending);
+        out.u64(self.cycles);
+    }
+
+    pub fn read(input: &mut StateReader) -> Result<CpuState, StateError> {
+        input.section(Self::TAG)?;
+        Ok(CpuState {
+            register_a: input.u8()?,
+            register_x: input.u8()?,
+            register_y: input.u8()?,
+            status: input.u8()?,
+            program_counter: input.u16()?,
+            stack_pointer: input.u8()?,
+            nmi_pending: input.bool()?,
+            irq_pending: input.bool()?,
+            cycles: input.u64()?,
+        })
+    }
+}
+
+/// What one `CPU::step` executed, for traces, profilers and debuggers that would
+/// otherwise decode the instruction a second time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Step {
+    /// Address of the opcode; the handler's first one when an interrupt came first
+    pub pc: u16,
+    /// Where execution goes on: the next instruction, or a jump and branch target
+    pub next_pc: u16,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    operand: [u8; 2],
+    len: u8,
+    /// Memory read or written, or the destination of a jump, branch or call.
+    /// `None` for immediate, implied and stack operands
+    pub address: Option<u16>,
+    /// Cycles taken, the interrupt before the instruction and DMC stalls included
+    pub cycles: u8,
+}
+
+impl Step {
+    /// Operand bytes following the opcode, 0 to 2 of them.
+    pub fn operand(&self) -> &[u8] {
+        &self.operand[..self.len as usize - 1]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddressingMode {
     Immediate,
     ZeroPage,
@@ -71,7 +150,83 @@ pub trait Mem {
     }
 }
 
-impl Mem for CPU<'_> {
+/// The machine around the CPU: memory, the clock of everything else and the
+/// interrupt lines. `Bus` is the whole console; a test, the NSF player or a
+/// debugger sandbox can plug the core into something smaller.
+pub trait CpuBus: Mem {
+    /// Runs the rest of the machine for `cycles` CPU cycles.
+    fn tick(&mut self, cycles: u8);
+
+    /// What a read of `addr` would return, without its side effects.
+    fn peek(&self, addr: u16) -> u8;
+
+    /// A new NMI since the last call.
+    fn poll_nmi_status(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Level of the IRQ line.
+    fn poll_irq_status(&self) -> bool {
+        false
+    }
+
+    /// Cycles the CPU is halted for by DMA since the last call.
+    fn take_dma_stall(&mut self) -> u8 {
+        0
+    }
+
+    /// What the RESET line does to the rest of the machine.
+    fn reset(&mut self) {}
+
+    /// Where the picture is being drawn, on a bus with a PPU.
+    fn ppu_position(&self) -> Option<PpuPosition> {
+        None
+    }
+}
+
+/// 64KB of RAM and nothing else: no mirrors, no devices, no interrupts.
+pub struct RamBus {
+    pub memory: Vec<u8>,
+    /// Cycles ticked so far
+    pub cycles: u64,
+}
+
+impl RamBus {
+    pub fn new() -> Self {
+        RamBus {
+            memory: vec![0; 0x10000],
+            cycles: 0,
+        }
+    }
+}
+
+impl Default for RamBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mem for RamBus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+}
+
+impl CpuBus for RamBus {
+    fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as u64;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+}
+
+impl<B: CpuBus> Mem for CPU<B> {
     fn mem_read(&mut self, addr: u16) -> u8 {
         self.bus.mem_read(addr)
     }
@@ -96,10 +251,10 @@ fn page_cross(addr1: u16, addr2: u16) -> bool {
 mod interrupt {
     #[derive(PartialEq, Eq, Clone, Copy)]
     pub enum InterruptType {
-        NMI,
-        IRQ,
-        BRK,
-        RESET,
+        Nmi,
+        Irq,
+        Brk,
+        Reset,
     }
 
     #[derive(PartialEq, Eq, Clone, Copy)]
@@ -107,40 +262,35 @@ mod interrupt {
         pub(super) itype: InterruptType,
         pub(super) vector_addr: u16,
         pub(super) b_flag_mask: u8,
-        pub(super) cpu_cycles: u8,
     }
 
     pub(super) const NMI: Interrupt = Interrupt {
-        itype: InterruptType::NMI,
+        itype: InterruptType::Nmi,
         vector_addr: 0xFFFA,
         b_flag_mask: 0b00100000, // Bit 5 set to 1, BREAK set to 0
-        cpu_cycles: 7,
     };
 
     pub(super) const IRQ: Interrupt = Interrupt {
-        itype: InterruptType::IRQ,
+        itype: InterruptType::Irq,
         vector_addr: 0xFFFE,
         b_flag_mask: 0b00100000, // Bit 5 set to 1, BREAK set to 0
-        cpu_cycles: 7,
     };
 
     pub(super) const BRK: Interrupt = Interrupt {
-        itype: InterruptType::BRK,
+        itype: InterruptType::Brk,
         vector_addr: 0xFFFE,
         b_flag_mask: 0b00110000, // Bit 5 and BREAK set to 1
-        cpu_cycles: 7,
     };
 
-    pub(super) const _RESET: Interrupt = Interrupt {
-        itype: InterruptType::RESET,
+    pub(super) const RESET: Interrupt = Interrupt {
+        itype: InterruptType::Reset,
         vector_addr: 0xFFFC,
         b_flag_mask: 0b00100000,
-        cpu_cycles: 7,
     };
 }
 
-impl<'a> CPU<'a> {
-    pub fn new<'b>(bus: Bus<'b>) -> CPU<'b> {
+impl<B: CpuBus> CPU<B> {
+    pub fn new(bus: B) -> CPU<B> {
         CPU {
             register_a: 0,
             register_x: 0,
@@ -152,20 +302,67 @@ impl<'a> CPU<'a> {
             nmi_pending: false,
             irq_pending: false,
             cycles: 0,
+            instruction_cycles: 0,
+            stall_cycles: 0,
+            operand_address: None,
         }
     }
 
-    /// CPU reset according to NES specifications
-    pub fn reset(&mut self) {
+    /// Switching the console on: cleared registers, then the reset sequence, which
+    /// leaves SP at $FD and the cycle counter at 7.
+    pub fn power_on(&mut self) {
         self.register_a = 0;
         self.register_x = 0;
         self.register_y = 0;
-        self.stack_pointer = STACK_RESET;
-        self.status = CpuFlags::from_bits_truncate(0b00100100);
-        self.program_counter = self.mem_read_u16(RESET_VECTOR);
+        self.stack_pointer = 0;
+        self.status = CpuFlags::from_bits_truncate(0b00100000);
+        self.cycles = 0;
+        self.reset();
+    }
+
+    /// The RESET button. Registers keep their values: the CPU runs the 7-cycle
+    /// interrupt sequence with its writes turned into reads, so SP only goes down
+    /// by 3, then sets I and jumps through $FFFC. The bus resets what else the
+    /// line reaches.
+    pub fn reset(&mut self) {
+        self.bus.reset();
         self.nmi_pending = false;
         self.irq_pending = false;
-        self.cycles = 0;
+        self.interrupt(interrupt::RESET);
+    }
+
+    /// The PPU dot reached by the instruction being executed. The bus runs the PPU
+    /// up to each access before making it, so from a register handler this is the
+    /// dot a read of $2002 sees; between instructions, the end of the last one.
+    pub fn ppu_position(&self) -> Option<PpuPosition> {
+        self.bus.ppu_position()
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            register_a: self.register_a,
+            register_x: self.register_x,
+            register_y: self.register_y,
+            status: self.status.bits(),
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            nmi_pending: self.nmi_pending,
+            irq_pending: self.irq_pending,
+            cycles: self.cycles,
+        }
+    }
+
+    /// Puts back a state taken with `state`. The bus is left as it is.
+    pub fn set_state(&mut self, state: CpuState) {
+        self.register_a = state.register_a;
+        self.register_x = state.register_x;
+        self.register_y = state.register_y;
+        self.status = CpuFlags::from_bits_truncate(state.status);
+        self.program_counter = state.program_counter;
+        self.stack_pointer = state.stack_pointer;
+        self.nmi_pending = state.nmi_pending;
+        self.irq_pending = state.irq_pending;
+        self.cycles = state.cycles;
     }
 
     /// Trigger an IRQ interrupt
@@ -173,131 +370,138 @@ impl<'a> CPU<'a> {
         self.irq_pending = true;
     }
 
-    /// Calculate effective address according to addressing mode (public method for trace.rs)
-    pub fn get_absolute_address(&mut self, mode: &AddressingMode, addr: u16) -> (u16, bool) {
-        match mode {
-            AddressingMode::Immediate => (addr, false),
-
-            AddressingMode::ZeroPage => (self.mem_read(addr) as u16, false),
+    /// One CPU cycle: the rest of the console (PPU, APU, mapper) runs for it before
+    /// the access made in the cycle, so registers read mid-instruction show the
+    /// state at that exact cycle.
+    fn tick(&mut self) {
+        self.bus.tick(1);
+        self.cycles += 1;
+        self.instruction_cycles = self.instruction_cycles.wrapping_add(1);
 
-            AddressingMode::ZeroPageX => {
-                let addr = self.mem_read(addr).wrapping_add(self.register_x) as u16;
-                (addr, false)
+        // A DMC sample fetch takes the bus: the CPU waits for it before its access
+        loop {
+            let stall = self.bus.take_dma_stall();
+            if stall == 0 {
+                break;
             }
-
-            AddressingMode::ZeroPageY => {
-                let addr = self.mem_read(addr).wrapping_add(self.register_y) as u16;
-                (addr, false)
+            for _ in 0..stall {
+                self.bus.tick(1);
             }
+            self.cycles += stall as u64;
+            self.stall_cycles = self.stall_cycles.saturating_add(stall);
+        }
+    }
 
-            AddressingMode::Absolute => (self.mem_read_u16(addr), false),
+    /// Bus read taking one cycle; `Mem::mem_read` stays untimed for the debugger and tools.
+    fn read(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.bus.mem_read(addr)
+    }
 
-            AddressingMode::AbsoluteX => {
-                let base = self.mem_read_u16(addr);
-                let addr = base.wrapping_add(self.register_x as u16);
-                (addr, page_cross(base, addr))
-            }
+    /// Bus write taking one cycle.
+    fn write(&mut self, addr: u16, data: u8) {
+        self.tick();
+        self.bus.mem_write(addr, data);
+    }
 
-            AddressingMode::AbsoluteY => {
-                let base = self.mem_read_u16(addr);
-                let addr = base.wrapping_add(self.register_y as u16);
-                (addr, page_cross(base, addr))
-            }
+    /// First half of a read-modify-write: while the ALU works, the 6502 writes the
+    /// value it read back unchanged. Registers with write side effects ($2007,
+    /// mapper ports) see both writes.
+    fn read_for_modify(&mut self, addr: u16) -> u8 {
+        let value = self.read(addr);
+        self.write(addr, value);
+        value
+    }
 
-            AddressingMode::IndirectX => {
-                let base = self.mem_read(addr);
-                let ptr = base.wrapping_add(self.register_x);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-                ((hi as u16) << 8 | (lo as u16), false)
-            }
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let lo = self.read(addr) as u16;
+        let hi = self.read(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
 
-            AddressingMode::IndirectY => {
-                let base = self.mem_read(addr);
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read(base.wrapping_add(1) as u16);
-                let deref_base = (hi as u16) << 8 | (lo as u16);
-                let deref = deref_base.wrapping_add(self.register_y as u16);
-                (deref, page_cross(deref_base, deref))
-            }
+    /// Indexed address. The 6502 adds the index to the low byte first and reads
+    /// there while it fixes the high byte; the read is only skipped by instructions
+    /// that just read, when no fix is needed. Stores and read-modify-writes always
+    /// make it: the access can hit a register ($2002, $2007, $4015) with its side
+    /// effects.
+    fn indexed(&mut self, base: u16, index: u8, dummy_read: bool) -> (u16, bool) {
+        let addr = base.wrapping_add(index as u16);
+        let crossed = page_cross(base, addr);
+        if crossed || dummy_read {
+            self.read((base & 0xFF00) | (addr & 0x00FF));
+        }
+        (addr, crossed)
+    }
 
-            AddressingMode::Relative => {
-                let offset = self.mem_read(addr) as i8;
-                let addr = addr.wrapping_add(1).wrapping_add(offset as u16);
-                (addr, false)
-            }
+    /// Calculate effective address according to addressing mode
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> (u16, bool) {
+        self.effective_address(mode, false)
+    }
 
-            AddressingMode::Indirect => {
-                let ptr = self.mem_read_u16(addr);
-                // 6502 bug: JMP ($xxFF) reads high byte from $xx00 instead of $xx+1,00
-                let addr = if ptr & 0x00FF == 0x00FF {
-                    let lo = self.mem_read(ptr);
-                    let hi = self.mem_read(ptr & 0xFF00);
-                    (hi as u16) << 8 | (lo as u16)
-                } else {
-                    self.mem_read_u16(ptr)
-                };
-                (addr, false)
-            }
+    /// Address written by a store or read-modify-write instruction.
+    fn get_store_address(&mut self, mode: &AddressingMode) -> u16 {
+        self.effective_address(mode, true).0
+    }
 
-            _ => panic!("Addressing mode {:?} not supported", mode),
+    fn effective_address(&mut self, mode: &AddressingMode, store: bool) -> (u16, bool) {
+        let (addr, page_cross) = self.address_for(mode, store);
+        if *mode != AddressingMode::Immediate {
+            self.operand_address = Some(addr);
         }
+        (addr, page_cross)
     }
 
-    /// Calculate effective address according to addressing mode
-    fn get_operand_address(&mut self, mode: &AddressingMode) -> (u16, bool) {
+    fn address_for(&mut self, mode: &AddressingMode, store: bool) -> (u16, bool) {
         match mode {
             AddressingMode::Immediate => (self.program_counter, false),
 
-            AddressingMode::ZeroPage => (self.mem_read(self.program_counter) as u16, false),
+            AddressingMode::ZeroPage => (self.read(self.program_counter) as u16, false),
 
             AddressingMode::ZeroPageX => {
-                let addr = self
-                    .mem_read(self.program_counter)
-                    .wrapping_add(self.register_x) as u16;
-                (addr, false)
+                let base = self.read(self.program_counter);
+                // Read of the base address while the index is added
+                self.read(base as u16);
+                (base.wrapping_add(self.register_x) as u16, false)
             }
 
             AddressingMode::ZeroPageY => {
-                let addr = self
-                    .mem_read(self.program_counter)
-                    .wrapping_add(self.register_y) as u16;
-                (addr, false)
+                let base = self.read(self.program_counter);
+                // Read of the base address while the index is added
+                self.read(base as u16);
+                (base.wrapping_add(self.register_y) as u16, false)
             }
 
-            AddressingMode::Absolute => (self.mem_read_u16(self.program_counter), false),
+            AddressingMode::Absolute => (self.read_u16(self.program_counter), false),
 
             AddressingMode::AbsoluteX => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_x as u16);
-                (addr, page_cross(base, addr))
+                let base = self.read_u16(self.program_counter);
+                self.indexed(base, self.register_x, store)
             }
 
             AddressingMode::AbsoluteY => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_y as u16);
-                (addr, page_cross(base, addr))
+                let base = self.read_u16(self.program_counter);
+                self.indexed(base, self.register_y, store)
             }
 
             AddressingMode::IndirectX => {
-                let base = self.mem_read(self.program_counter);
+                let base = self.read(self.program_counter);
+                self.read(base as u16);
                 let ptr = base.wrapping_add(self.register_x);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+                let lo = self.read(ptr as u16);
+                let hi = self.read(ptr.wrapping_add(1) as u16);
                 ((hi as u16) << 8 | (lo as u16), false)
             }
 
             AddressingMode::IndirectY => {
-                let base = self.mem_read(self.program_counter);
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read(base.wrapping_add(1) as u16);
+                let base = self.read(self.program_counter);
+                let lo = self.read(base as u16);
+                let hi = self.read(base.wrapping_add(1) as u16);
                 let deref_base = (hi as u16) << 8 | (lo as u16);
-                let deref = deref_base.wrapping_add(self.register_y as u16);
-                (deref, page_cross(deref_base, deref))
+                self.indexed(deref_base, self.register_y, store)
             }
 
             AddressingMode::Relative => {
-                let offset = self.mem_read(self.program_counter) as i8;
+                let offset = self.read(self.program_counter) as i8;
                 let addr = self
                     .program_counter
                     .wrapping_add(1)
@@ -306,14 +510,14 @@ impl<'a> CPU<'a> {
             }
 
             AddressingMode::Indirect => {
-                let ptr = self.mem_read_u16(self.program_counter);
+                let ptr = self.read_u16(self.program_counter);
                 // 6502 bug: JMP ($xxFF) reads high byte from $xx00 instead of $xx+1,00
                 let addr = if ptr & 0x00FF == 0x00FF {
-                    let lo = self.mem_read(ptr);
-                    let hi = self.mem_read(ptr & 0xFF00);
+                    let lo = self.read(ptr);
+                    let hi = self.read(ptr & 0xFF00);
                     (hi as u16) << 8 | (lo as u16)
                 } else {
-                    self.mem_read_u16(ptr)
+                    self.read_u16(ptr)
                 };
                 (addr, false)
             }
@@ -330,18 +534,18 @@ impl<'a> CPU<'a> {
 
     /// Stack management - Push
     fn stack_push(&mut self, data: u8) {
-        self.mem_write(STACK_BASE + self.stack_pointer as u16, data);
+        self.write(STACK_BASE + self.stack_pointer as u16, data);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
     /// Stack management - Pop
     fn stack_pop(&mut self) -> u8 {
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
-        self.mem_read(STACK_BASE + self.stack_pointer as u16)
+        self.read(STACK_BASE + self.stack_pointer as u16)
     }
 
     /// Push 16-bit to stack (high byte first)
-    fn stack_push_u16(&mut self, data: u16) {
+    pub fn stack_push_u16(&mut self, data: u16) {
         let hi = (data >> 8) as u8;
         let lo = (data & 0xff) as u8;
         self.stack_push(hi);
@@ -387,7 +591,7 @@ impl<'a> CPU<'a> {
     /// Comparison - corrected logic
     fn compare(&mut self, mode: &AddressingMode, compare_with: u8) -> bool {
         let (addr, page_cross) = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
+        let data = self.read(addr);
 
         // Carry flag set if compare_with >= data
         self.status.set(CpuFlags::CARRY, compare_with >= data);
@@ -402,7 +606,7 @@ impl<'a> CPU<'a> {
     fn branch(&mut self, condition: bool) {
         if condition {
             let old_pc = self.program_counter;
-            let offset = self.mem_read(self.program_counter) as i8;
+            let offset = self.read(self.program_counter) as i8;
             let new_pc = self
                 .program_counter
                 .wrapping_add(1)
@@ -411,18 +615,28 @@ impl<'a> CPU<'a> {
             self.program_counter = new_pc;
 
             // +1 cycle if branch taken
-            self.bus.tick(1);
+            self.tick();
 
             // +1 additional cycle if page boundary crossed
             if page_cross(old_pc.wrapping_add(1), new_pc) {
-                self.bus.tick(1);
+                self.tick();
             }
         }
     }
 
-    /// Interrupt handling
+    /// Interrupt handling, 7 cycles counting BRK's opcode and padding byte fetches
     fn interrupt(&mut self, interrupt: interrupt::Interrupt) {
-        if interrupt.itype != interrupt::InterruptType::RESET {
+        if interrupt.itype != interrupt::InterruptType::Brk {
+            // NMI and IRQ fetch the next opcode twice and throw it away
+            self.read(self.program_counter);
+            self.read(self.program_counter);
+        }
+        if interrupt.itype == interrupt::InterruptType::Reset {
+            for _ in 0..3 {
+                self.read(STACK_BASE + self.stack_pointer as u16);
+                self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+            }
+        } else {
             self.stack_push_u16(self.program_counter);
 
             let mut status = self.status;
@@ -433,9 +647,7 @@ impl<'a> CPU<'a> {
         }
 
         self.status.insert(CpuFlags::INTERRUPT_DISABLE);
-        self.program_counter = self.mem_read_u16(interrupt.vector_addr);
-
-        self.bus.tick(interrupt.cpu_cycles);
+        self.program_counter = self.read_u16(interrupt.vector_addr);
     }
 
     // Processor instructions
@@ -443,7 +655,7 @@ impl<'a> CPU<'a> {
     /// LDA - Load Accumulator
     fn lda(&mut self, mode: &AddressingMode) -> bool {
         let (addr, page_cross) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.register_a = value;
         self.update_zero_and_negative_flags(value);
         page_cross
@@ -452,7 +664,7 @@ impl<'a> CPU<'a> {
     /// LDX - Load X Register
     fn ldx(&mut self, mode: &AddressingMode) -> bool {
         let (addr, page_cross) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.register_x = value;
         self.update_zero_and_negative_flags(value);
         page_cross
@@ -461,7 +673,7 @@ impl<'a> CPU<'a> {
     /// LDY - Load Y Register
     fn ldy(&mut self, mode: &AddressingMode) -> bool {
         let (addr, page_cross) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.register_y = value;
         self.update_zero_and_negative_flags(value);
         page_cross
@@ -469,26 +681,26 @@ impl<'a> CPU<'a> {
 
     /// STA - Store Accumulator
     fn sta(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(mode);
-        self.mem_write(addr, self.register_a);
+        let addr = self.get_store_address(mode);
+        self.write(addr, self.register_a);
     }
 
     /// STX - Store X Register
     fn stx(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(mode);
-        self.mem_write(addr, self.register_x);
+        let addr = self.get_store_address(mode);
+        self.write(addr, self.register_x);
     }
 
     /// STY - Store Y Register
     fn sty(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(mode);
-        self.mem_write(addr, self.register_y);
+        let addr = self.get_store_address(mode);
+        self.write(addr, self.register_y);
     }
 
     /// ADC - Add with Carry
     fn adc(&mut self, mode: &AddressingMode) -> bool {
         let (addr, page_cross) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.add_to_register_a(value);
         page_cross
     }
@@ -496,7 +708,7 @@ impl<'a> CPU<'a> {
     /// SBC - Subtract with Carry
     fn sbc(&mut self, mode: &AddressingMode) -> bool {
         let (addr, page_cross) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.sub_from_register_a(value);
         page_cross
     }
@@ -504,7 +716,7 @@ impl<'a> CPU<'a> {
     /// AND - Logical AND
     fn and(&mut self, mode: &AddressingMode) -> bool {
         let (addr, page_cross) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.register_a &= value;
         self.update_zero_and_negative_flags(self.register_a);
         page_cross
@@ -513,7 +725,7 @@ impl<'a> CPU<'a> {
     /// EOR - Exclusive OR
     fn eor(&mut self, mode: &AddressingMode) -> bool {
         let (addr, page_cross) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.register_a ^= value;
         self.update_zero_and_negative_flags(self.register_a);
         page_cross
@@ -522,7 +734,7 @@ impl<'a> CPU<'a> {
     /// ORA - Logical OR
     fn ora(&mut self, mode: &AddressingMode) -> bool {
         let (addr, page_cross) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.register_a |= value;
         self.update_zero_and_negative_flags(self.register_a);
         page_cross
@@ -539,11 +751,11 @@ impl<'a> CPU<'a> {
                 self.register_a
             }
             _ => {
-                let (addr, _) = self.get_operand_address(mode);
-                let mut value = self.mem_read(addr);
+                let addr = self.get_store_address(mode);
+                let mut value = self.read_for_modify(addr);
                 self.status.set(CpuFlags::CARRY, value & 0x80 != 0);
                 value <<= 1;
-                self.mem_write(addr, value);
+                self.write(addr, value);
                 self.update_zero_and_negative_flags(value);
                 value
             }
@@ -561,11 +773,11 @@ impl<'a> CPU<'a> {
                 self.register_a
             }
             _ => {
-                let (addr, _) = self.get_operand_address(mode);
-                let mut value = self.mem_read(addr);
+                let addr = self.get_store_address(mode);
+                let mut value = self.read_for_modify(addr);
                 self.status.set(CpuFlags::CARRY, value & 0x01 != 0);
                 value >>= 1;
-                self.mem_write(addr, value);
+                self.write(addr, value);
                 self.update_zero_and_negative_flags(value);
                 value
             }
@@ -585,11 +797,11 @@ impl<'a> CPU<'a> {
                 self.register_a
             }
             _ => {
-                let (addr, _) = self.get_operand_address(mode);
-                let mut value = self.mem_read(addr);
+                let addr = self.get_store_address(mode);
+                let mut value = self.read_for_modify(addr);
                 self.status.set(CpuFlags::CARRY, value & 0x80 != 0);
                 value = (value << 1) | (old_carry as u8);
-                self.mem_write(addr, value);
+                self.write(addr, value);
                 self.update_zero_and_negative_flags(value);
                 value
             }
@@ -609,11 +821,11 @@ impl<'a> CPU<'a> {
                 self.register_a
             }
             _ => {
-                let (addr, _) = self.get_operand_address(mode);
-                let mut value = self.mem_read(addr);
+                let addr = self.get_store_address(mode);
+                let mut value = self.read_for_modify(addr);
                 self.status.set(CpuFlags::CARRY, value & 0x01 != 0);
                 value = (value >> 1) | ((old_carry as u8) << 7);
-                self.mem_write(addr, value);
+                self.write(addr, value);
                 self.update_zero_and_negative_flags(value);
                 value
             }
@@ -622,18 +834,18 @@ impl<'a> CPU<'a> {
 
     /// INC - Increment Memory
     fn inc(&mut self, mode: &AddressingMode) -> u8 {
-        let (addr, _) = self.get_operand_address(mode);
-        let value = self.mem_read(addr).wrapping_add(1);
-        self.mem_write(addr, value);
+        let addr = self.get_store_address(mode);
+        let value = self.read_for_modify(addr).wrapping_add(1);
+        self.write(addr, value);
         self.update_zero_and_negative_flags(value);
         value
     }
 
     /// DEC - Decrement Memory
     fn dec(&mut self, mode: &AddressingMode) -> u8 {
-        let (addr, _) = self.get_operand_address(mode);
-        let value = self.mem_read(addr).wrapping_sub(1);
-        self.mem_write(addr, value);
+        let addr = self.get_store_address(mode);
+        let value = self.read_for_modify(addr).wrapping_sub(1);
+        self.write(addr, value);
         self.update_zero_and_negative_flags(value);
         value
     }
@@ -727,7 +939,7 @@ impl<'a> CPU<'a> {
     /// BIT - Bit Test
     fn bit(&mut self, mode: &AddressingMode) {
         let (addr, _) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         let result = self.register_a & value;
 
         self.status.set(CpuFlags::ZERO, result == 0);
@@ -744,7 +956,8 @@ impl<'a> CPU<'a> {
     /// JSR - Jump to Subroutine
     fn jsr(&mut self) {
         self.stack_push_u16(self.program_counter.wrapping_add(1));
-        self.program_counter = self.mem_read_u16(self.program_counter);
+        self.program_counter = self.read_u16(self.program_counter);
+        self.operand_address = Some(self.program_counter);
     }
 
     /// RTS - Return from Subroutine
@@ -763,6 +976,8 @@ impl<'a> CPU<'a> {
 
     /// BRK - Force Interrupt
     fn brk(&mut self) {
+        // The byte after BRK is fetched and skipped
+        self.read(self.program_counter);
         self.program_counter = self.program_counter.wrapping_add(1);
         self.interrupt(interrupt::BRK);
     }
@@ -778,9 +993,9 @@ impl<'a> CPU<'a> {
 
     /// SAX - Store A AND X
     fn sax(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(mode);
+        let addr = self.get_store_address(mode);
         let value = self.register_a & self.register_x;
-        self.mem_write(addr, value);
+        self.write(addr, value);
     }
 
     /// DCP - Decrement and Compare
@@ -827,7 +1042,7 @@ impl<'a> CPU<'a> {
     /// ANC - AND with Carry
     fn anc(&mut self, mode: &AddressingMode) {
         let (addr, _) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.register_a &= value;
         self.update_zero_and_negative_flags(self.register_a);
         self.status
@@ -837,7 +1052,7 @@ impl<'a> CPU<'a> {
     /// ALR - AND and Logical Shift Right
     fn alr(&mut self, mode: &AddressingMode) {
         let (addr, _) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.register_a &= value;
         self.status
             .set(CpuFlags::CARRY, self.register_a & 0x01 != 0);
@@ -848,7 +1063,7 @@ impl<'a> CPU<'a> {
     /// ARR - AND and Rotate Right
     fn arr(&mut self, mode: &AddressingMode) {
         let (addr, _) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.register_a &= value;
 
         let old_carry = self.status.contains(CpuFlags::CARRY);
@@ -865,7 +1080,7 @@ impl<'a> CPU<'a> {
     /// AXS - AND X with A and Subtract
     fn axs(&mut self, mode: &AddressingMode) {
         let (addr, _) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         let x_and_a = self.register_x & self.register_a;
         let result = x_and_a.wrapping_sub(value);
 
@@ -876,18 +1091,15 @@ impl<'a> CPU<'a> {
 
     /// LXA - Load X and A (unstable behavior)
     fn lxa(&mut self, mode: &AddressingMode) {
-        let page_cross = self.lda(mode);
+        self.lda(mode);
         self.register_x = self.register_a;
-        if page_cross {
-            self.bus.tick(1);
-        }
     }
 
     /// XAA - Transfer X to A and AND (unstable behavior)
     fn xaa(&mut self, mode: &AddressingMode) {
         self.register_a = self.register_x;
         let (addr, _) = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read(addr);
         self.register_a &= value;
         self.update_zero_and_negative_flags(self.register_a);
     }
@@ -895,7 +1107,7 @@ impl<'a> CPU<'a> {
     /// LAS - Load A, X and S
     fn las(&mut self, mode: &AddressingMode) {
         let (addr, _) = self.get_operand_address(mode);
-        let value = self.mem_read(addr) & self.stack_pointer;
+        let value = self.read(addr) & self.stack_pointer;
         self.register_a = value;
         self.register_x = value;
         self.stack_pointer = value;
@@ -906,30 +1118,30 @@ impl<'a> CPU<'a> {
     fn tas(&mut self, mode: &AddressingMode) {
         let value = self.register_a & self.register_x;
         self.stack_pointer = value;
-        let (addr, _) = self.get_operand_address(mode);
+        let addr = self.get_store_address(mode);
         let data = value & ((addr >> 8) as u8).wrapping_add(1);
-        self.mem_write(addr, data);
+        self.write(addr, data);
     }
 
     /// AHX - AND A, X and High byte
     fn ahx(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(mode);
+        let addr = self.get_store_address(mode);
         let data = self.register_a & self.register_x & ((addr >> 8) as u8).wrapping_add(1);
-        self.mem_write(addr, data);
+        self.write(addr, data);
     }
 
     /// SHX - Store X AND High byte
     fn shx(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(mode);
+        let addr = self.get_store_address(mode);
         let data = self.register_x & ((addr >> 8) as u8).wrapping_add(1);
-        self.mem_write(addr, data);
+        self.write(addr, data);
     }
 
     /// SHY - Store Y AND High byte
     fn shy(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(mode);
+        let addr = self.get_store_address(mode);
         let data = self.register_y & ((addr >> 8) as u8).wrapping_add(1);
-        self.mem_write(addr, data);
+        self.write(addr, data);
     }
 
     /// NOP - No Operation (with read for certain variants)
@@ -950,10 +1162,6 @@ impl<'a> CPU<'a> {
         panic!("CPU halted by KIL instruction");
     }
 
-    pub fn collect_audio_sample(&mut self) -> Option<f32> {
-        self.bus.collect_audio_sample()
-    }
-
     // Utility methods for testing and debugging
 
     pub fn load_and_run(&mut self, program: Vec<u8>) {
@@ -974,10 +1182,8 @@ impl<'a> CPU<'a> {
 
     pub fn run_with_callback<F>(&mut self, mut callback: F)
     where
-        F: FnMut(&mut CPU),
+        F: FnMut(&mut CPU<B>),
     {
-        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
-
         loop {
             let code = self.mem_read(self.program_counter);
             callback(self);
@@ -986,7 +1192,7 @@ impl<'a> CPU<'a> {
                 //BRK
                 return;
             }
-            if opcodes.get(&code).is_none() {
+            if opcodes::lookup(code).is_none() {
                 // Not a valid opcode, might be data, stop execution
                 // This is a simple way to end test programs
                 return;
@@ -996,27 +1202,41 @@ impl<'a> CPU<'a> {
         }
     }
 
-    pub fn step(&mut self) -> u8 {
+    /// Runs one instruction, after the interrupt preempting it if any, and returns
+    /// what it did. The bus is ticked cycle by cycle as the instruction goes.
+    pub fn step(&mut self) -> Step {
+        self.instruction_cycles = 0;
+        self.stall_cycles = 0;
+
         // Interrupt handling
         if let Some(_nmi) = self.bus.poll_nmi_status() {
             self.interrupt(interrupt::NMI);
         }
 
-        // IRQ interrupt handling
-        if self.irq_pending && !self.status.contains(CpuFlags::INTERRUPT_DISABLE) {
+        // IRQ interrupt handling: `irq_pending` is a one-shot request from `trigger_irq`,
+        // while the bus IRQ line stays asserted until the source is acknowledged
+        let irq_line = self.irq_pending || self.bus.poll_irq_status();
+        if irq_line && !self.status.contains(CpuFlags::INTERRUPT_DISABLE) {
             self.irq_pending = false;
             self.interrupt(interrupt::IRQ);
         }
+        let interrupt_cycles = self.instruction_cycles;
+        self.instruction_cycles = 0;
+        self.operand_address = None;
 
-        let code = self.mem_read(self.program_counter);
+        let pc = self.program_counter;
+        let code = self.read(self.program_counter);
         self.program_counter = self.program_counter.wrapping_add(1);
         let program_counter_state = self.program_counter;
 
-        let opcodes: &HashMap<u8, &'static opcodes::OpCode> = &opcodes::OPCODES_MAP;
-        let opcode = opcodes
-            .get(&code)
-            .unwrap_or_else(|| panic!("OpCode 0x{:02X} not recognized", code));
+        let opcode =
+            opcodes::lookup(code).unwrap_or_else(|| panic!("OpCode 0x{:02X} not recognized", code));
 
+        // Taken before the instruction runs, which could overwrite its own operand
+        let operand = [
+            self.bus.peek(pc.wrapping_add(1)),
+            self.bus.peek(pc.wrapping_add(2)),
+        ];
         let mut page_cross = false;
 
         match code {
@@ -1334,20 +1554,41 @@ impl<'a> CPU<'a> {
             }
         }
 
-        let mut cycles = opcode.cycles;
-        if page_cross {
-            cycles += 1;
+        // Cycle management: table cycles not spent on a bus access are internal
+        // operations (stack pointer updates, ALU work), run at the end
+        let cycles = opcode.cycles + page_cross as u8;
+        while self.instruction_cycles < cycles {
+            self.tick();
         }
 
-        // Cycle management
-        self.bus.tick(cycles);
-
         // Update program counter if not modified by instruction
         if program_counter_state == self.program_counter {
             self.program_counter = self.program_counter.wrapping_add((opcode.len - 1) as u16);
         }
 
-        cycles
+        // Branches only read their offset when taken
+        if opcode.mode == AddressingMode::Relative {
+            let offset = operand[0] as i8 as u16;
+            self.operand_address = Some(pc.wrapping_add(2).wrapping_add(offset));
+        }
+
+        Step {
+            pc,
+            next_pc: self.program_counter,
+            opcode: code,
+            mnemonic: opcode.mnemonic,
+            mode: opcode.mode,
+            operand,
+            len: opcode.len,
+            address: self.operand_address,
+            cycles: interrupt_cycles + self.instruction_cycles + self.stall_cycles,
+        }
+    }
+}
+
+impl CPU<Bus<'_>> {
+    pub fn collect_audio_sample(&mut self) -> Option<f32> {
+        self.bus.collect_audio_sample()
     }
 }
 
@@ -1477,4 +1718,250 @@ mod test {
         assert!(cpu.status.contains(CpuFlags::CARRY)); // A >= M
         assert!(cpu.status.contains(CpuFlags::ZERO)); // A == M
     }
+
+    #[test]
+    fn test_cycle_counts() {
+        let bus = Bus::new(
+            test::test_rom_containing(vec![]),
+            44100.0,
+            |_ppu, _joypad| {},
+        );
+        let mut cpu = CPU::new(bus);
+        cpu.register_x = 0x10;
+        // STA $02F8,X (page cross); JSR $0610; ...; $0610: RTS; BNE back across a page
+        cpu.load(vec![0x9D, 0xF8, 0x02, 0x20, 0x10, 0x06]);
+        cpu.mem_write(0x0610, 0x60);
+        cpu.mem_write(0x06FD, 0xD0);
+        cpu.mem_write(0x06FE, 0x10);
+        cpu.program_counter = 0x0600;
+
+        let mut counts = vec![];
+        for _ in 0..3 {
+            let before = cpu.cycles;
+            let cycles = cpu.step().cycles;
+            assert_eq!(cpu.cycles - before, cycles as u64);
+            counts.push(cycles);
+        }
+        assert_eq!(counts, [5, 6, 6]);
+        assert_eq!(cpu.mem_read(0x0308), 0);
+
+        // Taken branch to the next page: 2 + 1 + 1
+        cpu.program_counter = 0x06FD;
+        assert_eq!(cpu.step().cycles, 4);
+        assert_eq!(cpu.program_counter, 0x070F);
+    }
+
+    #[test]
+    fn test_step_result() {
+        let bus = Bus::new(
+            test::test_rom_containing(vec![]),
+            44100.0,
+            |_ppu, _joypad| {},
+        );
+        let mut cpu = CPU::new(bus);
+        cpu.register_x = 0x10;
+        // STA $02F8,X; JSR $0610; $0610: INX; BEQ +2 (not taken)
+        cpu.load(vec![0x9D, 0xF8, 0x02, 0x20, 0x10, 0x06]);
+        cpu.mem_write(0x0610, 0xE8);
+        cpu.mem_write(0x0611, 0xF0);
+        cpu.mem_write(0x0612, 0x02);
+        cpu.program_counter = 0x0600;
+
+        let sta = cpu.step();
+        assert_eq!(
+            sta,
+            Step {
+                pc: 0x0600,
+                next_pc: 0x0603,
+                opcode: 0x9D,
+                mnemonic: "STA",
+                mode: AddressingMode::AbsoluteX,
+                operand: [0xF8, 0x02],
+                len: 3,
+                address: Some(0x0308),
+                cycles: 5,
+            }
+        );
+        assert_eq!(sta.operand(), [0xF8, 0x02]);
+
+        let jsr = cpu.step();
+        assert_eq!((jsr.address, jsr.next_pc), (Some(0x0610), 0x0610));
+        let inx = cpu.step();
+        assert_eq!((inx.operand(), inx.address, inx.cycles), (&[][..], None, 2));
+        let beq = cpu.step();
+        assert_eq!((beq.address, beq.next_pc), (Some(0x0615), 0x0613));
+    }
+
+    #[test]
+    fn test_ram_bus() {
+        let mut cpu = CPU::new(RamBus::new());
+        cpu.bus.memory[0xFFFC..0xFFFE].copy_from_slice(&[0x00, 0x80]);
+        cpu.power_on();
+        // STA $0800; LDX $0000; JMP ($80FF): no mirrors, and the page-wrapping pointer
+        cpu.bus.memory[0x8000..0x8009]
+            .copy_from_slice(&[0x8D, 0x00, 0x08, 0xAE, 0x00, 0x00, 0x6C, 0xFF, 0x80]);
+        cpu.bus.memory[0x80FF] = 0x34;
+        cpu.register_a = 0x42;
+
+        assert_eq!(cpu.step().cycles, 4);
+        assert_eq!(cpu.bus.memory[0x0800], 0x42);
+        assert!(crate::trace::trace(&cpu).starts_with("8003  AE 00 00  LDX $0000 = 00"));
+        cpu.step();
+        assert_eq!(cpu.register_x, 0);
+        assert_eq!(cpu.step().next_pc, 0x8D34);
+        assert_eq!(cpu.bus.cycles, 7 + 4 + 4 + 5);
+    }
+
+    #[test]
+    fn test_reset_keeps_registers() {
+        let mut cpu = CPU::new(RamBus::new());
+        cpu.bus.memory[0xFFFC..0xFFFE].copy_from_slice(&[0x00, 0x80]);
+        cpu.power_on();
+        assert_eq!(
+            (cpu.stack_pointer, cpu.status.bits(), cpu.cycles),
+            (0xFD, 0x24, 7)
+        );
+        assert_eq!(cpu.program_counter, 0x8000);
+
+        cpu.register_a = 0x12;
+        cpu.register_x = 0x34;
+        cpu.stack_pointer = 0xF0;
+        cpu.status = CpuFlags::from_bits_truncate(0b1010_0011);
+        cpu.program_counter = 0x9000;
+        cpu.reset();
+        assert_eq!((cpu.register_a, cpu.register_x), (0x12, 0x34));
+        // Three pushes that write nothing
+        assert_eq!(cpu.stack_pointer, 0xED);
+        assert!(cpu.bus.memory[0x01EE..=0x01F0]
+            .iter()
+            .all(|&byte| byte == 0));
+        assert_eq!(cpu.status.bits(), 0b1010_0111);
+        assert_eq!((cpu.program_counter, cpu.cycles), (0x8000, 14));
+    }
+
+    #[test]
+    fn test_mid_instruction_ppu_visibility() {
+        let bus = Bus::new(
+            test::test_rom_containing(vec![]),
+            44100.0,
+            |_ppu, _joypad| {},
+        );
+        let mut cpu = CPU::new(bus);
+        // Find the CPU cycle in which VBlank starts, from the NMI it raises
+        cpu.mem_write(0x2000, 0x80);
+        let mut vblank_cycle = 0;
+        while cpu.bus.poll_nmi_status().is_none() {
+            cpu.bus.tick(1);
+            vblank_cycle += 1;
+        }
+
+        let bus = Bus::new(
+            test::test_rom_containing(vec![]),
+            44100.0,
+            |_ppu, _joypad| {},
+        );
+        let mut cpu = CPU::new(bus);
+        cpu.load(vec![0xAD, 0x02, 0x20]); // LDA $2002
+        cpu.program_counter = 0x0600;
+        // The read is the 4th and last cycle of LDA absolute: it lands right in VBlank
+        for _ in 0..vblank_cycle - 4 {
+            cpu.bus.tick(1);
+        }
+        assert_eq!(cpu.step().cycles, 4);
+        assert_ne!(cpu.register_a & 0x80, 0);
+    }
+
+    #[test]
+    fn test_dmc_fetch_stalls_cpu() {
+        let bus = Bus::new(
+            test::test_rom_containing(vec![]),
+            44100.0,
+            |_ppu, _joypad| {},
+        );
+        let mut cpu = CPU::new(bus);
+        cpu.load(vec![0xEA; 0x100]); // NOP
+        cpu.program_counter = 0x0600;
+        // 17-byte sample at $C000, highest rate
+        cpu.mem_write(0x4010, 0x0F);
+        cpu.mem_write(0x4012, 0x00);
+        cpu.mem_write(0x4013, 0x01);
+        cpu.mem_write(0x4015, 0x10);
+
+        let counts: Vec<u8> = (0..100).map(|_| cpu.step().cycles).collect();
+        let stalled = counts.iter().filter(|&&cycles| cycles == 2 + 4).count();
+        assert!(stalled > 0);
+        assert!(counts.iter().all(|&cycles| cycles == 2 || cycles == 6));
+    }
+
+    #[test]
+    fn test_read_modify_write_writes_twice() {
+        let bus = Bus::new(
+            test::test_rom_containing(vec![]),
+            44100.0,
+            |_ppu, _joypad| {},
+        );
+        let mut cpu = CPU::new(bus);
+        let path = std::env::temp_dir().join(format!("nes_rmw_test_{}.csv", std::process::id()));
+        let addresses = crate::watch::parse_addresses("10").unwrap();
+        cpu.bus
+            .set_write_log(crate::watch::WriteLog::create(&path, addresses).unwrap());
+        cpu.mem_write(0x10, 0x41);
+        cpu.load(vec![0xE6, 0x10]); // INC $10
+        cpu.program_counter = 0x0600;
+        assert_eq!(cpu.step().cycles, 5);
+        cpu.bus.take_write_log().unwrap().finish().unwrap();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let values: Vec<&str> = log
+            .lines()
+            .skip(1)
+            .map(|line| line.rsplit(',').next().unwrap())
+            .collect();
+        assert_eq!(values, ["65", "65", "66"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_indexed_dummy_read() {
+        let bus = Bus::new(
+            test::test_rom_containing(vec![]),
+            44100.0,
+            |_ppu, _joypad| {},
+        );
+        let mut cpu = CPU::new(bus);
+        cpu.mem_write(0x2000, 0x80);
+        while cpu.bus.poll_nmi_status().is_none() {
+            cpu.bus.tick(1);
+        }
+        // LDA $20F2,X reads $2102, a mirror of $2002, after a dummy read of $2002
+        // itself: that first read is the one seeing VBlank, and clears it
+        cpu.load(vec![0xBD, 0xF2, 0x20]);
+        cpu.program_counter = 0x0600;
+        cpu.register_x = 0x10;
+        assert_eq!(cpu.step().cycles, 5);
+        assert_eq!(cpu.register_a & 0x80, 0);
+    }
+
+    #[test]
+    fn test_apu_frame_irq() {
+        // IRQ handler at $8000: LDA $4015; STA $10; RTI
+        let mut prg = vec![0xAD, 0x15, 0x40, 0x85, 0x10, 0x40];
+        prg.resize(0x8000, 0);
+        prg[0x7FFE..].copy_from_slice(&[0x00, 0x80]);
+        let bus = Bus::new(test::test_rom_containing(prg), 44100.0, |_ppu, _joypad| {});
+        let mut cpu = CPU::new(bus);
+        cpu.load(vec![0x58, 0x4C, 0x01, 0x06]); // CLI; JMP $0601
+        cpu.program_counter = 0x0600;
+        cpu.mem_write(0x4017, 0x00);
+
+        let mut cycles = 0u32;
+        while cpu.mem_read(0x10) == 0 {
+            cycles += cpu.step().cycles as u32;
+            assert!(cycles < 40000, "no frame IRQ");
+        }
+        // Taken at the end of the 4-step sequence, the handler seeing bit 6
+        assert!((29828..29850).contains(&cycles));
+        assert_eq!(cpu.mem_read(0x10) & 0x40, 0x40);
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+    }
 }
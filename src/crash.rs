@@ -0,0 +1,177 @@
+//
+// Crash reports. A panic in the core (a mapper indexing past its banks, an edge
+// of the cycle-accurate CPU nobody hit before) is caught where the frontend steps
+// the CPU instead of closing the window. The panic message, the game, where the
+// CPU was and what it just ran go to a text file next to the ROM, ready to be
+// attached to an issue, and the console is reset.
+//
+// The panic hook keeps the message and a backtrace for the report; the default
+// hook still prints them as usual.
+//
+
+use crate::cpu::{CpuState, Step};
+use crate::disasm::Instruction;
+use crate::screenshot;
+use std::backtrace::Backtrace;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Instructions kept for the report.
+pub const HISTORY_LEN: usize = 32;
+
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+/// Keeps what the next panic says, and where, until `take_panic`.
+pub fn install_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let report = format!("{}\n\nBacktrace:\n{}", info, Backtrace::force_capture());
+        *LAST_PANIC.lock().unwrap_or_else(|e| e.into_inner()) = Some(report);
+        default_hook(info);
+    }));
+}
+
+/// The last panic caught by the hook.
+pub fn take_panic() -> String {
+    LAST_PANIC
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// The last instructions run, with the registers they started from.
+#[derive(Debug, Default)]
+pub struct History {
+    steps: Vec<(CpuState, Step)>,
+    next: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, before: CpuState, step: Step) {
+        if self.steps.len() < HISTORY_LEN {
+            self.steps.push((before, step));
+        } else {
+            self.steps[self.next] = (before, step);
+        }
+        self.next = (self.next + 1) % HISTORY_LEN;
+    }
+
+    /// Oldest first, in the trace log's layout.
+    pub fn lines(&self) -> Vec<String> {
+        let (newest, oldest) = self.steps.split_at(self.next.min(self.steps.len()));
+        oldest
+            .iter()
+            .chain(newest)
+            .map(|(before, step)| {
+                let bytes = [&[step.opcode], step.operand()].concat();
+                let operand = match bytes[1..] {
+                    [lo] => Some(lo as u16),
+                    [lo, hi] => Some(u16::from_le_bytes([lo, hi])),
+                    _ => None,
+                };
+                let instruction = Instruction {
+                    addr: step.pc,
+                    bytes,
+                    mnemonic: step.mnemonic,
+                    mode: step.mode,
+                    operand,
+                    target: step.address,
+                };
+                let text = instruction.to_string();
+                format!(
+                    "{:47} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+                    text,
+                    before.register_a,
+                    before.register_x,
+                    before.register_y,
+                    before.status,
+                    before.stack_pointer,
+                    before.cycles
+                )
+            })
+            .collect()
+    }
+}
+
+/// Where the report of a crash while running `rom` goes.
+pub fn report_path(rom: &Path) -> PathBuf {
+    rom.with_file_name(format!("{}.crash.txt", screenshot::file_prefix(rom)))
+}
+
+/// The report: the panic, the game, the registers when it happened and the
+/// instructions before. `pc` is where the crashing instruction started.
+pub fn report(panic: &str, rom: &str, pc: u16, cpu: &CpuState, history: &History) -> String {
+    let mut out = format!(
+        "{} {} crashed\n\n{}\n\nGame:\n{}\n\n",
+        crate::APP_NAME,
+        env!("CARGO_PKG_VERSION"),
+        panic.trim_end(),
+        rom
+    );
+    out += &format!(
+        "Instruction at ${:04X}, then A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PC:{:04X}, CPU cycle {}\n\n",
+        pc,
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status,
+        cpu.stack_pointer,
+        cpu.program_counter,
+        cpu.cycles
+    );
+    out += "Last instructions:\n";
+    for line in history.lines() {
+        out += &line;
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::test_rom;
+    use crate::cpu::{Mem, CPU};
+
+    #[test]
+    fn test_report() {
+        let mut bus = Bus::new(test_rom(), 44100.0, |_ppu, _joypad| {});
+        // LDX #$01; DEX; BEQ back to the LDX
+        for (addr, code) in [0xa2, 0x01, 0xca, 0xf0, 0xfb].into_iter().enumerate() {
+            bus.mem_write(0x64 + addr as u16, code);
+        }
+        let mut cpu = CPU::new(bus);
+        cpu.program_counter = 0x64;
+        let mut history = History::new();
+        for _ in 0..HISTORY_LEN + 3 {
+            let before = cpu.state();
+            let step = cpu.step();
+            history.push(before, step);
+        }
+
+        let lines = history.lines();
+        assert_eq!(lines.len(), HISTORY_LEN);
+        // The oldest three are gone, the last one ran is a DEX
+        assert!(lines[0].starts_with("0064  A2 01     LDX #$01"));
+        assert!(lines[2].starts_with("0067  F0 FB     BEQ $0064"));
+        assert!(lines[31].starts_with("0066  CA        DEX"));
+        assert!(lines[31].contains("A:00 X:01 Y:00 P:24 SP:FD"));
+
+        let report = report(
+            "panicked at src/mapper/mod.rs:1:1",
+            "Mapper 0",
+            0x67,
+            &cpu.state(),
+            &history,
+        );
+        assert!(report.contains("Instruction at $0067, then A:00 X:00"));
+        assert!(report.ends_with(&format!("{}\n", lines[31])));
+    }
+}
@@ -0,0 +1,272 @@
+//
+// ld65 debug info (.dbg, from `ld65 --dbgfile`): maps CPU addresses back to the
+// assembly or C source lines of homebrew built with the cc65 toolchain.
+//
+// The file is line based, one record per line: a record type followed by
+// comma-separated key=value pairs, for example
+//
+//   file id=0,name="main.s",size=1234,mtime=0x5F5E1000,mod=0
+//   seg id=0,name="CODE",start=0x008000,size=0x0200,addrsize=absolute,type=ro
+//   span id=3,seg=0,start=16,size=2
+//   line id=7,file=0,line=42,span=3
+//   sym id=1,name="reset",addrsize=absolute,scope=0,def=5,val=0x8000,seg=0,type=lab
+//
+// Only CPU addresses are matched: on banked boards, code assembled for the same
+// address in several banks resolves to whichever line was listed first.
+//
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Line type for lines produced by macro expansion, which point into the macro body.
+const LINE_TYPE_MACRO: u32 = 2;
+
+struct SourceFile {
+    name: String,
+    /// Contents, when the file was found next to the .dbg file
+    lines: Option<Vec<String>>,
+}
+
+#[derive(Clone, Copy)]
+struct LineRef {
+    file: u32,
+    line: u32,
+    /// Size of the span covering the address; smaller is more precise
+    span_size: u32,
+}
+
+/// Where an address comes from in the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceLocation<'a> {
+    pub file: &'a str,
+    pub line: u32,
+    /// The source line itself, if the file could be read
+    pub text: Option<&'a str>,
+}
+
+impl fmt::Display for SourceLocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)?;
+        if let Some(text) = self.text {
+            write!(f, ": {}", text.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+pub struct DebugInfo {
+    files: HashMap<u32, SourceFile>,
+    addresses: HashMap<u16, LineRef>,
+    labels: HashMap<u16, String>,
+}
+
+type Record<'a> = HashMap<&'a str, &'a str>;
+
+/// Splits `key=value,key="quoted, value"` into a map, quotes removed.
+fn parse_record(attributes: &str) -> Record<'_> {
+    let mut record = HashMap::new();
+    let mut rest = attributes.trim();
+    while !rest.is_empty() {
+        let Some((key, tail)) = rest.split_once('=') else {
+            break;
+        };
+        let (value, tail) = if let Some(quoted) = tail.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let after = &quoted[(end + 1).min(quoted.len())..];
+            (&quoted[..end], after.strip_prefix(',').unwrap_or(after))
+        } else {
+            tail.split_once(',').unwrap_or((tail, ""))
+        };
+        record.insert(key.trim(), value);
+        rest = tail.trim_start();
+    }
+    record
+}
+
+fn number(record: &Record, key: &str, line: usize) -> Result<u32, String> {
+    let value = record
+        .get(key)
+        .ok_or_else(|| format!("line {}: missing '{}'", line, key))?;
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("line {}: invalid {} '{}'", line, key, value))
+}
+
+impl DebugInfo {
+    pub fn parse(text: &str) -> Result<DebugInfo, String> {
+        let mut files = HashMap::new();
+        let mut segments = HashMap::new();
+        let mut spans = HashMap::new();
+        let mut lines = vec![];
+        let mut labels = HashMap::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let number_at = index + 1;
+            let Some((kind, attributes)) = line.trim().split_once(char::is_whitespace) else {
+                continue;
+            };
+            let record = parse_record(attributes);
+            let get = |key| number(&record, key, number_at);
+            match kind {
+                "version" if get("major")? != 2 => {
+                    return Err(format!("line {}: unsupported .dbg version", number_at));
+                }
+                "file" => {
+                    let name = record.get("name").copied().unwrap_or_default();
+                    files.insert(
+                        get("id")?,
+                        SourceFile {
+                            name: name.to_string(),
+                            lines: None,
+                        },
+                    );
+                }
+                "seg" => {
+                    segments.insert(get("id")?, get("start")?);
+                }
+                "span" => {
+                    spans.insert(get("id")?, (get("seg")?, get("start")?, get("size")?));
+                }
+                "line" => {
+                    // Lines without code (comments, labels) have no span
+                    if let Some(span_list) = record.get("span") {
+                        let span_ids: Result<Vec<u32>, _> =
+                            span_list.split('+').map(str::parse).collect();
+                        let span_ids = span_ids
+                            .map_err(|_| format!("line {}: invalid span list", number_at))?;
+                        let line_type = record.get("type").map_or(Ok(0), |_| get("type"))?;
+                        lines.push((get("file")?, get("line")?, line_type, span_ids));
+                    }
+                }
+                "sym" if record.get("type") == Some(&"lab") => {
+                    if let (Some(name), Ok(value)) = (record.get("name"), get("val")) {
+                        labels
+                            .entry(value as u16)
+                            .or_insert_with(|| name.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut addresses: HashMap<u16, LineRef> = HashMap::new();
+        for (file, line, line_type, span_ids) in lines {
+            if line_type == LINE_TYPE_MACRO {
+                continue;
+            }
+            for span_id in span_ids {
+                let Some(&(segment, start, size)) = spans.get(&span_id) else {
+                    continue;
+                };
+                let Some(&base) = segments.get(&segment) else {
+                    continue;
+                };
+                let line_ref = LineRef {
+                    file,
+                    line,
+                    span_size: size,
+                };
+                for addr in (base + start)..(base + start + size) {
+                    let slot = addresses.entry(addr as u16).or_insert(line_ref);
+                    if size < slot.span_size {
+                        *slot = line_ref;
+                    }
+                }
+            }
+        }
+
+        Ok(DebugInfo {
+            files,
+            addresses,
+            labels,
+        })
+    }
+
+    /// Reads a .dbg file and the sources it references, looked up relative to the
+    /// .dbg file's directory. Missing sources only cost the source text.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<DebugInfo, String> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut info = DebugInfo::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for file in info.files.values_mut() {
+            file.lines = std::fs::read_to_string(dir.join(&file.name))
+                .ok()
+                .map(|source| source.lines().map(str::to_string).collect());
+        }
+        Ok(info)
+    }
+
+    /// The source line that generated the byte at `addr`.
+    pub fn location(&self, addr: u16) -> Option<SourceLocation<'_>> {
+        let line_ref = self.addresses.get(&addr)?;
+        let file = self.files.get(&line_ref.file)?;
+        let text = file
+            .lines
+            .as_ref()
+            .and_then(|lines| lines.get(line_ref.line.checked_sub(1)? as usize))
+            .map(String::as_str);
+        Some(SourceLocation {
+            file: &file.name,
+            line: line_ref.line,
+            text,
+        })
+    }
+
+    /// Label defined at `addr`, if any.
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DBG: &str = r#"version	major=2,minor=0
+info	csym=0,file=1,lib=0,line=3,mod=1,scope=1,seg=1,span=3,sym=1,type=0
+file	id=0,name="main.s",size=100,mtime=0x5F5E1000,mod=0
+seg	id=0,name="CODE",start=0x00C000,size=0x0010,addrsize=absolute,type=ro,oname="game.nes",ooffs=16
+span	id=0,seg=0,start=0,size=2
+span	id=1,seg=0,start=2,size=3
+span	id=2,seg=0,start=0,size=5
+line	id=0,file=0,line=3,span=0
+line	id=1,file=0,line=4,span=1
+line	id=2,file=0,line=9,type=2,span=2
+line	id=3,file=0,line=1
+sym	id=0,name="reset",addrsize=absolute,scope=0,def=0,val=0xC000,seg=0,type=lab
+"#;
+
+    #[test]
+    fn test_address_to_line() {
+        let info = DebugInfo::parse(DBG).unwrap();
+        assert_eq!(info.location(0xC000).map(|l| l.line), Some(3));
+        assert_eq!(info.location(0xC003).map(|l| l.line), Some(4));
+        assert_eq!(info.location(0xC005), None);
+        assert_eq!(info.location(0xC000).unwrap().to_string(), "main.s:3");
+        assert_eq!(info.label(0xC000), Some("reset"));
+
+        assert!(DebugInfo::parse("span id=0,seg=0,start=zz,size=1").is_err());
+    }
+
+    #[test]
+    fn test_load_reads_sources() {
+        let dir = std::env::temp_dir().join(format!("nes_dbg_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.s"), "; header\n.proc reset\n  sei\n  cld\n").unwrap();
+        std::fs::write(dir.join("game.dbg"), DBG).unwrap();
+
+        let info = DebugInfo::load(dir.join("game.dbg")).unwrap();
+        assert_eq!(
+            info.location(0xC002).unwrap().to_string(),
+            "main.s:4:   cld"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+//
+// Debug states: the registers of the CPU, PPU and APU and the cartridge's bank
+// registers as indented JSON, small enough to paste into a bug report and stable
+// enough to diff between two builds. Memory is left out on purpose; these are
+// for reading, a save state is what brings a game back.
+//
+// F8 writes one next to the ROM (<rom>.state.json); the IPC socket hands them
+// out with {"cmd": "debug_state"}. Loading one back only restores the CPU
+// registers, which is enough to retry an instruction from a reported PC.
+//
+
+use crate::bus::Bus;
+use crate::cpu::{CpuFlags, CPU};
+use crate::json::Value;
+use std::path::{Path, PathBuf};
+
+/// Bumped when fields are renamed or removed, so old dumps are not misread.
+pub const VERSION: u8 = 1;
+
+/// Where F8 writes the state of the game loaded from `rom`.
+pub fn dump_path(rom: &Path) -> PathBuf {
+    rom.with_extension("state.json")
+}
+
+pub fn export(cpu: &CPU<Bus>) -> Value {
+    let ppu = cpu.bus.ppu();
+    let mapper = cpu.bus.mapper().borrow();
+    let mut cartridge = vec![(
+        "mirroring",
+        Value::from(format!("{:?}", mapper.mirroring()).as_str()),
+    )];
+    cartridge.extend(mapper.debug_state());
+
+    Value::object(vec![
+        ("version", Value::from(VERSION)),
+        (
+            "cpu",
+            Value::object(vec![
+                ("a", Value::from(cpu.register_a)),
+                ("x", Value::from(cpu.register_x)),
+                ("y", Value::from(cpu.register_y)),
+                ("sp", Value::from(cpu.stack_pointer)),
+                ("pc", Value::from(cpu.program_counter)),
+                ("status", Value::from(cpu.status.bits())),
+                ("cycles", Value::from(cpu.cycles)),
+            ]),
+        ),
+        (
+            "ppu",
+            Value::object(vec![
+                ("ctrl", Value::from(ppu.ctrl.bits())),
+                ("mask", Value::from(ppu.mask.bits())),
+                ("status", Value::from(ppu.status.snapshot())),
+                ("oam_addr", Value::from(ppu.oam_addr)),
+                ("scanline", Value::from(ppu.scanline)),
+                ("frame", Value::from(ppu.frame_count)),
+                ("vram_addr", Value::from(ppu.loopy.v)),
+                ("temp_vram_addr", Value::from(ppu.loopy.t)),
+                ("fine_x", Value::from(ppu.loopy.x)),
+                ("write_toggle", Value::from(ppu.loopy.w)),
+                ("scroll_x", Value::from(ppu.loopy.scroll().0)),
+                ("scroll_y", Value::from(ppu.loopy.scroll().1)),
+                ("palette", Value::from(&ppu.palette_table[..])),
+            ]),
+        ),
+        ("apu", cpu.bus.apu().debug_state()),
+        ("cartridge", Value::object(cartridge)),
+    ])
+}
+
+/// Puts the CPU registers of a dump back. Fields other than "cpu" are ignored.
+pub fn import(cpu: &mut CPU<Bus>, state: &Value) -> Result<(), String> {
+    match state.get("version").and_then(Value::as_u64) {
+        Some(version) if version == VERSION as u64 => {}
+        Some(version) => return Err(format!("unsupported debug state version {}", version)),
+        None => return Err("missing 'version'".to_string()),
+    }
+    let registers = state.get("cpu").ok_or("missing 'cpu'")?;
+    let field = |name: &str, max: u64| {
+        registers
+            .get(name)
+            .and_then(Value::as_u64)
+            .filter(|&value| value <= max)
+            .ok_or(format!("'cpu.{}' is missing or out of range", name))
+    };
+    let (a, x, y) = (field("a", 0xFF)?, field("x", 0xFF)?, field("y", 0xFF)?);
+    let (sp, pc, status) = (
+        field("sp", 0xFF)?,
+        field("pc", 0xFFFF)?,
+        field("status", 0xFF)?,
+    );
+    cpu.register_a = a as u8;
+    cpu.register_x = x as u8;
+    cpu.register_y = y as u8;
+    cpu.stack_pointer = sp as u8;
+    cpu.program_counter = pc as u16;
+    cpu.status = CpuFlags::from_bits_truncate(status as u8);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_cpu;
+
+    #[test]
+    fn test_export_import() {
+        let mut cpu = test_cpu();
+        cpu.register_a = 0x12;
+        cpu.program_counter = 0xC123;
+        let state = export(&cpu);
+        let text = state.pretty();
+        assert!(text.contains("\"pc\": 49443"));
+        assert!(text.contains("\"mirroring\""));
+        assert_eq!(
+            state
+                .get("apu")
+                .and_then(|apu| apu.get("frame_mode"))
+                .and_then(Value::as_u64),
+            Some(4)
+        );
+
+        let mut other = test_cpu();
+        import(&mut other, &Value::parse(&text).unwrap()).unwrap();
+        assert_eq!((other.register_a, other.program_counter), (0x12, 0xC123));
+        assert_eq!(other.status.bits(), cpu.status.bits());
+
+        let old = Value::object(vec![("version", Value::from(0u8))]);
+        assert!(import(&mut other, &old).is_err());
+    }
+}
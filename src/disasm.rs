@@ -0,0 +1,186 @@
+//
+// 6502 disassembler. Decodes instructions out of any memory view (a closure
+// giving the byte at an address) so it works on a running CPU, a ROM image or a
+// dump alike: the trace log, the `disassemble` IPC command and debuggers share it.
+//
+// Targets are only worked out when the bytes alone give them: absolute and zero
+// page operands, branches, and the pointer of JMP (indirect) read through the view.
+// Indexed modes depend on registers, which is the trace's business.
+//
+
+use crate::cpu::AddressingMode;
+use crate::opcodes;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// One decoded instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub addr: u16,
+    /// Opcode and operand bytes, 1 to 3 of them
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    /// Operand as written: a byte or little-endian word, `None` without one
+    pub operand: Option<u16>,
+    /// Address read, written or jumped to, when known without running the code
+    pub target: Option<u16>,
+}
+
+impl Instruction {
+    /// Operand in assembler syntax, e.g. `#$10`, `($20),Y` or `$C005`.
+    pub fn operand_text(&self) -> String {
+        let value = self.operand.unwrap_or(0);
+        match self.mode {
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${:02X}", value),
+            AddressingMode::ZeroPage => format!("${:02X}", value),
+            AddressingMode::ZeroPageX => format!("${:02X},X", value),
+            AddressingMode::ZeroPageY => format!("${:02X},Y", value),
+            AddressingMode::Absolute => format!("${:04X}", value),
+            AddressingMode::AbsoluteX => format!("${:04X},X", value),
+            AddressingMode::AbsoluteY => format!("${:04X},Y", value),
+            AddressingMode::IndirectX => format!("(${:02X},X)", value),
+            AddressingMode::IndirectY => format!("(${:02X}),Y", value),
+            AddressingMode::Indirect => format!("(${:04X})", value),
+            AddressingMode::Relative => format!("${:04X}", self.target.unwrap_or(0)),
+        }
+    }
+
+    /// Address of the instruction that follows in memory.
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// Same layout as the trace log: `C000  4C F5 C5  JMP $C5F5`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let text = format!(
+            "{:04X}  {:8} {: >4} {}",
+            self.addr,
+            hex,
+            self.mnemonic,
+            self.operand_text()
+        );
+        write!(f, "{}", text.trim_end())
+    }
+}
+
+/// Decodes the instruction at `addr`.
+pub fn decode<F: FnMut(u16) -> u8>(read: &mut F, addr: u16) -> Instruction {
+    let code = read(addr);
+    // Official and unofficial opcodes together cover every byte
+    let opcode = opcodes::lookup(code).unwrap();
+    let bytes: Vec<u8> = (0..opcode.len as u16)
+        .map(|i| read(addr.wrapping_add(i)))
+        .collect();
+    let operand = match bytes[1..] {
+        [lo] => Some(lo as u16),
+        [lo, hi] => Some(u16::from_le_bytes([lo, hi])),
+        _ => None,
+    };
+
+    let target = match (opcode.mode, operand) {
+        (AddressingMode::ZeroPage | AddressingMode::Absolute, Some(operand)) => Some(operand),
+        (AddressingMode::Relative, Some(offset)) => {
+            Some(addr.wrapping_add(2).wrapping_add(offset as u8 as i8 as u16))
+        }
+        // JMP ($xxFF) takes the high byte from $xx00, not the next page
+        (AddressingMode::Indirect, Some(pointer)) => {
+            let hi_addr = (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF);
+            Some(u16::from_le_bytes([read(pointer), read(hi_addr)]))
+        }
+        _ => None,
+    };
+
+    Instruction {
+        addr,
+        bytes,
+        mnemonic: opcode.mnemonic,
+        mode: opcode.mode,
+        operand,
+        target,
+    }
+}
+
+/// Instructions decoded one after the other from the start of a range.
+pub struct Disassembly<F> {
+    read: F,
+    next: Option<u16>,
+    last: u16,
+}
+
+impl<F: FnMut(u16) -> u8> Iterator for Disassembly<F> {
+    type Item = Instruction;
+
+    fn next(&mut self) -> Option<Instruction> {
+        let addr = self.next.filter(|&addr| addr <= self.last)?;
+        let instruction = decode(&mut self.read, addr);
+        // Stop at the end of memory rather than wrap around to $0000
+        self.next = addr.checked_add(instruction.bytes.len() as u16);
+        Some(instruction)
+    }
+}
+
+/// Disassembles the instructions starting in `range`. The last one may run past
+/// the end of the range.
+pub fn disassemble<F: FnMut(u16) -> u8>(read: F, range: RangeInclusive<u16>) -> Disassembly<F> {
+    Disassembly {
+        read,
+        next: Some(*range.start()),
+        last: *range.end(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let mut memory = vec![0u8; 0x10000];
+        let code = [
+            0xA9, 0x10, // LDA #$10
+            0x91, 0x20, // STA ($20),Y
+            0xD0, 0xFA, // BNE $C000
+            0x6C, 0xFF, 0x02, // JMP ($02FF)
+            0x0A, // ASL A
+            0x20, 0x34, 0x12, // JSR $1234
+        ];
+        memory[0xC000..0xC000 + code.len()].copy_from_slice(&code);
+        memory[0x02FF] = 0x78;
+        memory[0x0200] = 0x56;
+        memory[0x0300] = 0xFF;
+
+        let lines: Vec<Instruction> =
+            disassemble(|addr| memory[addr as usize], 0xC000..=0xC009).collect();
+        let text: Vec<String> = lines.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "C000  A9 10     LDA #$10",
+                "C002  91 20     STA ($20),Y",
+                "C004  D0 FA     BNE $C000",
+                "C006  6C FF 02  JMP ($02FF)",
+                "C009  0A        ASL A",
+            ]
+        );
+        assert_eq!(lines[1].target, None);
+        assert_eq!(lines[2].target, Some(0xC000));
+        // The page-wrapping pointer fetch
+        assert_eq!(lines[3].target, Some(0x5678));
+
+        let jsr = decode(&mut |addr| memory[addr as usize], 0xC00A);
+        assert_eq!((jsr.mnemonic, jsr.target), ("JSR", Some(0x1234)));
+        assert_eq!(jsr.to_string(), "C00A  20 34 12  JSR $1234");
+        assert_eq!(disassemble(|_| 0xEA, 0xFFFF..=0xFFFF).count(), 1);
+    }
+}
@@ -0,0 +1,149 @@
+//
+// Expansion audio: sound chips on the cartridge (MMC5, VRC7, the FDS...) that
+// the console mixes with the APU through the cartridge connector. A chip only
+// has to produce its level. The board hands it over when it is built, and the
+// APU clocks it every CPU cycle, scales it by the source's volume and adds it to
+// its own mix. That way it goes through the same band-limited synthesis and
+// board filters as the APU channels.
+//
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub trait ExpansionAudio {
+    /// Short name of the chip, e.g. "mmc5"
+    fn name(&self) -> &'static str;
+
+    /// Runs the chip for one CPU cycle. Chips that only change level on
+    /// register writes have nothing to do.
+    fn clock(&mut self) {}
+
+    /// Current output, scaled so that 1.0 is as loud as the APU at full volume.
+    fn output(&self) -> f32;
+}
+
+/// Shared handle: the board writes the chip's registers, the APU plays it.
+pub type ExpansionRef = Rc<RefCell<dyn ExpansionAudio>>;
+
+#[derive(Clone)]
+struct Source {
+    chip: ExpansionRef,
+    volume: f32,
+}
+
+/// The chips registered with the APU and how loud each one plays.
+#[derive(Clone, Default)]
+pub struct ExpansionMixer {
+    sources: Vec<Source>,
+}
+
+impl ExpansionMixer {
+    pub fn register(&mut self, chip: ExpansionRef, volume: f32) {
+        self.sources.push(Source { chip, volume });
+    }
+
+    /// Forgets every chip, for the next cartridge.
+    pub fn clear(&mut self) {
+        self.sources.clear();
+    }
+
+    /// Changes the volume of the chips called `name`. Returns false if there is
+    /// none.
+    pub fn set_volume(&mut self, name: &str, volume: f32) -> bool {
+        let mut found = false;
+        for source in &mut self.sources {
+            if source.chip.borrow().name() == name {
+                source.volume = volume;
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Changes the volume of every chip.
+    pub fn set_all_volumes(&mut self, volume: f32) {
+        self.sources
+            .iter_mut()
+            .for_each(|source| source.volume = volume);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn clock(&mut self) {
+        for source in &self.sources {
+            source.chip.borrow_mut().clock();
+        }
+    }
+
+    /// The chips' outputs, each at its volume.
+    pub fn output(&self) -> f32 {
+        self.sources
+            .iter()
+            .map(|source| source.volume * source.chip.borrow().output())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Toggles between 0 and 1 every cycle.
+    #[derive(Default)]
+    struct Square {
+        high: bool,
+    }
+
+    impl ExpansionAudio for Square {
+        fn name(&self) -> &'static str {
+            "square"
+        }
+
+        fn clock(&mut self) {
+            self.high = !self.high;
+        }
+
+        fn output(&self) -> f32 {
+            self.high as u8 as f32
+        }
+    }
+
+    struct Constant;
+
+    impl ExpansionAudio for Constant {
+        fn name(&self) -> &'static str {
+            "constant"
+        }
+
+        fn output(&self) -> f32 {
+            1.0
+        }
+    }
+
+    #[test]
+    fn test_mixer() {
+        let mut mixer = ExpansionMixer::default();
+        assert_eq!(mixer.output(), 0.0);
+
+        mixer.register(Rc::new(RefCell::new(Square::default())), 0.5);
+        mixer.register(Rc::new(RefCell::new(Constant)), 0.25);
+        assert_eq!(mixer.output(), 0.25);
+        mixer.clock();
+        assert_eq!(mixer.output(), 0.75);
+        mixer.clock();
+        assert_eq!(mixer.output(), 0.25);
+
+        assert!(mixer.set_volume("constant", 1.0));
+        assert!(!mixer.set_volume("vrc7", 1.0));
+        assert_eq!(mixer.output(), 1.0);
+        mixer.set_all_volumes(2.0);
+        mixer.clock();
+        assert_eq!(mixer.output(), 4.0);
+
+        mixer.clear();
+        assert!(mixer.is_empty());
+        assert_eq!(mixer.output(), 0.0);
+    }
+}
@@ -0,0 +1,109 @@
+//
+// The console's analog audio path. Between the mixer and the RCA jack, the NES
+// board has two RC high-pass filters (about 90Hz and 440Hz) and a 14kHz
+// low-pass. The high-passes take out the DC offset of the mix (every channel
+// outputs positive levels only) and thin out the bass; the low-pass softens the
+// square waves' edges. Without them the output sounds boomy and harsh next to a
+// real console, and jumps when a channel is switched on or off.
+//
+// Each stage is a first order filter, as the RC circuits are, run at the output
+// sample rate.
+//
+
+use std::f32::consts::PI;
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    HighPass,
+    LowPass,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Filter {
+    kind: Kind,
+    /// Smoothing factor derived from the cutoff and the sample rate
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl Filter {
+    fn new(kind: Kind, cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let alpha = match kind {
+            Kind::HighPass => rc / (rc + dt),
+            Kind::LowPass => dt / (rc + dt),
+        };
+        Filter {
+            kind,
+            alpha,
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            Kind::HighPass => self.alpha * (self.previous_output + input - self.previous_input),
+            Kind::LowPass => self.previous_output + self.alpha * (input - self.previous_output),
+        };
+        self.previous_input = input;
+        self.previous_output = output;
+        output
+    }
+}
+
+/// The three stages of the NES, in the order the signal goes through them.
+#[derive(Debug, Clone)]
+pub struct FilterChain {
+    stages: [Filter; 3],
+}
+
+impl FilterChain {
+    pub fn nes(sample_rate: f64) -> Self {
+        let rate = sample_rate as f32;
+        FilterChain {
+            stages: [
+                Filter::new(Kind::HighPass, 90.0, rate),
+                Filter::new(Kind::HighPass, 440.0, rate),
+                Filter::new(Kind::LowPass, 14_000.0, rate),
+            ],
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.stages
+            .iter_mut()
+            .fold(sample, |signal, stage| stage.process(signal))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Peak output level over the last half of `seconds` of a full scale sine
+    fn peak(frequency: f32, seconds: f32) -> f32 {
+        let rate = 44100.0;
+        let mut chain = FilterChain::nes(rate as f64);
+        let samples = (rate * seconds) as usize;
+        (0..samples)
+            .map(|n| chain.process((2.0 * PI * frequency * n as f32 / rate).sin()))
+            .skip(samples / 2)
+            .fold(0.0, |peak: f32, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn test_nes_filters() {
+        // A constant level fades to nothing: the DC offset is gone
+        let mut chain = FilterChain::nes(44100.0);
+        let settled = (0..44100).map(|_| chain.process(0.5)).last().unwrap();
+        assert!(settled.abs() < 1e-4);
+
+        // Bass is cut, the midrange passes, the top end is softened
+        assert!(peak(30.0, 1.0) < 0.05);
+        assert!(peak(2000.0, 0.1) > 0.9);
+        assert!(peak(11025.0, 0.1) < 0.85);
+    }
+}
@@ -0,0 +1,150 @@
+//
+// FCEUX movies (.fm2): a header of `key value` lines, then one line per frame:
+//
+//   |commands|RLDUTSBA|RLDUTSBA||
+//
+// Commands are flags (1: soft reset, 2: power cycle); each pad field lists
+// Right, Left, Down, Up, sTart, Select, B and A, any character but '.' or ' '
+// meaning held. That is `JoypadButton`'s order from the top bit down. A port
+// with nothing in it has an empty field. With `fourscore 1` there are four pad
+// fields, players 1 and 3 on port 1.
+//
+// Only text movies starting from power-on with pads (or nothing) on the ports
+// can be played; FCEUX's own timing can still make well-known runs drift, which
+// the verify checkpoints help pin down.
+//
+
+use crate::movie::{Movie, MovieFrame};
+use crate::region::Region;
+
+/// FM2 command bits.
+const SOFT_RESET: u8 = 0x01;
+const POWER: u8 = 0x02;
+
+pub fn parse(text: &str) -> Result<Movie, String> {
+    let mut movie = Movie::default();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(record) = line.strip_prefix('|') {
+            let pads = if movie.four_score { 4 } else { 2 };
+            let frame = parse_frame(record, pads, movie.frames.is_empty()).map_err(error)?;
+            movie.frames.push(frame);
+            continue;
+        }
+
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "binary" if value == "1" => {
+                return Err(error("binary movies are not supported".into()))
+            }
+            "savestate" => return Err(error("the movie starts from a savestate".into())),
+            "palFlag" => movie.region = (value == "1").then_some(Region::Pal),
+            "fourscore" => movie.four_score = value == "1",
+            "port0" | "port1" if value != "0" && value != "1" => {
+                return Err(error(format!("{}: only pads can be played", key)))
+            }
+            _ => {}
+        }
+    }
+
+    Ok(movie)
+}
+
+/// One input line, without its leading '|'. `pads` pad fields follow the
+/// commands; a power cycle on the first frame is where playback starts anyway.
+fn parse_frame(record: &str, pads: usize, first: bool) -> Result<MovieFrame, String> {
+    let mut fields = record.split('|');
+    let commands = fields.next().unwrap_or_default();
+    let commands: u8 = commands
+        .parse()
+        .map_err(|_| format!("invalid commands '{}'", commands))?;
+    if commands & !(SOFT_RESET | POWER) != 0 {
+        return Err(format!("unsupported commands {}", commands));
+    }
+
+    let mut frame = MovieFrame {
+        reset: commands & SOFT_RESET != 0,
+        power: commands & POWER != 0 && !first,
+        ..MovieFrame::default()
+    };
+    for player in 0..pads {
+        let field = fields
+            .next()
+            .ok_or_else(|| format!("missing pad {}", player + 1))?;
+        frame.buttons[player] = pad_buttons(field)?;
+    }
+    Ok(frame)
+}
+
+fn pad_buttons(field: &str) -> Result<u8, String> {
+    if !field.is_empty() && field.len() != 8 {
+        return Err(format!("invalid pad '{}'", field));
+    }
+    Ok(field
+        .chars()
+        .enumerate()
+        .filter(|&(_, c)| c != '.' && c != ' ')
+        .fold(0, |buttons, (bit, _)| buttons | 0x80 >> bit))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HEADER: &str = "version 3\nemuVersion 22020\nromFilename game\npalFlag 0\n";
+
+    #[test]
+    fn test_parse_fm2() {
+        let text = format!(
+            "{}port0 1\nport1 1\n|2|........|........||\n|0|....T..A|R.......||\n|1|........|........||\n",
+            HEADER
+        );
+        let movie = parse(&text).unwrap();
+        assert_eq!(movie.len(), 3);
+        assert_eq!(movie.region, None);
+        assert!(!movie.frames[0].power);
+        assert_eq!(movie.frames[1].buttons, [0x09, 0x80, 0, 0]);
+        assert!(movie.frames[2].reset);
+
+        // Nothing in port 2: an empty field
+        let text = format!("{}port1 0\n|0|.L......|||\n", HEADER);
+        assert_eq!(parse(&text).unwrap().frames[0].buttons, [0x40, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_parse_fm2_four_score() {
+        let text = format!(
+            "{}palFlag 1\nfourscore 1\n|0|.......A|......B.|.....S..|....T...||\n",
+            HEADER
+        );
+        let movie = parse(&text).unwrap();
+        assert!(movie.four_score);
+        assert_eq!(movie.region, Some(Region::Pal));
+        assert_eq!(movie.frames[0].buttons, [0x01, 0x02, 0x04, 0x08]);
+    }
+
+    #[test]
+    fn test_parse_fm2_errors() {
+        assert_eq!(
+            parse("binary 1\n"),
+            Err("line 1: binary movies are not supported".to_string())
+        );
+        assert_eq!(
+            parse("port0 2\n"),
+            Err("line 1: port0: only pads can be played".to_string())
+        );
+        assert_eq!(
+            parse("|4|........|........||\n"),
+            Err("line 1: unsupported commands 4".to_string())
+        );
+        assert_eq!(
+            parse("|0|........\n"),
+            Err("line 1: missing pad 2".to_string())
+        );
+    }
+}
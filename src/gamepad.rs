@@ -0,0 +1,146 @@
+//
+// Pads plugged into the computer. The ones SDL knows (Xbox, PlayStation, Switch
+// and the many in its mapping database) are opened through the GameController
+// API, which gives every model the same layout; anything else stays a raw
+// joystick, mapped by button number (see remap.rs).
+//
+// On a known pad the button in the south position (Xbox A, PlayStation Cross)
+// is NES B and the east one NES A, where they sit on the NES pad. Back/Select,
+// Start, the d-pad and the left stick do the rest. Each pad can be remapped
+// with SDL's button names (a, b, x, y, back, start, leftshoulder, dpup...):
+//
+//   [gamepad.1]
+//   a = x
+//   b = a
+//
+
+use crate::config::Config;
+use crate::joypad::JoypadButton;
+use crate::remap::BUTTONS;
+use crate::stick::StickConfig;
+use sdl2::controller::{Button, GameController};
+use sdl2::joystick::Joystick;
+use std::collections::HashMap;
+
+const DEFAULT_BUTTONS: [Button; 8] = [
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+    Button::B,
+    Button::A,
+    Button::Back,
+    Button::Start,
+];
+
+/// Buttons of the `pad`th gamepad (0-based), from `[gamepad.N]`.
+pub fn button_map(config: &Config, pad: usize) -> HashMap<Button, JoypadButton> {
+    let section = format!("gamepad.{}", pad + 1);
+    BUTTONS
+        .iter()
+        .zip(DEFAULT_BUTTONS)
+        .map(|(&(button, name), default)| {
+            let pad_button = match config.get(&section, name) {
+                Some(button_name) => Button::from_string(button_name).unwrap_or_else(|| {
+                    eprintln!("[{}] {}: unknown button '{}'", section, name, button_name);
+                    default
+                }),
+                None => default,
+            };
+            (pad_button, button)
+        })
+        .collect()
+}
+
+enum Device {
+    Joystick(Joystick),
+    Controller(GameController),
+}
+
+/// An open pad and where its left stick is.
+pub struct Gamepad {
+    device: Device,
+    axes: [i16; 2],
+    dpad: JoypadButton,
+}
+
+impl Gamepad {
+    pub fn joystick(joystick: Joystick) -> Self {
+        Self::new(Device::Joystick(joystick))
+    }
+
+    pub fn controller(controller: GameController) -> Self {
+        Self::new(Device::Controller(controller))
+    }
+
+    fn new(device: Device) -> Self {
+        Gamepad {
+            device,
+            axes: [0, 0],
+            dpad: JoypadButton::empty(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match &self.device {
+            Device::Joystick(joystick) => joystick.name(),
+            Device::Controller(controller) => controller.name(),
+        }
+    }
+
+    pub fn instance_id(&self) -> u32 {
+        match &self.device {
+            Device::Joystick(joystick) => joystick.instance_id(),
+            Device::Controller(controller) => controller.instance_id(),
+        }
+    }
+
+    /// Directions the left stick is pushed in.
+    pub fn dpad(&self) -> JoypadButton {
+        self.dpad
+    }
+
+    pub fn is_controller(&self) -> bool {
+        matches!(self.device, Device::Controller(_))
+    }
+
+    /// Moves the left stick along `axis` (0: x, 1: y). Returns the d-pad
+    /// directions when they change, so that noise around the center does not let
+    /// go of a direction held on the d-pad.
+    pub fn move_stick(
+        &mut self,
+        axis: usize,
+        value: i16,
+        stick: &StickConfig,
+    ) -> Option<JoypadButton> {
+        self.axes[axis] = value;
+        let dpad = stick.dpad(self.axes[0], self.axes[1]);
+        if dpad == self.dpad {
+            return None;
+        }
+        self.dpad = dpad;
+        Some(dpad)
+    }
+}
+
+/// The pad among `pads` that sent an event: `controller` tells a GameController
+/// event from a joystick one. Known pads send both, and only answer the former.
+pub fn find(pads: &[Gamepad], which: u32, controller: bool) -> Option<usize> {
+    pads.iter()
+        .position(|pad| pad.instance_id() == which && pad.is_controller() == controller)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_layout() {
+        let map = button_map(&Config::default(), 0);
+        assert_eq!(map.len(), 8);
+        assert_eq!(map[&Button::A], JoypadButton::BUTTON_B);
+        assert_eq!(map[&Button::B], JoypadButton::BUTTON_A);
+        assert_eq!(map[&Button::Back], JoypadButton::SELECT);
+        assert_eq!(map[&Button::DPadLeft], JoypadButton::LEFT);
+    }
+}
@@ -0,0 +1,191 @@
+//
+// Animated GIF encoding for clips: one global color table, a looping application
+// extension and one LZW-compressed image per clip patch, drawn over the previous
+// frame ("do not dispose") with the clip's free color index as transparency.
+//
+
+use crate::clip::Clip;
+use std::collections::HashMap;
+
+const FRAMES_PER_SECOND: u32 = 60;
+const MAX_CODE_BITS: u32 = 12;
+
+/// Variable-width codes packed least significant bit first, in sub-blocks of up
+/// to 255 bytes.
+struct CodeWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl CodeWriter {
+    fn code(&mut self, code: u16, width: u32) {
+        self.buffer |= (code as u32) << self.count;
+        self.count += width;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self, out: &mut Vec<u8>) {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        for block in self.bytes.chunks(255) {
+            out.push(block.len() as u8);
+            out.extend(block);
+        }
+        out.push(0);
+    }
+}
+
+fn lzw(pixels: &[u8], min_code_size: u32, out: &mut Vec<u8>) {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut writer = CodeWriter {
+        bytes: vec![],
+        buffer: 0,
+        count: 0,
+    };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut width = min_code_size + 1;
+
+    writer.code(clear, width);
+    let Some((&first, rest)) = pixels.split_first() else {
+        writer.code(end, width);
+        return writer.finish(out);
+    };
+    let mut prefix = first as u16;
+    for &pixel in rest {
+        if let Some(&code) = table.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+        writer.code(prefix, width);
+        if next == 1 << MAX_CODE_BITS {
+            writer.code(clear, width);
+            table.clear();
+            next = end + 1;
+            width = min_code_size + 1;
+        } else {
+            table.insert((prefix, pixel), next);
+            // The decoder widens its codes one code later than the encoder adds them
+            if next == 1 << width && width < MAX_CODE_BITS {
+                width += 1;
+            }
+            next += 1;
+        }
+        prefix = pixel as u16;
+    }
+    writer.code(prefix, width);
+    writer.code(end, width);
+    writer.finish(out);
+}
+
+/// Delay in 1/100s between frames `start` and `start + duration`, rounded so the
+/// errors do not add up over the clip.
+fn delay(start: u32, duration: u32) -> u16 {
+    let time = |frame: u32| (frame * 100 + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND;
+    (time(start + duration) - time(start)) as u16
+}
+
+/// Encodes `clip` as a looping animated GIF at 30 frames per second.
+pub fn encode(clip: &Clip) -> Vec<u8> {
+    // Color table size is a power of two, with room for the transparent index
+    let bits = (1..=8)
+        .find(|&bits| 1usize << bits > clip.colors.len())
+        .unwrap_or(8);
+    let min_code_size = bits.max(2);
+
+    let mut gif = b"GIF89a".to_vec();
+    gif.extend((clip.width as u16).to_le_bytes());
+    gif.extend((clip.height as u16).to_le_bytes());
+    gif.push(0xF0 | (bits as u8 - 1)); // global color table, 8-bit colors
+    gif.extend([0, 0]); // background color, square pixels
+    for i in 0..1 << bits {
+        gif.extend(clip.colors.get(i).copied().unwrap_or_default());
+    }
+    // Loop forever
+    gif.extend(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+    for patch in clip.patches(2) {
+        let delay = delay(patch.start, patch.duration);
+        gif.extend([0x21, 0xF9, 4, 0x05]); // graphic control: do not dispose, transparent
+        gif.extend(delay.to_le_bytes());
+        gif.extend([clip.transparent(), 0]);
+
+        gif.push(0x2C);
+        for value in [patch.x, patch.y, patch.width, patch.height] {
+            gif.extend((value as u16).to_le_bytes());
+        }
+        gif.push(0); // no local color table, not interlaced
+        gif.push(min_code_size as u8);
+        lzw(&patch.pixels, min_code_size, &mut gif);
+    }
+    gif.push(0x3B);
+    gif
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Reference decoder for the LZW image data following the minimum code size.
+    fn unlzw(data: &[u8], min_code_size: u32) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![];
+        let mut pos = 0;
+        while data[pos] != 0 {
+            let len = data[pos] as usize;
+            bytes.extend(&data[pos + 1..pos + 1 + len]);
+            pos += len + 1;
+        }
+        let clear = 1usize << min_code_size;
+        let (mut bit, mut width) = (0, min_code_size + 1);
+        let mut table: Vec<Vec<u8>> = vec![];
+        let mut previous: Option<Vec<u8>> = None;
+        let mut out = vec![];
+        loop {
+            let code = (0..width).fold(0usize, |code, i| {
+                let b = bit + i as usize;
+                code | (((bytes[b / 8] >> (b % 8)) & 1) as usize) << i
+            });
+            bit += width as usize;
+            if code == clear {
+                table = (0..clear).map(|i| vec![i as u8]).collect();
+                table.extend([vec![], vec![]]);
+                width = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => [previous.clone(), vec![previous[0]]].concat(),
+                (None, None) => panic!("bad code"),
+            };
+            if let Some(previous) = previous {
+                table.push([previous, vec![entry[0]]].concat());
+                if table.len() == 1 << width && width < MAX_CODE_BITS {
+                    width += 1;
+                }
+            }
+            out.extend(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        let mut pixels: Vec<u8> = (0..20000u32).map(|i| ((i / 7) % 5) as u8).collect();
+        pixels.extend((0..9000u32).map(|i| (i * 7919 % 13) as u8));
+        let mut out = vec![];
+        lzw(&pixels, 4, &mut out);
+        assert_eq!(unlzw(&out, 4), pixels);
+        assert_eq!(delay(0, 2) + delay(2, 2) + delay(4, 2), 10);
+    }
+}
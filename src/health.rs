@@ -0,0 +1,67 @@
+//
+// Startup health check. A subsystem or device that does not come up (no sound
+// card, no joystick support in the SDL build) no longer aborts the emulator
+// before a window appears: the game runs without it, and what is missing is
+// logged with its cause and shown on screen once the picture is up.
+//
+// Without a window, a renderer or a texture there is nothing to run in; those
+// stay fatal, with a message instead of a panic.
+//
+
+use crate::i18n::{self, Msg};
+use std::fmt::Display;
+
+/// What the emulator is running without.
+#[derive(Debug, Default)]
+pub struct Health {
+    degraded: Vec<String>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Logs that the emulator goes on without something, and why.
+    pub fn degraded(&mut self, msg: Msg, cause: impl Display) {
+        let message = i18n::tr(msg).to_string();
+        eprintln!("{}: {}", message, cause);
+        self.degraded.push(message);
+    }
+
+    /// One line for the OSD: the first problem, and how many more the log has.
+    pub fn summary(&self) -> Option<String> {
+        let first = self.degraded.first()?;
+        Some(match self.degraded.len() {
+            1 => first.clone(),
+            count => format!("{} (+{})", first, count - 1),
+        })
+    }
+}
+
+/// Gives up on something the emulator cannot run without.
+pub fn fatal(cause: impl Display) -> ! {
+    eprintln!("{}", i18n::format(Msg::StartupFailed, &[&cause]));
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut health = Health::new();
+        assert_eq!(health.summary(), None);
+        health.degraded(Msg::NoAudio, "no device");
+        assert_eq!(
+            health.summary().as_deref(),
+            Some("No audio device, running silent")
+        );
+        health.degraded(Msg::NoJoysticks, "not built in");
+        assert_eq!(
+            health.summary().as_deref(),
+            Some("No audio device, running silent (+1)")
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//
+// User-facing emulator text (OSD messages, window title, errors shown to the
+// player), looked up by key in one table per language so a translation never has
+// to touch the code that prints it. Strings take their arguments as `{}`
+// placeholders, filled in order by `format`.
+//
+// The language comes from `language = en|fr` in the settings file, or else from
+// the LANG environment variable; English is the fallback for missing entries.
+//
+
+use crate::config::Config;
+use crate::input::DeviceKind;
+use crate::joypad::JoypadButton;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    English,
+    French,
+}
+
+static LANGUAGE: AtomicU8 = AtomicU8::new(Language::English as u8);
+
+impl Language {
+    /// Parses a code such as "fr", "fr_FR.UTF-8" or "en-US".
+    pub fn from_code(code: &str) -> Option<Language> {
+        match code.get(..2)?.to_ascii_lowercase().as_str() {
+            "en" => Some(Language::English),
+            "fr" => Some(Language::French),
+            _ => None,
+        }
+    }
+
+    pub fn detect(config: &Config) -> Language {
+        config
+            .get("", "language")
+            .map(str::to_string)
+            .or_else(|| std::env::var("LANG").ok())
+            .and_then(|code| Language::from_code(&code))
+            .unwrap_or(Language::English)
+    }
+}
+
+pub fn set_language(language: Language) {
+    LANGUAGE.store(language as u8, Ordering::Relaxed);
+}
+
+pub fn language() -> Language {
+    match LANGUAGE.load(Ordering::Relaxed) {
+        1 => Language::French,
+        _ => Language::English,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Msg {
+    WindowTitle,
+    /// Port number, device name
+    PortDevice,
+    /// Rate in Hz
+    AudioOutput,
+    /// Joystick number, name
+    JoystickConnected,
+    /// Device index, error
+    JoystickOpenFailed,
+    /// Path, error
+    SaveLoadFailed,
+    /// Path, error
+    SaveWriteFailed,
+    DeviceController,
+    DeviceNothing,
+    /// Frame count
+    BurstStarted,
+    /// Frame count
+    BurstSaved,
+    /// Error
+    BurstFailed,
+    /// Seconds
+    ClipSaving,
+    /// Path
+    ClipSaved,
+    /// Error
+    ClipFailed,
+    RecordingStarted,
+    /// Path of the video
+    RecordingSaved,
+    /// Error
+    RecordingFailed,
+    MovieStarted,
+    /// Path, frame count
+    MovieSaved,
+    /// Error
+    MovieFailed,
+    /// Song number, song count
+    NsfTrack,
+    NsfControls,
+    /// Chip names
+    NsfExpansionAudio,
+    /// Button name
+    RemapPrompt,
+    /// Path
+    RemapSaved,
+    RemapCancelled,
+    /// Error
+    RemapFailed,
+    /// File name
+    RomReloading,
+    /// Path
+    StateDumped,
+    /// Error
+    StateDumpFailed,
+    MenuTitle,
+    MenuResume,
+    MenuReset,
+    MenuScreenshot,
+    MenuQuit,
+    /// Palette name
+    PaletteReloaded,
+    /// Error
+    PaletteLoadFailed,
+    NoAudio,
+    NoJoysticks,
+    NoGamepads,
+    /// Error
+    StartupFailed,
+    /// Error
+    ViewFailed,
+    /// Report path
+    Crashed,
+    /// Error
+    CrashReportFailed,
+    ButtonUp,
+    ButtonDown,
+    ButtonLeft,
+    ButtonRight,
+}
+
+fn english(msg: Msg) -> &'static str {
+    match msg {
+        Msg::WindowTitle => "NES Emulator",
+        Msg::PortDevice => "Port {}: {}",
+        Msg::AudioOutput => "Audio output: {} Hz",
+        Msg::JoystickConnected => "Joystick {}: {}",
+        Msg::JoystickOpenFailed => "Could not open joystick {}: {}",
+        Msg::SaveLoadFailed => "Could not load {}: {}",
+        Msg::SaveWriteFailed => "Could not write {}: {}",
+        Msg::DeviceController => "Controller",
+        Msg::DeviceNothing => "Nothing",
+        Msg::BurstStarted => "Burst: saving {} frames",
+        Msg::BurstSaved => "Burst: {} frames saved",
+        Msg::BurstFailed => "Could not start the burst: {}",
+        Msg::ClipSaving => "Saving the last {} seconds...",
+        Msg::ClipSaved => "Clip saved: {}",
+        Msg::ClipFailed => "Could not save the clip: {}",
+        Msg::RecordingStarted => "Recording (F10 to stop)",
+        Msg::RecordingSaved => "Recording saved: {}",
+        Msg::RecordingFailed => "Could not record: {}",
+        Msg::MovieStarted => "Recording input from power-on (F11 to stop)",
+        Msg::MovieSaved => "Movie saved: {} ({} frames)",
+        Msg::MovieFailed => "Could not save the movie: {}",
+        Msg::NsfTrack => "Song {} / {}",
+        Msg::NsfControls => "Left/Right: change song  ESC: quit",
+        Msg::NsfExpansionAudio => "Expansion audio is not synthesized: {}",
+        Msg::RemapPrompt => "Press the key or button for {} (ESC: cancel)",
+        Msg::RemapSaved => "Controls saved to {}",
+        Msg::RemapCancelled => "Remapping cancelled",
+        Msg::RemapFailed => "Could not save the controls: {}",
+        Msg::RomReloading => "{} changed, reloading",
+        Msg::StateDumped => "Debug state written to {}",
+        Msg::StateDumpFailed => "Could not write the debug state: {}",
+        Msg::MenuTitle => "Paused",
+        Msg::MenuResume => "Resume",
+        Msg::MenuReset => "Reset",
+        Msg::MenuScreenshot => "Screenshot",
+        Msg::MenuQuit => "Quit",
+        Msg::PaletteReloaded => "Palette {} reloaded",
+        Msg::PaletteLoadFailed => "Could not load the palette: {}",
+        Msg::NoAudio => "No audio device, running silent",
+        Msg::NoJoysticks => "Joysticks unavailable, keyboard only",
+        Msg::NoGamepads => "Gamepad layouts unavailable, pads map by button number",
+        Msg::StartupFailed => "Cannot start: {}",
+        Msg::ViewFailed => "Could not switch the view: {}",
+        Msg::Crashed => "Emulation crashed, console reset. Report: {}",
+        Msg::CrashReportFailed => "Emulation crashed, the report could not be written: {}",
+        Msg::ButtonUp => "Up",
+        Msg::ButtonDown => "Down",
+        Msg::ButtonLeft => "Left",
+        Msg::ButtonRight => "Right",
+    }
+}
+
+/// Incomplete translations answer `None` for the keys they lack.
+fn french(msg: Msg) -> Option<&'static str> {
+    Some(match msg {
+        Msg::WindowTitle => "Émulateur NES",
+        Msg::PortDevice => "Port {} : {}",
+        Msg::AudioOutput => "Sortie audio : {} Hz",
+        Msg::JoystickConnected => "Manette {} : {}",
+        Msg::JoystickOpenFailed => "Impossible d'ouvrir la manette {} : {}",
+        Msg::SaveLoadFailed => "Impossible de charger {} : {}",
+        Msg::SaveWriteFailed => "Impossible d'écrire {} : {}",
+        Msg::DeviceController => "Manette",
+        Msg::DeviceNothing => "Rien",
+        Msg::BurstStarted => "Rafale : enregistrement de {} images",
+        Msg::BurstSaved => "Rafale : {} images enregistrées",
+        Msg::BurstFailed => "Impossible de lancer la rafale : {}",
+        Msg::ClipSaving => "Enregistrement des {} dernières secondes...",
+        Msg::ClipSaved => "Clip enregistré : {}",
+        Msg::ClipFailed => "Impossible d'enregistrer le clip : {}",
+        Msg::RecordingStarted => "Enregistrement (F10 pour arrêter)",
+        Msg::RecordingSaved => "Enregistrement sauvegardé : {}",
+        Msg::RecordingFailed => "Impossible d'enregistrer la vidéo : {}",
+        Msg::MovieStarted => "Enregistrement des entrées depuis l'allumage (F11 pour arrêter)",
+        Msg::MovieSaved => "Film sauvegardé : {} ({} images)",
+        Msg::MovieFailed => "Impossible de sauvegarder le film : {}",
+        Msg::NsfTrack => "Morceau {} / {}",
+        Msg::NsfControls => "Gauche/Droite : morceau  Échap : quitter",
+        Msg::NsfExpansionAudio => "Le son des puces d'extension n'est pas synthétisé : {}",
+        Msg::RemapPrompt => "Touche ou bouton pour {} (Échap : annuler)",
+        Msg::RemapSaved => "Commandes enregistrées dans {}",
+        Msg::RemapCancelled => "Configuration annulée",
+        Msg::RemapFailed => "Impossible d'enregistrer les commandes : {}",
+        Msg::RomReloading => "{} a changé, rechargement",
+        Msg::StateDumped => "État de débogage écrit dans {}",
+        Msg::StateDumpFailed => "Impossible d'écrire l'état de débogage : {}",
+        Msg::MenuTitle => "Pause",
+        Msg::MenuResume => "Reprendre",
+        Msg::MenuReset => "Réinitialiser",
+        Msg::MenuScreenshot => "Capture d'écran",
+        Msg::MenuQuit => "Quitter",
+        Msg::PaletteReloaded => "Palette {} rechargée",
+        Msg::PaletteLoadFailed => "Impossible de charger la palette : {}",
+        Msg::NoAudio => "Pas de sortie audio, jeu sans son",
+        Msg::NoJoysticks => "Manettes indisponibles, clavier seul",
+        Msg::NoGamepads => "Dispositions de manettes indisponibles, boutons par numéro",
+        Msg::StartupFailed => "Démarrage impossible : {}",
+        Msg::ViewFailed => "Impossible de changer d'affichage : {}",
+        Msg::Crashed => "L'émulation a planté, console réinitialisée. Rapport : {}",
+        Msg::CrashReportFailed => "L'émulation a planté, rapport impossible à écrire : {}",
+        Msg::ButtonUp => "Haut",
+        Msg::ButtonDown => "Bas",
+        Msg::ButtonLeft => "Gauche",
+        Msg::ButtonRight => "Droite",
+    })
+}
+
+/// The text for `msg` in `language`.
+pub fn lookup(language: Language, msg: Msg) -> &'static str {
+    match language {
+        Language::English => english(msg),
+        Language::French => french(msg).unwrap_or_else(|| english(msg)),
+    }
+}
+
+/// The text for `msg` in the current language.
+pub fn tr(msg: Msg) -> &'static str {
+    lookup(language(), msg)
+}
+
+/// The text for `msg` with its `{}` placeholders replaced by `args`, in order.
+pub fn format(msg: Msg, args: &[&dyn Display]) -> String {
+    let mut parts = tr(msg).split("{}");
+    let mut text = parts.next().unwrap_or_default().to_string();
+    for (index, part) in parts.enumerate() {
+        if let Some(arg) = args.get(index) {
+            text.push_str(&arg.to_string());
+        }
+        text.push_str(part);
+    }
+    text
+}
+
+/// Name of a controller button; A, B, Select and Start are printed on the pad.
+pub fn button_name(button: JoypadButton) -> &'static str {
+    match button {
+        JoypadButton::UP => tr(Msg::ButtonUp),
+        JoypadButton::DOWN => tr(Msg::ButtonDown),
+        JoypadButton::LEFT => tr(Msg::ButtonLeft),
+        JoypadButton::RIGHT => tr(Msg::ButtonRight),
+        JoypadButton::BUTTON_A => "A",
+        JoypadButton::BUTTON_B => "B",
+        JoypadButton::SELECT => "Select",
+        _ => "Start",
+    }
+}
+
+/// Name of a controller port device; product names stay as they are.
+pub fn device_name(kind: DeviceKind) -> String {
+    match kind {
+        DeviceKind::Joypad => tr(Msg::DeviceController).to_string(),
+        DeviceKind::None => tr(Msg::DeviceNothing).to_string(),
+        _ => kind.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup_and_format() {
+        assert_eq!(Language::from_code("fr_FR.UTF-8"), Some(Language::French));
+        assert_eq!(Language::from_code("de"), None);
+        assert_eq!(
+            Language::detect(&Config::parse("language = fr").unwrap()),
+            Language::French
+        );
+
+        assert_eq!(
+            lookup(Language::French, Msg::AudioOutput),
+            "Sortie audio : {} Hz"
+        );
+        // Tests share the global language, which stays English here
+        assert_eq!(format(Msg::PortDevice, &[&2, &"Zapper"]), "Port 2: Zapper");
+        assert_eq!(device_name(DeviceKind::None), "Nothing");
+    }
+}
@@ -0,0 +1,530 @@
+//
+// Controller ports: what is plugged into $4016/$4017 and how each device answers
+// the serial reads. Devices can be swapped while a game runs, the same way one would
+// unplug a pad and plug a Zapper on the real console.
+//
+
+use crate::config::Config;
+use crate::joypad::{Joypad, JoypadButton};
+use crate::render::frame::Frame;
+use std::fmt;
+
+/// Scanlines the photodiode keeps reporting light once the beam has drawn a bright
+/// aim point. Real guns hold it for roughly 10 to 25 lines; games that time their
+/// reads expect light around the aim row, and darkness the rest of the frame.
+const LIGHT_LINES: usize = 20;
+
+/// Light gun. Reads return D3 = 0 when the sensor sees a bright spot, D4 = 1 while
+/// the trigger is held.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Zapper {
+    /// Aim point, in NES pixels
+    pub x: usize,
+    pub y: usize,
+    pub trigger: bool,
+    /// The picture under the aim point is bright
+    pub light: bool,
+}
+
+impl Zapper {
+    /// Moves the aim point, kept on screen.
+    pub fn aim(&mut self, x: i32, y: i32) {
+        self.x = x.clamp(0, 255) as usize;
+        self.y = y.clamp(0, 239) as usize;
+    }
+
+    /// Updates the light sensor from the picture under the aim point.
+    pub fn sense_light(&mut self, frame: &Frame) {
+        self.light = false;
+        for y in self.y.saturating_sub(1)..=(self.y + 1).min(239) {
+            for x in self.x.saturating_sub(1)..=(self.x + 1).min(255) {
+                let base = (y * 256 + x) * 3;
+                let pixel = &frame.data[base..base + 3];
+                let luma = pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114;
+                if luma >= 160 * 1000 {
+                    self.light = true;
+                }
+            }
+        }
+    }
+
+    /// D3 only reports light while the beam is in the window after the aim row.
+    fn read(&self, scanline: usize) -> u8 {
+        let sensed = self.light && (self.y..self.y + LIGHT_LINES).contains(&scanline);
+        let light = if sensed { 0 } else { 0x08 };
+        let trigger = if self.trigger { 0x10 } else { 0 };
+        light | trigger
+    }
+}
+
+/// Light gun settings from the `[zapper]` section:
+///
+///   [zapper]
+///   offset_x = -2     # NES pixels added to the mouse position
+///   offset_y = 1
+///   crosshair = false # hide the aim marker
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZapperSettings {
+    pub offset_x: i32,
+    pub offset_y: i32,
+    pub crosshair: bool,
+}
+
+impl ZapperSettings {
+    pub fn from_config(config: &Config) -> Self {
+        let offset = |key| match config.get("zapper", key).map(str::parse) {
+            Some(Ok(offset)) => offset,
+            Some(Err(_)) => {
+                eprintln!("[zapper] {}: not a number of pixels", key);
+                0
+            }
+            None => 0,
+        };
+        ZapperSettings {
+            offset_x: offset("offset_x"),
+            offset_y: offset("offset_y"),
+            crosshair: config.get("zapper", "crosshair") != Some("false"),
+        }
+    }
+}
+
+/// Arkanoid "Vaus" controller: the knob position is latched on strobe and shifted
+/// out inverted, MSB first, on D3; the button is D4.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Paddle {
+    pub position: u8,
+    pub fire: bool,
+    strobe: bool,
+    shift: u8,
+}
+
+/// Knob range reported by the Arkanoid controller from one stop to the other.
+const PADDLE_MIN: u8 = 0x62;
+const PADDLE_MAX: u8 = 0xF2;
+
+impl Paddle {
+    /// Sets the knob from a horizontal screen position (0-255), for mouse control.
+    pub fn aim(&mut self, x: usize) {
+        let range = (PADDLE_MAX - PADDLE_MIN) as usize;
+        self.position = PADDLE_MIN + (x.min(255) * range / 255) as u8;
+    }
+
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.shift = !self.position;
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let data = self.peek();
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        data
+    }
+
+    fn peek(&self) -> u8 {
+        let fire = if self.fire { 0x10 } else { 0 };
+        (self.shift & 0x80) >> 4 | fire
+    }
+}
+
+/// One side of a Four Score adapter: two pads shifted out one after the other,
+/// followed by the signature byte games poll to detect the adapter: a 1 on its
+/// 4th bit for $4016, its 3rd for $4017.
+#[derive(Default)]
+pub struct FourScoreSide {
+    pub pads: [Joypad; 2],
+    signature: u8,
+    strobe: bool,
+    index: u8,
+}
+
+impl FourScoreSide {
+    /// Side plugged into `port` (0 for $4016, 1 for $4017).
+    pub fn new(port: usize) -> Self {
+        FourScoreSide {
+            pads: [Joypad::new(), Joypad::new()],
+            signature: if port == 0 { 0x08 } else { 0x04 },
+            strobe: false,
+            index: 0,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.index = 0;
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let bit = self.peek();
+        if !self.strobe && self.index < 24 {
+            self.index += 1;
+        }
+        bit
+    }
+
+    fn peek(&self) -> u8 {
+        (match self.index {
+            0..=7 => self.pads[0].buttons().bits() >> self.index,
+            8..=15 => self.pads[1].buttons().bits() >> (self.index - 8),
+            16..=23 => self.signature >> (self.index - 16),
+            _ => 1,
+        }) & 1
+    }
+}
+
+/// The kinds of device a port can hold, in the order the hotkey cycles through them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceKind {
+    Joypad,
+    Zapper,
+    Paddle,
+    FourScore,
+    None,
+}
+
+impl DeviceKind {
+    pub fn next(self) -> DeviceKind {
+        match self {
+            DeviceKind::Joypad => DeviceKind::Zapper,
+            DeviceKind::Zapper => DeviceKind::Paddle,
+            DeviceKind::Paddle => DeviceKind::FourScore,
+            DeviceKind::FourScore => DeviceKind::None,
+            DeviceKind::None => DeviceKind::Joypad,
+        }
+    }
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DeviceKind::Joypad => "Controller",
+            DeviceKind::Zapper => "Zapper",
+            DeviceKind::Paddle => "Arkanoid Paddle",
+            DeviceKind::FourScore => "Four Score",
+            DeviceKind::None => "Nothing",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub enum Device {
+    Joypad(Joypad),
+    Zapper(Zapper),
+    Paddle(Paddle),
+    FourScore(FourScoreSide),
+    None,
+}
+
+impl Device {
+    pub fn new(kind: DeviceKind, port: usize) -> Device {
+        match kind {
+            DeviceKind::Joypad => Device::Joypad(Joypad::new()),
+            DeviceKind::Zapper => Device::Zapper(Zapper::default()),
+            DeviceKind::Paddle => Device::Paddle(Paddle::default()),
+            DeviceKind::FourScore => Device::FourScore(FourScoreSide::new(port)),
+            DeviceKind::None => Device::None,
+        }
+    }
+
+    pub fn kind(&self) -> DeviceKind {
+        match self {
+            Device::Joypad(_) => DeviceKind::Joypad,
+            Device::Zapper(_) => DeviceKind::Zapper,
+            Device::Paddle(_) => DeviceKind::Paddle,
+            Device::FourScore(_) => DeviceKind::FourScore,
+            Device::None => DeviceKind::None,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        match self {
+            Device::Joypad(joypad) => joypad.write(data),
+            Device::Paddle(paddle) => paddle.write(data),
+            Device::FourScore(side) => side.write(data),
+            Device::Zapper(_) | Device::None => {}
+        }
+    }
+
+    fn read(&mut self, scanline: usize) -> u8 {
+        match self {
+            Device::Joypad(joypad) => joypad.read(),
+            Device::Zapper(zapper) => zapper.read(scanline),
+            Device::Paddle(paddle) => paddle.read(),
+            Device::FourScore(side) => side.read(),
+            Device::None => 0,
+        }
+    }
+
+    /// What `read` would return, without clocking the device.
+    fn peek(&self, scanline: usize) -> u8 {
+        match self {
+            Device::Joypad(joypad) => joypad.peek(),
+            Device::Zapper(zapper) => zapper.read(scanline),
+            Device::Paddle(paddle) => paddle.peek(),
+            Device::FourScore(side) => side.peek(),
+            Device::None => 0,
+        }
+    }
+}
+
+/// The two controller ports. Writes to $4016 strobe both, reads of $4016/$4017
+/// clock one each.
+pub struct ControllerPorts {
+    ports: [Device; 2],
+    last_strobe: u8,
+    /// Times the strobe went from 1 to 0, freezing the reports, since `take_latches`
+    latches: u32,
+    /// Scanline the PPU is on, kept up to date by the bus for the light gun
+    pub scanline: usize,
+}
+
+impl Default for ControllerPorts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ControllerPorts {
+    /// A pad in each port.
+    pub fn new() -> Self {
+        ControllerPorts {
+            ports: [Device::Joypad(Joypad::new()), Device::Joypad(Joypad::new())],
+            last_strobe: 0,
+            latches: 0,
+            scanline: 0,
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        if self.last_strobe & 1 == 1 && data & 1 == 0 {
+            self.latches += 1;
+        }
+        self.last_strobe = data;
+        for device in self.ports.iter_mut() {
+            device.write(data);
+        }
+    }
+
+    pub fn read(&mut self, port: usize) -> u8 {
+        self.ports[port].read(self.scanline)
+    }
+
+    /// The next report of `port`, left for the game to read.
+    pub fn peek(&self, port: usize) -> u8 {
+        self.ports[port].peek(self.scanline)
+    }
+
+    /// Controller latches since the last call. A frame without any is a lag frame:
+    /// the game did not look at its input.
+    pub fn take_latches(&mut self) -> u32 {
+        std::mem::take(&mut self.latches)
+    }
+
+    pub fn device(&self, port: usize) -> &Device {
+        &self.ports[port]
+    }
+
+    pub fn device_mut(&mut self, port: usize) -> &mut Device {
+        &mut self.ports[port]
+    }
+
+    /// Hot-swaps the device on a port. The new device sees the current strobe level
+    /// right away, so a game polling the port next frame reads its report (or the
+    /// Four Score signature) as if it had always been there.
+    pub fn connect(&mut self, port: usize, kind: DeviceKind) {
+        let mut device = Device::new(kind, port);
+        device.write(self.last_strobe);
+        self.ports[port] = device;
+    }
+
+    /// The first pad on a port: the plain pad, or the first pad of a Four Score
+    /// side.
+    pub fn joypad_mut(&mut self, port: usize) -> Option<&mut Joypad> {
+        match &mut self.ports[port] {
+            Device::Joypad(joypad) => Some(joypad),
+            Device::FourScore(side) => Some(&mut side.pads[0]),
+            _ => None,
+        }
+    }
+
+    /// The pad of `player` (0 to 3): the first pads of both ports, then the
+    /// second pads of Four Score sides, skipping ports that have none. With the
+    /// Four Score on both ports, players 1 and 3 are read from $4016 and 2 and 4
+    /// from $4017, as on the adapter.
+    pub fn player_joypad(&mut self, player: usize) -> Option<&mut Joypad> {
+        let (port, pad) = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .into_iter()
+            .filter(|&(port, pad)| match self.ports[port] {
+                Device::Joypad(_) => pad == 0,
+                Device::FourScore(_) => true,
+                _ => false,
+            })
+            .nth(player)?;
+        match &mut self.ports[port] {
+            Device::Joypad(joypad) => Some(joypad),
+            Device::FourScore(side) => Some(&mut side.pads[pad]),
+            _ => None,
+        }
+    }
+
+    /// Buttons for the pad on `port`, if one is plugged in (movie playback).
+    pub fn set_buttons(&mut self, port: usize, buttons: JoypadButton) {
+        if let Some(joypad) = self.joypad_mut(port) {
+            joypad.set_buttons(buttons);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_byte(ports: &mut ControllerPorts, port: usize) -> u8 {
+        (0..8).fold(0, |byte, bit| byte | (ports.read(port) & 1) << bit)
+    }
+
+    #[test]
+    fn test_hot_swap_keeps_strobe() {
+        let mut ports = ControllerPorts::new();
+        ports.connect(1, DeviceKind::None);
+        assert_eq!(ports.read(1), 0);
+
+        ports.write(1);
+        ports.connect(1, DeviceKind::Joypad);
+        ports
+            .joypad_mut(1)
+            .unwrap()
+            .set_buttons(JoypadButton::BUTTON_A);
+        assert_eq!(ports.read(1), 1);
+        assert_eq!(ports.device(1).kind(), DeviceKind::Joypad);
+    }
+
+    #[test]
+    fn test_four_score_signature() {
+        let mut ports = ControllerPorts::new();
+        ports.connect(0, DeviceKind::FourScore);
+        ports.connect(1, DeviceKind::FourScore);
+        if let Device::FourScore(side) = ports.device_mut(0) {
+            side.pads[0].set_buttons(JoypadButton::START);
+            side.pads[1].set_buttons(JoypadButton::BUTTON_B);
+        }
+
+        ports.write(1);
+        ports.write(0);
+        assert_eq!(read_byte(&mut ports, 0), 0x08);
+        assert_eq!(read_byte(&mut ports, 0), 0x02);
+        // Read one bit at a time: 0, 0, 0, 1, 0, 0, 0, 0
+        assert_eq!(read_byte(&mut ports, 0), 0x08);
+        assert_eq!(ports.read(0), 1);
+
+        for _ in 0..16 {
+            ports.read(1);
+        }
+        // 0, 0, 1, 0, 0, 0, 0, 0 on this side
+        assert_eq!(read_byte(&mut ports, 1), 0x04);
+        assert_eq!(ports.take_latches(), 1);
+        assert_eq!(ports.take_latches(), 0);
+    }
+
+    #[test]
+    fn test_player_joypads() {
+        let mut ports = ControllerPorts::new();
+        ports.connect(0, DeviceKind::Zapper);
+        ports
+            .player_joypad(0)
+            .unwrap()
+            .set_buttons(JoypadButton::UP);
+        assert_eq!(ports.joypad_mut(1).unwrap().buttons(), JoypadButton::UP);
+        assert!(ports.player_joypad(1).is_none());
+
+        ports.connect(0, DeviceKind::FourScore);
+        ports.connect(1, DeviceKind::FourScore);
+        for (player, buttons) in [
+            JoypadButton::UP,
+            JoypadButton::DOWN,
+            JoypadButton::LEFT,
+            JoypadButton::RIGHT,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            ports.player_joypad(player).unwrap().set_buttons(buttons);
+        }
+        assert!(ports.player_joypad(4).is_none());
+
+        ports.write(1);
+        ports.write(0);
+        // Players 1 and 3 on $4016, 2 and 4 on $4017
+        assert_eq!(read_byte(&mut ports, 0), JoypadButton::UP.bits());
+        assert_eq!(read_byte(&mut ports, 0), JoypadButton::LEFT.bits());
+        assert_eq!(read_byte(&mut ports, 1), JoypadButton::DOWN.bits());
+        assert_eq!(read_byte(&mut ports, 1), JoypadButton::RIGHT.bits());
+    }
+
+    #[test]
+    fn test_zapper_bits() {
+        let mut frame = Frame::new();
+        frame.set_pixel(100, 50, (255, 255, 255));
+
+        let mut zapper = Zapper {
+            x: 100,
+            y: 51,
+            ..Zapper::default()
+        };
+        zapper.sense_light(&frame);
+        assert_eq!(zapper.read(60), 0x00);
+        // Dark before the beam reaches the aim row and once the diode has decayed
+        assert_eq!(zapper.read(40), 0x08);
+        assert_eq!(zapper.read(51 + LIGHT_LINES), 0x08);
+
+        zapper.x = 10;
+        zapper.trigger = true;
+        zapper.sense_light(&frame);
+        assert_eq!(zapper.read(60), 0x18);
+
+        zapper.aim(-3, 300);
+        assert_eq!((zapper.x, zapper.y), (0, 239));
+    }
+
+    #[test]
+    fn test_zapper_settings() {
+        let config = Config::parse("[zapper]\noffset_x = -2\ncrosshair = false\n").unwrap();
+        assert_eq!(
+            ZapperSettings::from_config(&config),
+            ZapperSettings {
+                offset_x: -2,
+                offset_y: 0,
+                crosshair: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_paddle_aim() {
+        let mut paddle = Paddle::default();
+        paddle.aim(0);
+        assert_eq!(paddle.position, PADDLE_MIN);
+        paddle.aim(1000);
+        assert_eq!(paddle.position, PADDLE_MAX);
+    }
+
+    #[test]
+    fn test_paddle_serial_position() {
+        let mut ports = ControllerPorts::new();
+        ports.connect(1, DeviceKind::Paddle);
+        if let Device::Paddle(paddle) = ports.device_mut(1) {
+            paddle.position = 0b1010_0000;
+            paddle.fire = true;
+        }
+        ports.write(1);
+        ports.write(0);
+
+        let bits: Vec<u8> = (0..4).map(|_| ports.read(1)).collect();
+        assert_eq!(bits, vec![0x10, 0x18, 0x10, 0x18]);
+    }
+}
@@ -0,0 +1,427 @@
+//
+// Local control socket for external tools (debuggers, editor plugins, bots).
+//
+// Clients connect to a Unix socket and exchange one JSON object per line. Requests
+// carry a "cmd" field and get exactly one reply with "ok": true/false; the server
+// also pushes {"event": ...} lines to every client when the emulator changes state
+// (paused, resumed, reset).
+//
+//   {"cmd": "registers"}                 CPU registers and cycle, PPU frame, scanline and dot
+//   {"cmd": "peek", "addr": 768, "len": 16}
+//   {"cmd": "poke", "addr": 768, "data": [1, 2, 3]}
+//   {"cmd": "disassemble", "addr": 49152, "count": 10}
+//   {"cmd": "pause"} / {"cmd": "resume"} / {"cmd": "reset"}
+//   {"cmd": "debug_state"}              registers and banks, see debugstate.rs
+//   {"cmd": "load_debug_state", "state": {...}}
+//   {"cmd": "open", "path": "..."}     switch to another ROM
+//
+// In single-instance mode a second emulator started with a ROM sends it here with
+// "open" and exits, so opening games from a file manager reuses one window.
+//
+// Peeks read memory the way the CPU would see it but leave the hardware alone:
+// looking at $2002 does not clear the VBlank flag, nor $2007 move the VRAM address.
+// Pokes are regular CPU writes.
+//
+
+use crate::bus::Bus;
+use crate::cpu::{Mem, CPU};
+use crate::debugstate;
+use crate::disasm;
+use crate::json::Value;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+struct Client {
+    stream: UnixStream,
+    pending: Vec<u8>,
+    closed: bool,
+}
+
+pub struct IpcServer {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<Client>,
+    /// Set by "pause"; the main loop stops stepping the CPU while it holds
+    pub paused: bool,
+    /// ROM asked for by "open", waiting for the main loop to load it
+    pub open: Option<PathBuf>,
+}
+
+/// Socket used by single-instance mode when `--ipc` does not name one.
+pub fn default_socket_path() -> PathBuf {
+    let user = std::env::var("USER").unwrap_or_else(|_| "user".to_string());
+    std::env::temp_dir().join(format!("nes_emulator_{}.sock", user))
+}
+
+/// Hands `rom` to the emulator listening on `socket`. Fails if none is running.
+pub fn forward_rom(socket: &Path, rom: &Path) -> io::Result<()> {
+    let mut stream = UnixStream::connect(socket)?;
+    let rom = std::fs::canonicalize(rom).unwrap_or_else(|_| rom.to_path_buf());
+    let request = Value::object(vec![
+        ("cmd", Value::from("open")),
+        ("path", Value::from(rom.to_string_lossy().as_ref())),
+    ]);
+    writeln!(stream, "{}", request)?;
+    let mut reply = String::new();
+    io::BufRead::read_line(&mut io::BufReader::new(stream), &mut reply)?;
+    match Value::parse(&reply) {
+        Ok(reply) if reply.get("ok") == Some(&Value::Bool(true)) => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "unexpected reply {:?}",
+            reply.trim()
+        ))),
+    }
+}
+
+impl IpcServer {
+    /// Listens on `path`, replacing a socket left over by a previous run.
+    pub fn bind(path: &Path) -> io::Result<IpcServer> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(IpcServer {
+            listener,
+            path: path.to_path_buf(),
+            clients: vec![],
+            paused: false,
+            open: None,
+        })
+    }
+
+    /// Accepts new clients and answers every complete request. Never blocks.
+    pub fn poll(&mut self, cpu: &mut CPU<Bus>) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client {
+                    stream,
+                    pending: vec![],
+                    closed: false,
+                });
+            }
+        }
+
+        let mut events = vec![];
+        for client in self.clients.iter_mut() {
+            let mut buffer = [0; 1024];
+            loop {
+                match client.stream.read(&mut buffer) {
+                    Ok(0) => {
+                        client.closed = true;
+                        break;
+                    }
+                    Ok(n) => client.pending.extend_from_slice(&buffer[..n]),
+                    Err(e) => {
+                        client.closed = e.kind() != ErrorKind::WouldBlock;
+                        break;
+                    }
+                }
+            }
+
+            while let Some(end) = client.pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = client.pending.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (reply, event) = execute(cpu, &mut self.paused, &mut self.open, &line);
+                let _ = writeln!(client.stream, "{}", reply);
+                events.extend(event);
+            }
+        }
+
+        for event in events {
+            self.notify(event);
+        }
+        self.clients.retain(|client| !client.closed);
+    }
+
+    /// Pushes an {"event": ...} line to every client.
+    pub fn notify(&mut self, event: &str) {
+        let line = Value::object(vec![("event", Value::from(event))]).to_string();
+        self.clients
+            .retain_mut(|client| writeln!(client.stream, "{}", line).is_ok());
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn error(message: &str) -> Value {
+    Value::object(vec![
+        ("ok", Value::from(false)),
+        ("error", Value::from(message)),
+    ])
+}
+
+fn address(request: &Value) -> Result<u16, Value> {
+    match request.get("addr").and_then(Value::as_u64) {
+        Some(addr) if addr <= 0xFFFF => Ok(addr as u16),
+        _ => Err(error("'addr' must be an address between 0 and 65535")),
+    }
+}
+
+/// Runs one request line. Returns the reply and the event to broadcast, if any.
+pub fn execute(
+    cpu: &mut CPU<Bus>,
+    paused: &mut bool,
+    open: &mut Option<PathBuf>,
+    line: &str,
+) -> (Value, Option<&'static str>) {
+    let request = match Value::parse(line) {
+        Ok(request) => request,
+        Err(e) => return (error(&format!("invalid JSON: {}", e)), None),
+    };
+    let ok = || Value::object(vec![("ok", Value::from(true))]);
+
+    let position = cpu.bus.ppu().position();
+
+    match request.get("cmd").and_then(Value::as_str) {
+        Some("registers") => (
+            Value::object(vec![
+                ("ok", Value::from(true)),
+                ("a", Value::from(cpu.register_a)),
+                ("x", Value::from(cpu.register_x)),
+                ("y", Value::from(cpu.register_y)),
+                ("sp", Value::from(cpu.stack_pointer)),
+                ("pc", Value::from(cpu.program_counter)),
+                ("status", Value::from(cpu.status.bits())),
+                ("cycles", Value::from(cpu.cycles)),
+                ("frame", Value::from(position.frame)),
+                ("scanline", Value::from(position.scanline)),
+                ("dot", Value::from(position.dot)),
+                ("paused", Value::from(*paused)),
+            ]),
+            None,
+        ),
+        Some("peek") => {
+            let addr = match address(&request) {
+                Ok(addr) => addr,
+                Err(e) => return (e, None),
+            };
+            let len = request.get("len").and_then(Value::as_u64).unwrap_or(1);
+            if len > 0x10000 - addr as u64 {
+                return (error("'len' runs past the end of memory"), None);
+            }
+            let data = (0..len)
+                .map(|i| Value::from(cpu.bus.peek(addr + i as u16)))
+                .collect();
+            (
+                Value::object(vec![
+                    ("ok", Value::from(true)),
+                    ("data", Value::Array(data)),
+                ]),
+                None,
+            )
+        }
+        Some("disassemble") => {
+            let addr = match address(&request) {
+                Ok(addr) => addr,
+                Err(e) => return (e, None),
+            };
+            let count = request.get("count").and_then(Value::as_u64).unwrap_or(1);
+            let instructions = disasm::disassemble(|addr| cpu.bus.peek(addr), addr..=0xFFFF)
+                .take(count as usize)
+                .map(|instruction| {
+                    let mut fields = vec![
+                        ("addr", Value::from(instruction.addr)),
+                        ("bytes", Value::from(&instruction.bytes[..])),
+                        ("mnemonic", Value::from(instruction.mnemonic)),
+                        ("operand", Value::from(instruction.operand_text().as_str())),
+                    ];
+                    if let Some(target) = instruction.target {
+                        fields.push(("target", Value::from(target)));
+                    }
+                    Value::object(fields)
+                })
+                .collect();
+            (
+                Value::object(vec![
+                    ("ok", Value::from(true)),
+                    ("instructions", Value::Array(instructions)),
+                ]),
+                None,
+            )
+        }
+        Some("poke") => {
+            let addr = match address(&request) {
+                Ok(addr) => addr,
+                Err(e) => return (e, None),
+            };
+            let bytes: Option<Vec<u8>> =
+                request
+                    .get("data")
+                    .and_then(Value::as_array)
+                    .and_then(|data| {
+                        data.iter()
+                            .map(|b| b.as_u64().filter(|&b| b <= 0xFF).map(|b| b as u8))
+                            .collect()
+                    });
+            match bytes {
+                Some(bytes) if addr as usize + bytes.len() <= 0x10000 => {
+                    for (i, byte) in bytes.into_iter().enumerate() {
+                        cpu.mem_write(addr + i as u16, byte);
+                    }
+                    (ok(), None)
+                }
+                Some(_) => (error("'data' runs past the end of memory"), None),
+                None => (error("'data' must be an array of bytes"), None),
+            }
+        }
+        Some("pause") => {
+            *paused = true;
+            (ok(), Some("paused"))
+        }
+        Some("resume") => {
+            *paused = false;
+            (ok(), Some("resumed"))
+        }
+        Some("reset") => {
+            cpu.reset();
+            (ok(), Some("reset"))
+        }
+        Some("debug_state") => (
+            Value::object(vec![
+                ("ok", Value::from(true)),
+                ("state", debugstate::export(cpu)),
+            ]),
+            None,
+        ),
+        Some("load_debug_state") => match request.get("state") {
+            Some(state) => match debugstate::import(cpu, state) {
+                Ok(()) => (ok(), None),
+                Err(e) => (error(&e), None),
+            },
+            None => (error("'state' must be a debug state object"), None),
+        },
+        Some("open") => match request.get("path").and_then(Value::as_str) {
+            Some(path) => {
+                *open = Some(PathBuf::from(path));
+                (ok(), Some("opening"))
+            }
+            None => (error("'path' must be a file name"), None),
+        },
+        Some(cmd) => (error(&format!("unknown command '{}'", cmd)), None),
+        None => (error("missing 'cmd'"), None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_cpu;
+
+    #[test]
+    fn test_peek_poke_registers() {
+        let mut cpu = test_cpu();
+        let mut paused = false;
+        let mut open = None;
+
+        let (reply, _) = execute(
+            &mut cpu,
+            &mut paused,
+            &mut open,
+            r#"{"cmd":"poke","addr":512,"data":[1,2,255]}"#,
+        );
+        assert_eq!(reply.get("ok"), Some(&Value::Bool(true)));
+        let (reply, _) = execute(
+            &mut cpu,
+            &mut paused,
+            &mut open,
+            r#"{"cmd":"peek","addr":512,"len":3}"#,
+        );
+        assert_eq!(reply.to_string(), r#"{"ok":true,"data":[1,2,255]}"#);
+
+        // The bytes poked above, read as code
+        let (reply, _) = execute(
+            &mut cpu,
+            &mut paused,
+            &mut open,
+            r#"{"cmd":"disassemble","addr":512,"count":2}"#,
+        );
+        assert_eq!(
+            reply.to_string(),
+            r#"{"ok":true,"instructions":[{"addr":512,"bytes":[1,2],"mnemonic":"ORA","operand":"($02,X)"},{"addr":514,"bytes":[255,0,0],"mnemonic":"*ISC","operand":"$0000,X"}]}"#
+        );
+
+        cpu.register_a = 0x42;
+        let (reply, _) = execute(&mut cpu, &mut paused, &mut open, r#"{"cmd":"registers"}"#);
+        assert_eq!(reply.get("a").and_then(Value::as_u64), Some(0x42));
+        assert_eq!(reply.get("scanline").and_then(Value::as_u64), Some(0));
+
+        let (reply, _) = execute(
+            &mut cpu,
+            &mut paused,
+            &mut open,
+            r#"{"cmd":"poke","addr":70000,"data":[1]}"#,
+        );
+        assert_eq!(reply.get("ok"), Some(&Value::Bool(false)));
+        let (reply, _) = execute(
+            &mut cpu,
+            &mut paused,
+            &mut open,
+            r#"{"cmd":"peek","addr":512,"len":1e30}"#,
+        );
+        assert_eq!(reply.get("ok"), Some(&Value::Bool(false)));
+        let (reply, _) = execute(&mut cpu, &mut paused, &mut open, "not json");
+        assert_eq!(reply.get("ok"), Some(&Value::Bool(false)));
+
+        let (reply, _) = execute(&mut cpu, &mut paused, &mut open, r#"{"cmd":"debug_state"}"#);
+        let request = Value::object(vec![
+            ("cmd", Value::from("load_debug_state")),
+            ("state", reply.get("state").unwrap().clone()),
+        ]);
+        cpu.register_a = 0;
+        let (reply, _) = execute(&mut cpu, &mut paused, &mut open, &request.to_string());
+        assert_eq!(reply.get("ok"), Some(&Value::Bool(true)));
+        assert_eq!(cpu.register_a, 0x42);
+    }
+
+    #[test]
+    fn test_pause_over_socket() {
+        let path = std::env::temp_dir().join(format!("nes_ipc_test_{}.sock", std::process::id()));
+        let mut server = IpcServer::bind(&path).unwrap();
+        let mut cpu = test_cpu();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"{\"cmd\":\"pause\"}\n").unwrap();
+        server.poll(&mut cpu);
+        assert!(server.paused);
+
+        let mut reader = io::BufReader::new(client);
+        let mut reply = String::new();
+        let mut event = String::new();
+        io::BufRead::read_line(&mut reader, &mut reply).unwrap();
+        io::BufRead::read_line(&mut reader, &mut event).unwrap();
+        assert_eq!(reply.trim(), r#"{"ok":true}"#);
+        assert_eq!(event.trim(), r#"{"event":"paused"}"#);
+
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_forward_rom() {
+        let path = std::env::temp_dir().join(format!("nes_ipc_open_{}.sock", std::process::id()));
+        assert!(forward_rom(&path, Path::new("game.nes")).is_err());
+
+        let mut server = IpcServer::bind(&path).unwrap();
+        let client = std::thread::spawn({
+            let path = path.clone();
+            move || forward_rom(&path, Path::new("/roms/game.nes"))
+        });
+        let mut cpu = test_cpu();
+        while server.open.is_none() {
+            server.poll(&mut cpu);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        client.join().unwrap().unwrap();
+        assert_eq!(server.open, Some(PathBuf::from("/roms/game.nes")));
+    }
+}
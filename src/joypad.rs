@@ -1,5 +1,5 @@
 bitflags! {
-    #[derive(Copy, Clone)]
+    #[derive(Debug, Copy, Clone, PartialEq)]
     pub struct JoypadButton: u8 {
         const RIGHT             = 0b10000000;
         const LEFT              = 0b01000000;
@@ -18,6 +18,12 @@ pub struct Joypad {
     button_status: JoypadButton,
 }
 
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Joypad {
     pub fn new() -> Self {
         Joypad {
@@ -34,20 +40,36 @@ impl Joypad {
         }
     }
 
+    /// The next bit of the report, in bit 0. The other bits are left to the bus,
+    /// which fills D5-D7 from open bus.
     pub fn read(&mut self) -> u8 {
-        if self.button_index > 7 {
-            return 1;
-        }
-        let response = (self.button_status.bits() & (1 << self.button_index)) >> self.button_index;
+        let response = self.peek();
         if !self.strobe && self.button_index <= 7 {
             self.button_index += 1;
         }
         response
     }
 
+    /// The bit the next read returns, without shifting the report.
+    pub fn peek(&self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        (self.button_status.bits() & (1 << self.button_index)) >> self.button_index
+    }
+
     pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
         self.button_status.set(button, pressed);
     }
+
+    pub fn buttons(&self) -> JoypadButton {
+        self.button_status
+    }
+
+    /// Replaces the whole button state at once (movie playback).
+    pub fn set_buttons(&mut self, buttons: JoypadButton) {
+        self.button_status = buttons;
+    }
 }
 
 #[cfg(test)]
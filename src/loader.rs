@@ -0,0 +1,206 @@
+//
+// Game switching without freezing the window: the file is read, parsed and identified
+// on a worker thread, and the finished ROM is handed back to the emulation thread,
+// which swaps it in at the next frame boundary.
+//
+// With `--watch-rom` the same path reloads the game whenever its file is rebuilt,
+// for homebrew edit-build-run loops.
+//
+
+use crate::cartridge::Rom;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::SystemTime;
+
+/// A ROM parsed off the emulation thread, ready to be inserted.
+pub struct LoadedRom {
+    pub path: PathBuf,
+    pub rom: Rom,
+}
+
+/// Reads and parses a ROM file (or zipped ROM).
+pub fn load(path: &Path) -> Result<LoadedRom, String> {
+    let rom = Rom::from_path(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(LoadedRom {
+        path: path.to_path_buf(),
+        rom,
+    })
+}
+
+type LoadResult = (u64, Result<LoadedRom, String>);
+
+/// Runs `load` on worker threads. When several files are requested in a row only the
+/// most recent one is delivered, so a slow disk can't swap an older game in last.
+pub struct RomLoader {
+    sender: Sender<LoadResult>,
+    receiver: Receiver<LoadResult>,
+    generation: u64,
+}
+
+impl Default for RomLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RomLoader {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        RomLoader {
+            sender,
+            receiver,
+            generation: 0,
+        }
+    }
+
+    /// Starts loading `path` in the background.
+    pub fn request(&mut self, path: PathBuf) {
+        self.generation += 1;
+        let generation = self.generation;
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            // The loader may be gone by the time we finish, nothing to do then
+            let _ = sender.send((generation, load(&path)));
+        });
+    }
+
+    /// The latest requested ROM, once its worker is done. Never blocks.
+    pub fn poll(&self) -> Option<Result<LoadedRom, String>> {
+        self.receiver
+            .try_iter()
+            .filter(|(generation, _)| *generation == self.generation)
+            .map(|(_, result)| result)
+            .last()
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Notices when a ROM file is rewritten, by polling its modification time: cheap
+/// enough to do every few frames, and needs nothing from the system.
+pub struct RomWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    /// New time seen on the last check, not trusted until it holds for one more:
+    /// an assembler may still be writing the file
+    pending: Option<SystemTime>,
+}
+
+impl RomWatcher {
+    pub fn new(path: &Path) -> Self {
+        RomWatcher {
+            path: path.to_path_buf(),
+            modified: modified(path),
+            pending: None,
+        }
+    }
+
+    /// Follows `path` from now on, if it is not the file already watched (another
+    /// game was opened).
+    pub fn follow(&mut self, path: &Path) {
+        if path != self.path {
+            *self = RomWatcher::new(path);
+        }
+    }
+
+    /// True once the file has a new modification time that held since the previous
+    /// check. A deleted file is waited for rather than reported.
+    pub fn changed(&mut self) -> bool {
+        let now = modified(&self.path);
+        if now.is_none() || now == self.modified {
+            self.pending = None;
+            false
+        } else if now == self.pending {
+            self.modified = now;
+            self.pending = None;
+            true
+        } else {
+            self.pending = now;
+            false
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn write_rom(name: &str, fill: u8) -> PathBuf {
+        let mut bytes = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+        bytes.resize(16, 0);
+        bytes.extend(vec![fill; 0x4000 + 0x2000]);
+        let path = std::env::temp_dir().join(format!("{}-{}.nes", name, std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        path
+    }
+
+    fn wait(loader: &RomLoader) -> Result<LoadedRom, String> {
+        let start = Instant::now();
+        loop {
+            if let Some(result) = loader.poll() {
+                return result;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "loader timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_background_load() {
+        let path = write_rom("loader", 0xEA);
+        let mut loader = RomLoader::new();
+        loader.request(path.clone());
+
+        let loaded = wait(&loader).unwrap();
+        assert_eq!(loaded.path, path);
+        assert_eq!(loaded.rom.prg_rom[0], 0xEA);
+        assert_eq!(loaded.rom.info.crc32, crate::hash::crc32(&[0xEA; 0x6000]));
+        std::fs::remove_file(path).unwrap();
+
+        loader.request(PathBuf::from("/nonexistent/game.nes"));
+        assert!(wait(&loader).err().unwrap().contains("game.nes"));
+    }
+
+    #[test]
+    fn test_only_latest_request_is_delivered() {
+        let first = write_rom("loader-first", 1);
+        let second = write_rom("loader-second", 2);
+        let mut loader = RomLoader::new();
+        loader.request(first.clone());
+        loader.request(second.clone());
+
+        assert_eq!(wait(&loader).unwrap().rom.prg_rom[0], 2);
+        thread::sleep(Duration::from_millis(50));
+        assert!(loader.poll().is_none());
+
+        std::fs::remove_file(first).unwrap();
+        std::fs::remove_file(second).unwrap();
+    }
+
+    #[test]
+    fn test_watcher_waits_for_settled_file() {
+        let path = write_rom("watcher", 1);
+        let mut watcher = RomWatcher::new(&path);
+        assert!(!watcher.changed());
+
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        let later = SystemTime::now() + Duration::from_secs(10);
+        file.set_modified(later).unwrap();
+        assert!(!watcher.changed());
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!watcher.changed());
+    }
+}
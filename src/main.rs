@@ -1,24 +1,89 @@
+pub mod accuracy;
 pub mod apu;
+pub mod archive;
+pub mod blip;
 pub mod bus;
 pub mod cartridge;
+pub mod clip;
+pub mod config;
+pub mod console;
 pub mod cpu;
+pub mod crash;
+pub mod dbg;
+pub mod debugstate;
+pub mod disasm;
+pub mod expansion;
+pub mod filter;
+pub mod fm2;
+pub mod gamepad;
+pub mod gif;
+pub mod hash;
+pub mod health;
+pub mod i18n;
+pub mod input;
+#[cfg(unix)]
+pub mod ipc;
 pub mod joypad;
+pub mod json;
+pub mod loader;
+pub mod mapper;
+pub mod menu;
+pub mod movie;
+pub mod nsf;
 pub mod opcodes;
+pub mod png;
 pub mod ppu;
+pub mod priority;
+pub mod record;
+pub mod region;
+pub mod remap;
 pub mod render;
+pub mod romdb;
+pub mod savestate;
+pub mod screenshot;
+pub mod sfx;
+pub mod stats;
+pub mod stick;
+pub mod strict;
 pub mod trace;
+pub mod verify;
+pub mod watch;
 
 use bus::Bus;
 use cartridge::Rom;
+use clip::{ClipBuffer, ClipFormat};
 use cpu::CPU;
-use ppu::NesPPU;
+use gamepad::Gamepad;
+use health::Health;
+use i18n::Msg;
+use input::{ControllerPorts, Device, ZapperSettings};
+use joypad::JoypadButton;
+use loader::{RomLoader, RomWatcher};
+use menu::{Item, PauseMenu};
+use movie::MovieRecorder;
+use ppu::{NesPPU, Pipeline, PpuOptions};
+use region::Region;
+use remap::RemapCapture;
 use render::frame::Frame;
-use sdl2::audio::AudioSpecDesired;
+use render::icon;
+use render::layers::RenderMode;
+use render::palette::Palette;
+use render::profile::{Overscan, ViewProfile, PROFILES};
+use render::upscale::Upscaler;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::controller::Axis;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
 use sdl2::pixels::PixelFormatEnum;
-use std::collections::HashMap;
+use sdl2::render::{Texture, WindowCanvas};
+use sfx::UiSound;
+use stats::FrameTimer;
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use stick::StickConfig;
 
 #[macro_use]
 extern crate lazy_static;
@@ -26,140 +91,1514 @@ extern crate lazy_static;
 #[macro_use]
 extern crate bitflags;
 
+const APP_NAME: &str = "NES Emulator";
 const AUDIO_SAMPLE_RATE: f64 = 44100.0;
+/// Rates cycled through with F4.
+const SAMPLE_RATES: [f64; 3] = [44100.0, 48000.0, 22050.0];
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let config_path = take_option(&mut args, "--config");
+    let config_file = PathBuf::from(config_path.as_deref().unwrap_or(config::DEFAULT_PATH));
+    let mut config = config::Config::load(&config_file).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    i18n::set_language(i18n::Language::detect(&config));
+    let ipc_path = take_option(&mut args, "--ipc").map(PathBuf::from);
+    let single_instance = take_flag(&mut args, "--single-instance")
+        || config.get("", "single_instance") == Some("true");
+    let debug_info = take_option(&mut args, "--trace-source").map(|path| {
+        dbg::DebugInfo::load(&path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    });
+    let mut last_source_line = None;
+    let write_log_out =
+        take_option(&mut args, "--log-writes-out").unwrap_or(watch::DEFAULT_OUT.to_string());
+    let write_log = take_option(&mut args, "--log-writes").map(|list| {
+        let addresses = watch::parse_addresses(&list).unwrap_or_else(|e| {
+            eprintln!("--log-writes: {}", e);
+            std::process::exit(2);
+        });
+        watch::WriteLog::create(&write_log_out, addresses).unwrap_or_else(|e| {
+            eprintln!("Could not create {}: {}", write_log_out, e);
+            std::process::exit(1);
+        })
+    });
+    let ui_sounds_enabled = !take_flag(&mut args, "--no-ui-sounds");
+    let watch_rom = take_flag(&mut args, "--watch-rom");
+    let render_mode = match take_flag(&mut args, "--debug-priority") {
+        true => RenderMode::PriorityTint,
+        false => RenderMode::Normal,
+    };
+    let strict_break = take_flag(&mut args, "--strict-break");
+    if take_flag(&mut args, "--strict") || strict_break {
+        strict::enable();
+    }
+    let mut strict_seen = HashSet::new();
+    let sample_rate = take_option(&mut args, "--sample-rate").map_or(AUDIO_SAMPLE_RATE, |rate| {
+        match rate.parse::<f64>() {
+            Ok(hz) if hz.is_finite() && hz > 0.0 => hz,
+            _ => {
+                eprintln!("--sample-rate: '{}' is not a positive number", rate);
+                std::process::exit(2);
+            }
+        }
+    });
+
+    if args.get(1).map(String::as_str) == Some("verify-movie") {
+        if args.len() != 4 {
+            eprintln!("usage: nes_emulator verify-movie <rom> <movie>");
+            std::process::exit(2);
+        }
+        verify_movie(&args[2], &args[3]);
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("accuracy-report") {
+        let out = take_option(&mut args, "--out").unwrap_or("accuracy.html".to_string());
+        let history =
+            take_option(&mut args, "--history").unwrap_or("accuracy_history.json".to_string());
+        if args.len() != 3 {
+            eprintln!(
+                "usage: nes_emulator accuracy-report <manifest> [--out report.html] [--history history.json]"
+            );
+            std::process::exit(2);
+        }
+        accuracy_report(Path::new(&args[2]), Path::new(&out), Path::new(&history));
+        return;
+    }
+
+    let track = take_option(&mut args, "--track").map(|track| {
+        track.parse::<u8>().unwrap_or_else(|_| {
+            eprintln!("invalid track number '{}'", track);
+            std::process::exit(2);
+        })
+    });
+    let rom_path = args.get(1).map_or("mario_usa.nes", String::as_str);
+    // Emulation (and audio refills) run on this thread
+    priority::ThreadConfig::from_config(&config).apply();
+    if Path::new(rom_path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("nsf") || ext.eq_ignore_ascii_case("nsfe"))
+    {
+        play_nsf(rom_path, track, sample_rate);
+        return;
+    }
+
+    // --- Single Instance: hand the ROM to a running emulator if there is one ---
+    #[cfg(unix)]
+    let ipc_path = match single_instance {
+        true => {
+            let socket = ipc_path.unwrap_or_else(ipc::default_socket_path);
+            if ipc::forward_rom(&socket, Path::new(rom_path)).is_ok() {
+                println!("{} sent to the running emulator", rom_path);
+                return;
+            }
+            Some(socket)
+        }
+        false => ipc_path,
+    };
+    #[cfg(not(unix))]
+    if single_instance {
+        eprintln!("--single-instance is only available on Unix systems");
+    }
+
     // --- SDL2 Initialization ---
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let audio_subsystem = sdl_context.audio().unwrap();
+    set_app_metadata();
+    // Sound and joysticks are optional: the game runs without them (see health.rs)
+    let mut health = Health::new();
+    let sdl_context = sdl2::init().unwrap_or_else(|e| health::fatal(e));
+    let video_subsystem = sdl_context.video().unwrap_or_else(|e| health::fatal(e));
+    let audio_subsystem = sdl_context
+        .audio()
+        .map_err(|e| health.degraded(Msg::NoAudio, e))
+        .ok();
+    let joystick_subsystem = sdl_context
+        .joystick()
+        .map_err(|e| health.degraded(Msg::NoJoysticks, e))
+        .ok();
+    let controller_subsystem = joystick_subsystem.as_ref().and_then(|_| {
+        sdl_context
+            .game_controller()
+            .map_err(|e| health.degraded(Msg::NoGamepads, e))
+            .ok()
+    });
 
     // -- Window Configuration --
-    let window = video_subsystem
-        .window("NES Emulator", (256.0 * 2.0) as u32, (240.0 * 2.0) as u32)
+    let mut window = video_subsystem
+        .window(
+            i18n::tr(Msg::WindowTitle),
+            (256.0 * 2.0) as u32,
+            (240.0 * 2.0) as u32,
+        )
         .resizable()
         .position_centered()
         .build()
-        .unwrap();
+        .unwrap_or_else(|e| health::fatal(e));
+    icon::apply(&mut window);
 
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(2.0, 2.0).unwrap();
+    let mut canvas = window
+        .into_canvas()
+        .present_vsync()
+        .build()
+        .unwrap_or_else(|e| health::fatal(e));
+    let mut event_pump = sdl_context
+        .event_pump()
+        .unwrap_or_else(|e| health::fatal(e));
 
-    let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_streaming(PixelFormatEnum::RGB24, 256, 240)
-        .unwrap();
+    // -- View Profiles (TV mode presets, cycled with T; [video] overscan crops them all
+    // and [video] upscaler smooths the ones without CRT effects) --
+    let overscan = config
+        .get("video", "overscan")
+        .and_then(|text| match Overscan::parse(text) {
+            Ok(overscan) => Some(overscan),
+            Err(e) => {
+                eprintln!("[video] {}", e);
+                None
+            }
+        });
+    let upscaler = config
+        .get("video", "upscaler")
+        .map_or(Ok(Upscaler::None), Upscaler::parse)
+        .unwrap_or_else(|e| {
+            eprintln!("[video] {}", e);
+            Upscaler::None
+        });
+    let mut profile_index = 0;
+    let mut profile = PROFILES[profile_index]
+        .with_overscan(overscan)
+        .with_upscaler(upscaler);
+    let mut video_buffer = Vec::new();
+    apply_view_profile(&mut canvas, &profile);
 
-    // -- Audio Configuration --
-    let desired_spec = AudioSpecDesired {
-        freq: Some(AUDIO_SAMPLE_RATE as i32),
-        channels: Some(1),   // mono
-        samples: Some(1024), // default
+    let creator = canvas.texture_creator();
+    let create_texture = |profile: &ViewProfile| {
+        let (width, height) = profile.output_size();
+        creator.create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
     };
+    let mut texture = create_texture(&profile).unwrap_or_else(|e| health::fatal(e));
 
-    let audio_queue = audio_subsystem
-        .open_queue::<f32, _>(None, &desired_spec)
-        .unwrap();
-    audio_queue.resume();
+    // -- Audio Configuration --
+    // The device can be reopened at another rate while running (F4, or when the
+    // output device changes); the APU follows whatever rate the device gives us.
+    // Without one the game runs silent, paced by vsync, until a device appears.
+    let mut requested_rate = sample_rate;
+    let mut audio_queue = audio_subsystem.as_ref().and_then(|audio| {
+        open_audio(audio, requested_rate)
+            .map_err(|e| health.degraded(Msg::NoAudio, e))
+            .ok()
+    });
+    let audio_request = Arc::new(Mutex::new(None));
+    let audio_request_clone = audio_request.clone();
+    let mut ui_sounds =
+        sfx::SfxMixer::new(output_rate(&audio_queue, requested_rate), ui_sounds_enabled);
 
     // --- ROM Loading ---
-    let bytes: Vec<u8> = std::fs::read("mario_usa.nes").unwrap();
-    let rom = Rom::new(&bytes).unwrap();
+    let rom = Rom::from_path(rom_path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", rom_path, e);
+        std::process::exit(1);
+    });
+    println!("{}", rom);
+    println!("{}", rom.info);
+    // For crash reports, once the ROM is in the console
+    let mut rom_description = format!("{}\n{}", rom, rom.info);
     let mut frame = Frame::new();
 
-    // --- Key Mapping ---
-    let mut key_map = HashMap::new();
-    key_map.insert(Keycode::Down, joypad::JoypadButton::DOWN);
-    key_map.insert(Keycode::Up, joypad::JoypadButton::UP);
-    key_map.insert(Keycode::Right, joypad::JoypadButton::RIGHT);
-    key_map.insert(Keycode::Left, joypad::JoypadButton::LEFT);
-    key_map.insert(Keycode::Space, joypad::JoypadButton::SELECT);
-    key_map.insert(Keycode::Return, joypad::JoypadButton::START);
-    key_map.insert(Keycode::A, joypad::JoypadButton::BUTTON_A);
-    key_map.insert(Keycode::S, joypad::JoypadButton::BUTTON_B);
-
-    // --- Reset Logic ---
+    // --- Key Mapping (F7 remaps player 1, see remap.rs) ---
+    // Players 3 and 4 need a Four Score and keys of their own in the config
+    let mut key_maps: [_; 4] = std::array::from_fn(|player| remap::keyboard_map(&config, player));
+
+    // --- Gamepads (one per player, in the order they were plugged in) ---
+    // Pads are opened as they are plugged in, as game controllers when SDL knows
+    // them (see gamepad.rs)
+    let mut pads: Vec<Gamepad> = Vec::new();
+    let mut joy_button_map = remap::joystick_button_map(&config);
+    let mut remap_capture: Option<RemapCapture> = None;
+
+    // --- Reset / Quit Logic ---
     let should_reset = Arc::new(Mutex::new(false));
     let should_reset_clone = should_reset.clone();
+    let should_quit = Arc::new(Mutex::new(false));
+    let should_quit_clone = should_quit.clone();
+    let should_dump_state = Arc::new(Mutex::new(false));
+    let should_dump_state_clone = should_dump_state.clone();
+
+    // --- Game Switching (drop a ROM on the window) ---
+    // Loading happens on a worker thread; finished ROMs wait here for the main loop
+    let mut rom_loader = RomLoader::new();
+    let next_rom = Arc::new(Mutex::new(None));
+    let next_rom_clone = next_rom.clone();
+    // ROMs sent over the control socket (single-instance mode) join the same queue
+    let open_request: Arc<Mutex<Option<PathBuf>>> = Arc::new(Mutex::new(None));
+    let open_request_clone = open_request.clone();
+
+    // --- Frame Pacing Stats (F3 shows them in the title bar) ---
+    let frame_timer = Arc::new(Mutex::new(FrameTimer::new()));
+    let frame_timer_clone = frame_timer.clone();
+    let mut show_stats = false;
+
+    // --- Screenshot Bursts (F5 saves the next frames as PNGs) ---
+    let burst_frames = config.get_u32(
+        "screenshots",
+        "burst_frames",
+        screenshot::DEFAULT_BURST_FRAMES,
+    );
+    let screenshot_dir = PathBuf::from(config.get("screenshots", "dir").unwrap_or("screenshots"));
+    let mut burst = screenshot::BurstRecorder::new(screenshot_dir.clone());
+    let current_rom = Arc::new(Mutex::new(PathBuf::from(rom_path)));
+    let current_rom_clone = current_rom.clone();
+    let mut rom_watcher = watch_rom.then(|| RomWatcher::new(Path::new(rom_path)));
+
+    // --- Clips (F6 saves the last seconds as an animated GIF or APNG) ---
+    let clip_seconds = config.get_u32("clips", "seconds", clip::DEFAULT_SECONDS);
+    let clip_format = match config.get("clips", "format") {
+        Some(name) => ClipFormat::from_name(name).unwrap_or_else(|| {
+            eprintln!("Unknown clip format {}, saving GIFs", name);
+            ClipFormat::Gif
+        }),
+        None => ClipFormat::Gif,
+    };
+    let mut clip_buffer = ClipBuffer::new(clip_seconds);
+
+    // --- Video Recording (F10 starts and stops a Y4M + WAV recording) ---
+    let recording: Arc<Mutex<Option<record::Recorder>>> = Arc::new(Mutex::new(None));
+    let recording_clone = recording.clone();
+    let should_toggle_recording = Arc::new(Mutex::new(false));
+    let should_toggle_recording_clone = should_toggle_recording.clone();
+    let recording_dir = screenshot_dir.clone();
+
+    // --- Input Movies (F11 records the pad of port 1 from power-on, see movie.rs) ---
+    let movie: Arc<Mutex<Option<MovieRecorder>>> = Arc::new(Mutex::new(None));
+    let movie_clone = movie.clone();
+    let should_toggle_movie = Arc::new(Mutex::new(false));
+    let should_toggle_movie_clone = should_toggle_movie.clone();
+    // The recorder waits for the game to be loaded again
+    let mut movie_pending = false;
+    let zapper_settings = ZapperSettings::from_config(&config);
+    // Expansion audio level for every game, over the ROM database's recommendation
+    let expansion_gain = config
+        .get("audio", "expansion_gain")
+        .map(|_| config.get_f32("audio", "expansion_gain", 1.0));
+    // Console timing for every game, over what their headers ask for
+    let region = config
+        .get("console", "region")
+        .and_then(|text| match Region::parse(text) {
+            Ok(region) => region,
+            Err(e) => {
+                eprintln!("[console] {}", e);
+                None
+            }
+        });
+
+    // --- Palettes (F9 cycles; a custom .pal file is reloaded when it changes) ---
+    let mut palettes = Palette::builtin();
+    let mut palette_index = 0;
+    let custom_palette = config.get("video", "palette").map(PathBuf::from);
+    if let Some(path) = &custom_palette {
+        match Palette::load(path) {
+            Ok(palette) => {
+                palettes.push(palette);
+                palette_index = palettes.len() - 1;
+            }
+            Err(e) => eprintln!("{}", i18n::format(Msg::PaletteLoadFailed, &[&e])),
+        }
+    }
+    let mut palette_watcher = custom_palette.as_deref().map(RomWatcher::new);
+    let ppu_options = PpuOptions {
+        pipeline: match config.get("video", "pipeline") {
+            None | Some("dot") => Pipeline::Dot,
+            Some("scanline") => Pipeline::Scanline,
+            Some(other) => {
+                eprintln!("[video] pipeline: unknown '{}', using dot", other);
+                Pipeline::Dot
+            }
+        },
+        sprite_limit: config.get("video", "sprite_limit") != Some("false"),
+    };
+
+    // --- On-Screen Messages ---
+    let osd = Arc::new(Mutex::new(render::osd::Osd::new()));
+    let osd_clone = osd.clone();
+    if let Some(summary) = health.summary() {
+        osd.lock().unwrap().show(summary);
+    }
 
     // --- Main Loop ---
     let bus = Bus::new(
         rom,
-        AUDIO_SAMPLE_RATE,
-        move |ppu: &NesPPU, joypad: &mut joypad::Joypad| {
-            render::render(ppu, &mut frame);
-
-            texture
-                .with_lock(None, |buffer: &mut [u8], pitch: usize| {
-                    for y in 0..240 {
-                        for x in 0..256 {
-                            let offset = y * 256 * 3 + x * 3;
-                            let buffer_offset = y * pitch + x * 3;
-                            buffer[buffer_offset] = frame.data[offset];
-                            buffer[buffer_offset + 1] = frame.data[offset + 1];
-                            buffer[buffer_offset + 2] = frame.data[offset + 2];
-                        }
+        output_rate(&audio_queue, requested_rate),
+        move |ppu: &NesPPU, ports: &mut ControllerPorts| {
+            render::render_with(ppu, &mut frame, render_mode, &palettes[palette_index]);
+            if let Some(count) = burst.capture(&frame) {
+                let message = i18n::format(Msg::BurstSaved, &[&count]);
+                println!("{}", message);
+                osd_clone.lock().unwrap().show(message);
+            }
+            clip_buffer.push(&frame);
+            if let Some(recorder) = recording_clone.lock().unwrap().as_mut() {
+                recorder.push_frame(&frame);
+            }
+            for port in 0..2 {
+                if let Device::Zapper(zapper) = ports.device_mut(port) {
+                    zapper.sense_light(&frame);
+                    if zapper_settings.crosshair {
+                        render::crosshair::draw(&mut frame, zapper.x, zapper.y);
                     }
-                })
-                .unwrap();
-
-            canvas.copy(&texture, None, None).unwrap();
+                }
+            }
+            osd_clone.lock().unwrap().draw(&mut frame);
+            upload_frame(
+                &mut canvas,
+                &mut texture,
+                &profile,
+                &frame,
+                &mut video_buffer,
+            );
+            let present_start = std::time::Instant::now();
             canvas.present();
+            let mut timer = frame_timer_clone.lock().unwrap();
+            if let Some(stutter) =
+                timer.end_frame(std::time::Instant::now(), present_start.elapsed())
+            {
+                eprintln!("{}", stutter);
+            }
+            if show_stats && timer.summary().frames.is_multiple_of(30) {
+                let title = format!(
+                    "{} - {} - {}",
+                    i18n::tr(Msg::WindowTitle),
+                    profile.name,
+                    timer.summary()
+                );
+                canvas.window_mut().set_title(&title).unwrap();
+            }
+            drop(timer);
+
+            if let Some(path) = open_request_clone.lock().unwrap().take() {
+                rom_loader.request(path);
+                canvas.window_mut().raise();
+            }
+            // --watch-rom: a rebuilt ROM is swapped in like a dropped one
+            if let Some(watcher) = rom_watcher.as_mut() {
+                if ppu.get_frame_count().is_multiple_of(15) {
+                    watcher.follow(&current_rom_clone.lock().unwrap());
+                    if watcher.changed() {
+                        let name = watcher.path().file_name().unwrap_or_default();
+                        let message = i18n::format(Msg::RomReloading, &[&name.to_string_lossy()]);
+                        println!("{}", message);
+                        osd_clone.lock().unwrap().show(message);
+                        rom_loader.request(watcher.path().to_path_buf());
+                    }
+                }
+            }
+            // A custom palette being edited is picked and shown as soon as it is saved
+            if let Some(watcher) = palette_watcher.as_mut() {
+                if ppu.get_frame_count().is_multiple_of(15) && watcher.changed() {
+                    let message = match Palette::load(watcher.path()) {
+                        Ok(palette) => {
+                            let message = i18n::format(Msg::PaletteReloaded, &[&palette.name]);
+                            // The custom palette comes after the built-in ones
+                            palettes.truncate(Palette::builtin().len());
+                            palettes.push(palette);
+                            palette_index = palettes.len() - 1;
+                            message
+                        }
+                        Err(e) => i18n::format(Msg::PaletteLoadFailed, &[&e]),
+                    };
+                    println!("{}", message);
+                    osd_clone.lock().unwrap().show(message);
+                }
+            }
+            match rom_loader.poll() {
+                Some(Ok(loaded)) => *next_rom_clone.lock().unwrap() = Some(loaded),
+                Some(Err(e)) => eprintln!("{}", e),
+                None => {}
+            }
 
+            let mut open_menu = false;
             for event in event_pump.poll_iter() {
+                // Remapping (F7) takes every key and joystick button until it is done
+                let remap_input = match &event {
+                    _ if remap_capture.is_none() => None,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => Some(None),
+                    Event::KeyDown {
+                        keycode: Some(key),
+                        repeat: false,
+                        ..
+                    } => Some(Some(remap::Input::Key(*key))),
+                    Event::JoyButtonDown {
+                        which, button_idx, ..
+                    } if gamepad::find(&pads, *which, false).is_some() => {
+                        Some(Some(remap::Input::JoyButton(*button_idx)))
+                    }
+                    Event::ControllerButtonDown { which, button, .. } => {
+                        gamepad::find(&pads, *which, true)
+                            .map(|pad| Some(remap::Input::PadButton(pad, *button)))
+                    }
+                    _ => None,
+                };
+                if let Some(input) = remap_input {
+                    let capture = remap_capture.as_mut().unwrap();
+                    let message = match input {
+                        None => {
+                            remap_capture = None;
+                            i18n::tr(Msg::RemapCancelled).to_string()
+                        }
+                        Some(input) if capture.capture(input) => {
+                            let settings = capture.settings();
+                            remap_capture = None;
+                            match config.store(&config_file, &settings) {
+                                Ok(()) => {
+                                    key_maps[0] = remap::keyboard_map(&config, 0);
+                                    joy_button_map = remap::joystick_button_map(&config);
+                                    i18n::format(Msg::RemapSaved, &[&config_file.display()])
+                                }
+                                Err(e) => i18n::format(Msg::RemapFailed, &[&e]),
+                            }
+                        }
+                        Some(_) => capture.prompt(),
+                    };
+                    println!("{}", message);
+                    let mut osd = osd_clone.lock().unwrap();
+                    if remap_capture.is_some() {
+                        osd.pin(message);
+                    } else {
+                        osd.show(message);
+                    }
+                    continue;
+                }
+
                 match event {
                     Event::Quit { .. }
                     | Event::KeyDown {
                         keycode: Some(Keycode::Escape),
                         ..
-                    } => std::process::exit(0),
-
-                    Event::KeyDown { keycode, .. } => {
-                        if let Some(key) = keycode {
-                            match key {
-                                Keycode::R => *should_reset_clone.lock().unwrap() = true,
-                                _ => {
-                                    if let Some(button) = key_map.get(&key) {
-                                        joypad.set_button_pressed_status(*button, true);
-                                    }
+                    } => *should_quit_clone.lock().unwrap() = true,
+
+                    Event::KeyDown {
+                        keycode: Some(key), ..
+                    } => match key {
+                        Keycode::R => *should_reset_clone.lock().unwrap() = true,
+                        Keycode::P => open_menu = true,
+                        Keycode::T => {
+                            let next = (profile_index + 1) % PROFILES.len();
+                            let next_profile = PROFILES[next]
+                                .with_overscan(overscan)
+                                .with_upscaler(upscaler);
+                            match create_texture(&next_profile) {
+                                Ok(created) => {
+                                    profile_index = next;
+                                    profile = next_profile;
+                                    apply_view_profile(&mut canvas, &profile);
+                                    texture = created;
+                                    osd_clone.lock().unwrap().show(profile.name.to_string());
                                 }
+                                Err(e) => {
+                                    let message = i18n::format(Msg::ViewFailed, &[&e]);
+                                    eprintln!("{}", message);
+                                    osd_clone.lock().unwrap().show(message);
+                                }
+                            }
+                        }
+                        Keycode::F3 => {
+                            show_stats = !show_stats;
+                            if !show_stats {
+                                apply_view_profile(&mut canvas, &profile);
                             }
                         }
+                        Keycode::F5 => {
+                            let message = if burst.is_active() {
+                                i18n::format(Msg::BurstSaved, &[&burst.stop()])
+                            } else {
+                                let rom = current_rom_clone.lock().unwrap().clone();
+                                match burst.start(&rom, burst_frames) {
+                                    Ok(()) => i18n::format(Msg::BurstStarted, &[&burst_frames]),
+                                    Err(e) => i18n::format(Msg::BurstFailed, &[&e]),
+                                }
+                            };
+                            println!("{}", message);
+                            osd_clone.lock().unwrap().show(message);
+                        }
+                        Keycode::F6 if !clip_buffer.is_empty() => {
+                            let clip = clip_buffer.snapshot();
+                            let seconds = clip.frames.len() / 60;
+                            let rom = current_rom_clone.lock().unwrap().clone();
+                            let path = screenshot_dir.join(format!(
+                                "{}.{}",
+                                screenshot::file_prefix(&rom),
+                                clip_format.extension()
+                            ));
+                            osd_clone
+                                .lock()
+                                .unwrap()
+                                .show(i18n::format(Msg::ClipSaving, &[&seconds]));
+                            // Encoding takes a few seconds; the worker reports on the OSD
+                            let osd = osd_clone.clone();
+                            std::thread::spawn(move || {
+                                let saved = std::fs::create_dir_all(path.parent().unwrap())
+                                    .and_then(|()| clip.save(clip_format, &path));
+                                let message = match saved {
+                                    Ok(()) => i18n::format(Msg::ClipSaved, &[&path.display()]),
+                                    Err(e) => i18n::format(Msg::ClipFailed, &[&e]),
+                                };
+                                println!("{}", message);
+                                osd.lock().unwrap().show(message);
+                            });
+                        }
+                        Keycode::F8 => *should_dump_state_clone.lock().unwrap() = true,
+                        Keycode::F10 => *should_toggle_recording_clone.lock().unwrap() = true,
+                        Keycode::F11 => *should_toggle_movie_clone.lock().unwrap() = true,
+                        Keycode::F9 => {
+                            palette_index = (palette_index + 1) % palettes.len();
+                            let name = palettes[palette_index].name.clone();
+                            osd_clone.lock().unwrap().show(name);
+                        }
+                        Keycode::F7 => {
+                            let capture = RemapCapture::new();
+                            osd_clone.lock().unwrap().pin(capture.prompt());
+                            remap_capture = Some(capture);
+                        }
+                        Keycode::F4 => {
+                            let next = SAMPLE_RATES
+                                .iter()
+                                .position(|&rate| rate == requested_rate)
+                                .map_or(0, |i| (i + 1) % SAMPLE_RATES.len());
+                            requested_rate = SAMPLE_RATES[next];
+                            *audio_request_clone.lock().unwrap() = Some(requested_rate);
+                        }
+                        Keycode::F1 | Keycode::F2 => {
+                            let port = if key == Keycode::F1 { 0 } else { 1 };
+                            let kind = ports.device(port).kind().next();
+                            ports.connect(port, kind);
+                            let message = i18n::format(
+                                Msg::PortDevice,
+                                &[&(port + 1), &i18n::device_name(kind)],
+                            );
+                            println!("{}", message);
+                            osd_clone.lock().unwrap().show(message);
+                        }
+                        _ => {
+                            if let Some((player, button)) = keyboard_button(&key_maps, key) {
+                                if let Some(joypad) = ports.player_joypad(player) {
+                                    joypad.set_button_pressed_status(button, true);
+                                }
+                            }
+                        }
+                    },
+                    Event::KeyUp {
+                        keycode: Some(key), ..
+                    } => {
+                        if let Some((player, button)) = keyboard_button(&key_maps, key) {
+                            if let Some(joypad) = ports.player_joypad(player) {
+                                joypad.set_button_pressed_status(button, false);
+                            }
+                        }
+                    }
+
+                    Event::AudioDeviceAdded {
+                        iscapture: false, ..
+                    }
+                    | Event::AudioDeviceRemoved {
+                        iscapture: false, ..
+                    } => *audio_request_clone.lock().unwrap() = Some(requested_rate),
+
+                    Event::JoyDeviceAdded { which, .. }
+                    | Event::ControllerDeviceAdded { which, .. } => {
+                        // Known pads send both events and are opened as game controllers
+                        let known = controller_subsystem
+                            .as_ref()
+                            .is_some_and(|controllers| controllers.is_game_controller(which));
+                        let opened = match (&event, &controller_subsystem, &joystick_subsystem) {
+                            (Event::ControllerDeviceAdded { .. }, Some(controllers), _) => {
+                                controllers
+                                    .open(which)
+                                    .map(Gamepad::controller)
+                                    .map_err(|e| e.to_string())
+                            }
+                            (Event::JoyDeviceAdded { .. }, _, Some(joysticks)) if !known => {
+                                joysticks
+                                    .open(which)
+                                    .map(Gamepad::joystick)
+                                    .map_err(|e| e.to_string())
+                            }
+                            _ => continue,
+                        };
+                        match opened {
+                            Ok(pad) => {
+                                let message = i18n::format(
+                                    Msg::JoystickConnected,
+                                    &[&(pads.len() + 1), &pad.name()],
+                                );
+                                println!("{}", message);
+                                osd_clone.lock().unwrap().show(message);
+                                pads.push(pad);
+                            }
+                            Err(e) => {
+                                eprintln!(
+                                    "{}",
+                                    i18n::format(Msg::JoystickOpenFailed, &[&which, &e])
+                                )
+                            }
+                        }
+                    }
+                    Event::JoyDeviceRemoved { which, .. }
+                    | Event::ControllerDeviceRemoved { which, .. } => {
+                        pads.retain(|pad| pad.instance_id() != which);
                     }
-                    Event::KeyUp { keycode, .. } => {
-                        if let Some(key) = keycode {
-                            if let Some(button) = key_map.get(&key) {
-                                joypad.set_button_pressed_status(*button, false);
+                    Event::JoyAxisMotion {
+                        which,
+                        axis_idx,
+                        value,
+                        ..
+                    } if axis_idx < 2 => {
+                        if let Some(pad) = gamepad::find(&pads, which, false) {
+                            if let Some(dpad) =
+                                move_stick(&mut pads, pad, axis_idx as usize, value, &config)
+                            {
+                                if let Some(joypad) = ports.player_joypad(pad.min(3)) {
+                                    joypad.set_buttons(stick::with_dpad(joypad.buttons(), dpad));
+                                }
+                            }
+                        }
+                    }
+                    Event::ControllerAxisMotion {
+                        which,
+                        axis: axis @ (Axis::LeftX | Axis::LeftY),
+                        value,
+                        ..
+                    } => {
+                        if let Some(pad) = gamepad::find(&pads, which, true) {
+                            let axis = (axis == Axis::LeftY) as usize;
+                            if let Some(dpad) = move_stick(&mut pads, pad, axis, value, &config) {
+                                if let Some(joypad) = ports.player_joypad(pad.min(3)) {
+                                    joypad.set_buttons(stick::with_dpad(joypad.buttons(), dpad));
+                                }
+                            }
+                        }
+                    }
+                    Event::JoyButtonDown {
+                        which, button_idx, ..
+                    }
+                    | Event::JoyButtonUp {
+                        which, button_idx, ..
+                    } => {
+                        let pressed = matches!(event, Event::JoyButtonDown { .. });
+                        let pad = gamepad::find(&pads, which, false);
+                        if let (Some(button), Some(pad)) = (joy_button_map.get(&button_idx), pad) {
+                            if let Some(joypad) = ports.player_joypad(pad.min(3)) {
+                                joypad.set_button_pressed_status(*button, pressed);
+                            }
+                        }
+                    }
+                    Event::ControllerButtonDown { which, button, .. }
+                    | Event::ControllerButtonUp { which, button, .. } => {
+                        let pressed = matches!(event, Event::ControllerButtonDown { .. });
+                        let Some(pad) = gamepad::find(&pads, which, true) else {
+                            continue;
+                        };
+                        if let (Some(button), Some(joypad)) = (
+                            gamepad::button_map(&config, pad).get(&button),
+                            ports.player_joypad(pad.min(3)),
+                        ) {
+                            joypad.set_button_pressed_status(*button, pressed);
+                        }
+                    }
+
+                    Event::DropFile { filename, .. } => {
+                        rom_loader.request(PathBuf::from(filename));
+                    }
+
+                    Event::MouseMotion { x, y, .. } => {
+                        // Mouse events come in logical (letterboxed) coordinates
+                        let nes_x = (x.max(0) as f64 / profile.pixel_aspect) as usize
+                            + profile.overscan.left;
+                        let nes_y = y.max(0) as usize + profile.overscan.top;
+                        for port in 0..2 {
+                            match ports.device_mut(port) {
+                                Device::Zapper(zapper) => zapper.aim(
+                                    nes_x as i32 + zapper_settings.offset_x,
+                                    nes_y as i32 + zapper_settings.offset_y,
+                                ),
+                                Device::Paddle(paddle) => paddle.aim(nes_x),
+                                _ => {}
+                            }
+                        }
+                    }
+                    Event::MouseButtonDown {
+                        mouse_btn: MouseButton::Left,
+                        ..
+                    }
+                    | Event::MouseButtonUp {
+                        mouse_btn: MouseButton::Left,
+                        ..
+                    } => {
+                        let pressed = matches!(event, Event::MouseButtonDown { .. });
+                        for port in 0..2 {
+                            match ports.device_mut(port) {
+                                Device::Zapper(zapper) => zapper.trigger = pressed,
+                                Device::Paddle(paddle) => paddle.fire = pressed,
+                                _ => {}
                             }
                         }
                     }
                     _ => {}
                 }
             }
+
+            // --- Pause Menu (Select+Start on a pad, or P) ---
+            // The game stands still until the menu is closed; the menu reads NES
+            // buttons through the same key and joystick mappings as the game
+            open_menu |= (0..4).any(|player| {
+                ports
+                    .player_joypad(player)
+                    .is_some_and(|joypad| joypad.buttons().contains(menu::OPEN_CHORD))
+            });
+            if open_menu {
+                release_joypads(ports);
+                let paused = frame.data.clone();
+                let mut pause_menu = PauseMenu::new();
+                'menu: loop {
+                    let mut presses = Vec::new();
+                    for event in event_pump.poll_iter() {
+                        match event {
+                            Event::Quit { .. } => {
+                                *should_quit_clone.lock().unwrap() = true;
+                                break 'menu;
+                            }
+                            Event::KeyDown {
+                                keycode: Some(Keycode::Escape | Keycode::P),
+                                repeat: false,
+                                ..
+                            } => break 'menu,
+                            Event::KeyDown {
+                                keycode: Some(key),
+                                repeat: false,
+                                ..
+                            } => presses
+                                .extend(keyboard_button(&key_maps, key).map(|(_, button)| button)),
+                            Event::JoyButtonDown {
+                                which, button_idx, ..
+                            } if gamepad::find(&pads, which, false).is_some() => {
+                                presses.extend(joy_button_map.get(&button_idx))
+                            }
+                            Event::ControllerButtonDown { which, button, .. } => {
+                                if let Some(pad) = gamepad::find(&pads, which, true) {
+                                    presses.extend(gamepad::button_map(&config, pad).get(&button))
+                                }
+                            }
+                            Event::JoyAxisMotion {
+                                which,
+                                axis_idx,
+                                value,
+                                ..
+                            } if axis_idx < 2 => {
+                                let Some(pad) = gamepad::find(&pads, which, false) else {
+                                    continue;
+                                };
+                                let before = pads[pad].dpad();
+                                if let Some(dpad) =
+                                    move_stick(&mut pads, pad, axis_idx as usize, value, &config)
+                                {
+                                    presses.extend(menu::newly_pressed(before, dpad));
+                                }
+                            }
+                            Event::ControllerAxisMotion {
+                                which,
+                                axis: axis @ (Axis::LeftX | Axis::LeftY),
+                                value,
+                                ..
+                            } => {
+                                let Some(pad) = gamepad::find(&pads, which, true) else {
+                                    continue;
+                                };
+                                let before = pads[pad].dpad();
+                                let axis = (axis == Axis::LeftY) as usize;
+                                if let Some(dpad) = move_stick(&mut pads, pad, axis, value, &config)
+                                {
+                                    presses.extend(menu::newly_pressed(before, dpad));
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    for button in presses {
+                        match pause_menu.press(button) {
+                            None => {}
+                            Some(Item::Resume) => break 'menu,
+                            Some(Item::Reset) => {
+                                *should_reset_clone.lock().unwrap() = true;
+                                break 'menu;
+                            }
+                            // The next frame is saved, without the menu over it
+                            Some(Item::Screenshot) if burst.is_active() => break 'menu,
+                            Some(Item::Screenshot) => {
+                                let rom = current_rom_clone.lock().unwrap().clone();
+                                match burst.start(&rom, 1) {
+                                    Ok(()) => break 'menu,
+                                    Err(e) => {
+                                        pause_menu.set_note(i18n::format(Msg::BurstFailed, &[&e]))
+                                    }
+                                }
+                            }
+                            Some(Item::Quit) => {
+                                *should_quit_clone.lock().unwrap() = true;
+                                break 'menu;
+                            }
+                        }
+                    }
+
+                    frame.data.copy_from_slice(&paused);
+                    pause_menu.draw(&mut frame);
+                    // Presenting with vsync paces the menu
+                    upload_frame(
+                        &mut canvas,
+                        &mut texture,
+                        &profile,
+                        &frame,
+                        &mut video_buffer,
+                    );
+                    canvas.present();
+                }
+                // Buttons let go while the menu was open never reached the pads
+                release_joypads(ports);
+                frame_timer_clone.lock().unwrap().resume();
+            }
+
+            // The frame about to start plays with these buttons, after any reset
+            if let Some(recorder) = movie_clone.lock().unwrap().as_mut() {
+                let buttons = ports
+                    .joypad_mut(0)
+                    .map_or(JoypadButton::empty(), |joypad| joypad.buttons());
+                recorder.push_frame(buttons, *should_reset_clone.lock().unwrap());
+            }
         },
     );
 
     let mut cpu = CPU::new(bus);
-    cpu.reset();
+    cpu.bus.set_ppu_options(ppu_options);
+    if let Some(gain) = expansion_gain {
+        cpu.bus.override_expansion_gain(gain);
+    }
+    if let Some(region) = region {
+        cpu.bus.override_region(region);
+    }
+    if let Some(log) = write_log {
+        cpu.bus.set_write_log(log);
+    }
+    cpu.power_on();
+
+    // --- Battery Saves ---
+    let mut save_path = cartridge::save_path(Path::new(rom_path));
+    if let Err(e) = cpu.bus.restore_battery_ram(&save_path) {
+        eprintln!(
+            "{}",
+            i18n::format(Msg::SaveLoadFailed, &[&save_path.display(), &e])
+        );
+    }
+
+    // --- Tooling Socket ---
+    #[cfg(unix)]
+    let mut ipc_server = ipc_path.and_then(|path| match ipc::IpcServer::bind(&path) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            eprintln!("Could not listen on {}: {}", path.display(), e);
+            None
+        }
+    });
+    #[cfg(not(unix))]
+    if ipc_path.is_some() {
+        eprintln!("--ipc is only available on Unix systems");
+    }
+    let mut steps: u32 = 0;
+
+    // --- Crash Reports (see crash.rs) ---
+    crash::install_hook();
+    let mut history = crash::History::new();
+    let mut last_crash_frame = None;
 
     // --- Start emulator ---
     loop {
+        if *should_quit.lock().unwrap() {
+            if let Err(e) = cpu.bus.flush_battery_ram(&save_path) {
+                eprintln!(
+                    "{}",
+                    i18n::format(Msg::SaveWriteFailed, &[&save_path.display(), &e])
+                );
+            }
+            if let Some(Err(e)) = cpu.bus.take_write_log().map(watch::WriteLog::finish) {
+                eprintln!("Could not write {}: {}", write_log_out, e);
+            }
+            if let Some(recorder) = recording.lock().unwrap().take() {
+                println!("{}", finish_recording(recorder));
+            }
+            if let Some(recorder) = movie.lock().unwrap().take() {
+                let rom = current_rom.lock().unwrap();
+                println!("{}", finish_movie(recorder, &recording_dir, &rom));
+            }
+            println!("{}", frame_timer.lock().unwrap().summary());
+            break;
+        }
+
         // Audio sync: The desired hardware buffer size is 1024 samples * 4 bytes/sample = 4096 bytes.
         // To keep latency low, we pause the emulator if the queue size exceeds twice that (8192 bytes).
-        while audio_queue.size() > 8192 {
-            std::thread::sleep(std::time::Duration::from_micros(10));
+        if let Some(rate) = audio_request.lock().unwrap().take() {
+            let opened = audio_subsystem
+                .as_ref()
+                .map(|audio| open_audio(audio, rate));
+            let message = match opened {
+                Some(Ok(queue)) => {
+                    let obtained = queue.spec().freq as f64;
+                    audio_queue = Some(queue);
+                    cpu.bus.set_sample_rate(obtained);
+                    ui_sounds.set_sample_rate(obtained);
+                    let message = i18n::format(Msg::AudioOutput, &[&obtained]);
+                    println!("{}", message);
+                    message
+                }
+                Some(Err(e)) => {
+                    audio_queue = None;
+                    eprintln!("{}: {}", i18n::tr(Msg::NoAudio), e);
+                    i18n::tr(Msg::NoAudio).to_string()
+                }
+                None => i18n::tr(Msg::NoAudio).to_string(),
+            };
+            osd.lock().unwrap().show(message);
+        }
+
+        if let Some(audio_queue) = audio_queue.as_ref().filter(|queue| queue.size() > 8192) {
+            let wait_start = std::time::Instant::now();
+            while audio_queue.size() > 8192 {
+                std::thread::sleep(std::time::Duration::from_micros(10));
+            }
+            frame_timer
+                .lock()
+                .unwrap()
+                .add_audio_wait(wait_start.elapsed());
+        }
+
+        if std::mem::take(&mut *should_dump_state.lock().unwrap()) {
+            let path = debugstate::dump_path(Path::new(rom_path));
+            let state = debugstate::export(&cpu).pretty() + "\n";
+            let message = match std::fs::write(&path, state) {
+                Ok(()) => i18n::format(Msg::StateDumped, &[&path.display()]),
+                Err(e) => i18n::format(Msg::StateDumpFailed, &[&e]),
+            };
+            println!("{}", message);
+            osd.lock().unwrap().show(message);
+        }
+
+        if std::mem::take(&mut *should_toggle_recording.lock().unwrap()) {
+            let mut recording = recording.lock().unwrap();
+            let message = match recording.take() {
+                Some(recorder) => finish_recording(recorder),
+                None => match record::Recorder::start(
+                    &recording_dir,
+                    &current_rom.lock().unwrap(),
+                    cpu.bus.region().frame_rate(),
+                    cpu.bus.sample_rate() as u32,
+                ) {
+                    Ok(recorder) => {
+                        *recording = Some(recorder);
+                        i18n::tr(Msg::RecordingStarted).to_string()
+                    }
+                    Err(e) => i18n::format(Msg::RecordingFailed, &[&e]),
+                },
+            };
+            println!("{}", message);
+            osd.lock().unwrap().show(message);
+        }
+
+        if std::mem::take(&mut *should_toggle_movie.lock().unwrap()) {
+            let rom = current_rom.lock().unwrap().clone();
+            let message = match movie.lock().unwrap().take() {
+                Some(recorder) => finish_movie(recorder, &recording_dir, &rom),
+                None => {
+                    // Movies play from power-on: the game is loaded again first
+                    *open_request.lock().unwrap() = Some(rom);
+                    movie_pending = true;
+                    i18n::tr(Msg::MovieStarted).to_string()
+                }
+            };
+            println!("{}", message);
+            osd.lock().unwrap().show(message);
         }
 
         if *should_reset.lock().unwrap() {
             cpu.reset();
             *should_reset.lock().unwrap() = false;
+            #[cfg(unix)]
+            if let Some(server) = ipc_server.as_mut() {
+                server.notify("reset");
+            }
+        }
+
+        // Requests are answered between instructions, a few thousand times per second
+        #[cfg(unix)]
+        if let Some(server) = ipc_server.as_mut() {
+            if steps.is_multiple_of(256) || server.paused {
+                server.poll(&mut cpu);
+                if let Some(path) = server.open.take() {
+                    *open_request.lock().unwrap() = Some(path);
+                    server.paused = false;
+                }
+            }
+            if server.paused {
+                std::thread::sleep(std::time::Duration::from_millis(5));
+                continue;
+            }
+        }
+        steps = steps.wrapping_add(1);
+
+        // Source-level trace for cc65 homebrew: each new source line, then the instructions
+        if let Some(debug_info) = &debug_info {
+            let location = debug_info.location(cpu.program_counter);
+            if let Some(location) = location.filter(|l| Some((l.file, l.line)) != last_source_line)
+            {
+                if let Some(label) = debug_info.label(cpu.program_counter) {
+                    println!("{}:", label);
+                }
+                println!("{}", location);
+                last_source_line = Some((location.file, location.line));
+            }
+            println!("    {}", trace::trace(&cpu));
+        }
+
+        let pc = cpu.program_counter;
+        let before = cpu.state();
+        match panic::catch_unwind(AssertUnwindSafe(|| cpu.step())) {
+            Ok(step) => history.push(before, step),
+            Err(_) => {
+                // The frame callback may have been unwound holding one of these
+                osd.clear_poison();
+                frame_timer.clear_poison();
+                current_rom.clear_poison();
+
+                let report = crash::report(
+                    &crash::take_panic(),
+                    &rom_description,
+                    pc,
+                    &cpu.state(),
+                    &history,
+                );
+                let path = crash::report_path(&current_rom.lock().unwrap());
+                let message = match std::fs::write(&path, report) {
+                    Ok(()) => i18n::format(Msg::Crashed, &[&path.display()]),
+                    Err(e) => i18n::format(Msg::CrashReportFailed, &[&e]),
+                };
+                eprintln!("{}", message);
+                osd.lock().unwrap().show(message);
+
+                // A game that crashes again within a second of the reset will not
+                // get further: stop, saving the battery RAM on the way out
+                let frame = cpu.bus.ppu().get_frame_count();
+                if last_crash_frame.is_some_and(|last| frame < last + 60) {
+                    *should_quit.lock().unwrap() = true;
+                }
+                last_crash_frame = Some(frame);
+                history = crash::History::new();
+                cpu.power_on();
+                continue;
+            }
         }
 
-        cpu.step();
+        // Strict mode: report each kind of violation once per instruction address
+        let mut new_violation = false;
+        for diagnostic in strict::take() {
+            if strict_seen.insert((diagnostic.violation, pc)) {
+                eprintln!(
+                    "strict: {} (instruction at ${:04X}; A={:02X} X={:02X} Y={:02X} P={:02X} SP={:02X}, CPU cycle {})",
+                    diagnostic,
+                    pc,
+                    cpu.register_a,
+                    cpu.register_x,
+                    cpu.register_y,
+                    cpu.status.bits(),
+                    cpu.stack_pointer,
+                    cpu.cycles
+                );
+                new_violation = true;
+            }
+        }
+        if new_violation && strict_break {
+            #[cfg(unix)]
+            if let Some(server) = ipc_server.as_mut() {
+                server.paused = true;
+                server.notify("strict");
+                continue;
+            }
+            eprintln!("strict: stopping (attach a debugger with --ipc to pause instead)");
+            *should_quit.lock().unwrap() = true;
+        }
+
+        // The frame callback just ran if a new game is waiting: swap it in between frames
+        if let Some(loaded) = next_rom.lock().unwrap().take() {
+            match cpu.bus.flush_battery_ram(&save_path) {
+                Ok(()) if !cpu.bus.battery_ram().is_empty() => ui_sounds.play(UiSound::StateSaved),
+                Ok(()) => {}
+                Err(e) => eprintln!(
+                    "{}",
+                    i18n::format(Msg::SaveWriteFailed, &[&save_path.display(), &e])
+                ),
+            }
+            println!("{}", loaded.rom);
+            println!("{}", loaded.rom.info);
+            rom_description = format!("{}\n{}", loaded.rom, loaded.rom.info);
+            // A movie ends with its game; one that was asked for starts here
+            if let Some(recorder) = movie.lock().unwrap().take() {
+                let message = finish_movie(recorder, &recording_dir, &current_rom.lock().unwrap());
+                println!("{}", message);
+                osd.lock().unwrap().show(message);
+            }
+            if std::mem::take(&mut movie_pending) {
+                *movie.lock().unwrap() = Some(MovieRecorder::start());
+            }
+            cpu.bus.insert_cartridge(loaded.rom);
+            cpu.power_on();
+
+            save_path = cartridge::save_path(&loaded.path);
+            *current_rom.lock().unwrap() = loaded.path;
+            if let Err(e) = cpu.bus.restore_battery_ram(&save_path) {
+                eprintln!(
+                    "{}",
+                    i18n::format(Msg::SaveLoadFailed, &[&save_path.display(), &e])
+                );
+            }
+        }
 
         if let Some(sample) = cpu.collect_audio_sample() {
-            let _ = audio_queue.queue_audio(&[sample]);
+            if let Some(recorder) = recording.lock().unwrap().as_mut() {
+                recorder.push_sample(sample);
+            }
+            if let Some(audio_queue) = &audio_queue {
+                let _ = audio_queue.queue_audio(&[ui_sounds.mix(sample)]);
+            }
+        }
+    }
+}
+
+/// Closes a recording, for the OSD.
+fn finish_recording(recorder: record::Recorder) -> String {
+    match recorder.finish() {
+        Ok(path) => i18n::format(Msg::RecordingSaved, &[&path.display()]),
+        Err(e) => i18n::format(Msg::RecordingFailed, &[&e]),
+    }
+}
+
+/// Saves a movie next to the video recordings, for the OSD.
+fn finish_movie(recorder: MovieRecorder, dir: &Path, rom_path: &Path) -> String {
+    let movie = recorder.stop();
+    let path = dir.join(format!("{}.movie", screenshot::file_prefix(rom_path)));
+    let title = rom_path.file_name().unwrap_or_default().to_string_lossy();
+    match std::fs::create_dir_all(dir).and_then(|()| movie.save(&path, &title)) {
+        Ok(()) => i18n::format(Msg::MovieSaved, &[&path.display(), &movie.len()]),
+        Err(e) => i18n::format(Msg::MovieFailed, &[&e]),
+    }
+}
+
+/// Opens the default output device asking for `sample_rate`; the device may settle
+/// on another rate, read it back from the queue's spec.
+fn open_audio(audio: &sdl2::AudioSubsystem, sample_rate: f64) -> Result<AudioQueue<f32>, String> {
+    let desired_spec = AudioSpecDesired {
+        freq: Some(sample_rate as i32),
+        channels: Some(1),   // mono
+        samples: Some(1024), // default
+    };
+    let queue = audio.open_queue::<f32, _>(None, &desired_spec)?;
+    queue.resume();
+    Ok(queue)
+}
+
+/// Rate the APU should produce samples at: the device's, or the one asked for
+/// while running silent.
+fn output_rate(queue: &Option<AudioQueue<f32>>, requested: f64) -> f64 {
+    queue
+        .as_ref()
+        .map_or(requested, |queue| queue.spec().freq as f64)
+}
+
+/// Removes a `--name` switch from the arguments, returns whether it was there.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let found = args.iter().position(|arg| arg == name);
+    if let Some(index) = found {
+        args.remove(index);
+    }
+    found.is_some()
+}
+
+/// Removes `--name value` from the arguments and returns the value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+    if index + 1 >= args.len() {
+        eprintln!("{} needs a value", name);
+        std::process::exit(2);
+    }
+    args.remove(index);
+    Some(args.remove(index))
+}
+
+/// The player whose keys include `key`, and the button it presses.
+fn keyboard_button(
+    key_maps: &[HashMap<Keycode, JoypadButton>; 4],
+    key: Keycode,
+) -> Option<(usize, JoypadButton)> {
+    key_maps
+        .iter()
+        .enumerate()
+        .find_map(|(player, map)| Some((player, *map.get(&key)?)))
+}
+
+/// Moves the left stick of the `pad`th gamepad. Returns its new d-pad directions,
+/// if they changed.
+fn move_stick(
+    pads: &mut [Gamepad],
+    pad: usize,
+    axis: usize,
+    value: i16,
+    config: &config::Config,
+) -> Option<JoypadButton> {
+    pads[pad].move_stick(axis, value, &StickConfig::from_config(config, pad))
+}
+
+/// Lets go of every button of every pad.
+fn release_joypads(ports: &mut ControllerPorts) {
+    for player in 0..4 {
+        if let Some(joypad) = ports.player_joypad(player) {
+            joypad.set_buttons(JoypadButton::empty());
         }
     }
 }
+
+/// Scales `frame` through the view profile into the texture and copies it to the
+/// canvas, ready to be presented.
+fn upload_frame(
+    canvas: &mut WindowCanvas,
+    texture: &mut Texture,
+    profile: &ViewProfile,
+    frame: &Frame,
+    video_buffer: &mut Vec<u8>,
+) {
+    profile.apply(frame, video_buffer);
+    let (width, _) = profile.output_size();
+    texture.update(None, video_buffer, width * 3).unwrap();
+    canvas.copy(texture, None, None).unwrap();
+}
+
+/// Names the application for the desktop: SDL's app name, the audio stream name
+/// shown by sound mixers and the X11 window class used for task bar grouping.
+/// Hints only take effect if set before SDL starts.
+fn set_app_metadata() {
+    sdl2::hint::set("SDL_APP_NAME", APP_NAME);
+    sdl2::hint::set("SDL_AUDIO_DEVICE_APP_NAME", APP_NAME);
+    sdl2::hint::set("SDL_VIDEO_X11_WMCLASS", env!("CARGO_PKG_NAME"));
+}
+
+/// Letterboxes the picture to the profile's aspect ratio and shows its name in the title bar.
+fn apply_view_profile(canvas: &mut WindowCanvas, profile: &ViewProfile) {
+    let (width, height) = profile.display_size();
+    canvas.set_logical_size(width, height).unwrap();
+    canvas
+        .window_mut()
+        .set_title(&format!(
+            "{} - {}",
+            i18n::tr(Msg::WindowTitle),
+            profile.name
+        ))
+        .unwrap();
+}
+
+/// Music player mode for NSF files: no emulated picture, just the tune's details
+/// drawn with the OSD font. Left/Right change songs, ESC quits.
+fn play_nsf(path: &str, track: Option<u8>, sample_rate: f64) {
+    let nsf = nsf::Nsf::from_path(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    });
+    println!("{}", nsf);
+    let chips = nsf.expansion_names();
+    if !chips.is_empty() {
+        eprintln!(
+            "{}",
+            i18n::format(Msg::NsfExpansionAudio, &[&chips.join(", ")])
+        );
+    }
+
+    set_app_metadata();
+    let sdl_context = sdl2::init().unwrap_or_else(|e| health::fatal(e));
+    let video_subsystem = sdl_context.video().unwrap_or_else(|e| health::fatal(e));
+    let audio_subsystem = sdl_context.audio().unwrap_or_else(|e| health::fatal(e));
+    let mut window = video_subsystem
+        .window(&nsf.title, 256 * 2, 240 * 2)
+        .resizable()
+        .position_centered()
+        .build()
+        .unwrap_or_else(|e| health::fatal(e));
+    icon::apply(&mut window);
+    let mut canvas = window
+        .into_canvas()
+        .present_vsync()
+        .build()
+        .unwrap_or_else(|e| health::fatal(e));
+    canvas
+        .set_logical_size(256, 240)
+        .unwrap_or_else(|e| health::fatal(e));
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, 256, 240)
+        .unwrap_or_else(|e| health::fatal(e));
+    let mut event_pump = sdl_context
+        .event_pump()
+        .unwrap_or_else(|e| health::fatal(e));
+    let audio_queue =
+        open_audio(&audio_subsystem, sample_rate).unwrap_or_else(|e| health::fatal(e));
+
+    let mut player = nsf::Player::new(&nsf, audio_queue.spec().freq as f64);
+    let mut song = track.unwrap_or(nsf.starting_song).clamp(1, nsf.songs) - 1;
+    let rate = audio_queue.spec().freq as u64;
+    let mut frame = Frame::new();
+    let mut samples = Vec::with_capacity(4096);
+    let mut osd = render::osd::Osd::new();
+    // Samples played of the current song, for its length and fade
+    let mut played: u64 = 0;
+    let mut next_song = Some(song);
+
+    'playing: loop {
+        if let Some(next) = next_song.take() {
+            song = next;
+            audio_queue.clear();
+            player.start(song);
+            played = 0;
+            if let Some(name) = nsf.track(song).name {
+                osd.show(name);
+            }
+        }
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'playing,
+                Event::KeyDown {
+                    keycode: Some(key @ (Keycode::Left | Keycode::Right)),
+                    ..
+                } => {
+                    next_song = Some(if key == Keycode::Right {
+                        (song + 1) % nsf.songs
+                    } else {
+                        (song + nsf.songs - 1) % nsf.songs
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        // Keep about 2048 samples queued; presenting with vsync paces the loop.
+        // Songs with a known length fade out and make way for the next one.
+        let track = nsf.track(song);
+        while next_song.is_none() && audio_queue.size() + (samples.len() * 4) as u32 <= 8192 {
+            if let Some(sample) = player.step() {
+                match track.volume(played * 1000 / rate) {
+                    Some(volume) => samples.push(sample * volume),
+                    None => next_song = Some((song + 1) % nsf.songs),
+                }
+                played += 1;
+            }
+        }
+        let _ = audio_queue.queue_audio(&samples);
+        samples.clear();
+
+        frame.clear();
+        let white = (255, 255, 255);
+        let grey = (160, 160, 160);
+        render::font::draw_text(&mut frame, 16, 24, &nsf.title, white);
+        render::font::draw_text(&mut frame, 16, 40, &nsf.artist, grey);
+        render::font::draw_text(&mut frame, 16, 52, &nsf.copyright, grey);
+        let number = i18n::format(Msg::NsfTrack, &[&(song + 1), &nsf.songs]);
+        render::font::draw_text(&mut frame, 16, 80, &number, white);
+        if let Some(name) = &track.name {
+            render::font::draw_text(&mut frame, 16, 92, name, white);
+        }
+        let mut time = clock(played * 1000 / rate);
+        if let Some(length) = track.length_ms {
+            time = format!("{} / {}", time, clock(length as u64));
+        }
+        render::font::draw_text(&mut frame, 16, 104, &time, grey);
+        render::font::draw_text(&mut frame, 16, 200, i18n::tr(Msg::NsfControls), grey);
+        osd.draw(&mut frame);
+        texture.update(None, &frame.data, 256 * 3).unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+    }
+}
+
+/// `ms` as minutes and seconds, e.g. "2:05".
+fn clock(ms: u64) -> String {
+    format!("{}:{:02}", ms / 60_000, ms / 1000 % 60)
+}
+
+fn verify_movie(rom_path: &str, movie_path: &str) {
+    let rom = Rom::from_path(rom_path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", rom_path, e);
+        std::process::exit(1);
+    });
+    let movie = movie::Movie::load(movie_path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let report = verify::verify_movie(rom, &movie).unwrap_or_else(|e| {
+        eprintln!("{}: {}", movie_path, e);
+        std::process::exit(1);
+    });
+    let (last, periodic) = report.checkpoints.split_last().unwrap();
+    for checkpoint in periodic {
+        println!("{}", checkpoint);
+    }
+    println!("final {}", last);
+}
+
+fn accuracy_report(manifest_path: &Path, out_path: &Path, history_path: &Path) {
+    let fail = |e: String| -> ! {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    };
+    let manifest = std::fs::read_to_string(manifest_path)
+        .unwrap_or_else(|e| fail(format!("{}: {}", manifest_path.display(), e)));
+    let base = manifest_path.parent().unwrap_or(Path::new("."));
+    let tests = accuracy::parse_manifest(&manifest, base)
+        .unwrap_or_else(|e| fail(format!("{}: {}", manifest_path.display(), e)));
+
+    let results = accuracy::run_manifest(&tests);
+    for result in &results {
+        println!("[{}] {}: {}", result.category, result.name, result.outcome);
+    }
+
+    // A missing history starts a new one; an unreadable one is not overwritten
+    let history = match std::fs::read_to_string(history_path) {
+        Ok(text) => Some(
+            json::Value::parse(&text)
+                .unwrap_or_else(|e| fail(format!("{}: {}", history_path.display(), e))),
+        ),
+        Err(_) => None,
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let history = accuracy::add_to_history(history, &results, now);
+
+    std::fs::write(history_path, history.to_string())
+        .unwrap_or_else(|e| fail(format!("{}: {}", history_path.display(), e)));
+    std::fs::write(out_path, accuracy::html_report(&results, &history))
+        .unwrap_or_else(|e| fail(format!("{}: {}", out_path.display(), e)));
+    for (category, passed, total) in accuracy::totals(&results) {
+        println!("{}: {}/{}", category, passed, total);
+    }
+    println!("Report written to {}", out_path.display());
+}
@@ -0,0 +1,66 @@
+use super::{ChrMemory, Mapper, PrgRom};
+use crate::cartridge::{Mirroring, Rom};
+use crate::json::Value;
+use crate::strict::{self, Violation};
+
+/// Mapper 3 (CNROM): fixed PRG like NROM, any write to $8000-$FFFF selects an 8KB CHR bank.
+pub struct Cnrom {
+    prg_rom: PrgRom,
+    chr: ChrMemory,
+    chr_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl Cnrom {
+    pub fn new(mut rom: Rom) -> Self {
+        Cnrom {
+            chr: ChrMemory::for_rom(&mut rom),
+            prg_rom: PrgRom::new(rom.prg_rom),
+            chr_bank: 0,
+            mirroring: rom.screen_mirroring,
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => self.prg_rom.read(0, 0x8000, (addr - 0x8000) as usize),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            self.chr_bank = strict::wrap_bank(
+                data as usize,
+                self.chr.bank_count(0x2000),
+                Violation::ChrBankOutOfRange,
+            );
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank * 0x2000 + addr as usize)
+    }
+
+    fn chr_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_bank * 0x2000 + addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn battery_ram(&self) -> Vec<u8> {
+        self.chr.battery_data().to_vec()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        self.chr.load_battery(data);
+    }
+
+    fn debug_state(&self) -> Vec<(&'static str, Value)> {
+        vec![("chr_bank", Value::from(self.chr_bank))]
+    }
+}
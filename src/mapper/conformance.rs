@@ -0,0 +1,257 @@
+//
+// Conformance tests for every board `from_rom` builds. Each one runs on a tiny
+// `banked_rom`, where every 8KB PRG bank and every 1KB CHR bank is filled with
+// its own number: whatever the CPU or the PPU reads tells which bank is mapped
+// there. Only what a game can observe through the console bus is checked (reads,
+// $2007, nametable mirroring, the IRQ line), so the tests hold whatever the
+// boards look like inside. Bank switching of the boards with tests of their own
+// is left to those.
+//
+
+use crate::bus::Bus;
+use crate::cartridge::test::banked_rom;
+use crate::cartridge::{Mirroring, Rom};
+use crate::cpu::Mem;
+use crate::input::ControllerPorts;
+use crate::ppu::NesPPU;
+
+fn bus_for(rom: Rom) -> Bus<'static> {
+    Bus::new(rom, 44100.0, |_: &NesPPU, _: &mut ControllerPorts| {})
+}
+
+/// Reads PPU memory through $2006/$2007, skipping the buffered first read.
+fn ppu_read(bus: &mut Bus, addr: u16) -> u8 {
+    bus.mem_write(0x2006, (addr >> 8) as u8);
+    bus.mem_write(0x2006, addr as u8);
+    bus.mem_read(0x2007);
+    bus.mem_read(0x2007)
+}
+
+fn ppu_write(bus: &mut Bus, addr: u16, data: u8) {
+    bus.mem_write(0x2006, (addr >> 8) as u8);
+    bus.mem_write(0x2006, addr as u8);
+    bus.mem_write(0x2007, data);
+}
+
+/// Runs the console until `count` more scanlines have started.
+fn run_scanlines(bus: &mut Bus, count: usize) {
+    for _ in 0..count {
+        let scanline = bus.ppu().scanline;
+        while bus.ppu().scanline == scanline {
+            bus.tick(1);
+        }
+    }
+}
+
+#[test]
+fn test_nrom() {
+    let mut bus = bus_for(banked_rom(0, 16, 8, Mirroring::Vertical));
+    // A 16KB chip shows at both halves
+    assert_eq!((bus.mem_read(0x8000), bus.mem_read(0xC000)), (0, 0));
+    assert_eq!(bus.mem_read(0xE000), 1);
+    assert_eq!(ppu_read(&mut bus, 0x1C00), 7);
+    assert_eq!(bus.ppu().mirroring(), Mirroring::Vertical);
+
+    bus.mem_write(0x6123, 0x42);
+    assert_eq!(bus.mem_read(0x6123), 0x42);
+    // ROM ignores writes
+    bus.mem_write(0x8000, 0x42);
+    assert_eq!(bus.mem_read(0x8000), 0);
+}
+
+#[test]
+fn test_uxrom() {
+    let mut bus = bus_for(banked_rom(2, 128, 0, Mirroring::Horizontal));
+    ppu_write(&mut bus, 0x0010, 0x55);
+    assert_eq!(ppu_read(&mut bus, 0x0010), 0x55);
+    assert_eq!(bus.ppu().mirroring(), Mirroring::Horizontal);
+}
+
+#[test]
+fn test_cnrom() {
+    let mut bus = bus_for(banked_rom(3, 32, 32, Mirroring::Vertical));
+    assert_eq!(ppu_read(&mut bus, 0x0000), 0);
+    bus.mem_write(0x8000, 2);
+    assert_eq!(ppu_read(&mut bus, 0x0000), 16);
+    assert_eq!(ppu_read(&mut bus, 0x1FFF), 23);
+    assert_eq!((bus.mem_read(0x8000), bus.mem_read(0xFFFF)), (0, 3));
+}
+
+#[test]
+fn test_mmc3_mirroring() {
+    let mut bus = bus_for(banked_rom(4, 128, 128, Mirroring::Vertical));
+    assert_eq!(bus.ppu().mirroring(), Mirroring::Vertical);
+    bus.mem_write(0xA000, 1);
+    assert_eq!(bus.ppu().mirroring(), Mirroring::Horizontal);
+}
+
+#[test]
+fn test_mmc3_scanline_irq() {
+    let mut bus = bus_for(banked_rom(4, 128, 128, Mirroring::Vertical));
+    bus.mem_write(0xC000, 3);
+    bus.mem_write(0xC001, 0);
+    bus.mem_write(0xE001, 0);
+    bus.mem_write(0x2001, 0x18);
+
+    // The first clock reloads the counter, three more bring it to zero
+    run_scanlines(&mut bus, 3);
+    assert!(!bus.poll_irq_status());
+    run_scanlines(&mut bus, 1);
+    assert!(bus.poll_irq_status());
+    // The line stays asserted until acknowledged
+    run_scanlines(&mut bus, 1);
+    assert!(bus.poll_irq_status());
+    bus.mem_write(0xE000, 0);
+    assert!(!bus.poll_irq_status());
+
+    // Without rendering the counter does not move
+    bus.mem_write(0xE001, 0);
+    bus.mem_write(0x2001, 0);
+    run_scanlines(&mut bus, 8);
+    assert!(!bus.poll_irq_status());
+}
+
+#[test]
+fn test_namco108() {
+    let mut bus = bus_for(banked_rom(206, 128, 64, Mirroring::Vertical));
+    // Hardwired mirroring and no IRQ
+    bus.mem_write(0xA000, 1);
+    assert_eq!(bus.ppu().mirroring(), Mirroring::Vertical);
+    bus.mem_write(0xC000, 0);
+    bus.mem_write(0xC001, 0);
+    bus.mem_write(0xE001, 0);
+    bus.mem_write(0x2001, 0x18);
+    run_scanlines(&mut bus, 4);
+    assert!(!bus.poll_irq_status());
+}
+
+#[test]
+fn test_mmc5_registers() {
+    let mut bus = bus_for(banked_rom(5, 128, 128, Mirroring::Horizontal));
+    bus.mem_write(0x5100, 3);
+
+    // PRG-RAM at $8000 once unprotected
+    bus.mem_write(0x5102, 0b10);
+    bus.mem_write(0x5103, 0b01);
+    bus.mem_write(0x5114, 0x01);
+    bus.mem_write(0x8000, 0x42);
+    assert_eq!(bus.mem_read(0x8000), 0x42);
+    bus.mem_write(0x5113, 0x01);
+    assert_eq!(bus.mem_read(0x6000), 0x42);
+
+    bus.mem_write(0x5205, 7);
+    bus.mem_write(0x5206, 9);
+    assert_eq!((bus.mem_read(0x5205), bus.mem_read(0x5206)), (63, 0));
+
+    // ExRAM as CPU memory in mode 2
+    bus.mem_write(0x5104, 2);
+    bus.mem_write(0x5C10, 0x99);
+    assert_eq!(bus.mem_read(0x5C10), 0x99);
+}
+
+#[test]
+fn test_mmc5_scanline_irq() {
+    let mut bus = bus_for(banked_rom(5, 128, 128, Mirroring::Horizontal));
+    bus.mem_write(0x5203, 2);
+    bus.mem_write(0x5204, 0x80);
+    bus.mem_write(0x2001, 0x18);
+
+    run_scanlines(&mut bus, 2);
+    assert!(!bus.poll_irq_status());
+    run_scanlines(&mut bus, 1);
+    assert!(bus.poll_irq_status());
+    // Peeking leaves the pending flag for the game to see
+    assert_eq!(bus.peek(0x5204) & 0xC0, 0xC0);
+    assert!(bus.poll_irq_status());
+    assert_eq!(bus.mem_read(0x5204) & 0xC0, 0xC0);
+    assert!(!bus.poll_irq_status());
+}
+
+#[test]
+fn test_nanjing_fs304() {
+    let mut bus = bus_for(banked_rom(162, 128, 0, Mirroring::Vertical));
+    bus.mem_write(0x6000, 0x42);
+    assert_eq!(bus.mem_read(0x6000), 0x42);
+}
+
+#[test]
+fn test_nanjing_fc001() {
+    let mut bus = bus_for(banked_rom(163, 128, 0, Mirroring::Vertical));
+    // The protection trigger flips when $5101 goes from nonzero to zero, and
+    // $5500 shows the registers or nothing
+    bus.mem_write(0x5000, 2);
+    let before = bus.mem_read(0x5500);
+    bus.mem_write(0x5101, 1);
+    bus.mem_write(0x5101, 0);
+    assert_ne!(bus.mem_read(0x5500), before);
+
+    ppu_write(&mut bus, 0x1000, 0x77);
+    assert_eq!(ppu_read(&mut bus, 0x1000), 0x77);
+}
+
+#[test]
+fn test_vrc7_banks_and_mirroring() {
+    let mut bus = bus_for(banked_rom(85, 128, 128, Mirroring::Vertical));
+    assert_eq!(bus.mem_read(0xE000), 15);
+    // VRC7a pairs registers on A4, VRC7b on A3
+    bus.mem_write(0x8000, 3);
+    bus.mem_write(0x8010, 4);
+    bus.mem_write(0x9000, 5);
+    assert_eq!(
+        [0x8000, 0xA000, 0xC000].map(|addr| bus.mem_read(addr)),
+        [3, 4, 5]
+    );
+    bus.mem_write(0x8008, 6);
+    assert_eq!(bus.mem_read(0xA000), 6);
+
+    bus.mem_write(0xA000, 0x20);
+    bus.mem_write(0xD010, 0x27);
+    assert_eq!(ppu_read(&mut bus, 0x0000), 0x20);
+    assert_eq!(ppu_read(&mut bus, 0x1C00), 0x27);
+
+    // Work RAM only answers while enabled
+    bus.mem_write(0x6000, 0x42);
+    assert_eq!(bus.mem_read(0x6000), 0);
+    bus.mem_write(0xE000, 0x80);
+    bus.mem_write(0x6000, 0x42);
+    assert_eq!(bus.mem_read(0x6000), 0x42);
+
+    assert_eq!(bus.ppu().mirroring(), Mirroring::Vertical);
+    bus.mem_write(0xE000, 0x81);
+    assert_eq!(bus.ppu().mirroring(), Mirroring::Horizontal);
+    // One-screen: every nametable shows the same page
+    bus.mem_write(0xE000, 0x83);
+    ppu_write(&mut bus, 0x2000, 0x55);
+    assert_eq!(ppu_read(&mut bus, 0x2C00), 0x55);
+    bus.mem_write(0xE000, 0x82);
+    assert_ne!(ppu_read(&mut bus, 0x2400), 0x55);
+}
+
+#[test]
+fn test_vrc7_irq() {
+    let mut bus = bus_for(banked_rom(85, 128, 128, Mirroring::Vertical));
+    // Cycle mode: the counter overflows 0x100 - latch cycles after enabling
+    bus.mem_write(0xE010, 0xF0);
+    bus.mem_write(0xF000, 0x07);
+    bus.tick(15);
+    assert!(!bus.poll_irq_status());
+    bus.tick(1);
+    assert!(bus.poll_irq_status());
+    // Acknowledging keeps it counting, as bit 0 asked
+    bus.mem_write(0xF010, 0);
+    assert!(!bus.poll_irq_status());
+    bus.tick(16);
+    assert!(bus.poll_irq_status());
+
+    // Scanline mode: one clock per 341 dots
+    bus.mem_write(0xE010, 0xFE);
+    bus.mem_write(0xF000, 0x02);
+    bus.tick(227);
+    assert!(!bus.poll_irq_status());
+    bus.tick(1);
+    assert!(bus.poll_irq_status());
+    // Without bit 0, acknowledging stops the counter
+    bus.mem_write(0xF010, 0);
+    bus.tick(255);
+    assert!(!bus.poll_irq_status());
+}
@@ -0,0 +1,328 @@
+use super::{ChrMemory, Mapper, PrgRam};
+use crate::cartridge::{Mirroring, Rom};
+use crate::json::Value;
+use crate::strict::{self, Violation};
+
+/// The boards built around the MMC3 bank registers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Board {
+    /// Mapper 4 (MMC3): the full chip with PRG-RAM, mirroring control and the scanline IRQ
+    Mmc3,
+    /// Mapper 206 (Namco 108 / DxROM): the bank registers alone, at $8000-$9FFF
+    Namco108,
+}
+
+/// Mappers 4 and 206: four 8KB PRG windows (two switchable) and eight 1KB CHR
+/// windows (two 2KB banks plus four 1KB banks), selected by writing a register
+/// number to $8000 and its bank to $8001.
+///
+/// The Namco 108 is the chip the MMC3 was derived from: same eight bank registers,
+/// but no PRG/CHR mode bits, hardwired mirroring, no work RAM and no IRQ. Its bank
+/// lines are also narrower (64KB of CHR, 128KB of PRG).
+pub struct Mmc3 {
+    board: Board,
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+
+    // $8000
+    bank_select: u8,
+    // R0-R7, written through $8001
+    banks: [u8; 8],
+    // $A001: bit 7 enables the RAM, bit 6 protects it from writes
+    prg_ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_flag: bool,
+}
+
+impl Mmc3 {
+    pub fn new(mut rom: Rom, board: Board) -> Self {
+        Mmc3 {
+            board,
+            prg_ram: match board {
+                Board::Mmc3 => PrgRam::for_rom(&rom),
+                Board::Namco108 => PrgRam::new(0),
+            },
+            chr: ChrMemory::for_rom(&mut rom),
+            prg_rom: rom.prg_rom,
+            mirroring: rom.screen_mirroring,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            prg_ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_flag: false,
+        }
+    }
+
+    fn prg_mode_swapped(&self) -> bool {
+        self.board == Board::Mmc3 && self.bank_select & 0x40 != 0
+    }
+
+    fn chr_mode_inverted(&self) -> bool {
+        self.board == Board::Mmc3 && self.bank_select & 0x80 != 0
+    }
+
+    /// Offset in PRG-ROM for a CPU address in $8000-$FFFF.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank_count = (self.prg_rom.len() / 0x2000).max(1);
+        let second_last = bank_count.saturating_sub(2);
+        let r6 = self.banks[6] as usize;
+        let r7 = self.banks[7] as usize;
+        let bank = match ((addr - 0x8000) / 0x2000, self.prg_mode_swapped()) {
+            (0, false) | (2, true) => r6,
+            (0, true) | (2, false) => second_last,
+            (1, _) => r7,
+            _ => bank_count - 1,
+        };
+        strict::wrap_bank(bank, bank_count, Violation::PrgBankOutOfRange) * 0x2000
+            + (addr as usize & 0x1FFF)
+    }
+
+    /// Offset in CHR memory for a PPU address in $0000-$1FFF.
+    fn chr_offset(&self, addr: u16) -> usize {
+        // With the mode bit set, the 2KB banks move to $1000 and the 1KB banks to $0000
+        let addr = if self.chr_mode_inverted() {
+            addr ^ 0x1000
+        } else {
+            addr
+        } as usize;
+        let bank = match addr / 0x400 {
+            0 => self.banks[0] & 0xFE,
+            1 => self.banks[0] | 0x01,
+            2 => self.banks[1] & 0xFE,
+            3 => self.banks[1] | 0x01,
+            slot => self.banks[slot - 2],
+        } as usize;
+        let bank_count = self.chr.bank_count(0x400);
+        strict::wrap_bank(bank, bank_count, Violation::ChrBankOutOfRange) * 0x400 + (addr & 0x3FF)
+    }
+
+    fn write_bank_data(&mut self, data: u8) {
+        let register = (self.bank_select & 0x07) as usize;
+        self.banks[register] = match (self.board, register) {
+            (Board::Namco108, 0..=5) => data & 0x3F,
+            (Board::Namco108, _) => data & 0x0F,
+            (Board::Mmc3, _) => data,
+        };
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_protect & 0x80 != 0 => {
+                self.prg_ram.read((addr - 0x6000) as usize)
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if self.board == Board::Namco108 {
+            match addr {
+                0x8000..=0x9FFF if addr & 1 == 0 => self.bank_select = data & 0x07,
+                0x8000..=0x9FFF => self.write_bank_data(data),
+                _ => {}
+            }
+            return;
+        }
+
+        match (addr, addr & 1) {
+            (0x6000..=0x7FFF, _) if self.prg_ram_protect & 0xC0 == 0x80 => {
+                self.prg_ram.write((addr - 0x6000) as usize, data);
+            }
+            (0x8000..=0x9FFF, 0) => self.bank_select = data,
+            (0x8000..=0x9FFF, _) => self.write_bank_data(data),
+            // Four-screen boards wire the nametables themselves
+            (0xA000..=0xBFFF, 0) if self.mirroring != Mirroring::FourScreen => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            (0xA000..=0xBFFF, 0) => {}
+            (0xA000..=0xBFFF, _) => self.prg_ram_protect = data,
+            (0xC000..=0xDFFF, 0) => self.irq_latch = data,
+            (0xC000..=0xDFFF, _) => self.irq_reload = true,
+            (0xE000..=0xFFFF, 0) => {
+                self.irq_enabled = false;
+                self.irq_flag = false;
+            }
+            (0xE000..=0xFFFF, _) => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn chr_write(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    /// The real chip clocks its counter on PPU A12 rising edges, once per scanline
+    /// with the usual background-at-$0000 / sprites-at-$1000 setup; the scanline
+    /// hook stands in for that on the visible and pre-render lines.
+    fn scanline(&mut self, scanline: u16, rendering_enabled: bool) {
+        if self.board != Board::Mmc3 || !rendering_enabled || (240..261).contains(&scanline) {
+            return;
+        }
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_flag = true;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn battery_ram(&self) -> Vec<u8> {
+        [self.prg_ram.battery_data(), self.chr.battery_data()].concat()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let used = self.prg_ram.load_battery(data);
+        self.chr.load_battery(&data[used..]);
+    }
+
+    fn debug_state(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("bank_select", Value::from(self.bank_select)),
+            ("banks", Value::from(&self.banks[..])),
+            ("prg_ram_protect", Value::from(self.prg_ram_protect)),
+            ("irq_latch", Value::from(self.irq_latch)),
+            ("irq_counter", Value::from(self.irq_counter)),
+            ("irq_reload", Value::from(self.irq_reload)),
+            ("irq_enabled", Value::from(self.irq_enabled)),
+            ("irq_flag", Value::from(self.irq_flag)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::banked_rom;
+
+    fn mmc3_rom(mapper: u8) -> Rom {
+        banked_rom(mapper, 128, 64, Mirroring::Vertical)
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc3 = Mmc3::new(mmc3_rom(4), Board::Mmc3);
+        mmc3.cpu_write(0x8000, 6);
+        mmc3.cpu_write(0x8001, 3);
+        mmc3.cpu_write(0x8000, 7);
+        mmc3.cpu_write(0x8001, 5);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mmc3.cpu_read(a)),
+            [3, 5, 14, 15]
+        );
+
+        mmc3.cpu_write(0x8000, 0x40 | 7);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mmc3.cpu_read(a)),
+            [14, 5, 3, 15]
+        );
+    }
+
+    #[test]
+    fn test_chr_banks_and_inversion() {
+        let mut mmc3 = Mmc3::new(mmc3_rom(4), Board::Mmc3);
+        for (register, bank) in [(0, 9), (1, 20), (2, 30), (5, 33)] {
+            mmc3.cpu_write(0x8000, register);
+            mmc3.cpu_write(0x8001, bank);
+        }
+        // R0 is a 2KB bank: the low bit is ignored
+        assert_eq!(mmc3.chr_read(0x0000), 8);
+        assert_eq!(mmc3.chr_read(0x0400), 9);
+        assert_eq!(mmc3.chr_read(0x0800), 20);
+        assert_eq!(mmc3.chr_read(0x1000), 30);
+        assert_eq!(mmc3.chr_read(0x1C00), 33);
+
+        mmc3.cpu_write(0x8000, 0x80);
+        assert_eq!(mmc3.chr_read(0x0000), 30);
+        assert_eq!(mmc3.chr_read(0x1000), 8);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc3 = Mmc3::new(mmc3_rom(4), Board::Mmc3);
+        mmc3.cpu_write(0xC000, 2);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+
+        mmc3.scanline(0, true); // reload to 2
+        mmc3.scanline(1, true);
+        assert!(!mmc3.irq_pending());
+        mmc3.scanline(2, true);
+        assert!(mmc3.irq_pending());
+
+        mmc3.cpu_write(0xE000, 0);
+        assert!(!mmc3.irq_pending());
+        // Vblank lines don't count
+        mmc3.scanline(241, true);
+        assert_eq!(mmc3.irq_counter, 0);
+    }
+
+    #[test]
+    fn test_mirroring_and_prg_ram_protect() {
+        let mut mmc3 = Mmc3::new(mmc3_rom(4), Board::Mmc3);
+        mmc3.cpu_write(0xA000, 1);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+
+        mmc3.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc3.cpu_read(0x6000), 0x42);
+        mmc3.cpu_write(0xA001, 0xC0);
+        mmc3.cpu_write(0x6000, 0x43);
+        assert_eq!(mmc3.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_namco_108_subset() {
+        let mut namco = Mmc3::new(mmc3_rom(206), Board::Namco108);
+        // Mode bits are ignored and the registers only answer in $8000-$9FFF
+        namco.cpu_write(0x8000, 0xC6);
+        namco.cpu_write(0x8001, 0x13);
+        namco.cpu_write(0xA000, 1);
+        namco.cpu_write(0xC001, 0);
+        namco.cpu_write(0xE001, 0);
+        assert_eq!(namco.cpu_read(0x8000), 3);
+        assert_eq!(namco.cpu_read(0xC000), 14);
+        assert_eq!(namco.mirroring(), Mirroring::Vertical);
+
+        namco.cpu_write(0x9FFE, 2);
+        namco.cpu_write(0x9FFF, 0x45);
+        assert_eq!(namco.chr_read(0x1000), 5);
+
+        namco.cpu_write(0x6000, 0x42);
+        assert_eq!(namco.cpu_read(0x6000), 0);
+        for line in 0..240 {
+            namco.scanline(line, true);
+        }
+        assert!(!namco.irq_pending());
+    }
+}
@@ -0,0 +1,546 @@
+//
+// Mapper 5 (MMC5, ExROM boards): Nintendo's most complex mapper, used by Castlevania III,
+// Just Breed, Uncharted Waters... It provides four PRG banking modes with ROM/RAM
+// selection, two CHR bank sets (sprites / background in 8x16 sprite mode), 1KB of
+// extra RAM (ExRAM) usable as a nametable, a "fill mode" nametable, a scanline IRQ
+// counter, an 8x8 multiplier and two extra pulse channels plus a PCM channel.
+//
+
+use super::{ChrMemory, Mapper, PrgRam};
+use crate::cartridge::{Mirroring, Rom};
+use crate::expansion::{ExpansionAudio, ExpansionRef};
+use crate::json::Value;
+use crate::strict::{self, Violation};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Where a CPU address in $6000-$FFFF ends up.
+enum PrgTarget {
+    Rom(usize),
+    Ram(usize),
+}
+
+/// Register file of the MMC5 expansion audio ($5000-$5015).
+/// Only stored for now: the channels are not synthesized or mixed yet.
+#[derive(Default)]
+pub struct Mmc5Audio {
+    registers: [u8; 0x16],
+}
+
+impl Mmc5Audio {
+    fn write(&mut self, addr: u16, data: u8) {
+        self.registers[(addr - 0x5000) as usize] = data;
+    }
+}
+
+impl ExpansionAudio for Mmc5Audio {
+    fn name(&self) -> &'static str {
+        "mmc5"
+    }
+
+    /// Silent until the channels are implemented.
+    fn output(&self) -> f32 {
+        0.0
+    }
+}
+
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: ChrMemory,
+    exram: [u8; 1024],
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+
+    prg_ram_bank: u8,
+    prg_banks: [u8; 4],
+    chr_banks_a: [usize; 8],
+    chr_banks_b: [usize; 4],
+    chr_upper_bits: usize,
+    last_chr_set_b: bool,
+
+    // State snooped from the PPU registers
+    sprite_8x16: bool,
+    rendering_enabled: bool,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_flag: bool,
+    in_frame: bool,
+    scanline_counter: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    pub audio: Rc<RefCell<Mmc5Audio>>,
+}
+
+impl Mmc5 {
+    pub fn new(mut rom: Rom) -> Self {
+        Mmc5 {
+            prg_ram: PrgRam::for_rom(&rom),
+            chr: ChrMemory::for_rom(&mut rom),
+            prg_rom: rom.prg_rom,
+            exram: [0; 1024],
+            prg_mode: 3,
+            chr_mode: 3,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_ram_bank: 0,
+            // $5117 powers on as $FF so the reset vector is in the last bank
+            prg_banks: [0xFF; 4],
+            chr_banks_a: [0; 8],
+            chr_banks_b: [0; 4],
+            chr_upper_bits: 0,
+            last_chr_set_b: false,
+            sprite_8x16: false,
+            rendering_enabled: false,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_flag: false,
+            in_frame: false,
+            scanline_counter: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            audio: Rc::new(RefCell::new(Mmc5Audio::default())),
+        }
+    }
+
+    /// Resolves $6000-$FFFF according to the current PRG mode.
+    ///
+    /// Bank registers count 8KB units; 16KB and 32KB windows ignore their low bits.
+    /// Bit 7 of $5114-$5116 selects ROM (1) or RAM (0), $5117 is always ROM.
+    fn map_prg(&self, addr: u16) -> PrgTarget {
+        if addr < 0x8000 {
+            let bank = (self.prg_ram_bank & 0x07) as usize;
+            return PrgTarget::Ram(bank * 0x2000 + (addr & 0x1FFF) as usize);
+        }
+
+        let (register, window) = match (self.prg_mode, addr) {
+            (0, _) => (3, 0x8000),
+            (1, 0x8000..=0xBFFF) => (1, 0x4000),
+            (1, _) => (3, 0x4000),
+            (2, 0x8000..=0xBFFF) => (1, 0x4000),
+            (2, 0xC000..=0xDFFF) => (2, 0x2000),
+            (2, _) => (3, 0x2000),
+            (_, _) => (((addr - 0x8000) / 0x2000) as usize, 0x2000),
+        };
+
+        let value = self.prg_banks[register];
+        let is_rom = register == 3 || value & 0x80 != 0;
+        let bank_base = if is_rom {
+            (value & 0x7F) as usize * 0x2000
+        } else {
+            (value & 0x07) as usize * 0x2000
+        };
+        let offset = (bank_base & !(window - 1)) + (addr as usize & (window - 1));
+
+        if is_rom {
+            PrgTarget::Rom(offset)
+        } else {
+            PrgTarget::Ram(offset)
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+
+    /// Offset into CHR memory through bank set A (sprites) or B (background in 8x16 mode).
+    fn chr_offset(&self, addr: u16, use_set_b: bool) -> usize {
+        let addr = addr as usize & 0x1FFF;
+        let (bank, size) = if use_set_b {
+            match self.chr_mode {
+                0 => (self.chr_banks_b[3], 0x2000),
+                1 => (self.chr_banks_b[3], 0x1000),
+                2 => (self.chr_banks_b[(addr / 0x800) % 2 * 2 + 1], 0x800),
+                _ => (self.chr_banks_b[(addr / 0x400) % 4], 0x400),
+            }
+        } else {
+            match self.chr_mode {
+                0 => (self.chr_banks_a[7], 0x2000),
+                1 => (self.chr_banks_a[addr / 0x1000 * 4 + 3], 0x1000),
+                2 => (self.chr_banks_a[addr / 0x800 * 2 + 1], 0x800),
+                _ => (self.chr_banks_a[addr / 0x400], 0x400),
+            }
+        };
+        bank * size + addr % size
+    }
+
+    /// In 8x16 sprite mode the background always uses set B; in 8x8 mode both
+    /// sprites and background use whichever set was written last.
+    fn background_uses_set_b(&self) -> bool {
+        if self.sprite_8x16 {
+            true
+        } else {
+            self.last_chr_set_b
+        }
+    }
+
+    fn sprite_uses_set_b(&self) -> bool {
+        if self.sprite_8x16 {
+            false
+        } else {
+            self.last_chr_set_b
+        }
+    }
+
+    /// Source selected by $5105 for one of the four logical nametables.
+    fn nametable_source(&self, addr: u16) -> (u8, usize) {
+        let offset = (addr & 0x0FFF) as usize;
+        let table = offset / 0x400;
+        let source = (self.nametable_mapping >> (table * 2)) & 0b11;
+        (source, offset % 0x400)
+    }
+
+    fn read_register(&mut self, addr: u16) -> u8 {
+        let data = self.peek_register(addr);
+        // Reading the status acknowledges the IRQ
+        if addr == 0x5204 {
+            self.irq_flag = false;
+        }
+        data
+    }
+
+    fn peek_register(&self, addr: u16) -> u8 {
+        match addr {
+            0x5204 => {
+                let mut status = 0;
+                if self.irq_flag {
+                    status |= 0x80;
+                }
+                if self.in_frame {
+                    status |= 0x40;
+                }
+                status
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[(addr - 0x5C00) as usize],
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5015 => self.audio.borrow_mut().write(addr, data),
+            0x5100 => self.prg_mode = data & 0b11,
+            0x5101 => self.chr_mode = data & 0b11,
+            0x5102 => self.prg_ram_protect[0] = data & 0b11,
+            0x5103 => self.prg_ram_protect[1] = data & 0b11,
+            0x5104 => self.exram_mode = data & 0b11,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0b11,
+            0x5113 => self.prg_ram_bank = data,
+            0x5114..=0x5117 => self.prg_banks[(addr - 0x5114) as usize] = data,
+            0x5120..=0x5127 => {
+                self.chr_banks_a[(addr - 0x5120) as usize] = self.chr_upper_bits | data as usize;
+                self.last_chr_set_b = false;
+            }
+            0x5128..=0x512B => {
+                self.chr_banks_b[(addr - 0x5128) as usize] = self.chr_upper_bits | data as usize;
+                self.last_chr_set_b = true;
+            }
+            0x5130 => self.chr_upper_bits = ((data & 0b11) as usize) << 8,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            // Vertical split mode ($5200-$5202) is not emulated.
+            0x5C00..=0x5FFF if self.exram_mode != 3 => {
+                self.exram[(addr - 0x5C00) as usize] = data;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x5000..=0x5FFF => self.read_register(addr),
+            0x6000..=0xFFFF => match self.map_prg(addr) {
+                PrgTarget::Rom(offset) => {
+                    self.prg_rom[strict::wrap_offset(
+                        offset,
+                        self.prg_rom.len(),
+                        Violation::PrgBankOutOfRange,
+                    )]
+                }
+                PrgTarget::Ram(offset) => self.prg_ram.read(offset),
+            },
+            _ => 0,
+        }
+    }
+
+    fn cpu_peek(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x5000..=0x5FFF => self.peek_register(addr),
+            _ => self.cpu_read(addr),
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5FFF => self.write_register(addr, data),
+            0x6000..=0xFFFF => {
+                if let PrgTarget::Ram(offset) = self.map_prg(addr) {
+                    if self.prg_ram_writable() {
+                        self.prg_ram.write(offset, data);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.chr
+            .read(self.chr_offset(addr, self.background_uses_set_b()))
+    }
+
+    fn chr_write(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr, self.background_uses_set_b());
+        self.chr.write(offset, data);
+    }
+
+    fn sprite_chr_read(&mut self, addr: u16) -> u8 {
+        self.chr
+            .read(self.chr_offset(addr, self.sprite_uses_set_b()))
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        // Nametables are fully routed through `nametable_read`/`nametable_write`.
+        Mirroring::Vertical
+    }
+
+    fn battery_ram(&self) -> Vec<u8> {
+        [self.prg_ram.battery_data(), self.chr.battery_data()].concat()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let used = self.prg_ram.load_battery(data);
+        self.chr.load_battery(&data[used..]);
+    }
+
+    fn nametable_read(&mut self, addr: u16, ciram: &[u8]) -> Option<u8> {
+        let (source, offset) = self.nametable_source(addr);
+        Some(match source {
+            0 => ciram[offset],
+            1 => ciram[0x400 + offset],
+            2 if self.exram_mode <= 1 => self.exram[offset],
+            2 => 0,
+            _ if offset < 0x3C0 => self.fill_tile,
+            // The fill attribute is replicated into all four quadrants of the byte
+            _ => self.fill_attribute * 0b0101_0101,
+        })
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8, ciram: &mut [u8]) -> bool {
+        let (source, offset) = self.nametable_source(addr);
+        match source {
+            0 => ciram[offset] = data,
+            1 => ciram[0x400 + offset] = data,
+            2 if self.exram_mode <= 1 => self.exram[offset] = data,
+            _ => {}
+        }
+        true
+    }
+
+    fn ppu_register_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x2000 => self.sprite_8x16 = data & 0x20 != 0,
+            0x2001 => {
+                self.rendering_enabled = data & 0x18 != 0;
+                if !self.rendering_enabled {
+                    self.in_frame = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The real chip detects scanlines by watching PPU nametable fetches; hooking
+    /// the PPU's scanline boundary gives the same counter behavior.
+    fn scanline(&mut self, scanline: u16, rendering_enabled: bool) {
+        if scanline >= 240 || !rendering_enabled {
+            self.in_frame = false;
+            return;
+        }
+
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline_counter = 0;
+            self.irq_flag = false;
+        } else {
+            self.scanline_counter = self.scanline_counter.wrapping_add(1);
+            if self.scanline_counter == self.irq_compare {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_flag && self.irq_enabled
+    }
+
+    fn expansion_audio(&self) -> Vec<ExpansionRef> {
+        vec![self.audio.clone()]
+    }
+
+    fn debug_state(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("prg_mode", Value::from(self.prg_mode)),
+            ("chr_mode", Value::from(self.chr_mode)),
+            ("exram_mode", Value::from(self.exram_mode)),
+            ("nametable_mapping", Value::from(self.nametable_mapping)),
+            ("prg_ram_bank", Value::from(self.prg_ram_bank)),
+            ("prg_banks", Value::from(&self.prg_banks[..])),
+            ("chr_banks_a", Value::from(&self.chr_banks_a[..])),
+            ("chr_banks_b", Value::from(&self.chr_banks_b[..])),
+            ("chr_upper_bits", Value::from(self.chr_upper_bits)),
+            ("irq_compare", Value::from(self.irq_compare)),
+            ("irq_enabled", Value::from(self.irq_enabled)),
+            ("irq_flag", Value::from(self.irq_flag)),
+            ("in_frame", Value::from(self.in_frame)),
+            ("scanline_counter", Value::from(self.scanline_counter)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::banked_rom;
+
+    fn mmc5_rom() -> Rom {
+        Rom {
+            prg_ram_size: 0x8000,
+            ..banked_rom(5, 64, 16, Mirroring::Horizontal)
+        }
+    }
+
+    #[test]
+    fn test_prg_mode_3_banks() {
+        let mut mmc5 = Mmc5::new(mmc5_rom());
+        assert_eq!(mmc5.cpu_read(0xFFFC), 7);
+
+        mmc5.cpu_write(0x5114, 0x82);
+        mmc5.cpu_write(0x5115, 0x85);
+        assert_eq!(mmc5.cpu_read(0x8000), 2);
+        assert_eq!(mmc5.cpu_read(0xA000), 5);
+    }
+
+    #[test]
+    fn test_prg_mode_1_ignores_low_bit() {
+        let mut mmc5 = Mmc5::new(mmc5_rom());
+        mmc5.cpu_write(0x5100, 1);
+        mmc5.cpu_write(0x5115, 0x83);
+        assert_eq!(mmc5.cpu_read(0x8000), 2);
+        assert_eq!(mmc5.cpu_read(0xA000), 3);
+        assert_eq!(mmc5.cpu_read(0xC000), 6);
+    }
+
+    #[test]
+    fn test_prg_ram_32k_banks() {
+        let mut mmc5 = Mmc5::new(mmc5_rom());
+        mmc5.cpu_write(0x5102, 0b10);
+        mmc5.cpu_write(0x5103, 0b01);
+
+        for bank in 0..4 {
+            mmc5.cpu_write(0x5113, bank);
+            mmc5.cpu_write(0x6000, 0x10 + bank);
+        }
+        for bank in 0..4 {
+            mmc5.cpu_write(0x5113, bank);
+            assert_eq!(mmc5.cpu_read(0x6000), 0x10 + bank);
+        }
+    }
+
+    #[test]
+    fn test_prg_ram_write_protect() {
+        let mut mmc5 = Mmc5::new(mmc5_rom());
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_read(0x6000), 0);
+
+        mmc5.cpu_write(0x5102, 0b10);
+        mmc5.cpu_write(0x5103, 0b01);
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_chr_sets_in_8x16_mode() {
+        let mut mmc5 = Mmc5::new(mmc5_rom());
+        mmc5.cpu_write(0x5101, 3);
+        mmc5.cpu_write(0x5120, 4);
+        mmc5.cpu_write(0x5128, 9);
+        mmc5.ppu_register_write(0x2000, 0x20);
+
+        assert_eq!(mmc5.sprite_chr_read(0x0000), 4);
+        assert_eq!(mmc5.chr_read(0x0000), 9);
+    }
+
+    #[test]
+    fn test_fill_mode_nametable() {
+        let mut mmc5 = Mmc5::new(mmc5_rom());
+        let ciram = [0u8; 2048];
+        mmc5.cpu_write(0x5105, 0xFF);
+        mmc5.cpu_write(0x5106, 0x24);
+        mmc5.cpu_write(0x5107, 0x02);
+
+        assert_eq!(mmc5.nametable_read(0x2000, &ciram), Some(0x24));
+        assert_eq!(mmc5.nametable_read(0x23C0, &ciram), Some(0xAA));
+    }
+
+    #[test]
+    fn test_exram_as_nametable() {
+        let mut mmc5 = Mmc5::new(mmc5_rom());
+        let mut ciram = [0u8; 2048];
+        mmc5.cpu_write(0x5105, 0b10_00_01_00);
+        mmc5.cpu_write(0x5C05, 0x77);
+        mmc5.nametable_write(0x2405, 0x66, &mut ciram);
+
+        assert_eq!(ciram[0x405], 0x66);
+        assert_eq!(mmc5.nametable_read(0x2C05, &ciram), Some(0x77));
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc5 = Mmc5::new(mmc5_rom());
+        mmc5.cpu_write(0x5203, 3);
+        mmc5.cpu_write(0x5204, 0x80);
+
+        for line in 0..3 {
+            mmc5.scanline(line, true);
+            assert!(!mmc5.irq_pending());
+        }
+        mmc5.scanline(3, true);
+        assert!(mmc5.irq_pending());
+
+        assert_eq!(mmc5.cpu_read(0x5204), 0xC0);
+        assert!(!mmc5.irq_pending());
+
+        mmc5.scanline(240, true);
+        assert_eq!(mmc5.cpu_read(0x5204), 0x00);
+    }
+
+    #[test]
+    fn test_multiplier() {
+        let mut mmc5 = Mmc5::new(mmc5_rom());
+        mmc5.cpu_write(0x5205, 200);
+        mmc5.cpu_write(0x5206, 100);
+        assert_eq!(mmc5.cpu_read(0x5205), (20000u16 & 0xFF) as u8);
+        assert_eq!(mmc5.cpu_read(0x5206), (20000u16 >> 8) as u8);
+    }
+}
@@ -0,0 +1,387 @@
+//
+// Cartridge boards ("mappers") sit between the ROM chips and the CPU/PPU buses.
+// They decide which PRG bank the CPU sees at $8000-$FFFF, which CHR bank the PPU
+// sees at $0000-$1FFF, and on the larger boards they can also take over the
+// nametables and raise IRQs.
+//
+
+use crate::cartridge::{Mirroring, Rom};
+use crate::expansion::ExpansionRef;
+use crate::json::Value;
+use crate::strict::{self, Violation};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub mod cnrom;
+#[cfg(test)]
+mod conformance;
+pub mod mmc3;
+pub mod mmc5;
+pub mod nanjing;
+pub mod nrom;
+pub mod nsf;
+pub mod opll;
+pub mod uxrom;
+pub mod vrc7;
+
+/// Size of the work RAM mapped at $6000-$7FFF.
+pub const PRG_RAM_SIZE: usize = 8192;
+/// Largest PRG-RAM an MMC5 board can address (two 32KB chips).
+pub const MMC5_PRG_RAM_SIZE: usize = 65536;
+/// Size of the CHR-RAM given to boards that ship without CHR-ROM.
+pub const CHR_RAM_SIZE: usize = 8192;
+
+/// Shared handle: the CPU bus and the PPU both talk to the same cartridge.
+pub type MapperRef = Rc<RefCell<dyn Mapper>>;
+
+/// Common interface for all cartridge boards.
+pub trait Mapper {
+    /// CPU read in cartridge space ($4020-$FFFF).
+    fn cpu_read(&mut self, addr: u16) -> u8;
+    /// What `cpu_read` would return, for debuggers. Boards whose registers change
+    /// when read (IRQ acknowledge) override this to leave them alone.
+    fn cpu_peek(&mut self, addr: u16) -> u8 {
+        self.cpu_read(addr)
+    }
+    /// CPU write in cartridge space ($4020-$FFFF), usually a bank register.
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    /// PPU read in pattern table space ($0000-$1FFF) for background fetches and $2007.
+    fn chr_read(&mut self, addr: u16) -> u8;
+    /// PPU write in pattern table space, only meaningful with CHR-RAM.
+    fn chr_write(&mut self, addr: u16, data: u8);
+
+    /// Pattern fetch for sprites. Boards with a separate sprite bank set (MMC5) override this.
+    fn sprite_chr_read(&mut self, addr: u16) -> u8 {
+        self.chr_read(addr)
+    }
+
+    /// Nametable arrangement the board wires up with the console's 2KB of CIRAM.
+    fn mirroring(&self) -> Mirroring;
+
+    /// Lets the board serve a nametable read ($2000-$2FFF) itself.
+    /// `ciram` is the console VRAM, for boards that remap it freely.
+    /// Returning `None` falls back to the regular mirroring.
+    fn nametable_read(&mut self, _addr: u16, _ciram: &[u8]) -> Option<u8> {
+        None
+    }
+
+    /// Nametable write counterpart of `nametable_read`; returns true if the board handled it.
+    fn nametable_write(&mut self, _addr: u16, _data: u8, _ciram: &mut [u8]) -> bool {
+        false
+    }
+
+    /// Snoops CPU writes to the PPU registers ($2000-$2007).
+    fn ppu_register_write(&mut self, _addr: u16, _data: u8) {}
+
+    /// Called by the PPU at the start of every scanline (0-261, 0-311 on PAL).
+    /// `rendering_enabled` is set when the PPU fetches tiles on the line: rendering
+    /// on, and a visible or pre-render line.
+    fn scanline(&mut self, _scanline: u16, _rendering_enabled: bool) {}
+
+    /// Called after the CPU ran `cycles` cycles, for counters clocked by M2 (the
+    /// VRC IRQ).
+    fn cpu_cycles(&mut self, _cycles: u8) {}
+
+    /// The cartridge's own sound chips, for the APU to run and mix with its
+    /// channels. Boards without any stay silent.
+    fn expansion_audio(&self) -> Vec<ExpansionRef> {
+        vec![]
+    }
+
+    /// Level of the cartridge IRQ line.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// Battery-backed memory in save file order: PRG-NVRAM, then CHR-NVRAM.
+    /// Empty for cartridges without a battery.
+    fn battery_ram(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    /// Restores memory previously returned by `battery_ram`.
+    fn load_battery_ram(&mut self, _data: &[u8]) {}
+
+    /// Bank registers and other board state, as named fields for debug dumps.
+    /// Boards without any registers have nothing to show.
+    fn debug_state(&self) -> Vec<(&'static str, Value)> {
+        vec![]
+    }
+}
+
+/// Returns true if `from_rom` knows how to build this board.
+pub fn is_supported(mapper: u8) -> bool {
+    matches!(mapper, 0 | 2 | 3 | 4 | 5 | 85 | 162 | 163 | 206)
+}
+
+/// PRG-RAM to allocate for iNES 1.0 images, whose header does not say.
+pub fn default_prg_ram_size(mapper: u8) -> usize {
+    match mapper {
+        5 => MMC5_PRG_RAM_SIZE,
+        _ => PRG_RAM_SIZE,
+    }
+}
+
+/// Builds the board matching the iNES mapper number of `rom`.
+///
+/// `Rom::new` already rejects unknown mappers, so the panic is only reachable
+/// for hand-built `Rom` values.
+pub fn from_rom(rom: Rom) -> MapperRef {
+    match rom.mapper {
+        0 => Rc::new(RefCell::new(nrom::Nrom::new(rom))),
+        2 => Rc::new(RefCell::new(uxrom::Uxrom::new(rom))),
+        3 => Rc::new(RefCell::new(cnrom::Cnrom::new(rom))),
+        4 => Rc::new(RefCell::new(mmc3::Mmc3::new(rom, mmc3::Board::Mmc3))),
+        5 => Rc::new(RefCell::new(mmc5::Mmc5::new(rom))),
+        85 => Rc::new(RefCell::new(vrc7::Vrc7::new(rom))),
+        162 => Rc::new(RefCell::new(nanjing::Nanjing::new(
+            rom,
+            nanjing::Board::Fs304,
+        ))),
+        163 => Rc::new(RefCell::new(nanjing::Nanjing::new(
+            rom,
+            nanjing::Board::Fc001,
+        ))),
+        206 => Rc::new(RefCell::new(mmc3::Mmc3::new(rom, mmc3::Board::Namco108))),
+        id => panic!("Mapper {} is not supported", id),
+    }
+}
+
+/// Pattern table memory: the cartridge CHR-ROM, or CHR-RAM when the board has none.
+pub struct ChrMemory {
+    data: Vec<u8>,
+    writable: bool,
+    battery: bool,
+}
+
+impl ChrMemory {
+    /// CHR-ROM if there is any, otherwise `ram_size + nvram_size` bytes of CHR-RAM
+    /// (8KB if unspecified). Any battery-backed part makes the whole CHR-RAM persistent.
+    pub fn new(chr_rom: Vec<u8>, ram_size: usize, nvram_size: usize) -> Self {
+        if chr_rom.is_empty() {
+            let size = match ram_size + nvram_size {
+                0 => CHR_RAM_SIZE,
+                size => size,
+            };
+            ChrMemory {
+                data: vec![0; size],
+                writable: true,
+                battery: nvram_size > 0,
+            }
+        } else {
+            ChrMemory {
+                data: chr_rom,
+                writable: false,
+                battery: false,
+            }
+        }
+    }
+
+    /// CHR memory described by the cartridge header.
+    pub fn for_rom(rom: &mut Rom) -> Self {
+        ChrMemory::new(
+            std::mem::take(&mut rom.chr_rom),
+            rom.chr_ram_size,
+            rom.chr_nvram_size,
+        )
+    }
+
+    /// The part of CHR memory kept alive by the battery.
+    pub fn battery_data(&self) -> &[u8] {
+        if self.battery {
+            &self.data
+        } else {
+            &[]
+        }
+    }
+
+    /// Restores battery-backed CHR-RAM from the start of `data`, returns the bytes consumed.
+    pub fn load_battery(&mut self, data: &[u8]) -> usize {
+        let len = self.battery_data().len().min(data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+        len
+    }
+
+    /// Number of whole banks of `bank_size` bytes (at least one).
+    pub fn bank_count(&self, bank_size: usize) -> usize {
+        (self.data.len() / bank_size).max(1)
+    }
+
+    /// Reads from an absolute offset, wrapping around undersized chips.
+    pub fn read(&self, offset: usize) -> u8 {
+        self.data[offset % self.data.len()]
+    }
+
+    pub fn write(&mut self, offset: usize, data: u8) {
+        if self.writable {
+            let len = self.data.len();
+            self.data[offset % len] = data;
+        }
+    }
+}
+
+/// Cartridge PRG-ROM read through banks. Bank numbers wrap to the chip size, the
+/// way a board ignores register bits past its last address line, so one board
+/// serves every ROM size up to what its register can select (4MB for an 8-bit
+/// register of 16KB banks), and small chips are mirrored across big windows.
+pub struct PrgRom {
+    data: Vec<u8>,
+}
+
+impl PrgRom {
+    pub fn new(data: Vec<u8>) -> Self {
+        PrgRom { data }
+    }
+
+    /// Number of whole banks of `bank_size` bytes (at least one).
+    pub fn bank_count(&self, bank_size: usize) -> usize {
+        (self.data.len() / bank_size).max(1)
+    }
+
+    /// The last bank of `bank_size` bytes, which most boards keep fixed at $FFFF
+    /// for the reset vector.
+    pub fn last_bank(&self, bank_size: usize) -> usize {
+        self.bank_count(bank_size) - 1
+    }
+
+    /// Byte `offset` of bank `bank`; an empty chip reads back 0.
+    pub fn read(&self, bank: usize, bank_size: usize, offset: usize) -> u8 {
+        if self.data.is_empty() {
+            return 0;
+        }
+        let bank = strict::wrap_bank(
+            bank,
+            self.bank_count(bank_size),
+            Violation::PrgBankOutOfRange,
+        );
+        self.data[(bank * bank_size + offset % bank_size) % self.data.len()]
+    }
+}
+
+/// Work RAM on the CPU side, volatile and battery-backed chips seen as one region
+/// with the battery-backed chip first. A board without PRG-RAM reads back 0.
+pub struct PrgRam {
+    data: Vec<u8>,
+    battery_size: usize,
+}
+
+impl PrgRam {
+    pub fn new(size: usize) -> Self {
+        PrgRam {
+            data: vec![0; size],
+            battery_size: 0,
+        }
+    }
+
+    /// Volatile plus battery-backed PRG-RAM declared by the cartridge.
+    pub fn for_rom(rom: &Rom) -> Self {
+        PrgRam {
+            data: vec![0; rom.prg_ram_size + rom.prg_nvram_size],
+            battery_size: rom.prg_nvram_size,
+        }
+    }
+
+    /// The part of PRG-RAM kept alive by the battery.
+    pub fn battery_data(&self) -> &[u8] {
+        &self.data[..self.battery_size]
+    }
+
+    /// Restores battery-backed PRG-RAM from the start of `data`, returns the bytes consumed.
+    pub fn load_battery(&mut self, data: &[u8]) -> usize {
+        let len = self.battery_size.min(data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+        len
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Reads from an absolute offset, wrapping around undersized chips.
+    pub fn read(&self, offset: usize) -> u8 {
+        if self.data.is_empty() {
+            return 0;
+        }
+        self.data[offset % self.data.len()]
+    }
+
+    pub fn write(&mut self, offset: usize, data: u8) {
+        if !self.data.is_empty() {
+            let len = self.data.len();
+            self.data[offset % len] = data;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prg_ram_wraps_and_tolerates_absence() {
+        let mut ram = PrgRam::new(0x2000);
+        ram.write(0x2001, 0x42);
+        assert_eq!(ram.read(0x0001), 0x42);
+
+        let mut none = PrgRam::new(0);
+        none.write(0, 0x42);
+        assert_eq!(none.read(0), 0);
+    }
+
+    #[test]
+    fn test_chr_ram_size() {
+        assert_eq!(ChrMemory::new(vec![], 0, 0).bank_count(0x2000), 1);
+        assert_eq!(ChrMemory::new(vec![], 0x8000, 0).bank_count(0x2000), 4);
+        assert_eq!(
+            ChrMemory::new(vec![1; 0x4000], 0x8000, 0).bank_count(0x2000),
+            2
+        );
+    }
+
+    #[test]
+    fn test_battery_chr_ram() {
+        let mut chr = ChrMemory::new(vec![], 0, 0x2000);
+        chr.write(0x10, 0x42);
+        assert_eq!(chr.battery_data().len(), 0x2000);
+
+        let mut restored = ChrMemory::new(vec![], 0, 0x2000);
+        assert_eq!(restored.load_battery(chr.battery_data()), 0x2000);
+        assert_eq!(restored.read(0x10), 0x42);
+
+        assert!(ChrMemory::new(vec![], 0x2000, 0).battery_data().is_empty());
+        assert!(ChrMemory::new(vec![1; 0x2000], 0, 0x2000)
+            .battery_data()
+            .is_empty());
+    }
+
+    fn battery_rom() -> Rom {
+        let mut rom = crate::cartridge::test::test_rom();
+        rom.chr_rom = vec![];
+        rom.prg_ram_size = 0;
+        rom.prg_nvram_size = 0x2000;
+        rom.chr_ram_size = 0;
+        rom.chr_nvram_size = 0x2000;
+        rom
+    }
+
+    #[test]
+    fn test_battery_ram_container() {
+        let mut nrom = nrom::Nrom::new(battery_rom());
+        nrom.cpu_write(0x6000, 1);
+        nrom.chr_write(0x0000, 2);
+
+        let saved = nrom.battery_ram();
+        assert_eq!(saved.len(), 0x4000);
+        assert_eq!((saved[0], saved[0x2000]), (1, 2));
+
+        let mut restored = nrom::Nrom::new(battery_rom());
+        restored.load_battery_ram(&saved);
+        assert_eq!(restored.cpu_read(0x6000), 1);
+        assert_eq!(restored.chr_read(0x0000), 2);
+    }
+}
@@ -0,0 +1,275 @@
+use super::{ChrMemory, Mapper, PrgRam};
+use crate::cartridge::{Mirroring, Rom};
+use crate::json::Value;
+use crate::strict::{self, Violation};
+
+/// The two Chinese boards sharing the $5000-$5FFF register layout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Board {
+    /// Mapper 162 (Waixing FS304)
+    Fs304,
+    /// Mapper 163 (Nanjing FC-001)
+    Fc001,
+}
+
+/// Mappers 162/163: 32KB PRG banks selected through $5000-$5300, 8KB CHR-RAM and
+/// battery-backed work RAM at $6000.
+///
+/// The FC-001 can also swap the CHR-RAM halves on its own: with bit 7 of $5000 set,
+/// both pattern tables show the upper 4KB from scanline 128 to the end of the
+/// picture, which gives the background 512 unique tiles per screen. The real board
+/// detects this from the PPU address bus; here the scanline hook stands in for it.
+pub struct Nanjing {
+    board: Board,
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+
+    // $5000, $5100, $5200, $5300
+    regs: [u8; 4],
+    prg_bank: usize,
+
+    // FC-001 protection: $5101 going from nonzero to zero flips the trigger read at $5500
+    last_strobe: u8,
+    trigger: bool,
+
+    // 4KB CHR-RAM page shown in each pattern table
+    chr_pages: [usize; 2],
+    scanline: u16,
+}
+
+impl Nanjing {
+    pub fn new(mut rom: Rom, board: Board) -> Self {
+        let regs = match board {
+            Board::Fs304 => [3, 0, 0, 7],
+            Board::Fc001 => [0; 4],
+        };
+        let mut mapper = Nanjing {
+            board,
+            prg_ram: PrgRam::for_rom(&rom),
+            chr: ChrMemory::for_rom(&mut rom),
+            prg_rom: rom.prg_rom,
+            mirroring: rom.screen_mirroring,
+            regs,
+            prg_bank: 0,
+            last_strobe: 1,
+            trigger: false,
+            chr_pages: [0, 1],
+            scanline: 0,
+        };
+        mapper.update_prg_bank();
+        mapper
+    }
+
+    fn update_prg_bank(&mut self) {
+        let [low, _, high, mode] = self.regs;
+        let high = ((high & 0x0F) as usize) << 4;
+        self.prg_bank = match self.board {
+            Board::Fc001 => high | (low & 0x0F) as usize,
+            Board::Fs304 => match mode & 0x05 {
+                0 => high | (low & 0x0C) as usize | (self.regs[1] & 0x02) as usize,
+                1 => high | (low & 0x0C) as usize,
+                4 => high | (low & 0x0E) as usize | ((self.regs[1] >> 1) & 0x01) as usize,
+                _ => high | (low & 0x0F) as usize,
+            },
+        };
+    }
+
+    fn chr_auto_switch(&self) -> bool {
+        self.board == Board::Fc001 && self.regs[0] & 0x80 != 0
+    }
+
+    fn read_register(&self, addr: u16) -> u8 {
+        if self.board != Board::Fc001 {
+            return 0;
+        }
+        let [low, feedback, high, security] = self.regs;
+        match addr & 0x7700 {
+            0x5100 => security | high | low | (feedback ^ 0xFF),
+            0x5500 if self.trigger => security | low,
+            0x5500 => 0,
+            _ => 4,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        if self.board == Board::Fs304 {
+            self.regs[((addr >> 8) & 0x03) as usize] = data;
+            self.update_prg_bank();
+            return;
+        }
+
+        match addr {
+            0x5101 => {
+                if self.last_strobe != 0 && data == 0 {
+                    self.trigger = !self.trigger;
+                }
+                self.last_strobe = data;
+            }
+            // Forces the bank the protected games expect, until the next bank write
+            0x5100 if data == 6 => self.prg_bank = 3,
+            _ => {
+                let index = ((addr >> 8) & 0x03) as usize;
+                self.regs[index] = data;
+                if index == 0 && data & 0x80 == 0 && self.scanline < 128 {
+                    self.chr_pages = [0, 1];
+                }
+                if index != 3 {
+                    self.update_prg_bank();
+                }
+            }
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let page = self.chr_pages[(addr as usize >> 12) & 1];
+        page * 0x1000 + (addr & 0x0FFF) as usize
+    }
+}
+
+impl Mapper for Nanjing {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x5000..=0x5FFF => self.read_register(addr),
+            0x6000..=0x7FFF => self.prg_ram.read((addr - 0x6000) as usize),
+            0x8000..=0xFFFF => {
+                let offset = self.prg_bank * 0x8000 + (addr - 0x8000) as usize;
+                self.prg_rom
+                    [strict::wrap_offset(offset, self.prg_rom.len(), Violation::PrgBankOutOfRange)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5FFF => self.write_register(addr, data),
+            0x6000..=0x7FFF => self.prg_ram.write((addr - 0x6000) as usize, data),
+            _ => {}
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn chr_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn battery_ram(&self) -> Vec<u8> {
+        [self.prg_ram.battery_data(), self.chr.battery_data()].concat()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let used = self.prg_ram.load_battery(data);
+        self.chr.load_battery(&data[used..]);
+    }
+
+    fn scanline(&mut self, scanline: u16, _rendering_enabled: bool) {
+        self.scanline = scanline;
+        if self.chr_auto_switch() {
+            match scanline {
+                128 => self.chr_pages = [1, 1],
+                240 => self.chr_pages = [0, 0],
+                _ => {}
+            }
+        }
+    }
+
+    fn debug_state(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("regs", Value::from(&self.regs[..])),
+            ("prg_bank", Value::from(self.prg_bank)),
+            ("chr_pages", Value::from(&self.chr_pages[..])),
+            ("trigger", Value::from(self.trigger)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::banked_rom;
+
+    /// 1MB of PRG in 32KB banks: bank n starts with 8KB bank 4n
+    fn nanjing_rom(mapper: u8) -> Rom {
+        Rom {
+            battery: true,
+            prg_ram_size: 0,
+            prg_nvram_size: 0x2000,
+            ..banked_rom(mapper, 1024, 0, Mirroring::Vertical)
+        }
+    }
+
+    #[test]
+    fn test_fc001_prg_banks() {
+        let mut mapper = Nanjing::new(nanjing_rom(163), Board::Fc001);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+
+        mapper.cpu_write(0x5000, 0x05);
+        mapper.cpu_write(0x5200, 0x01);
+        assert_eq!(mapper.cpu_read(0xFFFF), 21 * 4 + 3);
+
+        mapper.cpu_write(0x5100, 6);
+        assert_eq!(mapper.cpu_read(0x8000), 3 * 4);
+    }
+
+    #[test]
+    fn test_fc001_protection_reads() {
+        let mut mapper = Nanjing::new(nanjing_rom(163), Board::Fc001);
+        mapper.cpu_write(0x5000, 0x01);
+        mapper.cpu_write(0x5300, 0x10);
+        assert_eq!(mapper.cpu_read(0x5100), 0x11 | 0xFF);
+        assert_eq!(mapper.cpu_read(0x5500), 0);
+
+        mapper.cpu_write(0x5101, 1);
+        mapper.cpu_write(0x5101, 0);
+        assert_eq!(mapper.cpu_read(0x5500), 0x11);
+        assert_eq!(mapper.cpu_read(0x5400), 4);
+    }
+
+    #[test]
+    fn test_fc001_chr_latch() {
+        let mut mapper = Nanjing::new(nanjing_rom(163), Board::Fc001);
+        mapper.chr_write(0x0000, 0xAA);
+        mapper.chr_write(0x1000, 0xBB);
+
+        mapper.cpu_write(0x5000, 0x80);
+        mapper.scanline(128, true);
+        assert_eq!(mapper.chr_read(0x0000), 0xBB);
+        assert_eq!(mapper.chr_read(0x1000), 0xBB);
+
+        mapper.scanline(240, true);
+        assert_eq!(mapper.chr_read(0x0000), 0xAA);
+        assert_eq!(mapper.chr_read(0x1000), 0xAA);
+
+        // Turning the latch off during the top half restores the plain 8KB layout
+        mapper.scanline(10, true);
+        mapper.cpu_write(0x5000, 0x00);
+        assert_eq!(mapper.chr_read(0x1000), 0xBB);
+    }
+
+    #[test]
+    fn test_fs304_prg_modes() {
+        let mut mapper = Nanjing::new(nanjing_rom(162), Board::Fs304);
+        // Power-on: mode 7, bank 3
+        assert_eq!(mapper.cpu_read(0x8000), 3 * 4);
+
+        mapper.cpu_write(0x5000, 0x0F);
+        mapper.cpu_write(0x5200, 0x01);
+        assert_eq!(mapper.cpu_read(0x8000), 0x1F * 4);
+
+        mapper.cpu_write(0x5300, 0x04);
+        mapper.cpu_write(0x5100, 0x02);
+        assert_eq!(mapper.cpu_read(0x8000), 0x1F * 4);
+
+        mapper.cpu_write(0x5300, 0x00);
+        assert_eq!(mapper.cpu_read(0x8000), 0x1E * 4);
+    }
+}
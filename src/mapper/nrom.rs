@@ -0,0 +1,69 @@
+use super::{ChrMemory, Mapper, PrgRam, PrgRom, CHR_RAM_SIZE, PRG_RAM_SIZE};
+use crate::cartridge::{Mirroring, Rom};
+
+/// Mapper 0 (NROM): 16KB or 32KB of PRG-ROM, 8KB of CHR, no bank switching.
+/// 16KB carts are mirrored into both halves of $8000-$FFFF.
+pub struct Nrom {
+    prg_rom: PrgRom,
+    prg_ram: PrgRam,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(mut rom: Rom) -> Self {
+        Nrom {
+            prg_ram: PrgRam::for_rom(&rom),
+            chr: ChrMemory::for_rom(&mut rom),
+            prg_rom: PrgRom::new(rom.prg_rom),
+            mirroring: rom.screen_mirroring,
+        }
+    }
+
+    /// Board with only pattern data, used by PPU unit tests.
+    pub fn with_chr(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        Nrom {
+            prg_rom: PrgRom::new(vec![]),
+            prg_ram: PrgRam::new(PRG_RAM_SIZE),
+            chr: ChrMemory::new(chr_rom, CHR_RAM_SIZE, 0),
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.read((addr - 0x6000) as usize),
+            0x8000..=0xFFFF => self.prg_rom.read(0, 0x8000, (addr - 0x8000) as usize),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.prg_ram.write((addr - 0x6000) as usize, data);
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn chr_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn battery_ram(&self) -> Vec<u8> {
+        [self.prg_ram.battery_data(), self.chr.battery_data()].concat()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let used = self.prg_ram.load_battery(data);
+        self.chr.load_battery(&data[used..]);
+    }
+}
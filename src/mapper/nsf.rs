@@ -0,0 +1,87 @@
+use super::{ChrMemory, Mapper, PrgRam, CHR_RAM_SIZE, PRG_RAM_SIZE};
+use crate::cartridge::Mirroring;
+use crate::json::Value;
+use crate::nsf::Nsf;
+
+/// Address of the player's idle loop (`JMP $5FF0`): init and play routines
+/// return here, and the CPU spins on it between play calls.
+pub const IDLE_LOOP: u16 = 0x5FF0;
+const BANK_SIZE: usize = 0x1000;
+
+/// The board of an NSF player cartridge: 8KB of work RAM and eight 4KB PRG
+/// windows at $8000-$FFFF, selected through $5FF8-$5FFF when the tune uses bank
+/// switching. Tunes that do not are loaded flat at their load address, which is
+/// the same thing with banks 0-7.
+pub struct NsfBoard {
+    data: Vec<u8>,
+    initial_banks: [u8; 8],
+    banks: [u8; 8],
+    prg_ram: PrgRam,
+    chr: ChrMemory,
+}
+
+impl NsfBoard {
+    pub fn new(nsf: &Nsf) -> Self {
+        // Padding puts the load address at the right offset of its bank
+        let (padding, initial_banks) = match nsf.bank_switching() {
+            Some(banks) => ((nsf.load_addr & 0x0FFF) as usize, banks),
+            None => (nsf.load_addr as usize - 0x8000, [0, 1, 2, 3, 4, 5, 6, 7]),
+        };
+        let mut data = vec![0; padding];
+        data.extend(&nsf.data);
+        data.resize(data.len().div_ceil(BANK_SIZE).max(1) * BANK_SIZE, 0);
+        NsfBoard {
+            data,
+            initial_banks,
+            banks: initial_banks,
+            prg_ram: PrgRam::new(PRG_RAM_SIZE),
+            chr: ChrMemory::new(vec![], CHR_RAM_SIZE, 0),
+        }
+    }
+
+    /// The bank registers values a tune starts with.
+    pub fn initial_banks(&self) -> [u8; 8] {
+        self.initial_banks
+    }
+}
+
+impl Mapper for NsfBoard {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            IDLE_LOOP => 0x4C,
+            0x5FF1 => IDLE_LOOP as u8,
+            0x5FF2 => (IDLE_LOOP >> 8) as u8,
+            0x6000..=0x7FFF => self.prg_ram.read((addr - 0x6000) as usize),
+            0x8000..=0xFFFF => {
+                let window = (addr as usize - 0x8000) / BANK_SIZE;
+                let bank = self.banks[window] as usize % (self.data.len() / BANK_SIZE);
+                self.data[bank * BANK_SIZE + addr as usize % BANK_SIZE]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5FF8..=0x5FFF => self.banks[(addr - 0x5FF8) as usize] = data,
+            0x6000..=0x7FFF => self.prg_ram.write((addr - 0x6000) as usize, data),
+            _ => {}
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn chr_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn debug_state(&self) -> Vec<(&'static str, Value)> {
+        vec![("banks", Value::from(&self.banks[..]))]
+    }
+}
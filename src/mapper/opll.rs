@@ -0,0 +1,489 @@
+//
+// The VRC7's sound: a cut-down Yamaha YM2413 (OPLL) with six FM channels. Each
+// channel is a pair of sine operators: the modulator's output bends the phase of
+// the carrier, whose output is heard. An instrument ("patch") sets how both
+// operators behave; the chip has 15 of them in ROM and one the game defines.
+//
+// The chip runs from the cartridge's 3.58MHz crystal and computes a sample every
+// 72 of its clocks, 36 CPU cycles. Levels are kept in the chip's unit of 0.375dB
+// of attenuation; the waveforms are computed in floating point rather than
+// through its log-sin tables.
+//
+
+use crate::expansion::ExpansionAudio;
+use std::f32::consts::PI;
+
+const CHANNELS: usize = 6;
+/// CPU cycles per sample
+const SAMPLE_CYCLES: u8 = 36;
+/// Envelope attenuation past which an operator is silent (48dB)
+const SILENT: f32 = 127.0;
+/// Level of one channel at full volume, against the APU's full mix
+const CHANNEL_GAIN: f32 = 0.12;
+
+/// The VRC7's built-in instruments, 1 to 15 (Nuke.YKT's die dump).
+const ROM_PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+/// Frequency multipliers, doubled (the first one is 1/2)
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/// Key scale level for each top 4 bits of the frequency at the highest octave, in dB
+const KEY_SCALE: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25,
+    42.0,
+];
+
+/// Vibrato offsets added to the frequency, by its top 3 bits and the step of the LFO
+const VIBRATO: [[i16; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0, 0, -1, 0],
+    [0, 1, 2, 1, 0, -1, -2, -1],
+    [0, 1, 3, 1, 0, -1, -3, -1],
+    [0, 2, 4, 2, 0, -2, -4, -2],
+    [0, 2, 5, 2, 0, -2, -5, -2],
+    [0, 3, 6, 3, 0, -3, -6, -3],
+    [0, 3, 7, 3, 0, -3, -7, -3],
+];
+
+/// How one operator of an instrument behaves.
+#[derive(Debug, Clone, Copy, Default)]
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    /// Holds at the sustain level while the key is down, instead of fading out
+    sustained: bool,
+    /// Envelope rates grow faster with the pitch
+    key_scale_rate: bool,
+    multiplier: u8,
+    key_scale_level: u8,
+    /// Negative half of the sine wave cut off
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+/// An instrument: the modulator, the carrier and how much the modulator feeds
+/// back into itself. Only the modulator has a level of its own; the carrier
+/// plays at the channel's volume.
+#[derive(Debug, Clone, Copy, Default)]
+struct Patch {
+    operators: [OperatorPatch; 2],
+    modulator_level: u8,
+    feedback: u8,
+}
+
+impl Patch {
+    fn from_bytes(bytes: &[u8; 8]) -> Patch {
+        let operator = |n: usize| OperatorPatch {
+            tremolo: bytes[n] & 0x80 != 0,
+            vibrato: bytes[n] & 0x40 != 0,
+            sustained: bytes[n] & 0x20 != 0,
+            key_scale_rate: bytes[n] & 0x10 != 0,
+            multiplier: bytes[n] & 0x0F,
+            key_scale_level: bytes[2 + n] >> 6,
+            rectified: bytes[3] & (0x08 << n) != 0,
+            attack: bytes[4 + n] >> 4,
+            decay: bytes[4 + n] & 0x0F,
+            sustain_level: bytes[6 + n] >> 4,
+            release: bytes[6 + n] & 0x0F,
+        };
+        Patch {
+            operators: [operator(0), operator(1)],
+            modulator_level: bytes[2] & 0x3F,
+            feedback: bytes[3] & 0x07,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// One operator of a channel: where it is in its sine wave and its envelope.
+#[derive(Debug, Clone, Copy)]
+struct Operator {
+    /// Position in the wave, a whole turn being 1 << 20
+    phase: u32,
+    stage: Stage,
+    /// Attenuation of the envelope, 0 (loudest) to `SILENT`
+    envelope: f32,
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Operator {
+            phase: 0,
+            stage: Stage::Release,
+            envelope: SILENT,
+        }
+    }
+}
+
+impl Operator {
+    fn key_on(&mut self, patch: &OperatorPatch) {
+        self.phase = 0;
+        self.stage = Stage::Attack;
+        if patch.attack == 15 {
+            self.envelope = 0.0;
+            self.stage = Stage::Decay;
+        }
+    }
+
+    /// Moves the envelope on by one sample. `key_scale` is the channel's octave
+    /// and top frequency bit, which speed up the envelope of high notes;
+    /// `sustain_pedal` the channel's sustain bit, which slows down releases.
+    fn clock_envelope(&mut self, patch: &OperatorPatch, key_scale: u8, sustain_pedal: bool) {
+        let rate = match self.stage {
+            Stage::Attack => patch.attack,
+            Stage::Decay => patch.decay,
+            Stage::Sustain if patch.sustained => 0,
+            Stage::Sustain => patch.release,
+            Stage::Release if sustain_pedal => 5,
+            Stage::Release if patch.sustained => patch.release,
+            Stage::Release => 7,
+        };
+        if rate == 0 {
+            return;
+        }
+        let key_scale = if patch.key_scale_rate {
+            key_scale
+        } else {
+            key_scale >> 2
+        };
+        let rate = (rate * 4 + key_scale).min(63);
+        // Attenuation units per sample: doubles every 4 rates
+        let speed = (4 + (rate & 3)) as f32 * (1u32 << (rate >> 2)) as f32 / 32768.0;
+
+        match self.stage {
+            Stage::Attack => {
+                // Exponential: fast at first, slowing down as it nears full level
+                self.envelope -= speed * (self.envelope / 8.0 + 1.0);
+                if self.envelope <= 0.0 || rate >= 60 {
+                    self.envelope = 0.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.envelope += speed;
+                let sustain_level = patch.sustain_level as f32 * 8.0;
+                if self.envelope >= sustain_level {
+                    self.envelope = sustain_level;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain | Stage::Release => {
+                self.envelope = (self.envelope + speed).min(SILENT);
+            }
+        }
+    }
+
+    /// Output for a wave position `phase_offset` turns past the operator's own,
+    /// attenuated by `attenuation` units on top of the envelope.
+    fn output(&self, patch: &OperatorPatch, phase_offset: f32, attenuation: f32) -> f32 {
+        if self.envelope >= SILENT {
+            return 0.0;
+        }
+        let angle = 2.0 * PI * (self.phase as f32 / (1 << 20) as f32 + phase_offset);
+        let wave = match angle.sin() {
+            wave if patch.rectified && wave < 0.0 => 0.0,
+            wave => wave,
+        };
+        let db = (self.envelope + attenuation) * 0.375;
+        wave * 10f32.powf(-db / 20.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Channel {
+    /// Frequency number, 9 bits
+    frequency: u16,
+    /// Octave
+    block: u8,
+    key: bool,
+    /// Makes released notes fade out slowly
+    sustain: bool,
+    instrument: u8,
+    /// Attenuation in steps of 3dB
+    volume: u8,
+    operators: [Operator; 2],
+    /// Last two outputs of the modulator, for its feedback
+    feedback: [f32; 2],
+}
+
+/// The VRC7 sound chip, written through $9010 (address) and $9030 (data).
+pub struct Opll {
+    address: u8,
+    /// Instrument 0, defined by the game through registers $00-$07
+    custom: [u8; 8],
+    channels: [Channel; CHANNELS],
+    /// CPU cycles into the current sample
+    cycles: u8,
+    /// Samples computed, for the tremolo and vibrato oscillators
+    samples: u32,
+    output: f32,
+    /// Held silent by the board ($E000 bit 6)
+    reset: bool,
+}
+
+impl Default for Opll {
+    fn default() -> Self {
+        Opll {
+            address: 0,
+            custom: [0; 8],
+            channels: [Channel::default(); CHANNELS],
+            cycles: 0,
+            samples: 0,
+            output: 0.0,
+            reset: false,
+        }
+    }
+}
+
+impl Opll {
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data;
+    }
+
+    pub fn write_data(&mut self, data: u8) {
+        let index = (self.address & 0x0F) as usize;
+        match self.address {
+            0x00..=0x07 => self.custom[index] = data,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[index];
+                channel.frequency = (channel.frequency & 0x100) | data as u16;
+            }
+            0x20..=0x25 => {
+                let patch = self.patch(self.channels[index].instrument);
+                let channel = &mut self.channels[index];
+                channel.frequency = (channel.frequency & 0xFF) | ((data as u16 & 0x01) << 8);
+                channel.block = (data >> 1) & 0x07;
+                channel.sustain = data & 0x20 != 0;
+                let key = data & 0x10 != 0;
+                if key && !channel.key {
+                    for (operator, patch) in channel.operators.iter_mut().zip(&patch.operators) {
+                        operator.key_on(patch);
+                    }
+                } else if !key {
+                    for operator in &mut channel.operators {
+                        operator.stage = Stage::Release;
+                    }
+                }
+                channel.key = key;
+            }
+            0x30..=0x35 => {
+                let channel = &mut self.channels[index];
+                channel.instrument = data >> 4;
+                channel.volume = data & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    /// Holds the chip silent while `reset` is set, every channel keyed off.
+    pub fn set_reset(&mut self, reset: bool) {
+        if reset {
+            self.channels = [Channel::default(); CHANNELS];
+            self.output = 0.0;
+        }
+        self.reset = reset;
+    }
+
+    fn patch(&self, instrument: u8) -> Patch {
+        match instrument {
+            0 => Patch::from_bytes(&self.custom),
+            n => Patch::from_bytes(&ROM_PATCHES[n as usize - 1]),
+        }
+    }
+
+    /// Computes the next sample of all channels.
+    fn sample(&mut self) -> f32 {
+        self.samples = self.samples.wrapping_add(1);
+        let vibrato_step = ((self.samples >> 10) & 7) as usize;
+        // Tremolo: a 3.7Hz triangle down to -4.875dB
+        let position = (self.samples / 64) % 210;
+        let tremolo = position.min(209 - position) as f32 * 13.0 / 104.0;
+
+        let mut mix = 0.0;
+        for n in 0..CHANNELS {
+            let patch = self.patch(self.channels[n].instrument);
+            let channel = &mut self.channels[n];
+            let key_scale = (channel.block << 1) | (channel.frequency >> 8) as u8;
+            let key_scale_db = (KEY_SCALE[(channel.frequency >> 5) as usize]
+                - 6.0 * (7 - channel.block) as f32)
+                .max(0.0);
+
+            let mut attenuation = [
+                patch.modulator_level as f32 * 2.0,
+                channel.volume as f32 * 8.0,
+            ];
+            for (operator, (patch, attenuation)) in channel
+                .operators
+                .iter_mut()
+                .zip(patch.operators.iter().zip(&mut attenuation))
+            {
+                operator.clock_envelope(patch, key_scale, channel.sustain);
+                if patch.key_scale_level > 0 {
+                    *attenuation +=
+                        key_scale_db / 0.375 / (1 << (3 - patch.key_scale_level)) as f32;
+                }
+                if patch.tremolo {
+                    *attenuation += tremolo;
+                }
+                let vibrato = match patch.vibrato {
+                    true => VIBRATO[(channel.frequency >> 6) as usize][vibrato_step],
+                    false => 0,
+                };
+                let frequency = (channel.frequency as i16 + vibrato) as u32;
+                operator.phase = operator.phase.wrapping_add(
+                    (frequency << channel.block) * MULTIPLIERS[patch.multiplier as usize],
+                ) & 0xFFFFF;
+            }
+
+            let [modulator, carrier] = &channel.operators;
+            let feedback = match patch.feedback {
+                0 => 0.0,
+                feedback => {
+                    (channel.feedback[0] + channel.feedback[1]) / 2.0 * (1 << feedback) as f32
+                        / 64.0
+                }
+            };
+            let modulation = modulator.output(&patch.operators[0], feedback, attenuation[0]);
+            channel.feedback = [modulation, channel.feedback[0]];
+            mix += carrier.output(&patch.operators[1], modulation, attenuation[1]);
+        }
+        mix * CHANNEL_GAIN
+    }
+}
+
+impl ExpansionAudio for Opll {
+    fn name(&self) -> &'static str {
+        "vrc7"
+    }
+
+    fn clock(&mut self) {
+        if self.reset {
+            return;
+        }
+        self.cycles += 1;
+        if self.cycles == SAMPLE_CYCLES {
+            self.cycles = 0;
+            self.output = self.sample();
+        }
+    }
+
+    fn output(&self) -> f32 {
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(opll: &mut Opll, address: u8, data: u8) {
+        opll.write_address(address);
+        opll.write_data(data);
+    }
+
+    /// Runs the chip for `count` samples, returns their outputs.
+    fn samples(opll: &mut Opll, count: usize) -> Vec<f32> {
+        (0..count)
+            .map(|_| {
+                (0..SAMPLE_CYCLES).for_each(|_| opll.clock());
+                opll.output()
+            })
+            .collect()
+    }
+
+    /// A custom instrument playing a plain sine: the modulator stays silent and
+    /// the carrier holds at full level.
+    fn sine_patch(opll: &mut Opll) {
+        for (address, data) in [0x00, 0x21, 0x3F, 0x00, 0x00, 0xF0, 0x00, 0x0F]
+            .into_iter()
+            .enumerate()
+        {
+            write(opll, address as u8, data);
+        }
+    }
+
+    #[test]
+    fn test_sine_pitch() {
+        let mut opll = Opll::default();
+        sine_patch(&mut opll);
+        // 440Hz: 290 * 49716Hz / 2^(19 - 4)
+        let frequency = 290u16;
+        write(&mut opll, 0x10, frequency as u8);
+        write(&mut opll, 0x30, 0x00);
+        write(&mut opll, 0x20, 0x10 | (4 << 1) | (frequency >> 8) as u8);
+        let output = samples(&mut opll, 49716);
+        let rising = output
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count();
+        assert!((438..=442).contains(&rising), "{}", rising);
+        let peak = output.iter().fold(0.0f32, |peak, &s| peak.max(s));
+        assert!((peak - CHANNEL_GAIN).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_release_and_reset() {
+        let mut opll = Opll::default();
+        sine_patch(&mut opll);
+        write(&mut opll, 0x10, 0x80);
+        write(&mut opll, 0x20, 0x10 | (4 << 1));
+        assert!(samples(&mut opll, 100).iter().any(|&s| s > 0.05));
+
+        // Released with the carrier's fastest rate, it is gone within a few samples
+        write(&mut opll, 0x20, 4 << 1);
+        samples(&mut opll, 50);
+        assert!(samples(&mut opll, 100).iter().all(|&s| s == 0.0));
+
+        // Held in reset, it stays silent
+        write(&mut opll, 0x20, 0x10 | (4 << 1));
+        samples(&mut opll, 10);
+        opll.set_reset(true);
+        assert_eq!(opll.output(), 0.0);
+        assert!(samples(&mut opll, 100).iter().all(|&s| s == 0.0));
+        // And comes out of it with every channel keyed off
+        opll.set_reset(false);
+        assert!(samples(&mut opll, 100).iter().all(|&s| s == 0.0));
+        write(&mut opll, 0x10, 0x80);
+        write(&mut opll, 0x20, 0x10 | (4 << 1));
+        assert!(samples(&mut opll, 100).iter().any(|&s| s > 0.05));
+    }
+
+    #[test]
+    fn test_rom_instruments_sound() {
+        for instrument in 1..16 {
+            let mut opll = Opll::default();
+            write(&mut opll, 0x30, instrument << 4);
+            write(&mut opll, 0x10, 0xAC);
+            write(&mut opll, 0x20, 0x10 | (4 << 1));
+            let output = samples(&mut opll, 4000);
+            assert!(output.iter().any(|&s| s.abs() > 0.01), "{}", instrument);
+            assert!(output.iter().all(|&s| s.abs() <= CHANNEL_GAIN));
+        }
+    }
+}
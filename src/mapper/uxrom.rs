@@ -0,0 +1,113 @@
+use super::{ChrMemory, Mapper, PrgRam, PrgRom};
+use crate::cartridge::{Mirroring, Rom};
+use crate::json::Value;
+
+const BANK_SIZE: usize = 0x4000;
+
+/// Mapper 2 (UxROM): a 16KB PRG bank selected by any write to $8000-$FFFF at
+/// $8000-$BFFF, the last bank fixed at $C000-$FFFF, and 8KB of CHR-RAM. Original
+/// boards decode 3 or 4 bits of the register; all 8 are used here, which covers the
+/// oversized 4MB images of romhacks and homebrew while smaller ROMs wrap as usual.
+pub struct Uxrom {
+    prg_rom: PrgRom,
+    prg_ram: PrgRam,
+    chr: ChrMemory,
+    prg_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl Uxrom {
+    pub fn new(mut rom: Rom) -> Self {
+        Uxrom {
+            prg_ram: PrgRam::for_rom(&rom),
+            chr: ChrMemory::for_rom(&mut rom),
+            prg_rom: PrgRom::new(rom.prg_rom),
+            prg_bank: 0,
+            mirroring: rom.screen_mirroring,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let offset = addr as usize % BANK_SIZE;
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.read((addr - 0x6000) as usize),
+            0x8000..=0xBFFF => self.prg_rom.read(self.prg_bank, BANK_SIZE, offset),
+            0xC000..=0xFFFF => {
+                let last = self.prg_rom.last_bank(BANK_SIZE);
+                self.prg_rom.read(last, BANK_SIZE, offset)
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.write((addr - 0x6000) as usize, data),
+            0x8000..=0xFFFF => self.prg_bank = data as usize,
+            _ => {}
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn chr_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn battery_ram(&self) -> Vec<u8> {
+        [self.prg_ram.battery_data(), self.chr.battery_data()].concat()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let used = self.prg_ram.load_battery(data);
+        self.chr.load_battery(&data[used..]);
+    }
+
+    fn debug_state(&self) -> Vec<(&'static str, Value)> {
+        vec![("prg_bank", Value::from(self.prg_bank))]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::banked_rom;
+
+    /// `banks` 16KB banks: bank n is 8KB banks 2n and 2n + 1.
+    fn uxrom_rom(banks: usize) -> Rom {
+        banked_rom(2, banks * 16, 0, Mirroring::Vertical)
+    }
+
+    #[test]
+    fn test_oversized_prg() {
+        // 4MB: all 256 banks reachable, the last one fixed at $C000. 8KB bank
+        // numbers wrap past 255, so bank 200 gets a mark of its own.
+        let mut rom = uxrom_rom(256);
+        rom.prg_rom[200 * BANK_SIZE] = 0xC8;
+        let mut uxrom = Uxrom::new(rom);
+        assert_eq!(uxrom.cpu_read(0xFFFC), 255);
+        uxrom.cpu_write(0x8000, 200);
+        assert_eq!(uxrom.cpu_read(0x8000), 0xC8);
+        assert_eq!(uxrom.cpu_read(0xBFFF), (200 * 2 + 1) as u8);
+        assert_eq!(uxrom.cpu_read(0xC000), 254);
+    }
+
+    #[test]
+    fn test_bank_wraps_on_small_prg() {
+        let mut uxrom = Uxrom::new(uxrom_rom(8));
+        uxrom.cpu_write(0xFFFF, 0x0D);
+        assert_eq!(uxrom.cpu_read(0x8000), 5 * 2);
+        assert_eq!(uxrom.cpu_read(0xC000), 7 * 2);
+
+        uxrom.chr_write(0x1234, 0x42);
+        assert_eq!(uxrom.chr_read(0x1234), 0x42);
+    }
+}
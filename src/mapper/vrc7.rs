@@ -0,0 +1,223 @@
+//
+// Mapper 85 (VRC7): Konami's board for Lagrange Point and Tiny Toon Adventures 2.
+// Three switchable 8KB PRG banks and the last one fixed, eight 1KB CHR banks,
+// mirroring control down to one-screen, work RAM, the VRC cycle/scanline IRQ
+// counter and, on Lagrange Point's VRC7a, the FM sound chip (see `opll`).
+//
+// The two variants tell their paired registers apart by a different address
+// line: A4 ($x010) on the VRC7a, A3 ($x008) on the VRC7b. Both are accepted.
+//
+
+use super::opll::Opll;
+use super::{ChrMemory, Mapper, PrgRam, PrgRom};
+use crate::cartridge::{Mirroring, Rom};
+use crate::expansion::ExpansionRef;
+use crate::json::Value;
+use crate::strict::{self, Violation};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+/// PPU dots per scanline, which the IRQ prescaler counts down 3 per CPU cycle
+const SCANLINE_DOTS: i16 = 341;
+
+pub struct Vrc7 {
+    prg_rom: PrgRom,
+    prg_ram: PrgRam,
+    chr: ChrMemory,
+    audio: Rc<RefCell<Opll>>,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    // $E000: mirroring in bits 0-1, sound reset in bit 6, work RAM enable in bit 7
+    control: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    /// Bit 0: enable after acknowledge, bit 1: enable, bit 2: count CPU cycles
+    /// instead of scanlines
+    irq_control: u8,
+    irq_prescaler: i16,
+    irq_flag: bool,
+}
+
+impl Vrc7 {
+    pub fn new(mut rom: Rom) -> Self {
+        Vrc7 {
+            prg_ram: PrgRam::for_rom(&rom),
+            chr: ChrMemory::for_rom(&mut rom),
+            prg_rom: PrgRom::new(rom.prg_rom),
+            audio: Rc::new(RefCell::new(Opll::default())),
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_control: 0,
+            irq_prescaler: SCANLINE_DOTS,
+            irq_flag: false,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        let bank_count = self.chr.bank_count(CHR_BANK_SIZE);
+        strict::wrap_bank(bank, bank_count, Violation::ChrBankOutOfRange) * CHR_BANK_SIZE
+            + (addr as usize % CHR_BANK_SIZE)
+    }
+
+    /// CIRAM page every nametable shows in the one-screen modes.
+    fn single_screen_page(&self) -> Option<usize> {
+        match self.control & 0x03 {
+            2 => Some(0),
+            3 => Some(1),
+            _ => None,
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0xFF {
+            self.irq_counter = self.irq_latch;
+            self.irq_flag = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let offset = addr as usize % PRG_BANK_SIZE;
+        match addr {
+            0x6000..=0x7FFF if self.control & 0x80 != 0 => {
+                self.prg_ram.read((addr - 0x6000) as usize)
+            }
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[(addr - 0x8000) as usize / PRG_BANK_SIZE];
+                self.prg_rom.read(bank as usize, PRG_BANK_SIZE, offset)
+            }
+            0xE000..=0xFFFF => {
+                let last = self.prg_rom.last_bank(PRG_BANK_SIZE);
+                self.prg_rom.read(last, PRG_BANK_SIZE, offset)
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let second = addr & 0x18 != 0;
+        match addr & 0xF000 {
+            0x6000 | 0x7000 if self.control & 0x80 != 0 => {
+                self.prg_ram.write((addr - 0x6000) as usize, data);
+            }
+            0x8000 => self.prg_banks[second as usize] = data & 0x3F,
+            0x9000 => match addr & 0x0030 {
+                0x0010 => self.audio.borrow_mut().write_address(data),
+                0x0030 => self.audio.borrow_mut().write_data(data),
+                _ => self.prg_banks[2] = data & 0x3F,
+            },
+            0xA000..=0xD000 => {
+                let index = ((addr - 0xA000) >> 12) as usize * 2 + second as usize;
+                self.chr_banks[index] = data;
+            }
+            0xE000 if !second => {
+                self.control = data;
+                self.audio.borrow_mut().set_reset(data & 0x40 != 0);
+            }
+            0xE000 => self.irq_latch = data,
+            0xF000 if !second => {
+                self.irq_control = data & 0x07;
+                self.irq_flag = false;
+                if data & 0x02 != 0 {
+                    self.irq_counter = self.irq_latch;
+                    self.irq_prescaler = SCANLINE_DOTS;
+                }
+            }
+            0xF000 => {
+                // Acknowledge: the enable goes back to what bit 0 asked for
+                self.irq_flag = false;
+                self.irq_control = (self.irq_control & !0x02) | ((self.irq_control & 0x01) << 1);
+            }
+            _ => {}
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn chr_write(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+
+    /// The one-screen modes are served by the nametable hooks.
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn nametable_read(&mut self, addr: u16, ciram: &[u8]) -> Option<u8> {
+        let page = self.single_screen_page()?;
+        Some(ciram[page * 0x400 + (addr & 0x3FF) as usize])
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8, ciram: &mut [u8]) -> bool {
+        let Some(page) = self.single_screen_page() else {
+            return false;
+        };
+        ciram[page * 0x400 + (addr & 0x3FF) as usize] = data;
+        true
+    }
+
+    /// In scanline mode a prescaler stands in for the PPU: it takes 113 2/3 CPU
+    /// cycles, 341 dots, to clock the counter.
+    fn cpu_cycles(&mut self, cycles: u8) {
+        if self.irq_control & 0x02 == 0 {
+            return;
+        }
+        for _ in 0..cycles {
+            if self.irq_control & 0x04 != 0 {
+                self.clock_irq_counter();
+                continue;
+            }
+            self.irq_prescaler -= 3;
+            if self.irq_prescaler <= 0 {
+                self.irq_prescaler += SCANLINE_DOTS;
+                self.clock_irq_counter();
+            }
+        }
+    }
+
+    fn expansion_audio(&self) -> Vec<ExpansionRef> {
+        vec![self.audio.clone()]
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn battery_ram(&self) -> Vec<u8> {
+        [self.prg_ram.battery_data(), self.chr.battery_data()].concat()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let used = self.prg_ram.load_battery(data);
+        self.chr.load_battery(&data[used..]);
+    }
+
+    fn debug_state(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("prg_banks", Value::from(&self.prg_banks[..])),
+            ("chr_banks", Value::from(&self.chr_banks[..])),
+            ("control", Value::from(self.control)),
+            ("irq_latch", Value::from(self.irq_latch)),
+            ("irq_counter", Value::from(self.irq_counter)),
+            ("irq_control", Value::from(self.irq_control)),
+            ("irq_flag", Value::from(self.irq_flag)),
+        ]
+    }
+}
@@ -0,0 +1,138 @@
+//
+// Pause menu, for playing from the couch with nothing but a gamepad. Select and
+// Start pressed together (or P on the keyboard) stop the game and show it over
+// the paused picture; the d-pad moves, A or Start picks an entry, B resumes.
+//
+// The menu takes NES buttons, the way the game would see them, so it follows the
+// keyboard and joystick mappings of the settings file without knowing them.
+//
+// Save and load state slots are still to come: they need save states holding the
+// whole console, and savestate.rs only has the CPU so far.
+//
+
+use crate::i18n::{self, Msg};
+use crate::joypad::JoypadButton;
+use crate::render::font;
+use crate::render::frame::Frame;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Item {
+    Resume,
+    Reset,
+    Screenshot,
+    Quit,
+}
+
+const ITEMS: [(Item, Msg); 4] = [
+    (Item::Resume, Msg::MenuResume),
+    (Item::Reset, Msg::MenuReset),
+    (Item::Screenshot, Msg::MenuScreenshot),
+    (Item::Quit, Msg::MenuQuit),
+];
+
+/// Buttons that open the menu when held together.
+pub const OPEN_CHORD: JoypadButton =
+    JoypadButton::from_bits_truncate(JoypadButton::SELECT.bits() | JoypadButton::START.bits());
+
+const WHITE: (u8, u8, u8) = (255, 255, 255);
+const GREY: (u8, u8, u8) = (150, 150, 150);
+const HIGHLIGHT: (u8, u8, u8) = (255, 200, 60);
+
+#[derive(Debug, Default)]
+pub struct PauseMenu {
+    selected: usize,
+    /// Line shown under the entries, e.g. why an entry did nothing
+    note: Option<String>,
+}
+
+impl PauseMenu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn selected(&self) -> Item {
+        ITEMS[self.selected].0
+    }
+
+    /// Handles a newly pressed button. Returns the entry it picks, if any; B
+    /// picks Resume from anywhere.
+    pub fn press(&mut self, button: JoypadButton) -> Option<Item> {
+        match button {
+            JoypadButton::UP => self.selected = (self.selected + ITEMS.len() - 1) % ITEMS.len(),
+            JoypadButton::DOWN => self.selected = (self.selected + 1) % ITEMS.len(),
+            JoypadButton::BUTTON_A | JoypadButton::START => return Some(self.selected()),
+            JoypadButton::BUTTON_B => return Some(Item::Resume),
+            _ => {}
+        }
+        None
+    }
+
+    pub fn set_note(&mut self, note: String) {
+        self.note = Some(note);
+    }
+
+    /// Dims the picture and draws the entries over it, centered.
+    pub fn draw(&self, frame: &mut Frame) {
+        for channel in frame.data.iter_mut() {
+            *channel /= 3;
+        }
+        let line_height = font::CELL_HEIGHT + 4;
+        let top = (240 - (ITEMS.len() + 2) * line_height) / 2;
+        let title = i18n::tr(Msg::MenuTitle);
+        font::draw_text(frame, centered(title), top, title, WHITE);
+
+        for (row, &(_, msg)) in ITEMS.iter().enumerate() {
+            let text = i18n::tr(msg);
+            let y = top + (row + 2) * line_height;
+            let x = centered(text);
+            if row == self.selected {
+                font::draw_text(frame, x - 2 * font::CELL_WIDTH, y, ">", HIGHLIGHT);
+                font::draw_text(frame, x, y, text, HIGHLIGHT);
+            } else {
+                font::draw_text(frame, x, y, text, GREY);
+            }
+        }
+
+        if let Some(note) = &self.note {
+            let y = top + (ITEMS.len() + 3) * line_height;
+            font::draw_text(frame, centered(note), y, note, GREY);
+        }
+    }
+}
+
+/// Left edge of `text` centered on the screen.
+fn centered(text: &str) -> usize {
+    128usize.saturating_sub(font::text_width(text) / 2)
+}
+
+/// Buttons in `now` that were up in `before`: the presses the menu reacts to.
+pub fn newly_pressed(before: JoypadButton, now: JoypadButton) -> Vec<JoypadButton> {
+    (now - before).iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_navigation() {
+        let mut menu = PauseMenu::new();
+        assert_eq!(menu.press(JoypadButton::UP), None);
+        assert_eq!(menu.selected(), Item::Quit);
+        menu.press(JoypadButton::DOWN);
+        menu.press(JoypadButton::DOWN);
+        assert_eq!(menu.press(JoypadButton::BUTTON_A), Some(Item::Reset));
+        assert_eq!(menu.press(JoypadButton::BUTTON_B), Some(Item::Resume));
+
+        let pressed = newly_pressed(
+            JoypadButton::SELECT,
+            JoypadButton::SELECT | JoypadButton::DOWN,
+        );
+        assert_eq!(pressed, vec![JoypadButton::DOWN]);
+
+        let mut frame = Frame::new();
+        frame.data.fill(90);
+        menu.draw(&mut frame);
+        assert_eq!(frame.data[0], 30);
+    }
+}
@@ -0,0 +1,200 @@
+//
+// Input movies: the joypad state for every emulated frame, replayed deterministically.
+//
+// Text format, one frame per line:
+//   <buttons as 2 hex digits> [<players 2 to 4, the same way>] [reset] [power]
+// where the bits follow `JoypadButton` (A = 0x01 ... RIGHT = 0x80).
+// Empty lines and lines starting with '#' are ignored. FCEUX's .fm2 movies are
+// read too (see fm2.rs).
+//
+// Movies are recorded from power-on (F11 in the frontend): `MovieRecorder` logs
+// the pad of port 1 as each frame starts, and the resets pressed on the way.
+//
+
+use crate::fm2;
+use crate::joypad::JoypadButton;
+use crate::region::Region;
+use std::fmt::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MovieFrame {
+    /// Buttons of players 1 to 4
+    pub buttons: [u8; 4],
+    /// Soft reset pressed at the start of this frame
+    pub reset: bool,
+    /// Console switched off and on at the start of this frame
+    pub power: bool,
+}
+
+impl MovieFrame {
+    pub fn joypad_buttons(&self, player: usize) -> JoypadButton {
+        JoypadButton::from_bits_truncate(self.buttons[player])
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Movie {
+    pub frames: Vec<MovieFrame>,
+    /// Played with a Four Score on both ports
+    pub four_score: bool,
+    /// Timing the movie was made with, over the ROM's
+    pub region: Option<Region>,
+}
+
+impl Movie {
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut frames = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace().peekable();
+            let buttons = fields.next().unwrap_or_default();
+            let mut frame = MovieFrame::default();
+            frame.buttons[0] = u8::from_str_radix(buttons, 16)
+                .map_err(|_| format!("line {}: invalid buttons '{}'", number + 1, buttons))?;
+            for player in 1..4 {
+                match fields.peek().map(|field| u8::from_str_radix(field, 16)) {
+                    Some(Ok(buttons)) => frame.buttons[player] = buttons,
+                    _ => break,
+                }
+                fields.next();
+            }
+            for flag in fields {
+                match flag {
+                    "reset" => frame.reset = true,
+                    "power" => frame.power = true,
+                    other => return Err(format!("line {}: unknown flag '{}'", number + 1, other)),
+                }
+            }
+
+            frames.push(frame);
+        }
+
+        Ok(Movie {
+            frames,
+            ..Movie::default()
+        })
+    }
+
+    /// Reads a movie in the text format, or FCEUX's for `.fm2` files.
+    pub fn load(path: &str) -> Result<Movie, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        if path.to_ascii_lowercase().ends_with(".fm2") {
+            fm2::parse(&text).map_err(|e| format!("{}: {}", path, e))
+        } else {
+            Movie::parse(&text)
+        }
+    }
+
+    /// The movie in the text format, after a `#` line with `title`.
+    pub fn to_text(&self, title: &str) -> String {
+        let mut text = format!("# {}\n", title);
+        for frame in &self.frames {
+            let players = frame.buttons.iter().rposition(|&buttons| buttons != 0);
+            write!(text, "{:02x}", frame.buttons[0]).unwrap();
+            for buttons in &frame.buttons[1..=players.unwrap_or(0)] {
+                write!(text, " {:02x}", buttons).unwrap();
+            }
+            let reset = if frame.reset { " reset" } else { "" };
+            let power = if frame.power { " power" } else { "" };
+            writeln!(text, "{}{}", reset, power).unwrap();
+        }
+        text
+    }
+
+    pub fn save(&self, path: &Path, title: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.to_text(title))
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+/// Builds a movie while the game is played, one frame per vblank.
+#[derive(Debug, Default)]
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    pub fn start() -> Self {
+        Self::default()
+    }
+
+    /// Logs the buttons held for the frame about to start, and whether the
+    /// console is reset before it.
+    pub fn push_frame(&mut self, buttons: JoypadButton, reset: bool) {
+        self.movie.frames.push(MovieFrame {
+            buttons: [buttons.bits(), 0, 0, 0],
+            reset,
+            power: false,
+        });
+    }
+
+    pub fn frames(&self) -> usize {
+        self.movie.len()
+    }
+
+    pub fn stop(self) -> Movie {
+        self.movie
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_movie() {
+        let movie = Movie::parse("# header\n00\n\n81\n08 reset\n00 02 00 40 power\n").unwrap();
+
+        assert_eq!(movie.len(), 4);
+        assert_eq!(movie.frames[1].buttons, [0x81, 0, 0, 0]);
+        assert!(movie.frames[1]
+            .joypad_buttons(0)
+            .contains(JoypadButton::RIGHT | JoypadButton::BUTTON_A));
+        assert!(movie.frames[2].reset);
+        assert_eq!(movie.frames[3].buttons, [0, 0x02, 0, 0x40]);
+        assert!(movie.frames[3].power);
+        assert_eq!(
+            movie.to_text("header"),
+            "# header\n00\n81\n08 reset\n00 02 00 40 power\n"
+        );
+    }
+
+    #[test]
+    fn test_recorded_movie_round_trip() {
+        let mut recorder = MovieRecorder::start();
+        recorder.push_frame(JoypadButton::empty(), false);
+        recorder.push_frame(JoypadButton::START | JoypadButton::BUTTON_A, false);
+        recorder.push_frame(JoypadButton::empty(), true);
+        assert_eq!(recorder.frames(), 3);
+
+        let movie = recorder.stop();
+        let text = movie.to_text("game.nes");
+        assert_eq!(text, "# game.nes\n00\n09\n00 reset\n");
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_parse_movie_errors() {
+        assert_eq!(
+            Movie::parse("zz"),
+            Err("line 1: invalid buttons 'zz'".to_string())
+        );
+        assert_eq!(
+            Movie::parse("00\n00 turbo"),
+            Err("line 2: unknown flag 'turbo'".to_string())
+        );
+    }
+}
@@ -0,0 +1,611 @@
+//
+// NSF music files: the sound code and data ripped from a game, with a header
+// giving the addresses of an init routine (called once per song, with the song
+// number in A) and a play routine (called at a fixed rate, usually 60Hz).
+//
+// The player builds a console around the tune with no picture: an `NsfBoard`
+// cartridge, a CPU that idles in a `JMP` loop between calls, and the APU output
+// collected sample by sample as in a game. The PPU never gets turned on, so
+// PAL-only tunes can run on a PAL console, at its clock and play rate.
+//
+// NSFe files, and NSF2 files with a metadata block after their data, are made of
+// chunks: a little-endian length, a four-letter id, then the payload. Besides
+// what the NSF header holds they can name each track and give its length and
+// fade, which the player uses to move on to the next song by itself. Chunks the
+// player does not know are skipped, unless their id starts with a capital
+// letter: the format says those cannot be ignored.
+//
+
+use crate::bus::Bus;
+use crate::cpu::{Mem, CPU};
+use crate::input::ControllerPorts;
+use crate::mapper::nsf::{NsfBoard, IDLE_LOOP};
+use crate::ppu::NesPPU;
+use crate::region::Region;
+use std::cell::RefCell;
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+
+pub const MAGIC: [u8; 5] = *b"NESM\x1A";
+pub const NSFE_MAGIC: [u8; 4] = *b"NSFE";
+const HEADER_SIZE: usize = 0x80;
+/// Play periods of tunes whose header leaves them at 0: 60.002Hz and 50.007Hz.
+const DEFAULT_NTSC_SPEED: u16 = 16639;
+const DEFAULT_PAL_SPEED: u16 = 19997;
+/// Fade of tracks with a length but no fade of their own, in milliseconds.
+const DEFAULT_FADE_MS: u32 = 8000;
+
+const EXPANSION_CHIPS: [&str; 6] = ["VRC6", "VRC7", "FDS", "MMC5", "Namco 163", "Sunsoft 5B"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NsfError {
+    /// The file does not start with "NESM\x1A" or "NSFE"
+    BadMagic,
+    /// The file ends inside the header or a chunk
+    TruncatedFile,
+    /// An NSFe file without this chunk, which every tune needs
+    MissingChunk(&'static str),
+    /// A chunk the player does not know, marked as one it cannot do without
+    UnsupportedChunk(String),
+    /// Code loaded below $8000, where the console has no cartridge ROM
+    BadLoadAddress(u16),
+    /// The file could not be read
+    Io(String),
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NsfError::BadMagic => write!(f, "File is not an NSF file"),
+            NsfError::TruncatedFile => write!(f, "File ends inside its header or a chunk"),
+            NsfError::MissingChunk(id) => write!(f, "NSFe file has no {} chunk", id),
+            NsfError::UnsupportedChunk(id) => write!(f, "Unsupported NSFe chunk {}", id),
+            NsfError::BadLoadAddress(addr) => {
+                write!(f, "Load address ${:04X} is below $8000", addr)
+            }
+            NsfError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for NsfError {}
+
+pub struct Nsf {
+    pub version: u8,
+    pub songs: u8,
+    /// First song to play, counted from 1
+    pub starting_song: u8,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    /// Play routine period on NTSC consoles, in microseconds
+    pub ntsc_speed: u16,
+    /// Bank register values at init; all zero for tunes without bank switching
+    pub bank_init: [u8; 8],
+    /// Play routine period on PAL consoles, in microseconds
+    pub pal_speed: u16,
+    /// Bit 0: PAL tune, bit 1: plays on both
+    pub region: u8,
+    /// Bit set of the expansion sound chips the tune uses
+    pub expansion_chips: u8,
+    /// Everything after the header, loaded at `load_addr`
+    pub data: Vec<u8>,
+    /// Names and lengths from NSFe/NSF2 metadata, in song order; may be shorter
+    /// than `songs`
+    pub tracks: Vec<Track>,
+}
+
+/// What NSFe/NSF2 metadata says about one song.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    pub name: Option<String>,
+    /// Play time before the fade, in milliseconds
+    pub length_ms: Option<u32>,
+    pub fade_ms: Option<u32>,
+}
+
+impl Track {
+    /// Volume `elapsed_ms` into the song: full, then fading out over the fade
+    /// time. `None` once the song is over; songs of unknown length never are.
+    pub fn volume(&self, elapsed_ms: u64) -> Option<f32> {
+        let Some(length) = self.length_ms else {
+            return Some(1.0);
+        };
+        let fade = self.fade_ms.unwrap_or(DEFAULT_FADE_MS) as u64;
+        match elapsed_ms.checked_sub(length as u64) {
+            None => Some(1.0),
+            Some(fading) if fading < fade => Some(1.0 - fading as f32 / fade as f32),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Id and payload of an NSFe chunk.
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/// Splits NSFe chunks until NEND or the end of `raw`.
+fn chunks(mut raw: &[u8]) -> Result<Vec<Chunk<'_>>, NsfError> {
+    let mut chunks = Vec::new();
+    while raw.len() >= 8 {
+        let len = u32::from_le_bytes(raw[0..4].try_into().unwrap()) as usize;
+        let id: [u8; 4] = raw[4..8].try_into().unwrap();
+        if id == *b"NEND" {
+            break;
+        }
+        let payload = raw
+            .get(8..8usize.saturating_add(len))
+            .ok_or(NsfError::TruncatedFile)?;
+        chunks.push((id, payload));
+        raw = &raw[8 + len..];
+    }
+    Ok(chunks)
+}
+
+/// Zero-terminated strings following each other, as in `auth` and `tlbl`.
+fn strings(payload: &[u8]) -> Vec<String> {
+    payload
+        .split(|&b| b == 0)
+        .map(|s| String::from_utf8_lossy(s).trim().to_string())
+        .collect()
+}
+
+/// Little-endian 32-bit times in milliseconds, negative for "not given".
+fn times(payload: &[u8]) -> Vec<Option<u32>> {
+    payload
+        .chunks_exact(4)
+        .map(|time| u32::try_from(i32::from_le_bytes(time.try_into().unwrap())).ok())
+        .collect()
+}
+
+/// Header text field: zero-terminated, in a 32-byte slot.
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).trim().to_string()
+}
+
+impl Nsf {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Nsf, NsfError> {
+        let raw = std::fs::read(path).map_err(|e| NsfError::Io(e.to_string()))?;
+        Nsf::new(&raw)
+    }
+
+    pub fn new(raw: &[u8]) -> Result<Nsf, NsfError> {
+        if raw.starts_with(&NSFE_MAGIC) {
+            return Nsf::from_nsfe(&raw[NSFE_MAGIC.len()..]);
+        }
+        if !raw.starts_with(&MAGIC) {
+            return Err(NsfError::BadMagic);
+        }
+        if raw.len() < HEADER_SIZE {
+            return Err(NsfError::TruncatedFile);
+        }
+        let word = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let load_addr = word(0x08);
+        if load_addr < 0x8000 {
+            return Err(NsfError::BadLoadAddress(load_addr));
+        }
+        // NSF2 may put metadata chunks after the data, whose length it then gives
+        let data_len = u32::from_le_bytes([raw[0x7D], raw[0x7E], raw[0x7F], 0]) as usize;
+        let (data, metadata) = match raw[HEADER_SIZE..].split_at_checked(data_len) {
+            Some(split) if raw[0x05] >= 2 && data_len > 0 => split,
+            _ => (&raw[HEADER_SIZE..], &[][..]),
+        };
+        let mut nsf = Nsf {
+            version: raw[0x05],
+            songs: raw[0x06].max(1),
+            starting_song: raw[0x07].clamp(1, raw[0x06].max(1)),
+            load_addr,
+            init_addr: word(0x0A),
+            play_addr: word(0x0C),
+            title: text(&raw[0x0E..0x2E]),
+            artist: text(&raw[0x2E..0x4E]),
+            copyright: text(&raw[0x4E..0x6E]),
+            ntsc_speed: word(0x6E),
+            bank_init: raw[0x70..0x78].try_into().unwrap(),
+            pal_speed: word(0x78),
+            region: raw[0x7A],
+            expansion_chips: raw[0x7B],
+            data: data.to_vec(),
+            tracks: Vec::new(),
+        };
+        for (id, payload) in chunks(metadata)? {
+            nsf.read_chunk(id, payload)?;
+        }
+        Ok(nsf)
+    }
+
+    /// Tune from the chunks of an NSFe file, after its magic.
+    fn from_nsfe(raw: &[u8]) -> Result<Nsf, NsfError> {
+        let chunks = chunks(raw)?;
+        let find = |id: &[u8; 4]| chunks.iter().find(|(chunk, _)| chunk == id);
+        let info = find(b"INFO").ok_or(NsfError::MissingChunk("INFO"))?.1;
+        let data = find(b"DATA").ok_or(NsfError::MissingChunk("DATA"))?.1;
+        if info.len() < 8 {
+            return Err(NsfError::TruncatedFile);
+        }
+        let word = |offset: usize| u16::from_le_bytes([info[offset], info[offset + 1]]);
+        let load_addr = word(0);
+        if load_addr < 0x8000 {
+            return Err(NsfError::BadLoadAddress(load_addr));
+        }
+        let songs = info.get(8).copied().unwrap_or(1).max(1);
+        let mut nsf = Nsf {
+            version: 1,
+            songs,
+            starting_song: info
+                .get(9)
+                .map_or(1, |&song| song.saturating_add(1).min(songs)),
+            load_addr,
+            init_addr: word(2),
+            play_addr: word(4),
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ntsc_speed: 0,
+            bank_init: [0; 8],
+            pal_speed: 0,
+            region: info[6],
+            expansion_chips: info[7],
+            data: data.to_vec(),
+            tracks: Vec::new(),
+        };
+        for &(id, payload) in &chunks {
+            match &id {
+                b"INFO" | b"DATA" => {}
+                b"BANK" => {
+                    let len = payload.len().min(8);
+                    nsf.bank_init[..len].copy_from_slice(&payload[..len]);
+                }
+                b"RATE" if payload.len() >= 4 => {
+                    nsf.ntsc_speed = u16::from_le_bytes([payload[0], payload[1]]);
+                    nsf.pal_speed = u16::from_le_bytes([payload[2], payload[3]]);
+                }
+                _ => nsf.read_chunk(id, payload)?,
+            }
+        }
+        Ok(nsf)
+    }
+
+    /// Takes in a metadata chunk shared by NSFe and NSF2.
+    fn read_chunk(&mut self, id: [u8; 4], payload: &[u8]) -> Result<(), NsfError> {
+        match &id {
+            b"auth" => {
+                let mut fields = strings(payload).into_iter();
+                for field in [&mut self.title, &mut self.artist, &mut self.copyright] {
+                    if let Some(text) = fields.next().filter(|text| !text.is_empty()) {
+                        *field = text;
+                    }
+                }
+            }
+            b"tlbl" => {
+                for (track, name) in self.tracks_mut().iter_mut().zip(strings(payload)) {
+                    track.name = Some(name).filter(|name| !name.is_empty());
+                }
+            }
+            b"time" => {
+                for (track, time) in self.tracks_mut().iter_mut().zip(times(payload)) {
+                    track.length_ms = time;
+                }
+            }
+            b"fade" => {
+                for (track, time) in self.tracks_mut().iter_mut().zip(times(payload)) {
+                    track.fade_ms = time;
+                }
+            }
+            // Header chunks, already read for NSFe and in the header for NSF2
+            b"INFO" | b"DATA" | b"BANK" | b"RATE" | b"NSF2" => {}
+            _ if id[0].is_ascii_uppercase() => {
+                return Err(NsfError::UnsupportedChunk(
+                    String::from_utf8_lossy(&id).into_owned(),
+                ))
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Per-song metadata, grown to one entry per song.
+    fn tracks_mut(&mut self) -> &mut [Track] {
+        self.tracks.resize(self.songs as usize, Track::default());
+        &mut self.tracks
+    }
+
+    /// Metadata of song `song`, counted from 0.
+    pub fn track(&self, song: u8) -> Track {
+        self.tracks.get(song as usize).cloned().unwrap_or_default()
+    }
+
+    /// Initial bank registers, if the tune uses bank switching.
+    pub fn bank_switching(&self) -> Option<[u8; 8]> {
+        self.bank_init
+            .iter()
+            .any(|&bank| bank != 0)
+            .then_some(self.bank_init)
+    }
+
+    /// Console to play the tune on: PAL for PAL-only tunes, NTSC otherwise.
+    pub fn region(&self) -> Region {
+        match self.region & 0b11 {
+            0b01 => Region::Pal,
+            _ => Region::Ntsc,
+        }
+    }
+
+    /// CPU cycles between two calls of the play routine.
+    pub fn cycles_per_play(&self) -> f64 {
+        let region = self.region();
+        let speed = match (region, self.ntsc_speed, self.pal_speed) {
+            (Region::Ntsc, 0, _) => DEFAULT_NTSC_SPEED,
+            (Region::Ntsc, speed, _) => speed,
+            (Region::Pal | Region::Dendy, _, 0) => DEFAULT_PAL_SPEED,
+            (Region::Pal | Region::Dendy, _, speed) => speed,
+        };
+        speed as f64 * region.cpu_mhz()
+    }
+
+    /// Names of the expansion chips the tune asks for.
+    pub fn expansion_names(&self) -> Vec<&'static str> {
+        EXPANSION_CHIPS
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| self.expansion_chips & (1 << bit) != 0)
+            .map(|(_, &name)| name)
+            .collect()
+    }
+}
+
+impl fmt::Display for Nsf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} - {} ({}), {} songs, play routine at {:.2}Hz",
+            self.title,
+            self.artist,
+            self.copyright,
+            self.songs,
+            self.region().cpu_clock_rate() as f64 / self.cycles_per_play()
+        )
+    }
+}
+
+/// Drives a tune: calls init when a song starts, then play at the tune's rate.
+pub struct Player<'a> {
+    pub cpu: CPU<Bus<'a>>,
+    region: Region,
+    init_addr: u16,
+    play_addr: u16,
+    banks: [u8; 8],
+    cycles_per_play: f64,
+    /// Cycles left before the next play call
+    until_play: f64,
+    /// CPU cycles run since the player was created
+    pub cycles: u64,
+}
+
+impl Player<'_> {
+    pub fn new<'a>(nsf: &Nsf, sample_rate: f64) -> Player<'a> {
+        let board = NsfBoard::new(nsf);
+        let banks = board.initial_banks();
+        let region = nsf.region();
+        let bus = Bus::with_mapper(
+            Rc::new(RefCell::new(board)),
+            region,
+            sample_rate,
+            |_: &NesPPU, _: &mut ControllerPorts| {},
+        );
+        Player {
+            cpu: CPU::new(bus),
+            region,
+            init_addr: nsf.init_addr,
+            play_addr: nsf.play_addr,
+            banks,
+            cycles_per_play: nsf.cycles_per_play(),
+            until_play: 0.0,
+            cycles: 0,
+        }
+    }
+
+    /// Starts song `song` (counted from 0) the way the NSF spec asks: cleared RAM,
+    /// silent APU, initial banks, then the init routine run to completion.
+    pub fn start(&mut self, song: u8) {
+        let cpu = &mut self.cpu;
+        cpu.power_on();
+        for addr in (0x0000..0x0800).chain(0x6000..0x8000) {
+            cpu.mem_write(addr, 0);
+        }
+        for addr in 0x4000..=0x4013 {
+            cpu.mem_write(addr, 0);
+        }
+        cpu.mem_write(0x4015, 0x0F);
+        cpu.mem_write(0x4017, 0x40);
+        for (register, &bank) in (0x5FF8..).zip(self.banks.iter()) {
+            cpu.mem_write(register, bank);
+        }
+
+        cpu.register_a = song;
+        cpu.register_x = (self.region == Region::Pal) as u8;
+        self.call(self.init_addr);
+        // An init routine still running after a second has hung
+        let limit = self.region.cpu_clock_rate();
+        let mut cycles = 0;
+        while self.cpu.program_counter != IDLE_LOOP && cycles < limit {
+            cycles += self.cpu.step().cycles as u64;
+            self.cpu.collect_audio_sample();
+        }
+        self.cycles += cycles;
+        self.until_play = self.cycles_per_play;
+    }
+
+    /// Makes the CPU run `addr` as a subroutine returning to the idle loop.
+    fn call(&mut self, addr: u16) {
+        self.cpu.stack_push_u16(IDLE_LOOP - 1);
+        self.cpu.program_counter = addr;
+    }
+
+    /// Runs one instruction, calling play when it is due and the previous call
+    /// has returned. Returns the audio sample completed meanwhile, if any.
+    pub fn step(&mut self) -> Option<f32> {
+        let cycles = self.cpu.step().cycles;
+        self.cycles += cycles as u64;
+        self.until_play -= cycles as f64;
+        if self.until_play <= 0.0 && self.cpu.program_counter == IDLE_LOOP {
+            // A late call does not make the next ones come faster
+            self.until_play = (self.until_play + self.cycles_per_play).max(0.0);
+            self.call(self.play_addr);
+        }
+        self.cpu.collect_audio_sample()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// An NSF whose init stores A at $00 and whose play routine counts calls in $01.
+    fn test_nsf(bank_init: [u8; 8]) -> Vec<u8> {
+        let mut raw = MAGIC.to_vec();
+        raw.extend([1, 3, 2]); // version, songs, starting song
+        raw.extend(0x8000u16.to_le_bytes()); // load
+        raw.extend(0x8000u16.to_le_bytes()); // init
+        raw.extend(0x8003u16.to_le_bytes()); // play
+        let mut title = b"Test Tune".to_vec();
+        title.resize(32, 0);
+        raw.extend(title);
+        raw.extend([0; 64]);
+        raw.extend(16639u16.to_le_bytes());
+        raw.extend(bank_init);
+        raw.extend([0; 8]);
+        raw.resize(HEADER_SIZE, 0);
+        raw.extend([0x85, 0x00, 0x60]); // init: STA $00; RTS
+        raw.extend([0xE6, 0x01, 0x60]); // play: INC $01; RTS
+        raw
+    }
+
+    #[test]
+    fn test_header() {
+        let nsf = Nsf::new(&test_nsf([0; 8])).unwrap();
+        assert_eq!(nsf.title, "Test Tune");
+        assert_eq!((nsf.songs, nsf.starting_song), (3, 2));
+        assert_eq!(nsf.bank_switching(), None);
+        assert_eq!(
+            Nsf::new(&test_nsf([0, 0, 0, 0, 0, 0, 0, 1]))
+                .unwrap()
+                .bank_switching(),
+            Some([0, 0, 0, 0, 0, 0, 0, 1])
+        );
+        assert_eq!(Nsf::new(b"NES\x1Axxxx").err(), Some(NsfError::BadMagic));
+
+        // PAL-only tunes play at the PAL period, on the PAL clock
+        let mut raw = test_nsf([0; 8]);
+        raw[0x7A] = 0b01;
+        let nsf = Nsf::new(&raw).unwrap();
+        assert_eq!(nsf.region(), Region::Pal);
+        assert!((nsf.cycles_per_play() - 19997.0 * 1.662607).abs() < 0.01);
+    }
+
+    fn chunk(id: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = (payload.len() as u32).to_le_bytes().to_vec();
+        chunk.extend(id);
+        chunk.extend(payload);
+        chunk
+    }
+
+    fn metadata() -> Vec<u8> {
+        let mut raw = chunk(b"tlbl", b"Intro\0\0Boss\0");
+        raw.extend(chunk(
+            b"time",
+            &[60_000i32.to_le_bytes(), (-1i32).to_le_bytes()].concat(),
+        ));
+        raw.extend(chunk(b"fade", &2000i32.to_le_bytes()));
+        raw.extend(chunk(b"plst", &[2, 1, 0]));
+        raw.extend(chunk(b"NEND", &[]));
+        raw
+    }
+
+    #[test]
+    fn test_nsfe_and_nsf2_metadata() {
+        let mut raw = NSFE_MAGIC.to_vec();
+        let mut info = [0x8000u16, 0x8000, 0x8003]
+            .iter()
+            .flat_map(|addr| addr.to_le_bytes())
+            .collect::<Vec<u8>>();
+        info.extend([0, 0, 3, 1]); // NTSC, no chips, 3 songs, starting at the second
+        raw.extend(chunk(b"INFO", &info));
+        raw.extend(chunk(b"DATA", &[0x85, 0x00, 0x60, 0xE6, 0x01, 0x60]));
+        raw.extend(chunk(b"auth", b"Tune\0Someone\0\0Ripper\0"));
+        raw.extend(metadata());
+        let nsf = Nsf::new(&raw).unwrap();
+        assert_eq!((nsf.songs, nsf.starting_song), (3, 2));
+        assert_eq!(
+            (nsf.title.as_str(), nsf.artist.as_str()),
+            ("Tune", "Someone")
+        );
+        assert_eq!(nsf.data.len(), 6);
+        let intro = nsf.track(0);
+        assert_eq!(intro.name.as_deref(), Some("Intro"));
+        assert_eq!((intro.length_ms, intro.fade_ms), (Some(60_000), Some(2000)));
+        assert_eq!(nsf.track(1), Track::default());
+        assert_eq!(nsf.track(2).name.as_deref(), Some("Boss"));
+
+        // The fade runs from the end of the length to silence
+        assert_eq!(intro.volume(59_000), Some(1.0));
+        assert_eq!(intro.volume(61_000), Some(0.5));
+        assert_eq!(intro.volume(62_000), None);
+        assert_eq!(nsf.track(1).volume(u64::MAX), Some(1.0));
+
+        let mut unknown = raw.clone();
+        unknown.truncate(unknown.len() - 8);
+        unknown.extend(chunk(b"XTRA", &[]));
+        assert_eq!(
+            Nsf::new(&unknown).err(),
+            Some(NsfError::UnsupportedChunk("XTRA".to_string()))
+        );
+
+        // NSF2: the same chunks after the data, whose length the header gives
+        let mut raw = test_nsf([0; 8]);
+        raw[0x05] = 2;
+        raw[0x7D] = 6;
+        raw.extend(metadata());
+        let nsf = Nsf::new(&raw).unwrap();
+        assert_eq!(nsf.data.len(), 6);
+        assert_eq!(nsf.track(0).length_ms, Some(60_000));
+        assert_eq!(nsf.title, "Test Tune");
+    }
+
+    #[test]
+    fn test_init_and_play_rate() {
+        let nsf = Nsf::new(&test_nsf([0; 8])).unwrap();
+        let mut player = Player::new(&nsf, 44100.0);
+        player.start(1);
+        assert_eq!(player.cpu.mem_read(0x00), 1);
+        assert_eq!(player.cpu.program_counter, IDLE_LOOP);
+
+        // One second plays sixty times
+        let start = player.cycles;
+        let mut samples = 0;
+        while player.cycles - start < 1_789_773 {
+            samples += player.step().is_some() as usize;
+        }
+        assert_eq!(player.cpu.mem_read(0x01), 60);
+        assert!((44000..45000).contains(&samples));
+    }
+
+    #[test]
+    fn test_bank_switching() {
+        // Bank 1 at $8000 holds the code: it is the file's second 4KB
+        let mut raw = test_nsf([1, 0, 0, 0, 0, 0, 0, 0]);
+        let code = raw.split_off(HEADER_SIZE);
+        raw.extend(vec![0xFF; 0x1000]);
+        raw.extend(code);
+        let nsf = Nsf::new(&raw).unwrap();
+        let mut player = Player::new(&nsf, 44100.0);
+        player.start(2);
+        assert_eq!(player.cpu.mem_read(0x00), 2);
+        player.cpu.mem_write(0x5FF8, 0);
+        assert_eq!(player.cpu.mem_read(0x8000), 0xFF);
+    }
+}
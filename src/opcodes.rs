@@ -1,5 +1,4 @@
 use crate::cpu::AddressingMode;
-use std::collections::HashMap;
 
 /// Represents a single 6502 CPU instruction opcode.
 pub struct OpCode {
@@ -374,13 +373,31 @@ lazy_static! {
 
     ];
 
-    /// A HashMap for quick lookup of an OpCode by its byte code.
-    /// This is generated at compile time from the `CPU_OPS_CODES` vector.
-    pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
-        let mut map = HashMap::new();
+    /// The OpCode of every byte code, indexed by it; `None` for the bytes that are
+    /// not in `CPU_OPS_CODES`. Built once from that vector, then a plain array
+    /// index on the `step()` hot path.
+    pub static ref OPCODE_TABLE: [Option<&'static OpCode>; 256] = {
+        let mut table = [None; 256];
         for cpuop in &*CPU_OPS_CODES {
-            map.insert(cpuop.code, cpuop);
+            table[cpuop.code as usize] = Some(cpuop);
         }
-        map
+        table
     };
 }
+
+/// The OpCode for a byte code, if it is one.
+pub fn lookup(code: u8) -> Option<&'static OpCode> {
+    OPCODE_TABLE[code as usize]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_opcode_table() {
+        // Official and unofficial opcodes together cover every byte
+        assert!((0..=255u8).all(|code| lookup(code).is_some_and(|op| op.code == code)));
+        assert_eq!(lookup(0xA9).map(|op| op.mnemonic), Some("LDA"));
+    }
+}
@@ -0,0 +1,190 @@
+//
+// PNG encoding for screenshots: 8-bit RGB, one zlib stream compressed with the
+// Deflate encoder from `archive`. Each row gets the "Sub" filter, which turns the
+// long flat runs of NES pictures into zeros.
+//
+// Clips are saved as APNG: the same chunks plus an animation control chunk, with
+// indexed pixels and one frame control + frame data chunk per clip patch.
+//
+
+use crate::archive;
+use crate::clip::Clip;
+use crate::hash::{adler32, crc32};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const FILTER_SUB: u8 = 1;
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+/// zlib wrapper (RFC 1950) around a Deflate stream.
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    out.extend(archive::deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+/// Encodes `rgb` (3 bytes per pixel, rows top to bottom) as a PNG file.
+pub fn encode_rgb(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let stride = width * 3;
+    let mut filtered = Vec::with_capacity((stride + 1) * height);
+    for row in rgb.chunks(stride).take(height) {
+        filtered.push(FILTER_SUB);
+        for (i, &byte) in row.iter().enumerate() {
+            let left = if i >= 3 { row[i - 3] } else { 0 };
+            filtered.push(byte.wrapping_sub(left));
+        }
+    }
+
+    let mut header = vec![];
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    header.extend([8, 2, 0, 0, 0]); // 8 bits, truecolor, deflate, adaptive filters, no interlace
+
+    let mut png = SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib(&filtered));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Encodes `clip` as a looping indexed-color APNG at 60 frames per second.
+pub fn encode_apng(clip: &Clip) -> Vec<u8> {
+    let patches = clip.patches(1);
+    let mut header = vec![];
+    header.extend((clip.width as u32).to_be_bytes());
+    header.extend((clip.height as u32).to_be_bytes());
+    header.extend([8, 3, 0, 0, 0]); // 8 bits, indexed color
+
+    let mut png = SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    let mut control = (patches.len() as u32).to_be_bytes().to_vec();
+    control.extend(0u32.to_be_bytes()); // loop forever
+    chunk(&mut png, b"acTL", &control);
+    let mut palette: Vec<u8> = clip.colors.iter().flatten().copied().collect();
+    palette.extend([0, 0, 0]);
+    chunk(&mut png, b"PLTE", &palette);
+    let mut alpha = vec![255; clip.colors.len()];
+    alpha.push(0);
+    chunk(&mut png, b"tRNS", &alpha);
+
+    let mut sequence = 0u32;
+    for (index, patch) in patches.iter().enumerate() {
+        let mut frame = sequence.to_be_bytes().to_vec();
+        for value in [patch.width, patch.height, patch.x, patch.y] {
+            frame.extend((value as u32).to_be_bytes());
+        }
+        frame.extend((patch.duration as u16).to_be_bytes());
+        frame.extend(60u16.to_be_bytes());
+        // Keep the previous frame; the first frame replaces, later ones blend over
+        frame.extend([0, if index == 0 { 0 } else { 1 }]);
+        chunk(&mut png, b"fcTL", &frame);
+        sequence += 1;
+
+        let mut rows = Vec::with_capacity((patch.width + 1) * patch.height);
+        for row in patch.pixels.chunks(patch.width) {
+            rows.push(0); // no filter, the usual choice for indexed pictures
+            rows.extend(row);
+        }
+        if index == 0 {
+            chunk(&mut png, b"IDAT", &zlib(&rows));
+        } else {
+            let mut data = sequence.to_be_bytes().to_vec();
+            data.extend(zlib(&rows));
+            chunk(&mut png, b"fdAT", &data);
+            sequence += 1;
+        }
+    }
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clip::ClipBuffer;
+    use crate::render::frame::Frame;
+
+    #[test]
+    fn test_encode_rgb() {
+        let rgb: Vec<u8> = (0..4 * 2).flat_map(|i| [i as u8 * 10, 0, 255]).collect();
+        let png = encode_rgb(4, 2, &rgb);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 4);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // Undo the zlib wrapper and the Sub filter to get the pixels back
+        let idat_len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        let zlib = &png[41..41 + idat_len];
+        let filtered = archive::inflate(&zlib[2..zlib.len() - 4], 0).unwrap();
+        assert_eq!(adler32(&filtered).to_be_bytes(), zlib[zlib.len() - 4..]);
+        let mut pixels = vec![];
+        for row in filtered.chunks(13) {
+            assert_eq!(row[0], FILTER_SUB);
+            let start = pixels.len();
+            for (i, &byte) in row[1..].iter().enumerate() {
+                let left = if i >= 3 { pixels[start + i - 3] } else { 0 };
+                pixels.push(byte.wrapping_add(left));
+            }
+        }
+        assert_eq!(pixels, rgb);
+    }
+
+    #[test]
+    fn test_encode_apng_chunks() {
+        let mut buffer = ClipBuffer::new(1);
+        let mut frame = Frame::new();
+        buffer.push(&frame);
+        frame.set_pixel(3, 4, (255, 255, 255));
+        buffer.push(&frame);
+        buffer.push(&frame);
+        let png = encode_apng(&buffer.snapshot());
+
+        let mut chunks = vec![];
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let kind = std::str::from_utf8(&png[pos + 4..pos + 8])
+                .unwrap()
+                .to_string();
+            if kind == "fcTL" {
+                let data = &png[pos + 8..pos + 8 + len];
+                // Width, height, x, y and the delay numerator
+                let field = |i: usize| u32::from_be_bytes(data[i..i + 4].try_into().unwrap());
+                chunks.push(format!(
+                    "fcTL {}x{}+{}+{} {}",
+                    field(4),
+                    field(8),
+                    field(12),
+                    field(16),
+                    u16::from_be_bytes([data[20], data[21]])
+                ));
+            } else {
+                chunks.push(kind);
+            }
+            pos += len + 12;
+        }
+        assert_eq!(
+            chunks,
+            [
+                "IHDR",
+                "acTL",
+                "PLTE",
+                "tRNS",
+                "fcTL 256x240+0+0 1",
+                "IDAT",
+                "fcTL 1x1+3+4 2",
+                "fdAT",
+                "IEND"
+            ]
+        );
+    }
+}
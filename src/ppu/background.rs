@@ -0,0 +1,103 @@
+//
+// Pipeline de l'arrière-plan, dot par dot comme sur la console. Tous les 8 dots
+// le PPU lit l'octet de nametable, l'octet d'attributs puis les deux plans de la
+// tuile suivante; ils sont chargés dans des registres à décalage de 16 bits qui
+// avancent d'un bit par pixel. Le scroll fin x choisit le bit lu.
+//
+// Les lectures se font au moment où la console les fait: un changement de banque
+// CHR, de scroll ou de câblage en cours d'image touche les pixels d'après.
+//
+// `scanline` fait le même travail pour toute une ligne d'un coup, pour le rendu
+// par ligne (Pipeline::Scanline).
+//
+
+use super::registers::loopy::Loopy;
+use super::NesPPU;
+
+/// Octets lus pour la tuile suivante
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fetch {
+    pub tile: u8,
+    /// Les deux bits de palette de la tuile, déjà extraits de l'octet d'attributs
+    pub palette: u8,
+    pub low: u8,
+    pub high: u8,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Background {
+    pub next: Fetch,
+    pattern_low: u16,
+    pattern_high: u16,
+    palette_low: u16,
+    palette_high: u16,
+}
+
+impl Background {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Charge la tuile lue dans l'octet bas des registres (dots 9, 17... 257, 329, 337)
+    pub fn reload(&mut self) {
+        let fill = |bit: u8| if bit != 0 { 0xff } else { 0x00 };
+        self.pattern_low = (self.pattern_low & 0xff00) | self.next.low as u16;
+        self.pattern_high = (self.pattern_high & 0xff00) | self.next.high as u16;
+        self.palette_low = (self.palette_low & 0xff00) | fill(self.next.palette & 1);
+        self.palette_high = (self.palette_high & 0xff00) | fill(self.next.palette & 2);
+    }
+
+    pub fn shift(&mut self) {
+        self.pattern_low <<= 1;
+        self.pattern_high <<= 1;
+        self.palette_low <<= 1;
+        self.palette_high <<= 1;
+    }
+
+    /// Entrée de palette RAM du pixel courant, 0 si le pixel est transparent
+    pub fn pixel(&self, fine_x: u8) -> u8 {
+        let bit = 0x8000 >> fine_x;
+        let value =
+            (self.pattern_low & bit != 0) as u8 | ((self.pattern_high & bit != 0) as u8) << 1;
+        if value == 0 {
+            return 0;
+        }
+        let palette =
+            (self.palette_low & bit != 0) as u8 | ((self.palette_high & bit != 0) as u8) << 1;
+        palette * 4 + value
+    }
+}
+
+/// Entrées de palette RAM d'une ligne d'arrière-plan, depuis `start` (v et x au
+/// début de la ligne)
+pub fn scanline(ppu: &NesPPU, start: Loopy) -> [u8; 256] {
+    let bank = ppu.ctrl.bknd_pattern_addr();
+    let mut line = [0; 256];
+    let mut cursor = start;
+    // La première tuile commence jusqu'à 7 pixels à gauche de l'écran
+    let mut tile_x = -(start.x as isize);
+    while tile_x < 256 {
+        let tile = ppu.read_nametable(cursor.tile_addr()) as u16;
+        let attribute = ppu.read_nametable(cursor.attribute_addr());
+        let palette = (attribute >> cursor.attribute_shift()) & 0b11;
+        let row = bank + tile * 16 + cursor.fine_y();
+        let (low, high) = (ppu.read_chr(row), ppu.read_chr(row + 8));
+
+        for x in 0..8 {
+            let Ok(screen_x) = usize::try_from(tile_x + x as isize) else {
+                continue;
+            };
+            if screen_x >= 256 {
+                break;
+            }
+            let value = (low >> (7 - x) & 1) | (high >> (7 - x) & 1) << 1;
+            if value != 0 {
+                line[screen_x] = palette * 4 + value;
+            }
+        }
+
+        cursor.increment_coarse_x();
+        tile_x += 8;
+    }
+    line
+}
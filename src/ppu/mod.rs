@@ -1,44 +1,122 @@
 use crate::cartridge::Mirroring;
-use registers::addr::AddrRegister;
+use crate::mapper::nrom::Nrom;
+use crate::mapper::MapperRef;
+use crate::region::Region;
+use crate::strict::{self, Violation};
+use background::Background;
 use registers::control::ControlRegister;
+use registers::loopy::Loopy;
 use registers::mask::MaskRegister;
-use registers::scroll::ScrollRegister;
 use registers::status::StatusRegister;
+use sprites::LineSprite;
+use std::cell::RefCell;
+use std::rc::Rc;
 
+pub mod background;
 pub mod registers;
+pub mod sprites;
+pub mod viewer;
+
+/// Images au bout desquelles un bit du latch d'open bus retombe à 0 (~600 ms)
+const OPEN_BUS_DECAY_FRAMES: u64 = 36;
+
+/// Écriture en palette faite pendant l'image visible, rejouée par le renderer
+/// pour les effets raster (dégradés de ciel, barres de couleur...)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PaletteWrite {
+    pub scanline: u16,
+    pub dot: usize,
+    pub index: usize,
+    pub value: u8,
+}
+
+/// Position du PPU: image, scanline (0-261, 0-311 en PAL et Dendy; 241 ouvre le
+/// VBlank, 291 sur Dendy) et dot (0-340).
+/// Affichée comme dans nestest.log: `  0, 21`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PpuPosition {
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+impl std::fmt::Display for PpuPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:3},{:3}", self.scanline, self.dot)
+    }
+}
+
+/// Façon dont le PPU produit l'arrière-plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pipeline {
+    /// Lectures dot par dot, comme la console
+    #[default]
+    Dot,
+    /// Toute la ligne d'un coup à la fin de sa partie visible, depuis le scroll
+    /// qu'elle avait en commençant: plus rapide, et les splits entre deux lignes
+    /// restent justes, mais un changement en cours de ligne n'a d'effet qu'à la
+    /// suivante
+    Scanline,
+}
+
+/// Réglages du PPU qui ne viennent pas du jeu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PpuOptions {
+    pub pipeline: Pipeline,
+    /// 8 sprites au plus par ligne, comme la console. Sans la limite, les jeux
+    /// qui en alignent plus ne clignotent plus (le drapeau de débordement reste
+    /// celui de la console)
+    pub sprite_limit: bool,
+}
+
+impl Default for PpuOptions {
+    fn default() -> Self {
+        PpuOptions {
+            pipeline: Pipeline::Dot,
+            sprite_limit: true,
+        }
+    }
+}
 
 pub struct NesPPU {
-    pub chr_rom: Vec<u8>,
-    pub mirroring: Mirroring,
+    pub mapper: MapperRef,
+    mirroring: Mirroring,
     pub ctrl: ControlRegister,
     pub mask: MaskRegister,
     pub status: StatusRegister,
-    pub scroll: ScrollRegister,
-    pub addr: AddrRegister,
+    pub loopy: Loopy,
     pub vram: [u8; 2048],
 
     pub oam_addr: u8,
     pub oam_data: [u8; 256],
     pub palette_table: [u8; 32],
+    // Palette au début du frame en cours et écritures faites depuis
+    pub frame_palette: [u8; 32],
+    pub palette_writes: Vec<PaletteWrite>,
+    pub options: PpuOptions,
+    // Console pour laquelle le PPU compte ses lignes
+    pub region: Region,
+    // Pipeline de l'arrière-plan et pixels qu'il a produits
+    background: Background,
+    background_pixels: Vec<u8>,
+    // v et x au début de la ligne en cours, pour le rendu par ligne
+    line_start: Loopy,
+    // OAM secondaire et PPUMASK de chaque ligne visible
+    line_sprites: Vec<Vec<LineSprite>>,
+    line_masks: [MaskRegister; 240],
 
     internal_data_buf: u8,
+    // Latch du bus de données entre le CPU et le PPU, ce que rendent les
+    // registres en écriture seule, et l'image où chaque bit a été rafraîchi
+    open_bus: u8,
+    open_bus_refresh: [u64; 8],
 
     pub scanline: u16,
     cycles: usize,
     pub nmi_interrupt: Option<u8>,
-    
+
     // Compteur de frames pour le debugging et les statistiques
     pub frame_count: u64,
-    
-    // Support pour les techniques avancées
-    pub fine_x_scroll: u8,
-    pub temp_vram_addr: u16,
-    pub write_toggle: bool,
-    
-    // Historique des changements pour le split scroll et autres effets
-    pub scroll_changes: Vec<(u16, u8, u8)>, // (scanline, x, y)
-    pub palette_changes: Vec<(u16, usize, u8, u8)>, // (scanline, cycle, addr, value)
-    pub ctrl_changes: Vec<(u16, usize, u8)>, // (scanline, cycle, value)
 }
 
 pub trait PPU {
@@ -61,35 +139,55 @@ impl NesPPU {
     }
 
     pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        NesPPU::with_mapper(Rc::new(RefCell::new(Nrom::with_chr(chr_rom, mirroring))))
+    }
+
+    /// Crée un PPU branché sur la cartouche: CHR et nametables passent par le mapper
+    pub fn with_mapper(mapper: MapperRef) -> Self {
+        let mirroring = mapper.borrow().mirroring();
         NesPPU {
-            chr_rom: chr_rom,
-            mirroring: mirroring,
+            mapper,
+            mirroring,
             ctrl: ControlRegister::new(),
             mask: MaskRegister::new(),
             status: StatusRegister::new(),
             oam_addr: 0,
-            scroll: ScrollRegister::new(),
-            addr: AddrRegister::new(),
+            loopy: Loopy::new(),
             vram: [0; 2048],
             oam_data: [0; 64 * 4],
             palette_table: [0; 32],
+            frame_palette: [0; 32],
+            palette_writes: Vec::new(),
+            options: PpuOptions::default(),
+            region: Region::default(),
+            background: Background::new(),
+            background_pixels: vec![0; 256 * 240],
+            line_start: Loopy::new(),
+            line_sprites: vec![Vec::new(); 240],
+            line_masks: [MaskRegister::new(); 240],
             internal_data_buf: 0,
+            open_bus: 0,
+            open_bus_refresh: [0; 8],
 
             cycles: 0,
             scanline: 0,
             nmi_interrupt: None,
             frame_count: 0,
-            
-            // Initialisation des nouvelles fonctionnalités
-            fine_x_scroll: 0,
-            temp_vram_addr: 0,
-            write_toggle: false,
-            scroll_changes: Vec::new(),
-            palette_changes: Vec::new(),
-            ctrl_changes: Vec::new(),
         }
     }
 
+    /// Câblage des nametables en vigueur
+    pub fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    /// Change le câblage des nametables. Les mappers comme MMC1/MMC3 le font
+    /// à tout moment, y compris en plein milieu d'une image: les accès suivants
+    /// suivent le nouveau câblage
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+
     // Horizontal:
     //   [ A ] [ a ]
     //   [ B ] [ b ]
@@ -115,11 +213,241 @@ impl NesPPU {
         }
     }
 
+    /// Lit un octet des pattern tables ($0000-$1FFF) pour l'arrière-plan via le mapper
+    pub fn read_chr(&self, addr: u16) -> u8 {
+        self.mapper.borrow_mut().chr_read(addr)
+    }
+
+    /// Lit un octet des pattern tables pour les sprites (MMC5 a des banques séparées)
+    pub fn read_sprite_chr(&self, addr: u16) -> u8 {
+        self.mapper.borrow_mut().sprite_chr_read(addr)
+    }
+
+    /// Lit un octet de nametable ($2000-$2FFF), le mapper pouvant rediriger l'accès
+    pub fn read_nametable(&self, addr: u16) -> u8 {
+        if let Some(value) = self.mapper.borrow_mut().nametable_read(addr, &self.vram) {
+            return value;
+        }
+        self.vram[self.mirror_vram_addr(addr) as usize]
+    }
+
+    /// Écrit un octet de nametable, le mapper pouvant rediriger l'accès
+    fn write_nametable(&mut self, addr: u16, value: u8) {
+        if !self
+            .mapper
+            .borrow_mut()
+            .nametable_write(addr, value, &mut self.vram)
+        {
+            self.vram[self.mirror_vram_addr(addr) as usize] = value;
+        }
+    }
+
+    /// Ce que lirait le CPU en $2002, $2004 ou $2007, sans effacer le VBlank,
+    /// remettre le latch à zéro ni avancer l'adresse: pour les débogueurs
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        let open_bus = self.open_bus();
+        match addr {
+            0x2002 => self.status.snapshot() & 0xe0 | open_bus & 0x1f,
+            0x2004 => self.oam_data[self.oam_addr as usize],
+            0x2007 => match self.loopy.addr() {
+                0x3f00..=0x3fff => {
+                    self.palette_table[NesPPU::palette_index(self.loopy.addr())] | open_bus & 0xc0
+                }
+                _ => self.internal_data_buf,
+            },
+            _ => open_bus,
+        }
+    }
+
+    /// Lecture par le CPU d'un registre ($2000-$2007). Les bits que le PPU ne
+    /// pilote pas (les 5 bas de $2002, les 2 hauts d'une couleur de palette, tous
+    /// ceux des registres en écriture seule) viennent du latch
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        let (value, driven) = match addr {
+            0x2002 => (self.read_status(), 0xe0),
+            0x2004 => (self.read_oam_data(), 0xff),
+            0x2007 if self.loopy.addr() >= 0x3f00 => (self.read_data(), 0x3f),
+            0x2007 => (self.read_data(), 0xff),
+            _ => return self.open_bus(),
+        };
+        self.refresh_open_bus(value, driven);
+        self.open_bus()
+    }
+
+    /// Les bits `driven` du latch prennent ceux de `value`: toute écriture dans
+    /// un registre, et les bits qu'une lecture pilote
+    pub fn refresh_open_bus(&mut self, value: u8, driven: u8) {
+        self.open_bus = self.open_bus & !driven | value & driven;
+        for bit in 0..8 {
+            if driven & (1 << bit) != 0 {
+                self.open_bus_refresh[bit] = self.frame_count;
+            }
+        }
+    }
+
+    /// Le latch, sans les bits qui ont eu le temps de retomber
+    pub fn open_bus(&self) -> u8 {
+        (0..8)
+            .filter(|&bit| {
+                self.frame_count.saturating_sub(self.open_bus_refresh[bit]) < OPEN_BUS_DECAY_FRAMES
+            })
+            .fold(0, |value, bit| value | self.open_bus & (1 << bit))
+    }
+
+    /// Rendu actif (arrière-plan ou sprites activés dans PPUMASK)
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask.show_background() || self.mask.show_sprites()
+    }
+
+    /// Index dans la palette RAM: $3F20-$3FFF répètent $3F00-$3F1F, et
+    /// $3F10/$3F14/$3F18/$3F1C sont des miroirs de $3F00/$3F04/$3F08/$3F0C
+    fn palette_index(addr: u16) -> usize {
+        let index = (addr & 0x1f) as usize;
+        match index {
+            0x10 | 0x14 | 0x18 | 0x1c => index - 0x10,
+            _ => index,
+        }
+    }
+
+    /// Écrit en palette et garde la trace du point (scanline, dot) où l'écriture a eu lieu
+    fn write_palette(&mut self, index: usize, value: u8) {
+        // La RAM de palette ne garde que 6 bits
+        if value > 0x3f {
+            strict::report(Violation::PaletteValue, || {
+                format!("${:02X} written to palette entry ${:02X}", value, index)
+            });
+        }
+        let value = value & 0x3f;
+        self.palette_table[index] = value;
+        if self.scanline < 240 {
+            self.palette_writes.push(PaletteWrite {
+                scanline: self.scanline,
+                dot: self.cycles,
+                index,
+                value,
+            });
+        }
+    }
+
+    /// Prévient le mapper du début d'une nouvelle scanline (compteurs IRQ du MMC5...)
+    fn notify_scanline(&mut self) {
+        let rendering = self.rendering_line();
+        self.mapper.borrow_mut().scanline(self.scanline, rendering);
+    }
+
+    /// Dernière ligne de l'image: 261 en NTSC, 311 en PAL et Dendy
+    fn pre_render_line(&self) -> u16 {
+        self.region.scanlines() - 1
+    }
+
+    /// Lignes où le PPU lit la mémoire pour afficher: visibles et pré-rendu
+    fn rendering_line(&self) -> bool {
+        self.rendering_enabled() && (self.scanline < 240 || self.scanline == self.pre_render_line())
+    }
+
     /// Incrémente l'adresse VRAM selon le bit de contrôle
     /// - Si le bit 2 du registre de contrôle est 0: incrémente de 1 (mode horizontal)
     /// - Si le bit 2 du registre de contrôle est 1: incrémente de 32 (mode vertical)
+    ///
+    /// Pendant le rendu, v sert de curseur à l'affichage: un accès à $2007 fait
+    /// alors avancer la tuile et la ligne à la fois, comme sur la console
     fn increment_vram_addr(&mut self) {
-        self.addr.increment(self.ctrl.vram_addr_increment());
+        if self.rendering_line() {
+            self.loopy.increment_coarse_x();
+            self.loopy.increment_y();
+        } else {
+            self.loopy.increment(self.ctrl.vram_addr_increment());
+        }
+    }
+
+    /// Un dot de rendu: lectures de la tuile suivante pour le pipeline de
+    /// l'arrière-plan, et v qui avance (tuile suivante tous les 8 dots, ligne
+    /// suivante au dot 256, puis recopie de t pour la ligne d'après)
+    fn render_dot(&mut self) {
+        let dot = self.cycles;
+        let fetching = (2..=257).contains(&dot) || (321..=337).contains(&dot);
+        if self.options.pipeline == Pipeline::Dot && fetching {
+            self.background.shift();
+            let pattern = self.ctrl.bknd_pattern_addr()
+                + self.background.next.tile as u16 * 16
+                + self.loopy.fine_y();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.background.reload();
+                    self.background.next.tile = self.read_nametable(self.loopy.tile_addr());
+                }
+                2 => {
+                    let attribute = self.read_nametable(self.loopy.attribute_addr());
+                    self.background.next.palette =
+                        (attribute >> self.loopy.attribute_shift()) & 0b11;
+                }
+                4 => self.background.next.low = self.read_chr(pattern),
+                6 => self.background.next.high = self.read_chr(pattern + 8),
+                _ => {}
+            }
+        }
+
+        match dot {
+            256 => {
+                self.loopy.increment_coarse_x();
+                self.loopy.increment_y();
+            }
+            257 => self.loopy.copy_horizontal(),
+            280..=304 if self.scanline == self.pre_render_line() => self.loopy.copy_vertical(),
+            // Après les écritures faites en HBlank, avant les deux premières
+            // tuiles de la ligne suivante
+            320 => self.line_start = self.loopy,
+            dot if dot % 8 == 0 && (dot < 256 || (328..=336).contains(&dot)) => {
+                self.loopy.increment_coarse_x()
+            }
+            _ => {}
+        }
+    }
+
+    /// Fin de la partie visible d'une ligne en rendu par ligne: toute la ligne
+    /// d'arrière-plan depuis le scroll qu'elle avait en commençant
+    fn render_scanline(&mut self) {
+        let mut line = match self.mask.show_background() {
+            true => background::scanline(self, self.line_start),
+            false => [0; 256],
+        };
+        if !self.mask.leftmost_8pxl_background() {
+            line[..8].fill(0);
+        }
+        let start = self.scanline as usize * 256;
+        self.background_pixels[start..start + 256].copy_from_slice(&line);
+    }
+
+    /// Dot 257: les sprites de la ligne suivante
+    fn evaluate_sprites(&mut self) {
+        let evaluation = sprites::evaluate(
+            &self.oam_data,
+            self.scanline,
+            self.ctrl.sprite_size(),
+            self.options.sprite_limit,
+        );
+        if evaluation.overflow {
+            self.status.set_sprite_overflow(true);
+        }
+        if let Some(next) = self.line_sprites.get_mut(self.scanline as usize + 1) {
+            *next = evaluation.sprites;
+        }
+    }
+
+    /// PPUMASK au début de la ligne visible `line`
+    pub fn line_mask(&self, line: usize) -> MaskRegister {
+        self.line_masks[line]
+    }
+
+    /// Sprites de l'OAM secondaire de la ligne visible `line`, dans l'ordre de l'OAM
+    pub fn line_sprites(&self, line: usize) -> &[LineSprite] {
+        &self.line_sprites[line]
+    }
+
+    /// Entrée de palette RAM du pixel d'arrière-plan (x, y) de l'image en cours,
+    /// 0 pour un pixel transparent ou sans rendu
+    pub fn background_pixel(&self, x: usize, y: usize) -> u8 {
+        self.background_pixels[y * 256 + x]
     }
 
     /// Avance le PPU d'un nombre donné de cycles avec support pour les effets avancés
@@ -127,71 +455,120 @@ impl NesPPU {
     ///
     /// Le PPU NES fonctionne avec des cycles précis:
     /// - 341 cycles par scanline
-    /// - 262 scanlines par frame (NTSC)
+    /// - 262 scanlines par frame en NTSC, 312 en PAL
     /// - Support pour split scroll, changements de palette mid-frame, etc.
-    pub fn tick(&mut self, cycles: u8) -> bool {
-        let mut remaining_cycles = cycles as usize;
-        
+    pub fn tick(&mut self, cycles: usize) -> bool {
+        let mut remaining_cycles = cycles;
+        let mut frame_done = false;
+
         while remaining_cycles > 0 {
+            // Le rendu activé au dernier dot de la ligne de pré-rendu d'une image
+            // impaire raccourcit la ligne sous le PPU: elle est déjà finie
+            if self.cycles >= self.scanline_length() {
+                frame_done |= self.next_scanline();
+                continue;
+            }
+
             // Calculer combien de cycles on peut traiter dans cette scanline
-            let cycles_until_next_scanline = 341 - self.cycles;
+            let cycles_until_next_scanline = self.scanline_length().saturating_sub(self.cycles);
             let cycles_to_process = remaining_cycles.min(cycles_until_next_scanline);
-            
+
             // Traiter cycle par cycle pour les effets mid-scanline
             for _ in 0..cycles_to_process {
                 self.cycles += 1;
-                
-                // Appliquer les changements de palette programmés pour ce cycle
-                self.apply_palette_changes_for_cycle();
-                
-                // Appliquer les changements de contrôle programmés pour ce cycle
-                self.apply_ctrl_changes_for_cycle();
-                
+
+                if self.rendering_line() {
+                    self.render_dot();
+                    if self.cycles == 257 && self.scanline < 240 {
+                        self.evaluate_sprites();
+                    }
+                }
+                if self.scanline < 240 {
+                    if self.cycles == 1 {
+                        self.line_masks[self.scanline as usize] = self.mask;
+                    }
+                    match self.options.pipeline {
+                        Pipeline::Dot if (1..=256).contains(&self.cycles) => {
+                            let shown = self.mask.show_background()
+                                && (self.cycles > 8 || self.mask.leftmost_8pxl_background());
+                            let pixel = match shown {
+                                true => self.background.pixel(self.loopy.x),
+                                false => 0,
+                            };
+                            self.background_pixels
+                                [self.scanline as usize * 256 + self.cycles - 1] = pixel;
+                        }
+                        Pipeline::Scanline if self.cycles == 256 => self.render_scanline(),
+                        _ => {}
+                    }
+                }
+
                 // Vérifier le sprite 0 hit pendant la scanline visible
                 if self.scanline < 240 && self.is_sprite_0_hit(self.cycles) {
                     self.status.set_sprite_zero_hit(true);
                 }
-                
+
                 // Gestion des scanlines spéciales
-                if self.cycles == 341 {
-                    self.end_of_scanline();
-                    if self.scanline >= 262 {
-                        return self.end_of_frame();
-                    }
+                if self.cycles >= self.scanline_length() {
+                    frame_done |= self.next_scanline();
                     break;
                 }
             }
-            
+
             remaining_cycles -= cycles_to_process;
         }
-        
-        false
+
+        frame_done
+    }
+
+    /// Dots de la scanline en cours, plus un: le dernier passe à la suivante.
+    /// Une image sur deux, la ligne de pré-rendu saute son dernier dot quand le
+    /// rendu est actif (pas en PAL)
+    fn scanline_length(&self) -> usize {
+        if self.scanline == self.pre_render_line()
+            && self.frame_count % 2 == 1
+            && self.rendering_enabled()
+            && self.region.skips_odd_frame_dot()
+        {
+            340
+        } else {
+            341
+        }
     }
-    
+
+    /// Passe à la scanline suivante, et à l'image suivante après la dernière.
+    /// Retourne true si une image vient de se terminer
+    fn next_scanline(&mut self) -> bool {
+        self.end_of_scanline();
+        self.scanline >= self.region.scanlines() && self.end_of_frame()
+    }
+
     /// Gère la fin d'une scanline
     fn end_of_scanline(&mut self) {
         self.cycles = 0;
         self.scanline += 1;
-        
-        // Appliquer les changements de scroll programmés pour cette scanline
-        self.apply_scroll_changes_for_scanline();
-        
-        // Scanline 241: début du VBlank
-        if self.scanline == 241 {
+        if self.scanline < self.region.scanlines() {
+            self.notify_scanline();
+        }
+
+        // Début du VBlank: scanline 241, 291 sur Dendy
+        if self.scanline == self.region.vblank_line() {
             self.status.set_vblank_status(true);
             self.status.set_sprite_zero_hit(false);
             if self.ctrl.generate_vblank_nmi() {
                 self.nmi_interrupt = Some(1);
             }
         }
-        
-        // Scanline 261: pré-render, reset des flags
-        if self.scanline == 261 {
+
+        // Ligne de pré-rendu: reset des flags
+        if self.scanline == self.pre_render_line() {
             self.status.set_sprite_zero_hit(false);
+            self.status.set_sprite_overflow(false);
             self.status.reset_vblank_status();
+            self.line_sprites.iter_mut().for_each(Vec::clear);
         }
     }
-    
+
     /// Gère la fin d'un frame
     fn end_of_frame(&mut self) -> bool {
         self.scanline = 0;
@@ -199,86 +576,39 @@ impl NesPPU {
         self.status.set_sprite_zero_hit(false);
         self.status.reset_vblank_status();
         self.frame_count = self.frame_count.wrapping_add(1);
-        
-        // Nettoyer les historiques des changements du frame précédent
-        self.scroll_changes.clear();
-        self.palette_changes.clear();
-        self.ctrl_changes.clear();
-        
+        self.notify_scanline();
+
+        self.frame_palette = self.palette_table;
+        self.palette_writes.clear();
+
         true
     }
-    
-    /// Applique les changements de scroll programmés pour la scanline actuelle
-    fn apply_scroll_changes_for_scanline(&mut self) {
-        for &(target_scanline, x, y) in &self.scroll_changes {
-            if target_scanline == self.scanline {
-                // Appliquer le changement de scroll
-                self.scroll.write(x);
-                self.scroll.write(y);
-            }
-        }
-    }
-    
-    /// Applique les changements de palette programmés pour le cycle actuel
-    fn apply_palette_changes_for_cycle(&mut self) {
-        let current_cycle = self.cycles;
-        for &(target_scanline, target_cycle, addr, value) in &self.palette_changes {
-            if target_scanline == self.scanline && target_cycle == current_cycle {
-                if addr < 32 {
-                    self.palette_table[addr as usize] = value;
-                }
-            }
-        }
-    }
-    
-    /// Applique les changements de contrôle programmés pour le cycle actuel
-    fn apply_ctrl_changes_for_cycle(&mut self) {
-        let current_cycle = self.cycles;
-        for &(target_scanline, target_cycle, value) in &self.ctrl_changes {
-            if target_scanline == self.scanline && target_cycle == current_cycle {
-                self.ctrl.update(value);
-            }
-        }
-    }
 
     /// Récupère et efface l'interruption NMI en attente
     /// Retourne Some(1) si une NMI était en attente, None sinon
     pub fn poll_nmi_interrupt(&mut self) -> Option<u8> {
         self.nmi_interrupt.take()
     }
-    
+
     /// Retourne le nombre de frames rendues depuis l'initialisation
     pub fn get_frame_count(&self) -> u64 {
         self.frame_count
     }
-    
+
+    /// Dernier dot exécuté
+    pub fn position(&self) -> PpuPosition {
+        PpuPosition {
+            frame: self.frame_count,
+            scanline: self.scanline,
+            dot: self.cycles as u16,
+        }
+    }
+
     /// Remet à zéro le compteur de frames
     pub fn reset_frame_count(&mut self) {
         self.frame_count = 0;
     }
-    
-    /// Programme un changement de scroll pour une scanline donnée (split scroll)
-    pub fn schedule_scroll_change(&mut self, scanline: u16, x: u8, y: u8) {
-        self.scroll_changes.push((scanline, x, y));
-    }
-    
-    /// Programme un changement de palette pour un cycle donné
-    pub fn schedule_palette_change(&mut self, scanline: u16, cycle: usize, addr: usize, value: u8) {
-        self.palette_changes.push((scanline, cycle, addr.try_into().unwrap(), value));
-    }
-    
-    /// Programme un changement de registre de contrôle pour un cycle donné
-    pub fn schedule_ctrl_change(&mut self, scanline: u16, cycle: usize, value: u8) {
-        self.ctrl_changes.push((scanline, cycle, value));
-    }
-    
-    /// Efface tous les changements programmés
-    pub fn clear_scheduled_changes(&mut self) {
-        self.scroll_changes.clear();
-        self.palette_changes.clear();
-        self.ctrl_changes.clear();
-    }
-    
+
     /// Retourne des informations de debug sur l'état du PPU
     pub fn debug_info(&self) -> String {
         format!(
@@ -288,18 +618,14 @@ impl NesPPU {
              - Frame: {}\n\
              - VBlank: {}\n\
              - Sprite 0 Hit: {}\n\
-             - Scroll X: {}, Y: {}\n\
-             - Changements programmés: {} scroll, {} palette, {} ctrl",
+             - Scroll X: {}, Y: {}",
             self.scanline,
             self.cycles,
             self.frame_count,
             self.status.is_in_vblank(),
             self.status.is_sprite_zero_hit(),
-            self.scroll.scroll_x,
-            self.scroll.scroll_y,
-            self.scroll_changes.len(),
-            self.palette_changes.len(),
-            self.ctrl_changes.len()
+            self.loopy.scroll().0,
+            self.loopy.scroll().1
         )
     }
 
@@ -308,14 +634,18 @@ impl NesPPU {
     fn is_sprite_0_hit(&self, cycle: usize) -> bool {
         let y = self.oam_data[0] as usize;
         let x = self.oam_data[3] as usize;
-        
+
         // Le sprite 0 hit se produit quand:
         // 1. On est sur la même scanline que le sprite 0
-        // 2. On a atteint ou dépassé la position X du sprite 0
+        // 2. Le pixel du dot est dans la largeur du sprite 0
         // 3. Les sprites sont activés dans le registre mask
         // 4. L'arrière-plan est également activé
+        // 5. Hors des 8 premiers pixels si l'un des deux y est masqué
+        let pixel_x = cycle.wrapping_sub(1);
+        let left_shown = self.mask.leftmost_8pxl_background() && self.mask.leftmost_8pxl_sprite();
         (y == self.scanline as usize)
-            && x <= cycle
+            && (x..x + 8).contains(&pixel_x)
+            && (pixel_x >= 8 || left_shown)
             && self.mask.show_sprites()
             && self.mask.show_background()
     }
@@ -323,22 +653,32 @@ impl NesPPU {
 
 impl PPU for NesPPU {
     fn write_to_ctrl(&mut self, value: u8) {
+        self.mapper.borrow_mut().ppu_register_write(0x2000, value);
         let before_nmi_status = self.ctrl.generate_vblank_nmi();
         self.ctrl.update(value);
+        self.loopy.write_ctrl(value);
         if !before_nmi_status && self.ctrl.generate_vblank_nmi() && self.status.is_in_vblank() {
             self.nmi_interrupt = Some(1);
         }
     }
 
     fn write_to_mask(&mut self, value: u8) {
+        self.mapper.borrow_mut().ppu_register_write(0x2001, value);
         self.mask.update(value);
     }
 
     fn read_status(&mut self) -> u8 {
-        let data = self.status.snapshot();
+        let mut data = self.status.snapshot();
+        // Course avec le VBlank: lu au dot même où il se lève, le drapeau est vu
+        // à 0; lu à ce dot ou aux deux suivants, la NMI de cette image n'a pas lieu
+        if self.scanline == self.region.vblank_line() && self.cycles <= 2 {
+            if self.cycles == 0 {
+                data &= !0x80;
+            }
+            self.nmi_interrupt = None;
+        }
         self.status.reset_vblank_status();
-        self.addr.reset_latch();
-        self.scroll.reset_latch();
+        self.loopy.reset_latch();
         data
     }
 
@@ -347,6 +687,11 @@ impl PPU for NesPPU {
     }
 
     fn write_to_oam_data(&mut self, value: u8) {
+        if self.oam_addr == 0xff {
+            strict::report(Violation::OamOverflow, || {
+                "$2004 write past the last sprite wraps to sprite 0".to_string()
+            });
+        }
         self.oam_data[self.oam_addr as usize] = value;
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
@@ -356,37 +701,29 @@ impl PPU for NesPPU {
     }
 
     fn write_to_scroll(&mut self, value: u8) {
-        self.scroll.write(value);
+        self.loopy.write_scroll(value);
     }
 
     fn write_to_ppu_addr(&mut self, value: u8) {
-        self.addr.update(value);
+        self.loopy.write_addr(value);
     }
 
     fn write_to_data(&mut self, value: u8) {
-        let addr = self.addr.get();
+        let addr = self.loopy.addr();
         match addr {
-            0..=0x1fff => println!("attempt to write to chr rom space {}", addr),
-            0x2000..=0x2fff => {
-                self.vram[self.mirror_vram_addr(addr) as usize] = value;
-            }
-            0x3000..=0x3eff => unimplemented!("addr {} shouldn't be used in reality", addr),
-
-            //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = addr - 0x10;
-                self.palette_table[(add_mirror - 0x3f00) as usize] = value;
-            }
-            0x3f00..=0x3fff => {
-                self.palette_table[(addr - 0x3f00) as usize] = value;
-            }
+            0..=0x1fff => self.mapper.borrow_mut().chr_write(addr, value),
+            0x2000..=0x2fff => self.write_nametable(addr, value),
+            // Espace miroir de 0x2000-0x2fff, que v atteint aussi pendant le rendu
+            // quand le fine Y est impair
+            0x3000..=0x3eff => self.write_nametable(addr - 0x1000, value),
+            0x3f00..=0x3fff => self.write_palette(NesPPU::palette_index(addr), value),
             _ => panic!("unexpected access to mirrored space {}", addr),
         }
         self.increment_vram_addr();
     }
 
     fn read_data(&mut self) -> u8 {
-        let addr = self.addr.get();
+        let addr = self.loopy.addr();
 
         self.increment_vram_addr();
 
@@ -394,42 +731,50 @@ impl PPU for NesPPU {
             // CHR ROM - utilise le buffer interne pour la lecture différée
             0..=0x1fff => {
                 let result = self.internal_data_buf;
-                self.internal_data_buf = self.chr_rom[addr as usize];
+                self.internal_data_buf = self.read_chr(addr);
                 result
             }
-            
+
             // VRAM nametables - utilise le buffer interne pour la lecture différée
             0x2000..=0x2fff => {
                 let result = self.internal_data_buf;
-                self.internal_data_buf = self.vram[self.mirror_vram_addr(addr) as usize];
+                self.internal_data_buf = self.read_nametable(addr);
                 result
             }
-            
+
             // Espace miroir de 0x2000-0x2fff
             0x3000..=0x3eff => {
                 let mirrored_addr = addr - 0x1000;
                 let result = self.internal_data_buf;
-                self.internal_data_buf = self.vram[self.mirror_vram_addr(mirrored_addr) as usize];
+                self.internal_data_buf = self.read_nametable(mirrored_addr);
                 result
             }
 
-            // Palette RAM avec mirroring - lecture immédiate (pas de buffer)
-            // Les adresses $3F10/$3F14/$3F18/$3F1C sont des miroirs de $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let mirrored_addr = addr - 0x10;
-                self.palette_table[(mirrored_addr - 0x3f00) as usize]
+            // Palette RAM avec mirroring - lecture immédiate, mais le buffer se
+            // remplit quand même avec la nametable "sous" la palette ($2F00-$2FFF)
+            0x3f00..=0x3fff => {
+                self.internal_data_buf = self.read_nametable(addr - 0x1000);
+                self.palette_table[NesPPU::palette_index(addr)]
             }
 
-            // Palette RAM normale - lecture immédiate
-            0x3f00..=0x3fff => self.palette_table[(addr - 0x3f00) as usize],
-            
-            _ => panic!("Accès inattendu à l'espace mémoire miroir à l'adresse 0x{:04X}", addr),
+            _ => panic!(
+                "Accès inattendu à l'espace mémoire miroir à l'adresse 0x{:04X}",
+                addr
+            ),
         }
     }
 
     fn write_oam_dma(&mut self, data: &[u8; 256]) {
         // DMA (Direct Memory Access) pour transférer 256 octets vers l'OAM
         // Ceci prend normalement 513 ou 514 cycles CPU selon l'alignement
+        if self.oam_addr != 0 {
+            strict::report(Violation::OamOverflow, || {
+                format!(
+                    "OAM DMA from OAMADDR=${:02X} wraps past the last sprite",
+                    self.oam_addr
+                )
+            });
+        }
         for x in data.iter() {
             self.oam_data[self.oam_addr as usize] = *x;
             self.oam_addr = self.oam_addr.wrapping_add(1);
@@ -451,6 +796,22 @@ pub mod test {
         assert_eq!(ppu.vram[0x0305], 0x66);
     }
 
+    #[test]
+    fn test_vram_write_while_rendering() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_scroll(0);
+        ppu.write_to_scroll(1);
+        ppu.write_to_mask(0x08);
+        // Pendant le rendu, le bit 12 de v est le bit 0 du fine Y
+        while ppu.scanline < 10 || ppu.loopy.addr() & 0x1000 == 0 {
+            ppu.tick(1);
+        }
+
+        let addr = ppu.loopy.addr();
+        ppu.write_to_data(0x66);
+        assert_eq!(ppu.read_nametable(addr - 0x1000), 0x66);
+    }
+
     #[test]
     fn test_ppu_vram_reads() {
         let mut ppu = NesPPU::new_empty_rom();
@@ -461,7 +822,7 @@ pub mod test {
         ppu.write_to_ppu_addr(0x05);
 
         ppu.read_data(); //load_into_buffer
-        assert_eq!(ppu.addr.get(), 0x2306);
+        assert_eq!(ppu.loopy.addr(), 0x2306);
         assert_eq!(ppu.read_data(), 0x66);
     }
 
@@ -497,6 +858,200 @@ pub mod test {
         assert_eq!(ppu.read_data(), 0x88);
     }
 
+    #[test]
+    fn test_loopy_registers() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0b10);
+        assert_eq!(ppu.loopy.t, 0x0800);
+        ppu.read_status();
+        ppu.write_to_scroll(0x7D);
+        assert_eq!((ppu.loopy.t, ppu.loopy.x, ppu.loopy.w), (0x080F, 5, true));
+        ppu.write_to_scroll(0x5E);
+        assert_eq!((ppu.loopy.t, ppu.loopy.w), (0x696F, false));
+        assert_eq!(ppu.loopy.scroll(), (0x7D, 0x5E));
+
+        // $2005 and $2006 share the latch: after one $2005 write, $2006 takes
+        // the low byte and copies t to v
+        ppu.write_to_scroll(0x00);
+        ppu.write_to_ppu_addr(0x3D);
+        assert_eq!((ppu.loopy.v, ppu.loopy.w), (0x693D, false));
+        ppu.read_status();
+        ppu.write_to_ppu_addr(0x3D);
+        ppu.write_to_ppu_addr(0xF0);
+        assert_eq!((ppu.loopy.t, ppu.loopy.v), (0x3DF0, 0x3DF0));
+    }
+
+    #[test]
+    fn test_background_pipeline() {
+        for pipeline in [Pipeline::Dot, Pipeline::Scanline] {
+            // Tile 1 is solid, in the second column of every row
+            let mut chr = vec![0; 0x2000];
+            chr[16..24].fill(0xFF);
+            let mut ppu = NesPPU::new(chr, Mirroring::Horizontal);
+            ppu.options.pipeline = pipeline;
+            for row in 0..30 {
+                ppu.vram[row * 32 + 1] = 1;
+            }
+            ppu.write_to_scroll(3);
+            ppu.write_to_scroll(0);
+            ppu.write_to_mask(0x0A);
+            while ppu.frame_count < 1 || ppu.scanline < 20 {
+                ppu.tick(1);
+            }
+
+            // Fine X moves the tile 3 pixels left, from the first line on
+            for y in [0, 10] {
+                let row: Vec<u8> = (0..16).map(|x| ppu.background_pixel(x, y)).collect();
+                assert_eq!(
+                    row,
+                    [0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+                    "{:?}",
+                    pipeline
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_mid_frame_ctrl_write() {
+        // Tile 1 is solid in the first pattern table only
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].fill(0xFF);
+        let mut ppu = NesPPU::new(chr, Mirroring::Horizontal);
+        ppu.vram[..0x3c0].fill(1);
+        ppu.write_to_mask(0x0A);
+        while ppu.frame_count < 1 || ppu.scanline < 50 {
+            ppu.tick(1);
+        }
+        // Written straight to the registers, the renderer picks it up next fetch
+        ppu.write_to_ctrl(0x10);
+        while ppu.scanline < 60 {
+            ppu.tick(1);
+        }
+        assert_eq!(ppu.background_pixel(100, 40), 1);
+        assert_eq!(ppu.background_pixel(100, 55), 0);
+    }
+
+    #[test]
+    fn test_open_bus_decay() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_data(0x0f);
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.refresh_open_bus(0xff, 0xff);
+        assert_eq!(ppu.read_register(0x2000), 0xff);
+        assert_eq!(ppu.read_register(0x2002), 0x1f);
+
+        // A palette read refreshes only the low 6 bits, which outlive the others
+        ppu.frame_count = 20;
+        assert_eq!(ppu.read_register(0x2007), 0x0f);
+        ppu.frame_count = 40;
+        assert_eq!(ppu.read_register(0x2005), 0x0f);
+        ppu.frame_count = 60;
+        assert_eq!(ppu.peek_register(0x2001), 0);
+    }
+
+    #[test]
+    fn test_odd_frame_skip() {
+        let dots_per_frame = |ppu: &mut NesPPU| {
+            let mut dots = 0;
+            while !ppu.tick(1) {
+                dots += 1;
+            }
+            dots + 1
+        };
+        let mut ppu = NesPPU::new_empty_rom();
+        assert_eq!(dots_per_frame(&mut ppu), 262 * 341);
+        assert_eq!(dots_per_frame(&mut ppu), 262 * 341);
+        ppu.write_to_mask(0x08);
+        assert_eq!(dots_per_frame(&mut ppu), 262 * 341);
+        assert_eq!(dots_per_frame(&mut ppu), 262 * 341 - 1);
+        assert_eq!(dots_per_frame(&mut ppu), 262 * 341);
+
+        // Rendering turned on at the last dot of an odd pre-render line ends it
+        // right there
+        ppu.write_to_mask(0x00);
+        while !(ppu.scanline == 261 && ppu.cycles == 340 && ppu.frame_count % 2 == 1) {
+            ppu.tick(1);
+        }
+        ppu.write_to_mask(0x08);
+        assert!(ppu.tick(1));
+        assert_eq!((ppu.scanline, ppu.cycles), (0, 1));
+        ppu.write_to_mask(0x00);
+        while !ppu.tick(1) {}
+
+        // PAL and Dendy frames are 312 lines and never skip a dot
+        for region in [Region::Pal, Region::Dendy] {
+            ppu.region = region;
+            assert_eq!(dots_per_frame(&mut ppu), 312 * 341);
+            assert_eq!(dots_per_frame(&mut ppu), 312 * 341);
+        }
+        // Dendy starts VBlank 50 lines later
+        while ppu.scanline != 241 {
+            ppu.tick(1);
+        }
+        assert!(!ppu.status.is_in_vblank());
+        while ppu.scanline != 291 {
+            ppu.tick(1);
+        }
+        assert!(ppu.status.is_in_vblank());
+    }
+
+    #[test]
+    fn test_vblank_race() {
+        for (dot, status, nmi) in [(0, 0x00, false), (2, 0x80, false), (3, 0x80, true)] {
+            let mut ppu = NesPPU::new_empty_rom();
+            ppu.write_to_ctrl(0x80);
+            for _ in 0..241 * 341 + dot {
+                ppu.tick(1);
+            }
+            assert_eq!(ppu.read_status() & 0x80, status, "dot {}", dot);
+            assert_eq!(ppu.poll_nmi_interrupt().is_some(), nmi, "dot {}", dot);
+            assert!(!ppu.status.is_in_vblank());
+        }
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x10);
+        ppu.write_to_data(0x11);
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x25);
+        ppu.write_to_data(0x22);
+
+        assert_eq!(ppu.palette_table[0x00], 0x11);
+        assert_eq!(ppu.palette_table[0x05], 0x22);
+        assert_eq!(ppu.palette_writes.len(), 2);
+        assert_eq!(ppu.palette_writes[1].index, 0x05);
+    }
+
+    #[test]
+    fn test_palette_read_fills_buffer() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.palette_table[0x05] = 0x22;
+        ppu.vram[0x0705] = 0x77; // $2F05, horizontal mirroring
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x05);
+        assert_eq!(ppu.read_data(), 0x22);
+
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_ppu_addr(0x00);
+        assert_eq!(ppu.read_data(), 0x77);
+    }
+
+    #[test]
+    fn test_palette_keeps_six_bits() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x01);
+        ppu.write_to_data(0xc5);
+        assert_eq!(ppu.palette_table[0x01], 0x05);
+    }
+
     // Horizontal: https://wiki.nesdev.com/w/index.php/Mirroring
     //   [0x2000 A ] [0x2400 a ]
     //   [0x2800 B ] [0x2C00 b ]
@@ -0,0 +1,143 @@
+// Registres internes du PPU, avec les noms que loopy leur a donnés:
+// - v: adresse VRAM courante, celle de $2007, qui sert aussi de curseur au rendu
+// - t: adresse temporaire, où $2000, $2005 et $2006 écrivent le scroll
+// - x: scroll fin horizontal (0-7)
+// - w: latch partagé par $2005 et $2006, remis à zéro par la lecture de $2002
+//
+// v et t ont la même disposition sur 15 bits:
+//
+//   yyy NN YYYYY XXXXX
+//   |   |  |     +------ colonne de tuile (coarse X)
+//   |   |  +------------ ligne de tuile (coarse Y)
+//   |   +--------------- nametable
+//   +------------------- scroll fin vertical
+
+const COARSE_X: u16 = 0x001f;
+const COARSE_Y: u16 = 0x03e0;
+const NAMETABLE_X: u16 = 0x0400;
+const NAMETABLE_Y: u16 = 0x0800;
+const FINE_Y: u16 = 0x7000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Loopy {
+    pub v: u16,
+    pub t: u16,
+    pub x: u8,
+    pub w: bool,
+}
+
+impl Loopy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// $2000: les deux bits de nametable vont dans t
+    pub fn write_ctrl(&mut self, data: u8) {
+        self.t = (self.t & !(NAMETABLE_X | NAMETABLE_Y)) | ((data as u16 & 0b11) << 10);
+    }
+
+    /// $2005: X puis Y
+    pub fn write_scroll(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & !COARSE_X) | (data as u16 >> 3);
+            self.x = data & 0b111;
+        } else {
+            self.t = (self.t & !(FINE_Y | COARSE_Y))
+                | ((data as u16 & 0b111) << 12)
+                | ((data as u16 & 0xf8) << 2);
+        }
+        self.w = !self.w;
+    }
+
+    /// $2006: octet haut (le bit 14 est effacé) puis octet bas, qui copie t dans v
+    pub fn write_addr(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & 0x00ff) | ((data as u16 & 0x3f) << 8);
+        } else {
+            self.t = (self.t & 0xff00) | data as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    pub fn reset_latch(&mut self) {
+        self.w = false;
+    }
+
+    /// Adresse sur le bus du PPU ($0000-$3FFF)
+    pub fn addr(&self) -> u16 {
+        self.v & 0x3fff
+    }
+
+    /// Après un accès à $2007 hors rendu
+    pub fn increment(&mut self, inc: u8) {
+        self.v = self.v.wrapping_add(inc as u16) & 0x7fff;
+    }
+
+    /// Tuile suivante, en passant à la nametable voisine après la colonne 31
+    pub fn increment_coarse_x(&mut self) {
+        if self.v & COARSE_X == 31 {
+            self.v = (self.v & !COARSE_X) ^ NAMETABLE_X;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// Ligne de pixels suivante. Après la ligne de tuiles 29 on passe à la
+    /// nametable du dessous; les lignes 30 et 31 (les attributs) reviennent à 0
+    /// sans en changer
+    pub fn increment_y(&mut self) {
+        if self.v & FINE_Y != FINE_Y {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !FINE_Y;
+        let coarse_y = match (self.v & COARSE_Y) >> 5 {
+            29 => {
+                self.v ^= NAMETABLE_Y;
+                0
+            }
+            31 => 0,
+            coarse_y => coarse_y + 1,
+        };
+        self.v = (self.v & !COARSE_Y) | (coarse_y << 5);
+    }
+
+    /// Dot 257: colonne et nametable horizontale reprises de t
+    pub fn copy_horizontal(&mut self) {
+        let mask = COARSE_X | NAMETABLE_X;
+        self.v = (self.v & !mask) | (self.t & mask);
+    }
+
+    /// Dots 280-304 de la ligne de pré-rendu: le reste repris de t
+    pub fn copy_vertical(&mut self) {
+        let mask = FINE_Y | NAMETABLE_Y | COARSE_Y;
+        self.v = (self.v & !mask) | (self.t & mask);
+    }
+
+    /// Case de nametable de la tuile pointée par v
+    pub fn tile_addr(&self) -> u16 {
+        0x2000 | (self.v & 0x0fff)
+    }
+
+    /// Octet d'attributs couvrant cette tuile
+    pub fn attribute_addr(&self) -> u16 {
+        0x23c0 | (self.v & 0x0c00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07)
+    }
+
+    /// Décalage dans l'octet d'attributs des deux bits de palette de cette tuile
+    pub fn attribute_shift(&self) -> u8 {
+        (((self.v >> 4) & 4) | (self.v & 2)) as u8
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        (self.v & FINE_Y) >> 12
+    }
+
+    /// Scroll (x, y) écrit dans t, tel que $2005 l'a reçu
+    pub fn scroll(&self) -> (u8, u8) {
+        let x = ((self.t & COARSE_X) << 3) as u8 | self.x;
+        let y = (((self.t & COARSE_Y) >> 2) | ((self.t & FINE_Y) >> 12)) as u8;
+        (x, y)
+    }
+}
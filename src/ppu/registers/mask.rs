@@ -1,4 +1,5 @@
 bitflags! {
+    #[derive(Clone, Copy)]
     pub struct MaskRegister: u8 {
         const GREYSCALE               = 0b00000001;
         const LEFTMOST_8PXL_BACKGROUND  = 0b00000010;
@@ -17,6 +18,12 @@ pub enum Color {
     Blue,
 }
 
+impl Default for MaskRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MaskRegister {
     pub fn new() -> Self {
         MaskRegister::from_bits_truncate(0b00000000)
@@ -42,6 +49,11 @@ impl MaskRegister {
         self.contains(MaskRegister::SHOW_SPRITES)
     }
 
+    /// Bits 5-7 (rouge, vert, bleu) pour indexer une palette avec emphase
+    pub fn emphasis(&self) -> u8 {
+        self.bits() >> 5
+    }
+
     pub fn emphasise(&self) -> Vec<Color> {
         let mut result = Vec::<Color>::new();
         if self.contains(MaskRegister::EMPHASISE_RED) {
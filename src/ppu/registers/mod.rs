@@ -1,5 +1,4 @@
-pub mod addr;
 pub mod control;
+pub mod loopy;
 pub mod mask;
-pub mod scroll;
-pub mod status;
\ No newline at end of file
+pub mod status;
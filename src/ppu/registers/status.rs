@@ -11,6 +11,12 @@ bitflags! {
     }
 }
 
+impl Default for StatusRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StatusRegister {
     pub fn new() -> Self {
         StatusRegister::from_bits_truncate(0b00000000)
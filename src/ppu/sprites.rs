@@ -0,0 +1,103 @@
+//
+// Évaluation des sprites: au dot 257 de chaque ligne, le PPU cherche dans l'OAM
+// les sprites de la ligne suivante et en copie au plus 8 dans l'OAM secondaire.
+// Le renderer ne dessine que ceux-là, d'où le clignotement des jeux qui en
+// alignent plus.
+//
+// Après le huitième, la console continue de chercher pour lever le drapeau de
+// débordement, mais avance dans l'octet lu en même temps que dans le sprite: elle
+// compare à la ligne des numéros de tuile, des attributs ou des X, et rate des
+// débordements ou en voit qui n'existent pas. Le drapeau reproduit ce défaut.
+//
+
+/// Un sprite de l'OAM secondaire: sa place dans l'OAM et ses 4 octets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineSprite {
+    pub index: u8,
+    pub y: u8,
+    pub tile: u8,
+    pub attributes: u8,
+    pub x: u8,
+}
+
+/// Sprites trouvés pour une ligne
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Evaluation {
+    /// Dans l'ordre de l'OAM: les 8 premiers, ou tous sans la limite
+    pub sprites: Vec<LineSprite>,
+    /// Ce que la console met dans le bit de débordement de $2002
+    pub overflow: bool,
+}
+
+/// Sprites de l'OAM présents `line - y` lignes sous leur Y, pour des sprites de
+/// `height` lignes. Sans `limit`, tous ceux de la ligne sont gardés
+pub fn evaluate(oam: &[u8; 256], line: u16, height: u8, limit: bool) -> Evaluation {
+    let in_range = |y: u8| line.wrapping_sub(y as u16) < height as u16;
+    let mut evaluation = Evaluation::default();
+
+    for n in 0..64 {
+        let bytes = &oam[n * 4..n * 4 + 4];
+        if in_range(bytes[0]) && (!limit || evaluation.sprites.len() < 8) {
+            evaluation.sprites.push(LineSprite {
+                index: n as u8,
+                y: bytes[0],
+                tile: bytes[1],
+                attributes: bytes[2],
+                x: bytes[3],
+            });
+        }
+    }
+
+    // Le parcours de la console: 8 sprites trouvés, puis l'octet m du sprite n
+    let mut found = 0;
+    let mut m = 0;
+    for n in 0..64 {
+        if found < 8 {
+            if in_range(oam[n * 4]) {
+                found += 1;
+            }
+        } else if in_range(oam[n * 4 + m]) {
+            evaluation.overflow = true;
+            break;
+        } else {
+            m = (m + 1) % 4;
+        }
+    }
+    evaluation
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let mut oam = [0xFF; 256];
+        // Nine sprites on line 20, then one whose tile number reads as a Y there
+        for n in 0..9 {
+            oam[n * 4] = 16;
+            oam[n * 4 + 3] = n as u8 * 8;
+        }
+        let limited = evaluate(&oam, 20, 8, true);
+        assert_eq!(limited.sprites.len(), 8);
+        assert_eq!(limited.sprites[7].x, 56);
+        assert!(limited.overflow);
+        assert_eq!(evaluate(&oam, 20, 8, false).sprites.len(), 9);
+
+        // Eight on the line: the search goes on with byte 1 of sprite 9, byte 2
+        // of sprite 10... so a tile number can pass for a Y
+        oam[8 * 4] = 0xFF;
+        assert!(!evaluate(&oam, 20, 8, true).overflow);
+        oam[9 * 4 + 1] = 20;
+        assert!(evaluate(&oam, 20, 8, true).overflow);
+        // and a real ninth sprite whose X is read instead is missed
+        oam[9 * 4 + 1] = 0xFF;
+        oam[11 * 4] = 16;
+        assert!(!evaluate(&oam, 20, 8, true).overflow);
+        assert_eq!(evaluate(&oam, 20, 8, false).sprites.len(), 9);
+
+        // 8x16 sprites are found 16 lines down
+        assert_eq!(evaluate(&oam, 30, 16, true).sprites.len(), 8);
+        assert!(evaluate(&oam, 30, 8, true).sprites.is_empty());
+    }
+}
@@ -0,0 +1,244 @@
+//
+// Visionneuses de debug: le contenu de la VRAM décodé en images, comme les
+// fenêtres PPU des autres émulateurs. Rien à voir avec l'image du jeu: tout est
+// lu d'un coup, avec la palette et les banques CHR du moment.
+//
+// Les nametables sont lues à travers le mirroring (et le mapper), les quatre
+// nametables logiques montrent donc ce que le scroll peut atteindre.
+//
+// Les pattern tables n'ont pas de palette à elles: on choisit celle avec
+// laquelle les colorier, comme dans la fenêtre PPU de FCEUX.
+//
+// Les 64 sprites de l'OAM sont décodés un par un, avec une vignette dans le sens
+// où ils s'affichent (retournements compris) et des trous là où ils sont
+// transparents, pour les superposer à l'image.
+//
+
+use super::NesPPU;
+use crate::render::frame::Frame;
+
+/// Un sprite de l'OAM, décodé
+#[derive(Debug, Clone, PartialEq)]
+pub struct OamEntry {
+    pub index: u8,
+    /// Position à l'écran: le sprite s'affiche à partir de la ligne sous son Y
+    pub x: u8,
+    pub y: u8,
+    pub tile: u8,
+    /// Palette de sprite, 0-3 (palettes 4-7 de la palette RAM)
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    /// 8 pixels de large, 8 ou 16 de haut selon $2000; None où il est transparent
+    pub thumbnail: Vec<Option<(u8, u8, u8)>>,
+}
+
+impl NesPPU {
+    /// Les nametables $2000, $2400, $2800 et $2C00 avec les pattern tables et
+    /// palettes actuelles, en couleurs de `colors`
+    pub fn render_nametables(&self, colors: &[(u8, u8, u8); 64]) -> [Frame; 4] {
+        let bank = self.ctrl.bknd_pattern_addr();
+        std::array::from_fn(|table| {
+            let base = 0x2000 + table as u16 * 0x400;
+            let mut frame = Frame::new();
+            for row in 0..30u16 {
+                for column in 0..32u16 {
+                    let tile = self.read_nametable(base + row * 32 + column) as u16;
+                    let attribute = self.read_nametable(base + 0x3c0 + row / 4 * 8 + column / 4);
+                    let shift = (row % 4 / 2) * 4 + (column % 4 / 2) * 2;
+                    let palette = (attribute >> shift) & 0b11;
+                    for (y, pixels) in self.tile_rows(bank + tile * 16).iter().enumerate() {
+                        for (x, &value) in pixels.iter().enumerate() {
+                            let color = self.palette_color(palette, value, colors);
+                            frame.set_pixel(column as usize * 8 + x, row as usize * 8 + y, color);
+                        }
+                    }
+                }
+            }
+            frame
+        })
+    }
+
+    /// Les deux pattern tables côte à côte ($0000 à gauche, $1000 à droite) sur
+    /// les 128 premières lignes, 16x16 tuiles chacune, coloriées avec la palette
+    /// `palette` (0-3 fond, 4-7 sprites)
+    pub fn render_pattern_tables(&self, palette: u8, colors: &[(u8, u8, u8); 64]) -> Frame {
+        let mut frame = Frame::new();
+        for table in 0..2u16 {
+            for tile in 0..256u16 {
+                let rows = self.tile_rows(table * 0x1000 + tile * 16);
+                let left = table as usize * 128 + (tile % 16) as usize * 8;
+                let top = (tile / 16) as usize * 8;
+                for (y, pixels) in rows.iter().enumerate() {
+                    for (x, &value) in pixels.iter().enumerate() {
+                        let color = self.palette_color(palette & 7, value, colors);
+                        frame.set_pixel(left + x, top + y, color);
+                    }
+                }
+            }
+        }
+        frame
+    }
+
+    /// Les 64 sprites de l'OAM, dans l'ordre, avec leur vignette
+    pub fn oam_entries(&self, colors: &[(u8, u8, u8); 64]) -> Vec<OamEntry> {
+        let height = self.ctrl.sprite_size() as usize;
+        self.oam_data
+            .chunks(4)
+            .enumerate()
+            .map(|(index, bytes)| {
+                let (tile, attributes) = (bytes[1], bytes[2]);
+                let palette = attributes & 0b11;
+                let flip_horizontal = attributes & 0x40 != 0;
+                let flip_vertical = attributes & 0x80 != 0;
+
+                // En 8x16, le bit 0 choisit la pattern table, la tuile paire est en haut
+                let addr = match height {
+                    8 => self.ctrl.sprt_pattern_addr() + tile as u16 * 16,
+                    _ => (tile as u16 & 1) * 0x1000 + (tile as u16 & 0xFE) * 16,
+                };
+                let rows: Vec<[u8; 8]> = (0..height / 8)
+                    .flat_map(|half| self.sprite_tile_rows(addr + half as u16 * 16))
+                    .collect();
+                let mut thumbnail = Vec::with_capacity(8 * height);
+                for y in 0..height {
+                    let row = rows[if flip_vertical { height - 1 - y } else { y }];
+                    for x in 0..8 {
+                        let value = row[if flip_horizontal { 7 - x } else { x }];
+                        thumbnail.push(match value {
+                            0 => None,
+                            _ => Some(self.palette_color(palette + 4, value, colors)),
+                        });
+                    }
+                }
+
+                OamEntry {
+                    index: index as u8,
+                    x: bytes[3],
+                    y: bytes[0],
+                    tile,
+                    palette,
+                    behind_background: attributes & 0x20 != 0,
+                    flip_horizontal,
+                    flip_vertical,
+                    thumbnail,
+                }
+            })
+            .collect()
+    }
+
+    /// Les 8 lignes de 8 pixels (0-3) de la tuile de l'arrière-plan à `addr`
+    fn tile_rows(&self, addr: u16) -> [[u8; 8]; 8] {
+        std::array::from_fn(|y| {
+            let low = self.read_chr(addr + y as u16);
+            let high = self.read_chr(addr + y as u16 + 8);
+            std::array::from_fn(|x| (low >> (7 - x) & 1) | (high >> (7 - x) & 1) << 1)
+        })
+    }
+
+    /// Pareil pour une tuile de sprite (MMC5 a des banques séparées)
+    fn sprite_tile_rows(&self, addr: u16) -> [[u8; 8]; 8] {
+        std::array::from_fn(|y| {
+            let low = self.read_sprite_chr(addr + y as u16);
+            let high = self.read_sprite_chr(addr + y as u16 + 8);
+            std::array::from_fn(|x| (low >> (7 - x) & 1) | (high >> (7 - x) & 1) << 1)
+        })
+    }
+
+    /// Couleur d'un pixel `value` de la palette `palette` (0-3 fond, 4-7 sprites)
+    fn palette_color(&self, palette: u8, value: u8, colors: &[(u8, u8, u8); 64]) -> (u8, u8, u8) {
+        let entry = match value {
+            0 => 0,
+            _ => NesPPU::palette_index((palette * 4 + value) as u16),
+        };
+        colors[self.palette_table[entry] as usize & 0x3f]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::ppu::PPU;
+    use crate::render::palette::SYSTEM_PALLETE;
+
+    #[test]
+    fn test_render_nametables() {
+        // Tile 1 is solid; placed top left of $2000 with palette 1, and at the
+        // bottom right of $2400, which vertical mirroring shows again at $2C00
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].fill(0xFF);
+        let mut ppu = NesPPU::new(chr, Mirroring::Vertical);
+        ppu.palette_table[..8].copy_from_slice(&[0x0F, 0x30, 0, 0, 0, 0x16, 0, 0]);
+        ppu.vram[0] = 1;
+        ppu.vram[0x3c0] = 0b01;
+        ppu.vram[0x400 + 29 * 32 + 31] = 1;
+
+        let tables = ppu.render_nametables(&SYSTEM_PALLETE);
+        let pixel = |frame: &Frame, x: usize, y: usize| {
+            let base = (y * 256 + x) * 3;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+        assert_eq!(pixel(&tables[0], 7, 7), SYSTEM_PALLETE[0x16]);
+        assert_eq!(pixel(&tables[0], 8, 0), SYSTEM_PALLETE[0x0F]);
+        assert_eq!(pixel(&tables[2], 0, 0), SYSTEM_PALLETE[0x16]);
+        for table in [1, 3] {
+            assert_eq!(pixel(&tables[table], 255, 239), SYSTEM_PALLETE[0x30]);
+            assert_eq!(pixel(&tables[table], 0, 0), SYSTEM_PALLETE[0x0F]);
+        }
+    }
+
+    #[test]
+    fn test_render_pattern_tables() {
+        // Tile 1 of $0000 has color 1 on top, tile $FF of $1000 color 2 below
+        let mut chr = vec![0; 0x2000];
+        chr[16..20].fill(0xFF);
+        chr[0x1FF8..0x2000].fill(0xFF);
+        let mut ppu = NesPPU::new(chr, Mirroring::Vertical);
+        ppu.palette_table[..0x1B].copy_from_slice(&[0x0F; 0x1B]);
+        ppu.palette_table[0x19..0x1B].copy_from_slice(&[0x21, 0x16]);
+
+        let frame = ppu.render_pattern_tables(6, &SYSTEM_PALLETE);
+        let pixel = |x: usize, y: usize| {
+            let base = (y * 256 + x) * 3;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+        assert_eq!(pixel(8, 3), SYSTEM_PALLETE[0x21]);
+        assert_eq!(pixel(8, 4), SYSTEM_PALLETE[0x0F]);
+        assert_eq!(pixel(255, 127), SYSTEM_PALLETE[0x16]);
+        assert_eq!(pixel(255, 128), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_oam_entries() {
+        // Tile 2 has only its top left pixel, tile 3 its bottom row
+        let mut chr = vec![0; 0x2000];
+        chr[32] = 0x80;
+        chr[48 + 7] = 0xFF;
+        let mut ppu = NesPPU::new(chr, Mirroring::Vertical);
+        ppu.palette_table[0x1D] = 0x27;
+        ppu.oam_data[4..8].copy_from_slice(&[50, 2, 0xE3, 100]);
+
+        let entries = ppu.oam_entries(&SYSTEM_PALLETE);
+        assert_eq!(entries.len(), 64);
+        let sprite = &entries[1];
+        assert_eq!(
+            (sprite.index, sprite.x, sprite.y, sprite.tile),
+            (1, 100, 50, 2)
+        );
+        assert_eq!(sprite.palette, 3);
+        assert!(sprite.behind_background && sprite.flip_horizontal && sprite.flip_vertical);
+        // Flipped both ways, the pixel ends up bottom right
+        assert_eq!(sprite.thumbnail.len(), 64);
+        assert_eq!(sprite.thumbnail[63], Some(SYSTEM_PALLETE[0x27]));
+        assert_eq!(sprite.thumbnail.iter().flatten().count(), 1);
+
+        // 8x16: tiles 2 and 3 stacked, flipped over the full height
+        ppu.write_to_ctrl(0x20);
+        let sprite = &ppu.oam_entries(&SYSTEM_PALLETE)[1];
+        assert_eq!(sprite.thumbnail.len(), 128);
+        assert!(sprite.thumbnail[..8].iter().all(Option::is_some));
+        assert_eq!(sprite.thumbnail[127], Some(SYSTEM_PALLETE[0x27]));
+    }
+}
@@ -0,0 +1,158 @@
+//
+// Scheduling of the emulation thread. On a busy system the emulator can miss the
+// moment to refill the audio queue and the sound crackles; a higher priority, or
+// a core of its own, makes that rarer. Both are opt-in from the config file:
+//
+//   [thread]
+//   priority = high   # normal (default), high or realtime
+//   core = 2          # pin to this core (Linux and Windows)
+//
+// Raising the priority usually needs extra permissions (rtkit or a nice limit on
+// Linux); when the system refuses, the emulator says so and runs as it is.
+//
+
+use crate::config::Config;
+use sdl2::sys::{SDL_SetThreadPriority, SDL_ThreadPriority};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    Normal,
+    High,
+    /// SDL's "time critical", meant for audio threads
+    Realtime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThreadConfig {
+    pub priority: Priority,
+    pub core: Option<usize>,
+}
+
+impl ThreadConfig {
+    /// Settings from the `[thread]` section; anything unreadable keeps the default.
+    pub fn from_config(config: &Config) -> Self {
+        let priority = match config.get("thread", "priority") {
+            None | Some("normal") => Priority::Normal,
+            Some("high") => Priority::High,
+            Some("realtime") => Priority::Realtime,
+            Some(other) => {
+                eprintln!("[thread] priority: unknown value '{}', using normal", other);
+                Priority::Normal
+            }
+        };
+        let core = config
+            .get("thread", "core")
+            .and_then(|core| match core.parse() {
+                Ok(core) => Some(core),
+                Err(_) => {
+                    eprintln!("[thread] core: not a core number");
+                    None
+                }
+            });
+        ThreadConfig { priority, core }
+    }
+
+    /// Applies the settings to the calling thread, reporting what could not be done.
+    pub fn apply(&self) {
+        if self.priority != Priority::Normal {
+            if let Err(e) = set_priority(self.priority) {
+                eprintln!(
+                    "Could not raise the thread priority ({}), running at normal priority",
+                    e
+                );
+            }
+        }
+        if let Some(core) = self.core {
+            if let Err(e) = pin_to_core(core) {
+                eprintln!("Could not pin the emulation thread to core {}: {}", core, e);
+            }
+        }
+    }
+}
+
+fn set_priority(priority: Priority) -> Result<(), String> {
+    let level = match priority {
+        Priority::Normal => SDL_ThreadPriority::SDL_THREAD_PRIORITY_NORMAL,
+        Priority::High => SDL_ThreadPriority::SDL_THREAD_PRIORITY_HIGH,
+        Priority::Realtime => SDL_ThreadPriority::SDL_THREAD_PRIORITY_TIME_CRITICAL,
+    };
+    // Safe to call before SDL_Init; it only touches the calling thread
+    match unsafe { SDL_SetThreadPriority(level) } {
+        0 => Ok(()),
+        _ => Err(sdl2::get_error()),
+    }
+}
+
+fn check_core(core: usize) -> Result<(), String> {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if core < cores {
+        Ok(())
+    } else {
+        Err(format!("this machine has cores 0-{}", cores - 1))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to_core(core: usize) -> Result<(), String> {
+    extern "C" {
+        fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const u64) -> i32;
+    }
+    check_core(core)?;
+    // glibc's cpu_set_t: a 1024-bit mask
+    let mut mask = [0u64; 16];
+    *mask.get_mut(core / 64).ok_or("core number too large")? |= 1 << (core % 64);
+    // pid 0 is the calling thread
+    match unsafe { sched_setaffinity(0, std::mem::size_of_val(&mask), mask.as_ptr()) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error().to_string()),
+    }
+}
+
+#[cfg(windows)]
+fn pin_to_core(core: usize) -> Result<(), String> {
+    use std::ffi::c_void;
+    extern "system" {
+        fn GetCurrentThread() -> *mut c_void;
+        fn SetThreadAffinityMask(thread: *mut c_void, mask: usize) -> usize;
+    }
+    check_core(core)?;
+    let mask = 1usize
+        .checked_shl(core as u32)
+        .ok_or("core number too large")?;
+    match unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) } {
+        0 => Err(std::io::Error::last_os_error().to_string()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn pin_to_core(core: usize) -> Result<(), String> {
+    check_core(core)?;
+    Err("not supported on this system".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_thread_config() {
+        let config = Config::parse("[thread]\npriority = high\ncore = 1\n").unwrap();
+        assert_eq!(
+            ThreadConfig::from_config(&config),
+            ThreadConfig {
+                priority: Priority::High,
+                core: Some(1)
+            }
+        );
+        let config = Config::parse("[thread]\npriority = turbo\ncore = first\n").unwrap();
+        assert_eq!(
+            ThreadConfig::from_config(&config),
+            ThreadConfig {
+                priority: Priority::Normal,
+                core: None
+            }
+        );
+        assert!(check_core(usize::MAX).is_err());
+    }
+}
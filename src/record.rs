@@ -0,0 +1,178 @@
+//
+// Video recording (F10): every frame and every audio sample from the moment it
+// starts, written as an uncompressed Y4M video and a WAV file side by side.
+// Neither needs an encoder, and any video tool muxes the pair into something
+// shareable:
+//
+//   ffmpeg -i game_1700000000.y4m -i game_1700000000.wav game.mp4
+//
+// Frames are converted to YCbCr 4:4:4 (BT.601, studio range) so colors do not
+// bleed into each other at NES resolution. Audio is 16-bit mono at the output
+// rate the recording started with. The WAV sizes are only known at the end, so
+// its header is written again when the recording stops.
+//
+
+use crate::render::frame::Frame;
+use crate::screenshot;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+
+pub struct Recorder {
+    video: BufWriter<File>,
+    audio: BufWriter<File>,
+    video_path: PathBuf,
+    audio_path: PathBuf,
+    sample_rate: u32,
+    frames: u32,
+    samples: u32,
+    /// First write error; the files stop growing after it
+    error: Option<io::Error>,
+}
+
+impl Recorder {
+    /// Starts `<game>_<time>.y4m` and `.wav` in `dir`. `frame_rate` is a
+    /// fraction, as Y4M wants it.
+    pub fn start(
+        dir: &Path,
+        rom_path: &Path,
+        frame_rate: (u64, u64),
+        sample_rate: u32,
+    ) -> io::Result<Recorder> {
+        std::fs::create_dir_all(dir)?;
+        let prefix = screenshot::file_prefix(rom_path);
+        let video_path = dir.join(format!("{}.y4m", prefix));
+        let audio_path = dir.join(format!("{}.wav", prefix));
+
+        let mut video = BufWriter::new(File::create(&video_path)?);
+        writeln!(
+            video,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+            WIDTH, HEIGHT, frame_rate.0, frame_rate.1
+        )?;
+        let mut audio = BufWriter::new(File::create(&audio_path)?);
+        audio.write_all(&wav_header(sample_rate, 0))?;
+
+        Ok(Recorder {
+            video,
+            audio,
+            video_path,
+            audio_path,
+            sample_rate,
+            frames: 0,
+            samples: 0,
+            error: None,
+        })
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn push_frame(&mut self, frame: &Frame) {
+        let mut planes = vec![0; WIDTH * HEIGHT * 3];
+        let (y_plane, chroma) = planes.split_at_mut(WIDTH * HEIGHT);
+        let (u_plane, v_plane) = chroma.split_at_mut(WIDTH * HEIGHT);
+        for (i, rgb) in frame.data.chunks_exact(3).enumerate() {
+            let (r, g, b) = (rgb[0] as i32, rgb[1] as i32, rgb[2] as i32);
+            y_plane[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            u_plane[i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            v_plane[i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+        let written = self
+            .video
+            .write_all(b"FRAME\n")
+            .and_then(|()| self.video.write_all(&planes));
+        self.keep(written);
+        self.frames += 1;
+    }
+
+    /// One sample of the game's sound, -1.0 to 1.0.
+    pub fn push_sample(&mut self, sample: f32) {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        let written = self.audio.write_all(&value.to_le_bytes());
+        self.keep(written);
+        self.samples += 1;
+    }
+
+    /// Closes both files; returns the video's path.
+    pub fn finish(mut self) -> io::Result<PathBuf> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.video.flush()?;
+        self.audio.flush()?;
+        let audio = self.audio.get_mut();
+        audio.seek(SeekFrom::Start(0))?;
+        audio.write_all(&wav_header(self.sample_rate, self.samples * 2))?;
+        Ok(self.video_path)
+    }
+
+    pub fn audio_path(&self) -> &Path {
+        &self.audio_path
+    }
+
+    fn keep(&mut self, result: io::Result<()>) {
+        if let Err(e) = result {
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+/// RIFF header of a 16-bit mono PCM file with `data_size` bytes of samples.
+fn wav_header(sample_rate: u32, data_size: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(36 + data_size).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // mono
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+    header
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_recording() {
+        let dir = std::env::temp_dir().join(format!("nes_record_test_{}", std::process::id()));
+        let mut recorder =
+            Recorder::start(&dir, Path::new("roms/game.nes"), (60, 1), 44100).unwrap();
+        let audio_path = recorder.audio_path().to_path_buf();
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, (255, 255, 255));
+        recorder.push_frame(&frame);
+        recorder.push_frame(&Frame::new());
+        for sample in [0.0, 1.0, -1.0] {
+            recorder.push_sample(sample);
+        }
+        assert_eq!(recorder.frames(), 2);
+        let video_path = recorder.finish().unwrap();
+
+        let video = std::fs::read(&video_path).unwrap();
+        let header = b"YUV4MPEG2 W256 H240 F60:1 Ip A1:1 C444\nFRAME\n";
+        assert!(video.starts_with(header));
+        assert_eq!(video.len(), header.len() - 6 + 2 * (6 + 256 * 240 * 3));
+        // White is Y 235, U and V 128; black is Y 16
+        let planes = &video[header.len()..];
+        assert_eq!((planes[0], planes[1]), (235, 16));
+        assert_eq!((planes[256 * 240], planes[2 * 256 * 240]), (128, 128));
+
+        let audio = std::fs::read(&audio_path).unwrap();
+        assert_eq!(audio.len(), 44 + 6);
+        assert_eq!(&audio[40..44], &6u32.to_le_bytes());
+        assert_eq!(&audio[44..], &[0, 0, 0xFF, 0x7F, 0x01, 0x80]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
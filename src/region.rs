@@ -0,0 +1,188 @@
+//
+// Console regions. NTSC and PAL consoles run the same chips from different
+// crystals, so everything counted in CPU cycles (the APU's frame sequencer and
+// its noise and DMC periods, the length of a second) changes with the region.
+// Those numbers live here rather than in the chips that use them.
+//
+// The PPU differs too: a PAL frame has 312 scanlines, 70 of them VBlank, and
+// the PPU runs 3.2 dots per CPU cycle instead of 3. Games run on the region
+// their header asks for, unless the settings force one.
+//
+// Dendy, the Famiclone sold across the former Soviet Union, mixes the two: a
+// PAL crystal and 312 lines, but the NTSC dot ratio and APU tables, with the
+// VBlank moved 50 lines down so NTSC games keep their timing in it.
+//
+
+use crate::cartridge::TimingMode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+/// Timer periods of the noise channel, in CPU cycles.
+const NOISE_PERIODS_NTSC: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const NOISE_PERIODS_PAL: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// Output rates of the DMC, in CPU cycles per sample bit.
+const DMC_RATES_NTSC: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const DMC_RATES_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+impl Region {
+    /// The console a dump was made for. Dumps for both regions run as NTSC.
+    pub fn for_timing(timing: TimingMode) -> Region {
+        match timing {
+            TimingMode::Pal => Region::Pal,
+            TimingMode::Dendy => Region::Dendy,
+            TimingMode::Ntsc | TimingMode::MultiRegion => Region::Ntsc,
+        }
+    }
+
+    /// `[console] region`: `auto` (from the header, `None`), `ntsc`, `pal` or
+    /// `dendy`.
+    pub fn parse(text: &str) -> Result<Option<Region>, String> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(None),
+            "ntsc" => Ok(Some(Region::Ntsc)),
+            "pal" => Ok(Some(Region::Pal)),
+            "dendy" => Ok(Some(Region::Dendy)),
+            other => Err(format!(
+                "unknown region '{}', expected auto, ntsc, pal or dendy",
+                other
+            )),
+        }
+    }
+
+    /// CPU clock rate, in Hz.
+    pub fn cpu_clock_rate(self) -> u64 {
+        match self {
+            Region::Ntsc => 1_789_773,
+            Region::Pal => 1_662_607,
+            Region::Dendy => 1_773_447,
+        }
+    }
+
+    /// CPU cycles in one microsecond, for periods given in microseconds (NSF).
+    pub fn cpu_mhz(self) -> f64 {
+        self.cpu_clock_rate() as f64 / 1e6
+    }
+
+    /// CPU cycles in a frame with rendering on, rounded up: NTSC frames
+    /// alternate 29781 and 29780 cycles, PAL ones are 33247.5 on average and
+    /// Dendy ones 35464.
+    pub fn cycles_per_frame(self) -> usize {
+        match self {
+            Region::Ntsc => 29781,
+            Region::Pal => 33248,
+            Region::Dendy => 35464,
+        }
+    }
+
+    /// Scanlines in a frame, the pre-render line last.
+    pub fn scanlines(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// Scanline where VBlank starts and the NMI fires.
+    pub fn vblank_line(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// PPU dots per CPU cycle, as a fraction: 3 on NTSC and Dendy, 16/5 on PAL.
+    pub fn ppu_dots_per_cpu_cycle(self) -> (u32, u32) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    /// Frames per second, as a fraction: 60.0988 on NTSC (with its frames one
+    /// dot short every other time), 50.0070 on PAL and Dendy.
+    pub fn frame_rate(self) -> (u64, u64) {
+        let (dots, cycles) = self.ppu_dots_per_cpu_cycle();
+        let skipped = self.skips_odd_frame_dot() as u64;
+        let dots_per_two_frames = 2 * 341 * self.scanlines() as u64 - skipped;
+        (
+            2 * self.cpu_clock_rate() * dots as u64,
+            dots_per_two_frames * cycles as u64,
+        )
+    }
+
+    /// Whether the pre-render line drops a dot on odd frames. Only the NTSC PPU
+    /// does.
+    pub fn skips_odd_frame_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    /// CPU cycles of the frame sequencer's steps: the four quarter frames of the
+    /// 4-step sequence, then the fifth step of the 5-step one. Each falls between
+    /// two APU cycles, on an odd CPU cycle. Dendy keeps the NTSC APU.
+    pub fn frame_sequence(self) -> [u32; 5] {
+        match self {
+            Region::Ntsc | Region::Dendy => [7457, 14913, 22371, 29829, 37281],
+            Region::Pal => [8313, 16627, 24939, 33253, 41565],
+        }
+    }
+
+    pub fn noise_periods(self) -> &'static [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NOISE_PERIODS_NTSC,
+            Region::Pal => &NOISE_PERIODS_PAL,
+        }
+    }
+
+    pub fn dmc_rates(self) -> &'static [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => &DMC_RATES_NTSC,
+            Region::Pal => &DMC_RATES_PAL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_region_timing() {
+        // 29780.5 and 33247.5 cycles at the nominal 60.0988Hz and 50.007Hz
+        let rate =
+            |region: Region| region.cpu_clock_rate() as f64 / region.cycles_per_frame() as f64;
+        assert!((rate(Region::Ntsc) - 60.0988).abs() < 0.01);
+        assert!((rate(Region::Pal) - 50.007).abs() < 0.01);
+        assert!((rate(Region::Dendy) - 50.007).abs() < 0.01);
+        let regions = [Region::Ntsc, Region::Pal, Region::Dendy];
+        // Steps fall on odd CPU cycles, between two APU cycles
+        for region in regions {
+            assert!(region.frame_sequence().iter().all(|step| step % 2 == 1));
+        }
+        // The PPU frame lasts as many CPU cycles, give or take the odd-frame dot
+        for region in regions {
+            let (dots, cycles) = region.ppu_dots_per_cpu_cycle();
+            let frame = (region.scanlines() as u32 * 341 * cycles).div_ceil(dots);
+            assert_eq!(frame, region.cycles_per_frame() as u32);
+            let (num, den) = region.frame_rate();
+            assert!((num as f64 / den as f64 - rate(region)).abs() < 0.01);
+        }
+        assert_eq!(Region::parse("PAL"), Ok(Some(Region::Pal)));
+        assert_eq!(Region::parse("auto"), Ok(None));
+        assert!(Region::parse("secam").is_err());
+    }
+}
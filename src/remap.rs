@@ -0,0 +1,212 @@
+//
+// Controller mapping: which keyboard keys and joystick buttons press which NES
+// buttons, read from the `[keyboard]`, `[keyboard2]` to `[keyboard4]` (the other
+// players; 3 and 4 have no keys unless given some) and `[joystick]` sections of
+// the settings file (key names as SDL spells them, button numbers as the joystick
+// reports them):
+//
+//   [keyboard]
+//   a = X
+//   b = Z
+//
+//   [keyboard2]
+//   start = Backspace
+//
+//   [joystick]
+//   start = 9
+//
+// F7 walks through the buttons asking for each one in turn, and writes the
+// answers to the settings file.
+//
+
+use crate::config::Config;
+use crate::i18n::{self, Msg};
+use crate::joypad::JoypadButton;
+use sdl2::controller::Button;
+use sdl2::keyboard::Keycode;
+use std::collections::HashMap;
+
+/// NES buttons in prompt order, with their settings key.
+pub const BUTTONS: [(JoypadButton, &str); 8] = [
+    (JoypadButton::UP, "up"),
+    (JoypadButton::DOWN, "down"),
+    (JoypadButton::LEFT, "left"),
+    (JoypadButton::RIGHT, "right"),
+    (JoypadButton::BUTTON_A, "a"),
+    (JoypadButton::BUTTON_B, "b"),
+    (JoypadButton::SELECT, "select"),
+    (JoypadButton::START, "start"),
+];
+
+const DEFAULT_KEYS: [Keycode; 8] = [
+    Keycode::Up,
+    Keycode::Down,
+    Keycode::Left,
+    Keycode::Right,
+    Keycode::A,
+    Keycode::S,
+    Keycode::Space,
+    Keycode::Return,
+];
+
+/// The second player's keys: IJKL and the letters around them, clear of the
+/// first player's and the hotkeys.
+const DEFAULT_KEYS_2: [Keycode; 8] = [
+    Keycode::I,
+    Keycode::K,
+    Keycode::J,
+    Keycode::L,
+    Keycode::G,
+    Keycode::H,
+    Keycode::Y,
+    Keycode::U,
+];
+
+/// Joystick buttons for A, B, Select and Start; the stick is the d-pad.
+const DEFAULT_JOY_BUTTONS: [Option<u8>; 8] =
+    [None, None, None, None, Some(0), Some(1), Some(6), Some(7)];
+
+/// Keys of `player` (0 to 3).
+pub fn keyboard_map(config: &Config, player: usize) -> HashMap<Keycode, JoypadButton> {
+    let (section, defaults) = match player {
+        0 => ("keyboard", Some(DEFAULT_KEYS)),
+        1 => ("keyboard2", Some(DEFAULT_KEYS_2)),
+        2 => ("keyboard3", None),
+        _ => ("keyboard4", None),
+    };
+    BUTTONS
+        .iter()
+        .enumerate()
+        .filter_map(|(index, &(button, name))| {
+            let default = defaults.map(|keys| keys[index]);
+            let key = match config.get(section, name) {
+                Some(key_name) => Keycode::from_name(key_name).or_else(|| {
+                    eprintln!("[{}] {}: unknown key '{}'", section, name, key_name);
+                    default
+                }),
+                None => default,
+            };
+            key.map(|key| (key, button))
+        })
+        .collect()
+}
+
+pub fn joystick_button_map(config: &Config) -> HashMap<u8, JoypadButton> {
+    BUTTONS
+        .iter()
+        .zip(DEFAULT_JOY_BUTTONS)
+        .filter_map(|(&(button, name), default)| {
+            let index = match config.get("joystick", name).map(str::parse) {
+                Some(Ok(index)) => Some(index),
+                Some(Err(_)) => {
+                    eprintln!("[joystick] {}: not a button number", name);
+                    default
+                }
+                None => default,
+            };
+            index.map(|index| (index, button))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    Key(Keycode),
+    JoyButton(u8),
+    /// Button of the `n`th gamepad SDL knows
+    PadButton(usize, Button),
+}
+
+/// The F7 remapping flow: one captured input per NES button, in `BUTTONS` order.
+#[derive(Debug, Default)]
+pub struct RemapCapture {
+    inputs: Vec<Input>,
+}
+
+impl RemapCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What to show while waiting for the next input.
+    pub fn prompt(&self) -> String {
+        let (button, _) = BUTTONS[self.inputs.len()];
+        i18n::format(Msg::RemapPrompt, &[&i18n::button_name(button)])
+    }
+
+    /// Assigns `input` to the button being asked for. An input already given to
+    /// an earlier button is ignored, so one key cannot end up pressing two buttons.
+    /// Returns true once every button has one.
+    pub fn capture(&mut self, input: Input) -> bool {
+        if !self.inputs.contains(&input) {
+            self.inputs.push(input);
+        }
+        self.inputs.len() == BUTTONS.len()
+    }
+
+    /// The captured mapping as `(section, key, value)` settings.
+    pub fn settings(&self) -> Vec<(String, String, String)> {
+        self.inputs
+            .iter()
+            .zip(BUTTONS)
+            .map(|(input, (_, name))| match input {
+                Input::Key(key) => ("keyboard".to_string(), name.to_string(), key.name()),
+                Input::JoyButton(index) => {
+                    ("joystick".to_string(), name.to_string(), index.to_string())
+                }
+                Input::PadButton(pad, button) => (
+                    format!("gamepad.{}", pad + 1),
+                    name.to_string(),
+                    button.string(),
+                ),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capture_and_maps() {
+        let mut capture = RemapCapture::new();
+        for index in 0..7 {
+            assert!(!capture.capture(Input::JoyButton(index)));
+        }
+        // Button 3 is Right already
+        assert!(!capture.capture(Input::JoyButton(3)));
+        assert!(capture.capture(Input::JoyButton(9)));
+        let settings = capture.settings();
+        assert_eq!(
+            settings[7],
+            ("joystick".to_string(), "start".to_string(), "9".to_string())
+        );
+
+        let mut config = Config::default();
+        let path = std::env::temp_dir().join(format!("nes_remap_test_{}.cfg", std::process::id()));
+        config.store(&path, &settings).unwrap();
+        let map = joystick_button_map(&Config::load(&path).unwrap());
+        assert_eq!(map.len(), 8);
+        assert_eq!(map[&9], JoypadButton::START);
+        assert_eq!(map[&0], JoypadButton::UP);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            joystick_button_map(&Config::default())[&7],
+            JoypadButton::START
+        );
+    }
+
+    #[test]
+    fn test_second_player_keys() {
+        let first = keyboard_map(&Config::default(), 0);
+        let second = keyboard_map(&Config::default(), 1);
+        assert_eq!(second[&Keycode::I], JoypadButton::UP);
+        assert_eq!(second[&Keycode::U], JoypadButton::START);
+        assert_eq!(first[&Keycode::Return], JoypadButton::START);
+        // The default sets share no key
+        assert!(first.keys().all(|key| !second.contains_key(key)));
+        assert!(keyboard_map(&Config::default(), 3).is_empty());
+    }
+}
@@ -0,0 +1,52 @@
+//
+// Zapper aim marker. The mouse pointer is hidden behind the letterboxing and the
+// TV profile's scaling, so the point the gun senses is drawn on the picture itself,
+// after the light sensor has looked at the frame.
+//
+
+use super::frame::Frame;
+
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+/// Arm length, and gap left around the aim point so the target stays visible
+const ARM: usize = 4;
+const GAP: usize = 2;
+
+/// Draws a cross around `(x, y)` by inverting the pixels under it, which keeps it
+/// readable over both dark and bright scenes.
+pub fn draw(frame: &mut Frame, x: usize, y: usize) {
+    for distance in GAP..GAP + ARM {
+        let arms = [
+            (x.checked_sub(distance), Some(y)),
+            (Some(x + distance), Some(y)),
+            (Some(x), y.checked_sub(distance)),
+            (Some(x), Some(y + distance)),
+        ];
+        for (px, py) in arms {
+            if let (Some(px), Some(py)) = (px, py) {
+                if px < WIDTH && py < HEIGHT {
+                    let base = (py * WIDTH + px) * 3;
+                    for channel in &mut frame.data[base..base + 3] {
+                        *channel = !*channel;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crosshair_inverts_arms() {
+        let mut frame = Frame::new();
+        draw(&mut frame, 0, 100);
+        let pixel = |frame: &Frame, x: usize, y: usize| frame.data[(y * WIDTH + x) * 3];
+        assert_eq!(pixel(&frame, 0, 100), 0);
+        assert_eq!(pixel(&frame, GAP, 100), 0xFF);
+        assert_eq!(pixel(&frame, 0, 100 - GAP - ARM + 1), 0xFF);
+        assert_eq!(pixel(&frame, 0, 100 - GAP - ARM), 0);
+    }
+}
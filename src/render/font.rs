@@ -0,0 +1,104 @@
+//
+// Built-in bitmap font for on-screen text, so the OSD needs no font file at
+// runtime. Glyphs are 5x7 pixels drawn in 6x8 cells; they come from font.txt and
+// cover ASCII plus the accented letters used by the French strings.
+//
+
+use super::frame::Frame;
+use std::collections::HashMap;
+
+pub const CELL_WIDTH: usize = 6;
+pub const CELL_HEIGHT: usize = 8;
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const REPLACEMENT: char = '\u{FFFD}';
+
+lazy_static! {
+    static ref GLYPHS: HashMap<char, [u8; GLYPH_HEIGHT]> = parse(include_str!("font.txt"));
+}
+
+/// Reads glyphs: a line holding the character, then one line of `#`/`.` per row.
+/// Lines starting with "# " are comments.
+fn parse(text: &str) -> HashMap<char, [u8; GLYPH_HEIGHT]> {
+    let mut lines = text
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with("# "));
+    let mut glyphs = HashMap::new();
+    while let Some(name) = lines.next() {
+        let mut rows = [0; GLYPH_HEIGHT];
+        for row in rows.iter_mut() {
+            let line = lines.next().expect("font.txt: truncated glyph");
+            *row = line
+                .bytes()
+                .take(GLYPH_WIDTH)
+                .fold(0, |bits, pixel| bits << 1 | (pixel == b'#') as u8);
+        }
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => {
+                glyphs.insert(c, rows);
+            }
+            _ => panic!("font.txt: bad glyph name '{}'", name),
+        }
+    }
+    glyphs
+}
+
+fn glyph(c: char) -> &'static [u8; GLYPH_HEIGHT] {
+    GLYPHS.get(&c).unwrap_or_else(|| &GLYPHS[&REPLACEMENT])
+}
+
+/// Width in pixels of `text` drawn on one line.
+pub fn text_width(text: &str) -> usize {
+    text.chars().count() * CELL_WIDTH
+}
+
+/// Draws `text` with its top-left corner at (x, y), with a dark drop shadow so it
+/// reads over any picture. Pixels outside the frame are clipped.
+pub fn draw_text(frame: &mut Frame, x: usize, y: usize, text: &str, rgb: (u8, u8, u8)) {
+    for (index, c) in text.chars().enumerate() {
+        let left = x + index * CELL_WIDTH;
+        let rows = glyph(c);
+        for (shadow, color) in [(1, (0, 0, 0)), (0, rgb)] {
+            for (dy, bits) in rows.iter().enumerate() {
+                for dx in 0..GLYPH_WIDTH {
+                    if bits & (0x10 >> dx) != 0 {
+                        frame.set_pixel(left + dx + shadow, y + dy + shadow, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_font_covers_text() {
+        for c in (' '..='~').chain("éèàùçêâôûîëïöüÉÀÇ".chars()) {
+            assert!(GLYPHS.contains_key(&c), "missing glyph {:?}", c);
+        }
+        assert_eq!(glyph('\u{1F600}'), glyph(REPLACEMENT));
+        assert_eq!(text_width("Pause"), 30);
+    }
+
+    #[test]
+    fn test_draw_text() {
+        let mut frame = Frame::new();
+        draw_text(&mut frame, 10, 20, "I", (255, 255, 255));
+        let pixel = |x: usize, y: usize| {
+            let base = (y * 256 + x) * 3;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+        // Top bar of the I, and nothing left of it
+        assert_eq!(pixel(11, 20), (255, 255, 255));
+        assert_eq!(pixel(10, 20), (0, 0, 0));
+        assert_eq!(pixel(12, 26), (255, 255, 255));
+
+        // Clipped at the edge instead of panicking
+        draw_text(&mut frame, 250, 236, "WW", (255, 255, 255));
+    }
+}
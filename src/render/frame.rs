@@ -7,6 +7,15 @@ pub struct Frame {
     pub background_buffer: Vec<u8>,
     pub sprite_buffer: Vec<u8>,
     pub priority_buffer: Vec<bool>, // true = sprite has priority
+    // Color index (0-3) of each pixel in the buffers above, 0 = transparent
+    pub background_index: Vec<u8>,
+    pub sprite_index: Vec<u8>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Frame {
@@ -20,6 +29,8 @@ impl Frame {
             background_buffer: vec![0; buffer_size],
             sprite_buffer: vec![0; buffer_size],
             priority_buffer: vec![false; Frame::WIDTH * Frame::HEIGHT],
+            background_index: vec![0; Frame::WIDTH * Frame::HEIGHT],
+            sprite_index: vec![0; Frame::WIDTH * Frame::HEIGHT],
         }
     }
 
@@ -33,18 +44,27 @@ impl Frame {
         }
     }
 
-    /// Sets a background pixel in the separate buffer
-    pub fn set_background_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+    /// Sets a background pixel in the separate buffer; `index` is its color
+    /// within the tile, 0 for the transparent one
+    pub fn set_background_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8), index: u8) {
         if x < Frame::WIDTH && y < Frame::HEIGHT {
             let base = y * 3 * Frame::WIDTH + x * 3;
             self.background_buffer[base] = rgb.0;
             self.background_buffer[base + 1] = rgb.1;
             self.background_buffer[base + 2] = rgb.2;
+            self.background_index[y * Frame::WIDTH + x] = index;
         }
     }
 
-    /// Sets a sprite pixel in the separate buffer
-    pub fn set_sprite_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8), priority: bool) {
+    /// Sets a sprite pixel in the separate buffer; `index` as for the background
+    pub fn set_sprite_pixel(
+        &mut self,
+        x: usize,
+        y: usize,
+        rgb: (u8, u8, u8),
+        index: u8,
+        priority: bool,
+    ) {
         if x < Frame::WIDTH && y < Frame::HEIGHT {
             let base = y * 3 * Frame::WIDTH + x * 3;
             let pixel_index = y * Frame::WIDTH + x;
@@ -52,6 +72,7 @@ impl Frame {
             self.sprite_buffer[base] = rgb.0;
             self.sprite_buffer[base + 1] = rgb.1;
             self.sprite_buffer[base + 2] = rgb.2;
+            self.sprite_index[pixel_index] = index;
             self.priority_buffer[pixel_index] = priority;
         }
     }
@@ -68,17 +89,13 @@ impl Frame {
                 self.data[base + 1] = self.background_buffer[base + 1];
                 self.data[base + 2] = self.background_buffer[base + 2];
 
-                // Check if there is a non-transparent sprite at this position
-                let sprite_transparent = self.sprite_buffer[base] == 0
-                    && self.sprite_buffer[base + 1] == 0
-                    && self.sprite_buffer[base + 2] == 0;
+                // Transparency comes from the color index, not the RGB value:
+                // black is a color like any other
+                let sprite_transparent = self.sprite_index[pixel_index] == 0;
 
                 // If the sprite is not transparent, apply it according to its priority
                 if !sprite_transparent {
-                    let _sprite_behind_bg = !self.priority_buffer[pixel_index];
-                    let bg_transparent = self.background_buffer[base] == 0
-                        && self.background_buffer[base + 1] == 0
-                        && self.background_buffer[base + 2] == 0;
+                    let bg_transparent = self.background_index[pixel_index] == 0;
 
                     // Sprite is visible if:
                     // - It is in front of the background (priority = true), OR
@@ -99,6 +116,24 @@ impl Frame {
         self.background_buffer.fill(0);
         self.sprite_buffer.fill(0);
         self.priority_buffer.fill(false);
+        self.background_index.fill(0);
+        self.sprite_index.fill(0);
+    }
+
+    /// The picture as RGBA8888, opaque, for frontends whose textures want four
+    /// bytes per pixel
+    pub fn as_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(Frame::WIDTH * Frame::HEIGHT * 4);
+        self.write_rgba(&mut rgba);
+        rgba
+    }
+
+    /// Same as `as_rgba`, into a buffer kept from frame to frame
+    pub fn write_rgba(&self, out: &mut Vec<u8>) {
+        out.clear();
+        for pixel in self.data.chunks_exact(3) {
+            out.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 0xFF]);
+        }
     }
 
     /// Returns the frame dimensions
@@ -106,3 +141,33 @@ impl Frame {
         (Frame::WIDTH, Frame::HEIGHT)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_composite_buffers() {
+        let mut frame = Frame::new();
+        // A black sprite pixel over a white background still shows
+        frame.set_background_pixel(0, 0, (255, 255, 255), 1);
+        frame.set_sprite_pixel(0, 0, (0, 0, 0), 3, true);
+        // Behind a black background pixel, a sprite stays hidden
+        frame.set_background_pixel(1, 0, (0, 0, 0), 2);
+        frame.set_sprite_pixel(1, 0, (255, 0, 0), 1, false);
+        // and shows where the background is transparent
+        frame.set_sprite_pixel(2, 0, (255, 0, 0), 1, false);
+
+        frame.composite_buffers();
+        assert_eq!(&frame.data[..9], &[0, 0, 0, 0, 0, 0, 255, 0, 0]);
+    }
+
+    #[test]
+    fn test_as_rgba() {
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 0, (1, 2, 3));
+        let rgba = frame.as_rgba();
+        assert_eq!(rgba.len(), 256 * 240 * 4);
+        assert_eq!(&rgba[..8], &[0, 0, 0, 255, 1, 2, 3, 255]);
+    }
+}
@@ -0,0 +1,69 @@
+//
+// Priority debug view (--debug-priority): every pixel is tinted by what drew it,
+// so a sprite showing through the background it should hide behind, or the
+// backdrop peeking through a hole, stands out at a glance.
+//
+//   blue     background
+//   green    sprite in front of the background
+//   red      sprite behind the background
+//   grey     backdrop (palette entry 0)
+//
+
+use super::frame::Frame;
+
+/// What a pixel of the picture shows.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Layer {
+    #[default]
+    Backdrop,
+    Background,
+    SpriteFront,
+    SpriteBehind,
+}
+
+impl Layer {
+    fn tint(self) -> (u8, u8, u8) {
+        match self {
+            Layer::Backdrop => (0x60, 0x60, 0x60),
+            Layer::Background => (0x20, 0x60, 0xFF),
+            Layer::SpriteFront => (0x20, 0xE0, 0x40),
+            Layer::SpriteBehind => (0xFF, 0x30, 0x30),
+        }
+    }
+}
+
+/// How `render_with` composes the picture.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RenderMode {
+    #[default]
+    Normal,
+    /// The normal picture, each pixel blended half and half with its layer's tint
+    PriorityTint,
+}
+
+/// Blends every pixel of `frame` with the tint of the layer that drew it.
+/// `layers` holds one entry per pixel, row by row.
+pub fn tint(frame: &mut Frame, layers: &[Layer]) {
+    for (pixel, layer) in frame.data.chunks_exact_mut(3).zip(layers) {
+        let (r, g, b) = layer.tint();
+        for (channel, tint) in pixel.iter_mut().zip([r, g, b]) {
+            *channel = ((*channel as u16 + tint as u16) / 2) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tint() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, (0xFF, 0xFF, 0xFF));
+        let mut layers = vec![Layer::Backdrop; 256 * 240];
+        layers[0] = Layer::SpriteBehind;
+        tint(&mut frame, &layers);
+        assert_eq!(&frame.data[..3], &[0xFF, 0x97, 0x97]);
+        assert_eq!(&frame.data[3..6], &[0x30, 0x30, 0x30]);
+    }
+}
@@ -1,112 +1,174 @@
+pub mod crosshair;
+pub mod font;
 pub mod frame;
+pub mod icon;
+pub mod layers;
+pub mod osd;
 pub mod palette;
+pub mod profile;
+pub mod upscale;
 
-use crate::cartridge::Mirroring;
+use crate::ppu::registers::mask::MaskRegister;
 use crate::ppu::NesPPU;
 use frame::Frame;
+use layers::{Layer, RenderMode};
+use palette::Palette;
 
-fn bg_pallette(
-    ppu: &NesPPU,
-    attribute_table: &[u8],
-    tile_column: usize,
-    tile_row: usize,
-) -> [u8; 4] {
-    let attr_table_idx = tile_row / 4 * 8 + tile_column / 4;
-    let attr_byte = attribute_table[attr_table_idx];
-
-    let pallet_idx = match (tile_column % 4 / 2, tile_row % 4 / 2) {
-        (0, 0) => attr_byte & 0b11,
-        (1, 0) => (attr_byte >> 2) & 0b11,
-        (0, 1) => (attr_byte >> 4) & 0b11,
-        (1, 1) => (attr_byte >> 6) & 0b11,
-        (_, _) => panic!("should not happen"),
-    };
-
-    let pallete_start: usize = 1 + (pallet_idx as usize) * 4;
-    [
-        ppu.palette_table[0],
-        ppu.palette_table[pallete_start],
-        ppu.palette_table[pallete_start + 1],
-        ppu.palette_table[pallete_start + 2],
-    ]
+/// Fetches the 16 bytes of a tile from the pattern tables.
+fn fetch_tile(ppu: &NesPPU, addr: u16, sprite: bool) -> [u8; 16] {
+    let mut tile = [0; 16];
+    for (i, byte) in tile.iter_mut().enumerate() {
+        *byte = if sprite {
+            ppu.read_sprite_chr(addr + i as u16)
+        } else {
+            ppu.read_chr(addr + i as u16)
+        };
+    }
+    tile
 }
 
-fn sprite_palette(ppu: &NesPPU, pallete_idx: u8) -> [u8; 4] {
-    let start = 0x11 + (pallete_idx * 4) as usize;
-    [
-        0,
-        ppu.palette_table[start],
-        ppu.palette_table[start + 1],
-        ppu.palette_table[start + 2],
-    ]
+/// Palette RAM as it was at every point of the picture.
+///
+/// Rendering happens once per frame, after the fact, so the palette writes the PPU
+/// logged during the visible scanlines are replayed here: each line is split into
+/// segments starting at the dot where a write landed. Lines PPUMASK started in
+/// grayscale only keep the brightness column of the system palette.
+struct PaletteTimeline<'a> {
+    lines: Vec<Vec<(usize, [u8; 32])>>,
+    masks: Vec<MaskRegister>,
+    colors: &'a Palette,
 }
 
-struct Rect {
-    x1: usize,
-    y1: usize,
-    x2: usize,
-    y2: usize,
+impl<'a> PaletteTimeline<'a> {
+    fn new(ppu: &NesPPU, colors: &'a Palette) -> Self {
+        let mut current = ppu.frame_palette;
+        let mut writes = ppu.palette_writes.iter().peekable();
+        let mut lines = Vec::with_capacity(240);
+
+        for y in 0..240u16 {
+            // Writes made in HBlank (or before the line starts) affect the whole line
+            while let Some(write) =
+                writes.next_if(|w| w.scanline < y || (w.scanline == y && w.dot == 0))
+            {
+                current[write.index] = write.value;
+            }
+
+            let mut segments = vec![(0, current)];
+            while let Some(write) = writes.next_if(|w| w.scanline == y && w.dot < 256) {
+                current[write.index] = write.value;
+                match segments.last_mut() {
+                    Some((start, palette)) if *start == write.dot => *palette = current,
+                    _ => segments.push((write.dot, current)),
+                }
+            }
+            lines.push(segments);
+        }
+
+        let masks = (0..240).map(|y| ppu.line_mask(y)).collect();
+        PaletteTimeline {
+            lines,
+            masks,
+            colors,
+        }
+    }
+
+    /// System palette color of palette RAM `entry` at screen pixel (x, y).
+    fn color(&self, x: usize, y: usize, entry: usize) -> (u8, u8, u8) {
+        let (_, palette) = self.lines[y]
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= x)
+            .unwrap();
+        let mask = self.masks[y];
+        let index = match mask.is_grayscale() {
+            true => palette[entry] & 0x30,
+            false => palette[entry],
+        };
+        self.colors.color(index, mask.emphasis())
+    }
 }
 
-impl Rect {
-    fn new(x1: usize, y1: usize, x2: usize, y2: usize) -> Self {
-        Rect {
-            x1: x1,
-            y1: y1,
-            x2: x2,
-            y2: y2,
+/// First palette RAM entry of a sprite palette.
+fn sprite_palette(pallete_idx: u8) -> usize {
+    0x11 + (pallete_idx * 4) as usize
+}
+
+/// Colors the background pixels the PPU produced dot by dot during the frame.
+fn render_background(
+    ppu: &NesPPU,
+    palettes: &PaletteTimeline,
+    frame: &mut Frame,
+    layers: &mut [Layer],
+) {
+    for y in 0..240 {
+        for x in 0..256 {
+            let entry = ppu.background_pixel(x, y);
+            let rgb = palettes.color(x, y, entry as usize);
+            frame.set_background_pixel(x, y, rgb, entry & 0b11);
+            layers[y * 256 + x] = match entry {
+                0 => Layer::Backdrop,
+                _ => Layer::Background,
+            };
         }
     }
 }
 
-fn render_name_table(
+/// Draws the sprites the PPU found for each line into the sprite buffer, the
+/// first in OAM on top, where PPUMASK showed them when the line started.
+/// A sprite shows from the line after its Y. 8x16 sprites pick their pattern
+/// table with bit 0 of the tile number and stack two tiles, top one even.
+/// The first sprite keeps its priority: behind the background, it hides the
+/// sprites under it even where the background covers it.
+fn render_sprites(
     ppu: &NesPPU,
+    palettes: &PaletteTimeline,
     frame: &mut Frame,
-    name_table: &[u8],
-    view_port: Rect,
-    shift_x: isize,
-    shift_y: isize,
+    layers: &mut [Layer],
 ) {
-    let bank = ppu.ctrl.bknd_pattern_addr();
-
-    let attribute_table = &name_table[0x3c0..0x400];
-
-    for i in 0..0x3c0 {
-        let tile_column = i % 32;
-        let tile_row = i / 32;
-        let tile_idx = name_table[i] as u16;
-        let tile =
-            &ppu.chr_rom[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
-        let palette = bg_pallette(ppu, attribute_table, tile_column, tile_row);
-
-        for y in 0..=7 {
-            let mut upper = tile[y];
-            let mut lower = tile[y + 8];
-
-            for x in (0..=7).rev() {
-                let value = (1 & lower) << 1 | (1 & upper);
-                upper = upper >> 1;
-                lower = lower >> 1;
-                let rgb = match value {
-                    0 => palette::SYSTEM_PALLETE[ppu.palette_table[0] as usize],
-                    1 => palette::SYSTEM_PALLETE[palette[1] as usize],
-                    2 => palette::SYSTEM_PALLETE[palette[2] as usize],
-                    3 => palette::SYSTEM_PALLETE[palette[3] as usize],
-                    _ => panic!("can't be"),
-                };
-                let pixel_x = tile_column * 8 + x;
-                let pixel_y = tile_row * 8 + y;
-
-                if pixel_x >= view_port.x1
-                    && pixel_x < view_port.x2
-                    && pixel_y >= view_port.y1
-                    && pixel_y < view_port.y2
-                {
-                    frame.set_pixel(
-                        (shift_x + pixel_x as isize) as usize,
-                        (shift_y + pixel_y as isize) as usize,
-                        rgb,
-                    );
+    let height = ppu.ctrl.sprite_size() as usize;
+    frame.sprite_index.fill(0);
+
+    for pixel_y in 0..240 {
+        let mask = ppu.line_mask(pixel_y);
+        if !mask.show_sprites() {
+            continue;
+        }
+        for sprite in ppu.line_sprites(pixel_y).iter().rev() {
+            let flip_vertical = sprite.attributes >> 7 & 1 == 1;
+            let flip_horizontal = sprite.attributes >> 6 & 1 == 1;
+            let front = sprite.attributes >> 5 & 1 == 0;
+            let sprite_palette = sprite_palette(sprite.attributes & 0b11);
+
+            let row = (pixel_y as u16 - 1 - sprite.y as u16) as usize;
+            let row = if flip_vertical { height - 1 - row } else { row };
+            let tile_addr = match height {
+                8 => ppu.ctrl.sprt_pattern_addr() + sprite.tile as u16 * 16,
+                _ => {
+                    let bank = (sprite.tile as u16 & 1) * 0x1000;
+                    bank + (sprite.tile as u16 & 0xFE) * 16 + (row as u16 / 8) * 16
+                }
+            };
+            let tile = fetch_tile(ppu, tile_addr, true);
+            let upper = tile[row % 8];
+            let lower = tile[row % 8 + 8];
+
+            for x in 0..8 {
+                let bit = if flip_horizontal { x } else { 7 - x };
+                let value = (lower >> bit & 1) << 1 | (upper >> bit & 1);
+                if value == 0 {
+                    continue;
+                }
+                let pixel_x = sprite.x as usize + x;
+                if pixel_x < 256 && (pixel_x >= 8 || mask.leftmost_8pxl_sprite()) {
+                    let entry = sprite_palette + value as usize - 1;
+                    let rgb = palettes.color(pixel_x, pixel_y, entry);
+                    frame.set_sprite_pixel(pixel_x, pixel_y, rgb, value, front);
+                    let index = pixel_y * 256 + pixel_x;
+                    layers[index] = match (front, frame.background_index[index]) {
+                        (true, _) => Layer::SpriteFront,
+                        (false, 0) => Layer::SpriteBehind,
+                        (false, _) => Layer::Background,
+                    };
                 }
             }
         }
@@ -114,106 +176,327 @@ fn render_name_table(
 }
 
 pub fn render(ppu: &NesPPU, frame: &mut Frame) {
-    let scroll_x = (ppu.scroll.scroll_x) as usize;
-    let scroll_y = (ppu.scroll.scroll_y) as usize;
-
-    let (main_nametable, second_nametable) = match (&ppu.mirroring, ppu.ctrl.nametable_addr()) {
-        (Mirroring::Vertical, 0x2000)
-        | (Mirroring::Vertical, 0x2800)
-        | (Mirroring::Horizontal, 0x2000)
-        | (Mirroring::Horizontal, 0x2400) => (&ppu.vram[0..0x400], &ppu.vram[0x400..0x800]),
-        (Mirroring::Vertical, 0x2400)
-        | (Mirroring::Vertical, 0x2C00)
-        | (Mirroring::Horizontal, 0x2800)
-        | (Mirroring::Horizontal, 0x2C00) => (&ppu.vram[0x400..0x800], &ppu.vram[0..0x400]),
-        (_, _) => {
-            panic!("Not supported mirroring type {:?}", ppu.mirroring);
+    render_with(ppu, frame, RenderMode::Normal, &Palette::default());
+}
+
+/// Renders with the system palette `colors`, emphasis included.
+pub fn render_with(ppu: &NesPPU, frame: &mut Frame, mode: RenderMode, colors: &Palette) {
+    let palettes = PaletteTimeline::new(ppu, colors);
+    let mut layers = vec![Layer::Backdrop; 256 * 240];
+    render_background(ppu, &palettes, frame, &mut layers);
+
+    render_sprites(ppu, &palettes, frame, &mut layers);
+    frame.composite_buffers();
+
+    if mode == RenderMode::PriorityTint {
+        layers::tint(frame, &layers);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::test_rom_containing;
+    use crate::cpu::CPU;
+    use crate::input::ControllerPorts;
+    use crate::ppu::registers::status::StatusRegister;
+    use crate::ppu::PPU;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn write_backdrop(ppu: &mut NesPPU, value: u8) {
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_data(value);
+    }
+
+    fn pixel(frame: &Frame, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * 256 + x) * 3;
+        (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+    }
+
+    fn advance(ppu: &mut NesPPU, dots: usize) {
+        for _ in 0..dots {
+            ppu.tick(1);
+        }
+    }
+
+    #[test]
+    fn test_palette_writes_apply_at_their_dot() {
+        let mut ppu = NesPPU::new(vec![0; 0x2000], crate::cartridge::Mirroring::Horizontal);
+        write_backdrop(&mut ppu, 0x01);
+        advance(&mut ppu, 100 * 341);
+        write_backdrop(&mut ppu, 0x16);
+        advance(&mut ppu, 20 * 341 + 128);
+        write_backdrop(&mut ppu, 0x2a);
+        advance(&mut ppu, 121 * 341);
+        assert_eq!(ppu.scanline, 241);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+
+        let color = |index: usize| palette::SYSTEM_PALLETE[index];
+        assert_eq!(pixel(&frame, 0, 99), color(0x01));
+        assert_eq!(pixel(&frame, 0, 100), color(0x16));
+        assert_eq!(pixel(&frame, 127, 120), color(0x16));
+        assert_eq!(pixel(&frame, 128, 120), color(0x2a));
+        assert_eq!(pixel(&frame, 0, 121), color(0x2a));
+    }
+
+    #[test]
+    fn test_split_scroll() {
+        // Tile 1 is solid; the right half of the first nametable uses it
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].fill(0xFF);
+        let mut ppu = NesPPU::new(chr, crate::cartridge::Mirroring::Vertical);
+        for row in 0..30 {
+            ppu.vram[row * 32 + 16..row * 32 + 32].fill(1);
         }
-    };
-
-    render_name_table(
-        ppu,
-        frame,
-        main_nametable,
-        Rect::new(scroll_x, scroll_y, 256, 240),
-        -(scroll_x as isize),
-        -(scroll_y as isize),
-    );
-    if scroll_x > 0 {
-        render_name_table(
-            ppu,
-            frame,
-            second_nametable,
-            Rect::new(0, 0, scroll_x, 240),
-            (256 - scroll_x) as isize,
-            0,
+        ppu.frame_palette[..2].copy_from_slice(&[0x0F, 0x30]);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_mask(0x0A);
+
+        // Scrolled by half a screen from line 101 on, as a status bar split
+        advance(&mut ppu, 100 * 341 + 10);
+        ppu.write_to_scroll(128);
+        ppu.write_to_scroll(0);
+        advance(&mut ppu, 141 * 341);
+        assert_eq!(ppu.scanline, 241);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        let (black, white) = (palette::SYSTEM_PALLETE[0x0F], palette::SYSTEM_PALLETE[0x30]);
+        assert_eq!(
+            (pixel(&frame, 0, 100), pixel(&frame, 200, 100)),
+            (black, white)
         );
-    } else if scroll_y > 0 {
-        render_name_table(
-            ppu,
-            frame,
-            second_nametable,
-            Rect::new(0, 0, 256, scroll_y),
-            0,
-            (240 - scroll_y) as isize,
+        assert_eq!(
+            (pixel(&frame, 0, 101), pixel(&frame, 200, 101)),
+            (white, black)
+        );
+        assert_eq!(
+            (pixel(&frame, 127, 239), pixel(&frame, 128, 239)),
+            (white, black)
         );
     }
 
-    for i in (0..ppu.oam_data.len()).step_by(4).rev() {
-        let tile_idx = ppu.oam_data[i + 1] as u16;
-        let tile_x = ppu.oam_data[i + 3] as usize;
-        let tile_y = ppu.oam_data[i] as usize;
+    #[test]
+    fn test_sprite_limit() {
+        for sprite_limit in [true, false] {
+            // Nine solid sprites side by side on lines 21-28, the rest hidden
+            let mut chr = vec![0; 0x2000];
+            chr[16..24].fill(0xFF);
+            let mut ppu = NesPPU::new(chr, crate::cartridge::Mirroring::Horizontal);
+            ppu.options.sprite_limit = sprite_limit;
+            ppu.oam_data.fill(0xFF);
+            for n in 0..9 {
+                ppu.oam_data[n * 4..n * 4 + 4].copy_from_slice(&[20, 1, 0, n as u8 * 16]);
+            }
+            ppu.frame_palette[0x11] = 0x30;
+            ppu.write_to_mask(0x10);
+            advance(&mut ppu, 241 * 341);
+            assert!(ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
 
-        let flip_vertical = if ppu.oam_data[i + 2] >> 7 & 1 == 1 {
-            true
-        } else {
-            false
-        };
-        let flip_horizontal = if ppu.oam_data[i + 2] >> 6 & 1 == 1 {
-            true
-        } else {
-            false
+            let mut frame = Frame::new();
+            render(&ppu, &mut frame);
+            let white = palette::SYSTEM_PALLETE[0x30];
+            assert_eq!(pixel(&frame, 7 * 16, 21), white);
+            assert_eq!(pixel(&frame, 8 * 16, 21) == white, !sprite_limit);
+            assert_ne!(pixel(&frame, 0, 20), white);
+        }
+    }
+
+    #[test]
+    fn test_tall_sprites() {
+        // Tiles $10 and $11 of the second pattern table: a top row, then a bottom row
+        let mut chr = vec![0; 0x2000];
+        chr[0x1100] = 0xF0;
+        chr[0x1117] = 0x0F;
+        let mut ppu = NesPPU::new(chr, crate::cartridge::Mirroring::Horizontal);
+        ppu.oam_data.fill(0xFF);
+        ppu.oam_data[..8].copy_from_slice(&[20, 0x11, 0x00, 8, 20, 0x11, 0x80, 40]);
+        ppu.frame_palette[0x11] = 0x30;
+        ppu.write_to_ctrl(0x20);
+        ppu.write_to_mask(0x10);
+        advance(&mut ppu, 241 * 341);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        let white = palette::SYSTEM_PALLETE[0x30];
+        let lit = |frame: &Frame, x: usize, y: usize| pixel(frame, x, y) == white;
+        assert!(lit(&frame, 8, 21) && !lit(&frame, 12, 21));
+        assert!(lit(&frame, 12, 36) && !lit(&frame, 8, 36));
+        // Flipped, the bottom tile comes first and upside down
+        assert!(lit(&frame, 44, 21) && !lit(&frame, 40, 21));
+        assert!(lit(&frame, 40, 36));
+    }
+
+    #[test]
+    fn test_left_clip() {
+        // A solid background, and sprite 0 solid over its first tile
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].fill(0xFF);
+        let mut ppu = NesPPU::new(chr, crate::cartridge::Mirroring::Horizontal);
+        ppu.vram[..0x3C0].fill(1);
+        ppu.oam_data.fill(0xFF);
+        ppu.oam_data[..4].copy_from_slice(&[20, 1, 0, 0]);
+        ppu.frame_palette[..0x12].copy_from_slice(&[
+            0x0F, 0x16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x30,
+        ]);
+        ppu.write_to_mask(0x18);
+        advance(&mut ppu, 22 * 341);
+        assert!(!ppu.status.is_sprite_zero_hit());
+        advance(&mut ppu, 219 * 341);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        let color = |index: usize| palette::SYSTEM_PALLETE[index];
+        assert_eq!(pixel(&frame, 7, 21), color(0x0F));
+        assert_eq!(pixel(&frame, 8, 21), color(0x16));
+
+        // Showing both in the left column lets the sprite draw and hit there
+        let mut ppu = NesPPU::new(vec![0; 0x2000], crate::cartridge::Mirroring::Horizontal);
+        ppu.oam_data[..4].copy_from_slice(&[20, 1, 0, 0]);
+        ppu.write_to_mask(0x1E);
+        advance(&mut ppu, 22 * 341 + 4);
+        assert!(ppu.status.is_sprite_zero_hit());
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        // Tile 1 is solid and covers the left half of the background
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].fill(0xFF);
+        let mut ppu = NesPPU::new(chr, crate::cartridge::Mirroring::Horizontal);
+        for row in 0..30 {
+            ppu.vram[row * 32..row * 32 + 16].fill(1);
+        }
+        ppu.oam_data.fill(0xFF);
+        ppu.oam_data[..20].copy_from_slice(&[
+            20, 1, 0x20, 80, // behind, over the front sprite below
+            20, 1, 0x00, 80, // in front
+            20, 1, 0x20, 16, // behind the background
+            20, 1, 0x20, 200, // behind the backdrop only
+            20, 1, 0x01, 40, // in front, black
+        ]);
+        ppu.frame_palette[..0x16].copy_from_slice(&[
+            0x0F, 0x30, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x16, 0, 0, 0, 0x0F,
+        ]);
+        ppu.write_to_mask(0x18);
+        advance(&mut ppu, 241 * 341);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        let color = |index: usize| palette::SYSTEM_PALLETE[index];
+        assert_eq!(pixel(&frame, 20, 21), color(0x30));
+        assert_eq!(pixel(&frame, 204, 21), color(0x16));
+        assert_eq!(pixel(&frame, 44, 21), color(0x0F));
+        // The first sprite wins the pixel, then hides behind the background
+        assert_eq!(pixel(&frame, 84, 21), color(0x30));
+    }
+
+    #[test]
+    fn test_grayscale() {
+        let mut ppu = NesPPU::new(vec![0; 0x2000], crate::cartridge::Mirroring::Horizontal);
+        ppu.frame_palette[0] = 0x16;
+        advance(&mut ppu, 100 * 341);
+        ppu.write_to_mask(0x01);
+        advance(&mut ppu, 141 * 341);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_eq!(pixel(&frame, 0, 99), palette::SYSTEM_PALLETE[0x16]);
+        assert_eq!(pixel(&frame, 0, 100), palette::SYSTEM_PALLETE[0x10]);
+    }
+
+    #[test]
+    fn test_priority_tint() {
+        // Tile 1 is solid, tile 0 (the whole background) transparent
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].fill(0xFF);
+        let mut ppu = NesPPU::new(chr, crate::cartridge::Mirroring::Horizontal);
+        ppu.oam_data[..8].copy_from_slice(&[10, 1, 0x20, 10, 10, 1, 0x00, 40]);
+        ppu.write_to_mask(0x18);
+        advance(&mut ppu, 241 * 341);
+
+        let mut normal = Frame::new();
+        render(&ppu, &mut normal);
+        let mut tinted = Frame::new();
+        render_with(
+            &ppu,
+            &mut tinted,
+            RenderMode::PriorityTint,
+            &Palette::default(),
+        );
+
+        let blend = |(r, g, b): (u8, u8, u8), (tr, tg, tb): (u8, u8, u8)| {
+            let mix = |c: u8, t: u8| ((c as u16 + t as u16) / 2) as u8;
+            (mix(r, tr), mix(g, tg), mix(b, tb))
         };
-        let pallette_idx = ppu.oam_data[i + 2] & 0b11;
-        let sprite_palette = sprite_palette(ppu, pallette_idx);
-        let bank: u16 = ppu.ctrl.sprt_pattern_addr();
-
-        let tile =
-            &ppu.chr_rom[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
-
-        for y in 0..=7 {
-            let mut upper = tile[y];
-            let mut lower = tile[y + 8];
-            'ololo: for x in (0..=7).rev() {
-                let value = (1 & lower) << 1 | (1 & upper);
-                upper = upper >> 1;
-                lower = lower >> 1;
-                let rgb = match value {
-                    0 => continue 'ololo, // skip coloring the pixel
-                    1 => palette::SYSTEM_PALLETE[sprite_palette[1] as usize],
-                    2 => palette::SYSTEM_PALLETE[sprite_palette[2] as usize],
-                    3 => palette::SYSTEM_PALLETE[sprite_palette[3] as usize],
-                    _ => panic!("can't be"),
-                };
-                match (flip_horizontal, flip_vertical) {
-                    (false, false) => {
-                        frame.set_pixel(tile_x + x, tile_y + y, rgb);
-                        // frame.set_pixel(tile_x + x, tile_y + y +250, rgb);
-                    }
-                    (true, false) => {
-                        frame.set_pixel(tile_x + 7 - x, tile_y + y, rgb);
-                        // frame.set_pixel(tile_x + 7 - x , tile_y + y + 250, rgb);
-                    }
-                    (false, true) => {
-                        frame.set_pixel(tile_x + x, tile_y + 7 - y, rgb);
-                        // frame.set_pixel(tile_x + x, tile_y + 7 - y + 250, rgb);
-                    }
-                    (true, true) => {
-                        frame.set_pixel(tile_x + 7 - x, tile_y + 7 - y, rgb);
-                        // frame.set_pixel(tile_x + 7 - x, tile_y + 7 - y+250, rgb);
-                    }
-                }
-            }
+        assert_eq!(
+            pixel(&tinted, 12, 12),
+            blend(pixel(&normal, 12, 12), (0xFF, 0x30, 0x30))
+        );
+        assert_eq!(
+            pixel(&tinted, 42, 12),
+            blend(pixel(&normal, 42, 12), (0x20, 0xE0, 0x40))
+        );
+        assert_eq!(
+            pixel(&tinted, 100, 100),
+            blend(pixel(&normal, 100, 100), (0x60, 0x60, 0x60))
+        );
+    }
+
+    // Raster bars: with rendering off, the main loop keeps rewriting the backdrop
+    // color, so every scanline shows several bands.
+    #[test]
+    fn test_raster_bars_rom() {
+        let mut program = vec![
+            0x78, // SEI
+            0xA9, 0x80, 0x8D, 0x00, 0x20, // LDA #$80, STA $2000 (NMI on)
+            0xA9, 0x00, 0x8D, 0x01, 0x20, // LDA #$00, STA $2001
+            0xA2, 0x00, // LDX #$00
+            0xA9, 0x3F, 0x8D, 0x06, 0x20, // loop: LDA #$3F, STA $2006
+            0xA9, 0x00, 0x8D, 0x06, 0x20, // LDA #$00, STA $2006
+            0xE8, 0x8A, 0x29, 0x3F, // INX, TXA, AND #$3F
+            0x8D, 0x07, 0x20, // STA $2007
+            0x4C, 0x0D, 0x80, // JMP loop
+            0x40, // nmi: RTI
+        ];
+        program.resize(0x7FFA, 0);
+        program.extend([0x21, 0x80, 0x00, 0x80, 0x00, 0x80]);
+
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let frames_clone = frames.clone();
+        let bus = Bus::new(
+            test_rom_containing(program),
+            44100.0,
+            move |ppu: &NesPPU, _ports: &mut ControllerPorts| {
+                let mut frame = Frame::new();
+                render(ppu, &mut frame);
+                frames_clone.borrow_mut().push(frame);
+            },
+        );
+        let mut cpu = CPU::new(bus);
+        cpu.power_on();
+        while frames.borrow().len() < 2 {
+            cpu.step();
+            cpu.collect_audio_sample();
         }
+
+        let frames = frames.borrow();
+        let frame = &frames[1];
+        let mut colors: Vec<(u8, u8, u8)> = (0..240)
+            .flat_map(|y| (0..256).map(move |x| (x, y)))
+            .map(|(x, y)| pixel(frame, x, y))
+            .collect();
+        colors.sort();
+        colors.dedup();
+        assert!(colors.len() > 16, "only {} colors", colors.len());
+
+        // Bands also change within a line, not only between lines
+        assert!((0..240).any(|y| pixel(frame, 0, y) != pixel(frame, 128, y)));
     }
 }
@@ -0,0 +1,49 @@
+//
+// On-screen display: a short message drawn over the picture for a couple of
+// seconds after a hotkey or device change.
+//
+
+use super::font;
+use super::frame::Frame;
+
+/// How long a message stays up, in frames.
+const MESSAGE_FRAMES: u32 = 120;
+
+/// Inside the 8 pixels most TVs crop, bottom-left.
+const X: usize = 12;
+const Y: usize = 240 - 12 - font::CELL_HEIGHT;
+
+#[derive(Default)]
+pub struct Osd {
+    /// Text and frames left, `None` for a message that stays until replaced
+    message: Option<(String, Option<u32>)>,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows `text`, replacing the current message.
+    pub fn show(&mut self, text: String) {
+        self.message = Some((text, Some(MESSAGE_FRAMES)));
+    }
+
+    /// Shows `text` until the next message, e.g. a prompt waiting for input.
+    pub fn pin(&mut self, text: String) {
+        self.message = Some((text, None));
+    }
+
+    /// Draws the current message on the frame and counts down its display time.
+    pub fn draw(&mut self, frame: &mut Frame) {
+        if let Some((text, frames_left)) = &mut self.message {
+            font::draw_text(frame, X, Y, text, (255, 255, 255));
+            if let Some(frames) = frames_left {
+                *frames -= 1;
+                if *frames == 0 {
+                    self.message = None;
+                }
+            }
+        }
+    }
+}
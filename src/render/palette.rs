@@ -1,3 +1,17 @@
+//
+// System palettes: the RGB color of each of the 64 colors the PPU can output.
+// The PPU produces a composite video signal, not RGB, so there is no single right
+// answer; emulators ship several and users pick the one they like. F9 cycles
+// through them while playing.
+//
+// A custom palette can be loaded from a `.pal` file (64 RGB triplets, the format
+// other emulators and palette generators write). Longer files carry the colors
+// again for each of the 7 combinations of PPUMASK's emphasis bits; with only the
+// base colors, emphasis dims the channels it does not emphasize, as a TV would.
+//
+
+use std::path::Path;
+
 pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
     (0x80, 0x80, 0x80),
     (0x00, 0x3D, 0xA6),
@@ -64,3 +78,179 @@ pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
     (0x11, 0x11, 0x11),
     (0x11, 0x11, 0x11),
 ];
+
+/// Bytes of the 64 base colors in a `.pal` file.
+const PAL_SIZE: usize = 64 * 3;
+/// What emphasis leaves of the other channels, when the palette has no emphasis
+/// colors of its own.
+const EMPHASIS_DIM: f32 = 0.816;
+
+/// Composite signal levels of the 2C02, in volts: the low and high levels of the
+/// four luma rows, then black and white.
+const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+/// Phase of color 1 on the color burst, in twelfths of a turn.
+const HUE_OFFSET: f32 = 4.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    /// Shown on the OSD when the palette is picked
+    pub name: String,
+    pub colors: [(u8, u8, u8); 64],
+    /// The colors under each emphasis combination, PPUMASK bits 5-7 minus one
+    pub emphasis: [[(u8, u8, u8); 64]; 7],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new("Default", SYSTEM_PALLETE)
+    }
+}
+
+impl Palette {
+    /// A palette of 64 base colors, with emphasis worked out from them.
+    pub fn new(name: &str, colors: [(u8, u8, u8); 64]) -> Self {
+        let emphasis = std::array::from_fn(|bits| {
+            let bits = bits + 1;
+            colors.map(|(r, g, b)| {
+                let channel = |value: u8, bit: usize| match bits & bit {
+                    0 => (value as f32 * EMPHASIS_DIM).round() as u8,
+                    _ => value,
+                };
+                (channel(r, 1), channel(g, 2), channel(b, 4))
+            })
+        });
+        Palette {
+            name: name.to_string(),
+            colors,
+            emphasis,
+        }
+    }
+
+    /// The palettes that come with the emulator, the default first.
+    pub fn builtin() -> Vec<Palette> {
+        vec![
+            Palette::default(),
+            Palette::new(
+                "Composite",
+                std::array::from_fn(|index| decode_composite(index as u8)),
+            ),
+        ]
+    }
+
+    /// RGB of system color `index` under the emphasis bits of PPUMASK (0-7:
+    /// red, green, blue).
+    pub fn color(&self, index: u8, emphasis: u8) -> (u8, u8, u8) {
+        match emphasis & 7 {
+            0 => self.colors[index as usize & 0x3f],
+            bits => self.emphasis[bits as usize - 1][index as usize & 0x3f],
+        }
+    }
+
+    /// Palette from the contents of a `.pal` file.
+    pub fn from_pal(name: &str, data: &[u8]) -> Result<Palette, String> {
+        if data.len() != PAL_SIZE && data.len() != 8 * PAL_SIZE {
+            return Err(format!(
+                "a .pal file holds 64 (or 512) RGB colors, this one is {} bytes",
+                data.len()
+            ));
+        }
+        let table = |start: usize| -> [(u8, u8, u8); 64] {
+            std::array::from_fn(|i| {
+                let i = start + i * 3;
+                (data[i], data[i + 1], data[i + 2])
+            })
+        };
+        let mut palette = Palette::new(name, table(0));
+        if data.len() > PAL_SIZE {
+            palette.emphasis = std::array::from_fn(|bits| table((bits + 1) * PAL_SIZE));
+        }
+        Ok(palette)
+    }
+
+    /// Palette from a `.pal` file, named after it.
+    pub fn load(path: &Path) -> Result<Palette, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        Palette::from_pal(&name, &data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Color `index` as a TV decodes the PPU's signal: the square wave of the hue
+/// over the twelve phases of a color cycle, averaged into luma and chroma (YIQ).
+fn decode_composite(index: u8) -> (u8, u8, u8) {
+    let hue = index & 0x0F;
+    let row = (index >> 4) as usize;
+    if hue >= 0x0E {
+        return (0, 0, 0);
+    }
+    let (low, high) = match hue {
+        0x00 => (SIGNAL_HIGH[row], SIGNAL_HIGH[row]),
+        0x0D => (SIGNAL_LOW[row], SIGNAL_LOW[row]),
+        _ => (SIGNAL_LOW[row], SIGNAL_HIGH[row]),
+    };
+
+    let (mut y, mut i, mut q) = (0.0f32, 0.0f32, 0.0f32);
+    for phase in 0..12 {
+        let level = if (hue as usize + phase) % 12 < 6 {
+            high
+        } else {
+            low
+        };
+        let signal = (level - BLACK) / (WHITE - BLACK);
+        let angle = std::f32::consts::PI * (phase as f32 + HUE_OFFSET) / 6.0;
+        y += signal / 12.0;
+        i += signal * angle.cos() / 12.0;
+        q += signal * angle.sin() / 12.0;
+    }
+
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    (
+        channel(y + 0.956 * i + 0.621 * q),
+        channel(y - 0.272 * i - 0.647 * q),
+        channel(y - 1.106 * i + 1.703 * q),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_palettes() {
+        let composite = &Palette::builtin()[1];
+        let hue = |color: (u8, u8, u8)| {
+            let (r, g, b) = color;
+            if b > r && b > g {
+                'b'
+            } else if r > g {
+                'r'
+            } else {
+                'g'
+            }
+        };
+        // Blue, red and green columns, white at the top and black past $0D
+        assert_eq!(hue(composite.colors[0x12]), 'b');
+        assert_eq!(hue(composite.colors[0x16]), 'r');
+        assert_eq!(hue(composite.colors[0x1A]), 'g');
+        assert_eq!(composite.colors[0x30], (255, 255, 255));
+        assert_eq!(composite.colors[0x0F], (0, 0, 0));
+
+        let mut data = vec![0u8; 64 * 3 * 8];
+        data[3..6].copy_from_slice(&[1, 2, 3]);
+        // Color $01 with blue emphasis (bits 4, the fifth table)
+        data[4 * 192 + 3..4 * 192 + 6].copy_from_slice(&[7, 8, 9]);
+        let custom = Palette::from_pal("mine", &data).unwrap();
+        assert_eq!(custom.colors[1], (1, 2, 3));
+        assert_eq!(custom.color(0x01, 4), (7, 8, 9));
+        assert_eq!(custom.color(0x01, 0), (1, 2, 3));
+        assert!(Palette::from_pal("short", &data[..100]).is_err());
+
+        // Without emphasis colors, red emphasis dims green and blue
+        let base = Palette::from_pal("base", &[250; 192]).unwrap();
+        assert_eq!(base.color(0x20, 1), (250, 204, 204));
+        assert_eq!(base.color(0x20, 7), (250, 250, 250));
+    }
+}
@@ -0,0 +1,267 @@
+use crate::render::frame::Frame;
+use crate::render::upscale::Upscaler;
+
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+
+/// Pixels hidden on each edge of the picture, like the bezel of a real TV.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    pub const NONE: Overscan = Overscan {
+        top: 0,
+        bottom: 0,
+        left: 0,
+        right: 0,
+    };
+
+    /// What most TVs hid: 8 lines at the top and bottom.
+    pub const TV: Overscan = Overscan {
+        top: 8,
+        bottom: 8,
+        left: 0,
+        right: 0,
+    };
+
+    /// `[video] overscan`: `off`, `on` (the TV crop), `all` (8 pixels off every
+    /// edge) or four numbers, `top,bottom,left,right`.
+    pub fn parse(text: &str) -> Result<Overscan, String> {
+        let overscan = match text.trim() {
+            "off" | "false" => Overscan::NONE,
+            "on" | "true" => Overscan::TV,
+            "all" => Overscan {
+                top: 8,
+                bottom: 8,
+                left: 8,
+                right: 8,
+            },
+            edges => {
+                let numbers: Vec<usize> = edges
+                    .split(',')
+                    .map(|n| n.trim().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("overscan '{}' is not on, off, all or 4 numbers", text))?;
+                let [top, bottom, left, right] = numbers[..] else {
+                    return Err(format!("overscan '{}' needs top,bottom,left,right", text));
+                };
+                Overscan {
+                    top,
+                    bottom,
+                    left,
+                    right,
+                }
+            }
+        };
+        if overscan.top + overscan.bottom >= HEIGHT || overscan.left + overscan.right >= WIDTH {
+            return Err(format!("overscan '{}' crops the whole picture", text));
+        }
+        Ok(overscan)
+    }
+}
+
+/// A named bundle of display settings the user can cycle through at runtime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewProfile {
+    pub name: &'static str,
+    pub overscan: Overscan,
+    /// Width/height of one NES pixel on screen (8:7 on an NTSC television)
+    pub pixel_aspect: f64,
+    /// Horizontal color bleed approximating the composite video signal
+    pub ntsc_filter: bool,
+    /// Dark gaps between lines, doubling the output height
+    pub scanlines: bool,
+    /// Smoothing filter, run on the cropped picture
+    pub upscaler: Upscaler,
+}
+
+pub const PROFILES: [ViewProfile; 3] = [
+    ViewProfile {
+        name: "Sharp Pixels",
+        overscan: Overscan::NONE,
+        pixel_aspect: 1.0,
+        ntsc_filter: false,
+        scanlines: false,
+        upscaler: Upscaler::None,
+    },
+    ViewProfile {
+        name: "CRT TV",
+        overscan: Overscan::TV,
+        pixel_aspect: 8.0 / 7.0,
+        ntsc_filter: true,
+        scanlines: true,
+        upscaler: Upscaler::None,
+    },
+    ViewProfile {
+        name: "Handheld",
+        overscan: Overscan {
+            top: 8,
+            bottom: 8,
+            left: 8,
+            right: 8,
+        },
+        pixel_aspect: 1.0,
+        ntsc_filter: false,
+        scanlines: false,
+        upscaler: Upscaler::None,
+    },
+];
+
+impl ViewProfile {
+    /// The profile with the crop of the `[video] overscan` setting, if there is one.
+    pub fn with_overscan(self, overscan: Option<Overscan>) -> Self {
+        ViewProfile {
+            overscan: overscan.unwrap_or(self.overscan),
+            ..self
+        }
+    }
+
+    /// The profile smoothed by the `[video] upscaler` setting. Profiles with CRT
+    /// effects keep their look: color bleed and scanlines are meant for the raw
+    /// pixels.
+    pub fn with_upscaler(self, upscaler: Upscaler) -> Self {
+        ViewProfile {
+            upscaler: match self.ntsc_filter || self.scanlines {
+                true => Upscaler::None,
+                false => upscaler,
+            },
+            ..self
+        }
+    }
+
+    /// Size of the picture after cropping, in NES pixels.
+    pub fn visible_size(&self) -> (usize, usize) {
+        (
+            WIDTH - self.overscan.left - self.overscan.right,
+            HEIGHT - self.overscan.top - self.overscan.bottom,
+        )
+    }
+
+    /// Size of the buffer produced by `apply`.
+    pub fn output_size(&self) -> (usize, usize) {
+        let (width, height) = self.visible_size();
+        let factor = self.upscaler.factor();
+        if self.scanlines {
+            (width * factor, height * 2 * factor)
+        } else {
+            (width * factor, height * factor)
+        }
+    }
+
+    /// Size the output should be stretched to on screen, honoring the pixel aspect.
+    pub fn display_size(&self) -> (u32, u32) {
+        let (width, height) = self.visible_size();
+        (
+            (width as f64 * self.pixel_aspect).round() as u32,
+            height as u32,
+        )
+    }
+
+    /// Turns a frame into an RGB24 buffer of `output_size()`.
+    pub fn apply(&self, frame: &Frame, out: &mut Vec<u8>) {
+        let (width, height) = self.visible_size();
+        let rows = if self.scanlines { height * 2 } else { height };
+        out.clear();
+        out.reserve(width * rows * 3);
+
+        let mut line = Vec::with_capacity(width * 3);
+        for y in 0..height {
+            let src_y = y + self.overscan.top;
+            line.clear();
+            for x in 0..width {
+                let src = (src_y * WIDTH + x + self.overscan.left) * 3;
+                let pixel = &frame.data[src..src + 3];
+                if self.ntsc_filter && x > 0 {
+                    let previous = &frame.data[src - 3..src];
+                    for channel in 0..3 {
+                        let blended = (pixel[channel] as u16 * 3 + previous[channel] as u16) / 4;
+                        line.push(blended as u8);
+                    }
+                } else {
+                    line.extend_from_slice(pixel);
+                }
+            }
+
+            out.extend_from_slice(&line);
+            if self.scanlines {
+                out.extend(line.iter().map(|&c| (c as u16 * 3 / 5) as u8));
+            }
+        }
+
+        if self.upscaler != Upscaler::None {
+            let cropped = std::mem::take(out);
+            self.upscaler.apply(&cropped, width, rows, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_profile_sizes() {
+        let [sharp, crt, handheld] = PROFILES;
+        assert_eq!(sharp.output_size(), (256, 240));
+        assert_eq!(crt.output_size(), (256, 448));
+        assert_eq!(crt.display_size(), (293, 224));
+        assert_eq!(handheld.output_size(), (240, 224));
+    }
+
+    #[test]
+    fn test_apply_crops_overscan() {
+        let mut frame = Frame::new();
+        frame.set_pixel(8, 8, (10, 20, 30));
+
+        let mut out = vec![];
+        PROFILES[2].apply(&frame, &mut out);
+        assert_eq!(out.len(), 240 * 224 * 3);
+        assert_eq!(&out[0..3], &[10, 20, 30]);
+    }
+
+    #[test]
+    fn test_overscan_setting() {
+        assert_eq!(Overscan::parse("on"), Ok(Overscan::TV));
+        assert_eq!(Overscan::parse("off"), Ok(Overscan::NONE));
+        let sides = Overscan::parse("8, 8, 4, 4").unwrap();
+        assert_eq!((sides.left, sides.right), (4, 4));
+        assert!(Overscan::parse("8,8").is_err());
+        assert!(Overscan::parse("120,120,0,0").is_err());
+
+        let cropped = PROFILES[0].with_overscan(Some(sides));
+        assert_eq!(cropped.output_size(), (248, 224));
+        assert_eq!(PROFILES[1].with_overscan(None), PROFILES[1]);
+    }
+
+    #[test]
+    fn test_upscaler_setting() {
+        let smooth = PROFILES[2].with_upscaler(Upscaler::Xbrz);
+        assert_eq!(smooth.output_size(), (480, 448));
+        // Same size on screen, twice the pixels
+        assert_eq!(smooth.display_size(), PROFILES[2].display_size());
+        let mut out = vec![];
+        smooth.apply(&Frame::new(), &mut out);
+        assert_eq!(out.len(), 480 * 448 * 3);
+        assert_eq!(PROFILES[1].with_upscaler(Upscaler::Xbrz), PROFILES[1]);
+    }
+
+    #[test]
+    fn test_apply_crt_filters() {
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 8, (200, 200, 200));
+
+        let mut out = vec![];
+        PROFILES[1].apply(&frame, &mut out);
+        let row = 256 * 3;
+        // Color bleeds into the next pixel
+        assert_eq!(&out[3..6], &[150, 150, 150]);
+        assert_eq!(&out[6..9], &[50, 50, 50]);
+        // Every other output line is a darker copy
+        assert_eq!(&out[row + 3..row + 6], &[90, 90, 90]);
+    }
+}
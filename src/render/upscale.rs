@@ -0,0 +1,322 @@
+//
+// Smart upscaling: doubles the picture while rounding off the staircases of
+// diagonal edges, for those who prefer a smoothed look over sharp pixels.
+//
+// The filter is xBRZ at scale 2 (Zenju's rule set for the xBR family). For every
+// point where four pixels meet, it weighs the color gradients along both
+// diagonals and decides which of the four pixels an edge runs through. Each
+// pixel then looks at its four corners: a corner crossed by an edge gets the
+// neighbor's color blended in, more or less of it depending on whether the edge
+// is shallow, steep, diagonal or only clips the corner. Colors are compared by
+// their distance in YCbCr, so the filter follows what the eye sees as an edge.
+//
+// Flat areas and the pixel art's dithering (checkerboards) are left untouched.
+//
+
+/// `[video] upscaler`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Upscaler {
+    #[default]
+    None,
+    Xbrz,
+}
+
+impl Upscaler {
+    pub fn parse(text: &str) -> Result<Upscaler, String> {
+        match text.trim() {
+            "none" | "off" => Ok(Upscaler::None),
+            "xbrz" => Ok(Upscaler::Xbrz),
+            other => Err(format!(
+                "unknown upscaler '{}', expected none or xbrz",
+                other
+            )),
+        }
+    }
+
+    /// How many output pixels one input pixel becomes, each way.
+    pub fn factor(self) -> usize {
+        match self {
+            Upscaler::None => 1,
+            Upscaler::Xbrz => 2,
+        }
+    }
+
+    /// Scales the RGB24 picture `input` of `width` by `height` into `out`.
+    pub fn apply(self, input: &[u8], width: usize, height: usize, out: &mut Vec<u8>) {
+        match self {
+            Upscaler::None => {
+                out.clear();
+                out.extend_from_slice(input);
+            }
+            Upscaler::Xbrz => xbrz2x(input, width, height, out),
+        }
+    }
+}
+
+type Rgb = [u8; 3];
+
+/// How corners are blended, two bits per corner
+const BLEND_NONE: u8 = 0;
+const BLEND_NORMAL: u8 = 1;
+const BLEND_DOMINANT: u8 = 2;
+
+// Corners in the per-pixel blend byte, clockwise so a rotation is a shift
+const TOP_LEFT: u8 = 0;
+const TOP_RIGHT: u8 = 2;
+const BOTTOM_RIGHT: u8 = 4;
+const BOTTOM_LEFT: u8 = 6;
+
+// xBRZ's tuning
+const LUMINANCE_WEIGHT: f64 = 1.0;
+const EQUAL_COLOR_TOLERANCE: f64 = 30.0;
+const CENTER_DIRECTION_BIAS: f64 = 4.0;
+const DOMINANT_DIRECTION_THRESHOLD: f64 = 3.6;
+const STEEP_DIRECTION_THRESHOLD: f64 = 2.2;
+
+/// Perceived difference between two colors
+fn distance(a: Rgb, b: Rgb) -> f64 {
+    const K_B: f64 = 0.0722;
+    const K_R: f64 = 0.2126;
+    const K_G: f64 = 1.0 - K_B - K_R;
+    let r = a[0] as f64 - b[0] as f64;
+    let g = a[1] as f64 - b[1] as f64;
+    let b = a[2] as f64 - b[2] as f64;
+    let y = K_R * r + K_G * g + K_B * b;
+    let c_b = 0.5 / (1.0 - K_B) * (b - y);
+    let c_r = 0.5 / (1.0 - K_R) * (r - y);
+    ((LUMINANCE_WEIGHT * y).powi(2) + c_b * c_b + c_r * c_r).sqrt()
+}
+
+fn similar(a: Rgb, b: Rgb) -> bool {
+    distance(a, b) < EQUAL_COLOR_TOLERANCE
+}
+
+/// `front` laid over `back` with an opacity of `numerator / denominator`
+fn blend(back: Rgb, front: Rgb, numerator: u32, denominator: u32) -> Rgb {
+    std::array::from_fn(|c| {
+        let mixed = front[c] as u32 * numerator + back[c] as u32 * (denominator - numerator);
+        (mixed / denominator) as u8
+    })
+}
+
+/// Blend types of the corners meeting between pixels F, G, J and K:
+///
+/// ```text
+///   A B C D
+///   E F G H      F's bottom right, G's bottom left,
+///   I J K L      J's top right and K's top left
+///   M N O P
+/// ```
+fn corner_blends(k: [[Rgb; 4]; 4]) -> [u8; 4] {
+    let [[_, b, c, _], [e, f, g, h], [i, j, k, l], [_, n, o, _]] = k;
+    let mut blends = [BLEND_NONE; 4];
+    if (f == g && j == k) || (f == j && g == k) {
+        return blends;
+    }
+    let jg = distance(i, f)
+        + distance(f, c)
+        + distance(n, k)
+        + distance(k, h)
+        + CENTER_DIRECTION_BIAS * distance(j, g);
+    let fk = distance(e, j)
+        + distance(j, o)
+        + distance(b, g)
+        + distance(g, l)
+        + CENTER_DIRECTION_BIAS * distance(f, k);
+
+    let strength = |weak: f64, strong: f64| match DOMINANT_DIRECTION_THRESHOLD * weak < strong {
+        true => BLEND_DOMINANT,
+        false => BLEND_NORMAL,
+    };
+    if jg < fk {
+        // The edge runs along J-G: F and K are cut
+        let blend = strength(jg, fk);
+        if f != g && f != j {
+            blends[0] = blend;
+        }
+        if k != j && k != g {
+            blends[3] = blend;
+        }
+    } else if fk < jg {
+        let blend = strength(fk, jg);
+        if j != f && j != k {
+            blends[2] = blend;
+        }
+        if g != f && g != k {
+            blends[1] = blend;
+        }
+    }
+    blends
+}
+
+/// Position in an `n` by `n` square, turned a quarter clockwise `times` times
+/// back to where it was before the turn
+fn unrotate((mut row, mut column): (usize, usize), n: usize, times: usize) -> (usize, usize) {
+    for _ in 0..times {
+        (row, column) = (n - 1 - column, row);
+    }
+    (row, column)
+}
+
+fn xbrz2x(input: &[u8], width: usize, height: usize, out: &mut Vec<u8>) {
+    let pixel = |x: isize, y: isize| -> Rgb {
+        let x = x.clamp(0, width as isize - 1) as usize;
+        let y = y.clamp(0, height as isize - 1) as usize;
+        let i = (y * width + x) * 3;
+        [input[i], input[i + 1], input[i + 2]]
+    };
+
+    // Blend types of every pixel's four corners, from the 2x2 blocks around them
+    let mut corners = vec![0u8; width * height];
+    for y in -1..height as isize {
+        for x in -1..width as isize {
+            let kernel = std::array::from_fn(|row| {
+                std::array::from_fn(|column| pixel(x - 1 + column as isize, y - 1 + row as isize))
+            });
+            let blends = corner_blends(kernel);
+            let mut set = |dx: isize, dy: isize, corner: u8, blend: u8| {
+                let (px, py) = (x + dx, y + dy);
+                if blend != BLEND_NONE
+                    && (0..width as isize).contains(&px)
+                    && (0..height as isize).contains(&py)
+                {
+                    corners[py as usize * width + px as usize] |= blend << corner;
+                }
+            };
+            set(0, 0, BOTTOM_RIGHT, blends[0]);
+            set(1, 0, BOTTOM_LEFT, blends[1]);
+            set(0, 1, TOP_RIGHT, blends[2]);
+            set(1, 1, TOP_LEFT, blends[3]);
+        }
+    }
+
+    out.clear();
+    out.resize(width * height * 12, 0);
+    for y in 0..height {
+        for x in 0..width {
+            let original: [[Rgb; 3]; 3] = std::array::from_fn(|row| {
+                std::array::from_fn(|column| {
+                    pixel(
+                        x as isize - 1 + column as isize,
+                        y as isize - 1 + row as isize,
+                    )
+                })
+            });
+            let center = original[1][1];
+            let mut block = [[center; 2]; 2];
+
+            // Each corner in turn, with the picture turned so it is the bottom right one
+            for rotation in 0..4 {
+                let blend_info = corners[y * width + x].rotate_left(2 * rotation as u32);
+                let corner = |at: u8| (blend_info >> at) & 0b11;
+                if corner(BOTTOM_RIGHT) == BLEND_NONE {
+                    continue;
+                }
+                let at = |row, column| {
+                    let (row, column) = unrotate((row, column), 3, rotation);
+                    original[row][column]
+                };
+                let (b, c, d) = (at(0, 1), at(0, 2), at(1, 0));
+                let (e, f, g, h, i) = (center, at(1, 2), at(2, 0), at(2, 1), at(2, 2));
+
+                let line_blend = if corner(BOTTOM_RIGHT) >= BLEND_DOMINANT {
+                    true
+                } else if corner(TOP_RIGHT) != BLEND_NONE && !similar(e, g) {
+                    // A corner blended next to it would leave a gap along the line
+                    false
+                } else if corner(BOTTOM_LEFT) != BLEND_NONE && !similar(e, c) {
+                    false
+                } else {
+                    // Not where three pixels of one color frame a corner of another
+                    !(!similar(e, i)
+                        && similar(g, h)
+                        && similar(h, i)
+                        && similar(i, f)
+                        && similar(f, c))
+                };
+
+                let color = if distance(e, f) <= distance(e, h) {
+                    f
+                } else {
+                    h
+                };
+                let mut mix = |row, column, numerator, denominator| {
+                    let (row, column) = unrotate((row, column), 2, rotation);
+                    block[row][column] = blend(block[row][column], color, numerator, denominator);
+                };
+                if !line_blend {
+                    // Only the corner is rounded: 1 - pi/4 of it
+                    mix(1, 1, 21, 100);
+                    continue;
+                }
+                let fg = distance(f, g);
+                let hc = distance(h, c);
+                let shallow = STEEP_DIRECTION_THRESHOLD * fg <= hc && e != g && d != g;
+                let steep = STEEP_DIRECTION_THRESHOLD * hc <= fg && e != c && b != c;
+                match (shallow, steep) {
+                    (true, true) => {
+                        mix(1, 0, 1, 4);
+                        mix(0, 1, 1, 4);
+                        mix(1, 1, 5, 6);
+                    }
+                    (true, false) => {
+                        mix(1, 0, 1, 4);
+                        mix(1, 1, 3, 4);
+                    }
+                    (false, true) => {
+                        mix(0, 1, 1, 4);
+                        mix(1, 1, 3, 4);
+                    }
+                    (false, false) => mix(1, 1, 1, 2),
+                }
+            }
+
+            for (row, pixels) in block.iter().enumerate() {
+                let start = ((y * 2 + row) * width * 2 + x * 2) * 3;
+                out[start..start + 3].copy_from_slice(&pixels[0]);
+                out[start + 3..start + 6].copy_from_slice(&pixels[1]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_xbrz() {
+        // A white triangle under the diagonal of an 8x8 black picture
+        let (width, height) = (8, 8);
+        let mut input = vec![0; width * height * 3];
+        for y in 0..height {
+            for x in 0..=y {
+                input[(y * width + x) * 3..][..3].fill(255);
+            }
+        }
+        let mut out = vec![];
+        Upscaler::Xbrz.apply(&input, width, height, &mut out);
+        assert_eq!(out.len(), 16 * 16 * 3);
+        let at = |x: usize, y: usize| out[(y * 16 + x) * 3];
+
+        // Inside and outside stay as they were
+        assert_eq!(at(0, 15), 255);
+        assert_eq!(at(15, 0), 0);
+        // The staircase is smoothed: the step's outer corner takes some white,
+        // the inner corner some black
+        assert!((1..255).contains(&at(3, 2)));
+        assert!((1..255).contains(&at(4, 3)));
+        assert_eq!(at(2, 2), 255);
+        assert_eq!(at(3, 3), 255);
+
+        // Flat colors and checkerboards are left alone
+        let checker: Vec<u8> = (0..width * height)
+            .flat_map(|i| [((i % width + i / width) % 2 * 255) as u8; 3])
+            .collect();
+        Upscaler::Xbrz.apply(&checker, width, height, &mut out);
+        assert!(out.iter().all(|&c| c == 0 || c == 255));
+
+        assert_eq!(Upscaler::parse("xbrz"), Ok(Upscaler::Xbrz));
+        assert!(Upscaler::parse("hq9x").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//
+// Embedded ROM database. Many dumps in circulation carry iNES headers with the wrong
+// mapper or mirroring; identifying the game by a hash of its ROM data lets us fix the
+// header and report a title.
+//
+
+use crate::cartridge::Mirroring;
+
+/// What the database knows about one dump.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameEntry {
+    pub crc32: u32,
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    /// Recommended level of the cartridge's expansion audio against the APU, for
+    /// boards whose games were mixed louder or softer than the default 1.0
+    pub expansion_gain: Option<f32>,
+    pub title: String,
+}
+
+lazy_static! {
+    static ref DATABASE: Vec<GameEntry> =
+        parse(include_str!("romdb.txt")).expect("embedded ROM database is malformed");
+}
+
+/// Finds a game by the CRC32 of its PRG-ROM followed by its CHR-ROM.
+pub fn lookup(crc32: u32) -> Option<&'static GameEntry> {
+    DATABASE.iter().find(|entry| entry.crc32 == crc32)
+}
+
+/// Parses the database text format, see romdb.txt.
+pub fn parse(text: &str) -> Result<Vec<GameEntry>, String> {
+    let mut entries = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |what: &str| format!("line {}: {}", number + 1, what);
+
+        // Four single-word columns, an optional `gain=`, then the title takes the
+        // rest of the line
+        let mut rest = line;
+        let mut next = |name: &str| {
+            let (field, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            rest = tail.trim_start();
+            if field.is_empty() {
+                Err(error(&format!("missing {}", name)))
+            } else {
+                Ok(field)
+            }
+        };
+        let crc32 = u32::from_str_radix(next("crc32")?, 16).map_err(|_| error("invalid crc32"))?;
+        let mapper = next("mapper")?
+            .parse()
+            .map_err(|_| error("invalid mapper"))?;
+        let mirroring = match next("mirroring")? {
+            "H" => Mirroring::Horizontal,
+            "V" => Mirroring::Vertical,
+            "4" => Mirroring::FourScreen,
+            other => return Err(error(&format!("invalid mirroring '{}'", other))),
+        };
+        let battery = match next("battery")? {
+            "0" => false,
+            "1" => true,
+            other => return Err(error(&format!("invalid battery flag '{}'", other))),
+        };
+        let mut expansion_gain = None;
+        if let Some(tail) = rest.strip_prefix("gain=") {
+            let (gain, title) = tail.split_once(char::is_whitespace).unwrap_or((tail, ""));
+            expansion_gain = Some(gain.parse().map_err(|_| error("invalid gain"))?);
+            rest = title.trim_start();
+        }
+        if rest.is_empty() {
+            return Err(error("missing title"));
+        }
+        let title = rest.to_string();
+
+        entries.push(GameEntry {
+            crc32,
+            mapper,
+            mirroring,
+            battery,
+            expansion_gain,
+            title,
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_database() {
+        let entries = parse("# comment\n\n0000ABCD  4  H  1  Some Game (USA)\n").unwrap();
+        assert_eq!(
+            entries,
+            vec![GameEntry {
+                crc32: 0xABCD,
+                mapper: 4,
+                mirroring: Mirroring::Horizontal,
+                battery: true,
+                expansion_gain: None,
+                title: "Some Game (USA)".to_string(),
+            }]
+        );
+        let entries = parse("0000ABCD  5  H  0  gain=1.5  Loud Game (Japan)\n").unwrap();
+        assert_eq!(entries[0].expansion_gain, Some(1.5));
+        assert_eq!(entries[0].title, "Loud Game (Japan)");
+        assert!(parse("0000ABCD 5 H 0 gain=loud Game").is_err());
+
+        assert!(parse("0000ABCD 4 X 1 Bad").unwrap_err().contains("line 1"));
+        assert!(parse("0000ABCD 4").is_err());
+        assert!(lookup(0x3337_EC46).is_some());
+    }
+}
@@ -0,0 +1,214 @@
+//
+// Save states: the machine's state as compact binary, to be written out and read
+// back by the same emulator. Unlike debug states (debugstate.rs) they are not for
+// reading, and only need to round-trip exactly.
+//
+// A state is the magic "NESS", a format version, then one section per component
+// in a fixed order, each a 4-byte tag followed by that component's fields, little
+// endian. Only the CPU is saved so far; the PPU, APU, memory and cartridge get
+// their own sections before the pause menu can offer them.
+//
+
+use crate::bus::Bus;
+use crate::cpu::{CpuState, CPU};
+use std::fmt;
+
+pub const MAGIC: &[u8; 4] = b"NESS";
+/// Bumped whenever a section changes, so older states are refused, not misread.
+pub const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    /// The data does not start with "NESS"
+    BadMagic,
+    /// Written by another version of the format
+    UnsupportedVersion(u8),
+    /// A section is not the one expected at this point
+    UnexpectedSection([u8; 4]),
+    /// The data ends inside a section
+    Truncated,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Save state version {} is not supported", version)
+            }
+            StateError::UnexpectedSection(tag) => write!(
+                f,
+                "Unexpected save state section {}",
+                String::from_utf8_lossy(tag)
+            ),
+            StateError::Truncated => write!(f, "Save state ends early"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Serializes fields in order.
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts the section of one component.
+    pub fn section(&mut self, tag: &[u8; 4]) {
+        self.data.extend_from_slice(tag);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads fields back in the order they were written.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    /// Checks that the next section is `tag`.
+    pub fn section(&mut self, tag: &[u8; 4]) -> Result<(), StateError> {
+        let found: [u8; 4] = self.take(4)?.try_into().unwrap();
+        if &found != tag {
+            return Err(StateError::UnexpectedSection(found));
+        }
+        Ok(())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), StateError> {
+        out.copy_from_slice(self.take(out.len())?);
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+}
+
+/// The state of the console, as far as it can be saved yet.
+pub fn save(cpu: &CPU<Bus>) -> Vec<u8> {
+    let mut out = StateWriter::new();
+    out.bytes(MAGIC);
+    out.u8(VERSION);
+    cpu.state().write(&mut out);
+    out.finish()
+}
+
+/// Restores a state from `save`. Nothing is changed if it cannot be read.
+pub fn load(cpu: &mut CPU<Bus>, data: &[u8]) -> Result<(), StateError> {
+    let mut input = StateReader::new(data);
+    let mut magic = [0; 4];
+    input.bytes(&mut magic).map_err(|_| StateError::BadMagic)?;
+    if &magic != MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    match input.u8()? {
+        VERSION => {}
+        version => return Err(StateError::UnsupportedVersion(version)),
+    }
+    // Read everything first, so a truncated state leaves the console as it was
+    let registers = CpuState::read(&mut input)?;
+    cpu.set_state(registers);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_cpu;
+    use crate::cpu::CpuFlags;
+
+    #[test]
+    fn test_cpu_round_trip() {
+        let mut cpu = test_cpu();
+        cpu.register_a = 0x12;
+        cpu.register_x = 0x34;
+        cpu.register_y = 0x56;
+        cpu.stack_pointer = 0xF0;
+        cpu.program_counter = 0xC123;
+        cpu.status = CpuFlags::from_bits_truncate(0xE5);
+        cpu.irq_pending = true;
+        cpu.cycles = 0x1_0000_0007;
+        let state = save(&cpu);
+
+        let mut other = test_cpu();
+        load(&mut other, &state).unwrap();
+        assert_eq!(
+            (other.register_a, other.register_x, other.register_y),
+            (0x12, 0x34, 0x56)
+        );
+        assert_eq!((other.stack_pointer, other.program_counter), (0xF0, 0xC123));
+        assert_eq!(other.status.bits(), 0xE5);
+        assert!(other.irq_pending && !other.nmi_pending);
+        assert_eq!(other.cycles, 0x1_0000_0007);
+        assert_eq!(save(&other), state);
+
+        let mut fresh = test_cpu();
+        assert_eq!(
+            load(&mut fresh, &state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        );
+        assert_eq!(fresh.program_counter, 0);
+        assert_eq!(load(&mut fresh, b"NES\x1A"), Err(StateError::BadMagic));
+        let mut newer = state.clone();
+        newer[4] = VERSION + 1;
+        assert_eq!(
+            load(&mut fresh, &newer),
+            Err(StateError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//
+// Screenshot bursts: every emulated frame saved as a numbered PNG for a set number
+// of frames, for making GIFs or stepping through rendering bugs one frame at a
+// time. Frames are copied as they are finished and encoded on a worker thread,
+// so a burst costs the emulation thread one 180KB copy per frame.
+//
+
+use crate::png;
+use crate::render::frame::Frame;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+/// Frames saved per burst when the settings file does not say.
+pub const DEFAULT_BURST_FRAMES: u32 = 120;
+
+/// `<game>_<unix time>`, the start of capture file names.
+pub fn file_prefix(rom_path: &Path) -> String {
+    let game = rom_path
+        .file_stem()
+        .map_or("frame".into(), |stem| stem.to_string_lossy());
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    format!("{}_{}", game, time)
+}
+
+struct Job {
+    path: PathBuf,
+    rgb: Vec<u8>,
+}
+
+pub struct BurstRecorder {
+    dir: PathBuf,
+    sender: Sender<Job>,
+    /// File name prefix of the current burst, and frames still to capture
+    burst: Option<(String, u32)>,
+    captured: u32,
+}
+
+impl BurstRecorder {
+    /// Bursts are written to `dir`, created on first use.
+    pub fn new(dir: PathBuf) -> Self {
+        let (sender, receiver) = channel::<Job>();
+        thread::spawn(move || {
+            for job in receiver {
+                let png = png::encode_rgb(256, 240, &job.rgb);
+                if let Err(e) = std::fs::write(&job.path, png) {
+                    eprintln!("Could not write {}: {}", job.path.display(), e);
+                }
+            }
+        });
+        BurstRecorder {
+            dir,
+            sender,
+            burst: None,
+            captured: 0,
+        }
+    }
+
+    /// Starts capturing the next `frames` frames, named after `rom_path`.
+    pub fn start(&mut self, rom_path: &Path, frames: u32) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        self.burst = Some((file_prefix(rom_path), frames));
+        self.captured = 0;
+        Ok(())
+    }
+
+    /// Ends the burst early; returns the number of frames captured.
+    pub fn stop(&mut self) -> u32 {
+        self.burst = None;
+        self.captured
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.burst.is_some()
+    }
+
+    /// Queues the frame if a burst is running. Returns the number of frames
+    /// captured when this frame completes the burst.
+    pub fn capture(&mut self, frame: &Frame) -> Option<u32> {
+        let (prefix, remaining) = self.burst.as_mut()?;
+        let path = self
+            .dir
+            .join(format!("{}_{:05}.png", prefix, self.captured));
+        let _ = self.sender.send(Job {
+            path,
+            rgb: frame.data.clone(),
+        });
+        self.captured += 1;
+        *remaining -= 1;
+        if *remaining == 0 {
+            Some(self.stop())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_burst_writes_numbered_frames() {
+        let dir = std::env::temp_dir().join(format!("nes_burst_test_{}", std::process::id()));
+        let mut recorder = BurstRecorder::new(dir.clone());
+        let frame = Frame::new();
+        assert_eq!(recorder.capture(&frame), None);
+
+        recorder.start(Path::new("roms/game.nes"), 2).unwrap();
+        assert_eq!(recorder.capture(&frame), None);
+        assert_eq!(recorder.capture(&frame), Some(2));
+        assert!(!recorder.is_active());
+        assert_eq!(recorder.capture(&frame), None);
+
+        // Dropping the recorder closes the queue; wait for the worker to drain it
+        drop(recorder);
+        let mut names = vec![];
+        for _ in 0..200 {
+            names = std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            if names.len() == 2
+                && names
+                    .iter()
+                    .all(|n| dir.join(n).metadata().unwrap().len() > 0)
+            {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        names.sort();
+        assert_eq!(names.len(), 2);
+        assert!(names[0].starts_with("game_") && names[0].ends_with("_00000.png"));
+        assert!(names[1].ends_with("_00001.png"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
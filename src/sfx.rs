@@ -0,0 +1,117 @@
+//
+// Feedback sounds for emulator actions (state saved, rewind, achievements). They
+// are synthesized here and mixed after the APU, at a low volume, so they never
+// go through the emulated sound hardware.
+//
+
+use std::f32::consts::TAU;
+
+/// Peak level of a UI sound relative to full scale.
+const VOLUME: f32 = 0.08;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UiSound {
+    StateSaved,
+    RewindStart,
+    RewindEnd,
+    Achievement,
+}
+
+impl UiSound {
+    /// Notes as (frequency in Hz, duration in seconds).
+    fn notes(self) -> &'static [(f32, f32)] {
+        match self {
+            UiSound::StateSaved => &[(880.0, 0.05), (1318.5, 0.08)],
+            UiSound::RewindStart => &[(659.3, 0.05), (440.0, 0.07)],
+            UiSound::RewindEnd => &[(440.0, 0.05), (659.3, 0.07)],
+            UiSound::Achievement => &[(1046.5, 0.06), (1318.5, 0.06), (1568.0, 0.12)],
+        }
+    }
+}
+
+struct Voice {
+    notes: &'static [(f32, f32)],
+    note: usize,
+    /// Samples played of the current note
+    position: u32,
+    phase: f32,
+}
+
+pub struct SfxMixer {
+    pub enabled: bool,
+    sample_rate: f32,
+    voices: Vec<Voice>,
+}
+
+impl SfxMixer {
+    pub fn new(sample_rate: f64, enabled: bool) -> Self {
+        SfxMixer {
+            enabled,
+            sample_rate: sample_rate as f32,
+            voices: vec![],
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate as f32;
+    }
+
+    pub fn play(&mut self, sound: UiSound) {
+        if self.enabled {
+            self.voices.push(Voice {
+                notes: sound.notes(),
+                note: 0,
+                position: 0,
+                phase: 0.0,
+            });
+        }
+    }
+
+    /// Adds the playing UI sounds to one APU output sample.
+    pub fn mix(&mut self, sample: f32) -> f32 {
+        let mut out = sample;
+        for voice in self.voices.iter_mut() {
+            let (freq, duration) = voice.notes[voice.note];
+            let length = (duration * self.sample_rate) as u32;
+            // Short fade in and out so notes don't click
+            let edge = (length / 8).max(1);
+            let remaining = length.saturating_sub(voice.position);
+            let fade = voice.position.min(remaining).min(edge) as f32 / edge as f32;
+
+            out += voice.phase.sin() * VOLUME * fade;
+            voice.phase = (voice.phase + TAU * freq / self.sample_rate) % TAU;
+            voice.position += 1;
+            if voice.position >= length {
+                voice.position = 0;
+                voice.note += 1;
+            }
+        }
+        self.voices.retain(|voice| voice.note < voice.notes.len());
+        out
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.voices.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ui_sound_is_mixed_then_ends() {
+        let mut mixer = SfxMixer::new(44100.0, true);
+        mixer.play(UiSound::StateSaved);
+
+        let mixed: Vec<f32> = (0..44100).map(|_| mixer.mix(0.25)).collect();
+        let peak = mixed.iter().map(|s| (s - 0.25).abs()).fold(0.0, f32::max);
+        assert!(peak > 0.05 && peak <= VOLUME);
+        assert!(!mixer.is_playing());
+        assert_eq!(*mixed.last().unwrap(), 0.25);
+
+        let mut muted = SfxMixer::new(44100.0, false);
+        muted.play(UiSound::Achievement);
+        assert_eq!(muted.mix(0.25), 0.25);
+    }
+}
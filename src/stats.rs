@@ -0,0 +1,193 @@
+//
+// Frame pacing statistics: how regularly finished frames reach the screen, and
+// why they sometimes don't. Each gap between two frames is split into the time
+// spent waiting for the audio queue to drain, waiting on vsync in `present`, and
+// everything else (emulating the frame); a gap over the stutter threshold is
+// blamed on whichever took longest.
+//
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Gaps longer than this are reported as stutters.
+pub const STUTTER_THRESHOLD: Duration = Duration::from_millis(20);
+
+/// Weight of the newest frame in the smoothed frame time.
+const SMOOTHING: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StutterCause {
+    AudioWait,
+    Vsync,
+    SlowEmulation,
+}
+
+impl fmt::Display for StutterCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            StutterCause::AudioWait => "audio wait",
+            StutterCause::Vsync => "vsync",
+            StutterCause::SlowEmulation => "slow emulation",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stutter {
+    pub gap: Duration,
+    pub cause: StutterCause,
+}
+
+impl fmt::Display for Stutter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "stutter: {:.1}ms between frames ({})",
+            self.gap.as_secs_f64() * 1000.0,
+            self.cause
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct FrameTimer {
+    last_frame: Option<Instant>,
+    audio_wait: Duration,
+
+    frames: u64,
+    smoothed_ms: f64,
+    worst: Duration,
+    /// Stutter counts indexed like `StutterCause`
+    stutters: [u64; 3],
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time the emulation thread spent blocked on a full audio queue.
+    pub fn add_audio_wait(&mut self, wait: Duration) {
+        self.audio_wait += wait;
+    }
+
+    /// Records a frame shown at `now`, `present` being the time spent in the
+    /// presenting call. Returns the stutter if the gap since the previous frame was one.
+    pub fn end_frame(&mut self, now: Instant, present: Duration) -> Option<Stutter> {
+        let audio_wait = std::mem::take(&mut self.audio_wait);
+        let previous = self.last_frame.replace(now)?;
+        let gap = now.duration_since(previous);
+
+        self.frames += 1;
+        let gap_ms = gap.as_secs_f64() * 1000.0;
+        self.smoothed_ms = if self.frames == 1 {
+            gap_ms
+        } else {
+            self.smoothed_ms + (gap_ms - self.smoothed_ms) * SMOOTHING
+        };
+        self.worst = self.worst.max(gap);
+
+        if gap <= STUTTER_THRESHOLD {
+            return None;
+        }
+        let emulation = gap.saturating_sub(audio_wait + present);
+        let cause = if audio_wait >= present && audio_wait >= emulation {
+            StutterCause::AudioWait
+        } else if present >= emulation {
+            StutterCause::Vsync
+        } else {
+            StutterCause::SlowEmulation
+        };
+        self.stutters[cause as usize] += 1;
+        Some(Stutter { gap, cause })
+    }
+
+    /// Forgets the last frame after the emulator stood still on purpose (the
+    /// pause menu), so the wait is not counted as a stutter.
+    pub fn resume(&mut self) {
+        self.last_frame = None;
+    }
+
+    pub fn summary(&self) -> FrameSummary {
+        FrameSummary {
+            frames: self.frames,
+            smoothed_ms: self.smoothed_ms,
+            worst_ms: self.worst.as_secs_f64() * 1000.0,
+            audio_wait: self.stutters[StutterCause::AudioWait as usize],
+            vsync: self.stutters[StutterCause::Vsync as usize],
+            slow_emulation: self.stutters[StutterCause::SlowEmulation as usize],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameSummary {
+    pub frames: u64,
+    pub smoothed_ms: f64,
+    pub worst_ms: f64,
+    pub audio_wait: u64,
+    pub vsync: u64,
+    pub slow_emulation: u64,
+}
+
+impl FrameSummary {
+    pub fn stutters(&self) -> u64 {
+        self.audio_wait + self.vsync + self.slow_emulation
+    }
+}
+
+impl fmt::Display for FrameSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.1}ms/frame, worst {:.1}ms, {} stutters (audio wait {}, vsync {}, slow emulation {})",
+            self.smoothed_ms,
+            self.worst_ms,
+            self.stutters(),
+            self.audio_wait,
+            self.vsync,
+            self.slow_emulation
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_stutter_causes() {
+        let start = Instant::now();
+        let mut timer = FrameTimer::new();
+        assert_eq!(timer.end_frame(start, ms(1)), None);
+        assert_eq!(timer.end_frame(start + ms(16), ms(10)), None);
+
+        timer.add_audio_wait(ms(30));
+        let stutter = timer.end_frame(start + ms(56), ms(2)).unwrap();
+        assert_eq!(stutter.cause, StutterCause::AudioWait);
+        assert_eq!(stutter.gap, ms(40));
+
+        assert_eq!(
+            timer.end_frame(start + ms(86), ms(25)).map(|s| s.cause),
+            Some(StutterCause::Vsync)
+        );
+        assert_eq!(
+            timer.end_frame(start + ms(136), ms(5)).map(|s| s.cause),
+            Some(StutterCause::SlowEmulation)
+        );
+
+        let summary = timer.summary();
+        assert_eq!(summary.frames, 4);
+        assert_eq!(summary.stutters(), 3);
+        assert_eq!(summary.worst_ms, 50.0);
+
+        timer.resume();
+        assert_eq!(timer.end_frame(start + ms(5000), ms(1)), None);
+        assert_eq!(timer.summary().frames, 4);
+    }
+}
@@ -0,0 +1,143 @@
+//
+// Analog sticks driving the d-pad. The stick position is cut into eight direction
+// sectors around a dead zone; the diagonal bias widens or narrows the diagonal
+// sectors. Both are set per controller in the config file:
+//
+//   [joystick.1]
+//   dead_zone = 0.35      # fraction of full deflection ignored around the center
+//   diagonal_bias = -0.5  # -1: four-way only, 0: even sectors, 1: mostly diagonals
+//
+
+use crate::config::Config;
+use crate::joypad::JoypadButton;
+
+const DPAD: JoypadButton = JoypadButton::from_bits_truncate(
+    JoypadButton::UP.bits()
+        | JoypadButton::DOWN.bits()
+        | JoypadButton::LEFT.bits()
+        | JoypadButton::RIGHT.bits(),
+);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StickConfig {
+    pub dead_zone: f32,
+    pub diagonal_bias: f32,
+}
+
+impl Default for StickConfig {
+    fn default() -> Self {
+        StickConfig {
+            dead_zone: 0.25,
+            diagonal_bias: 0.0,
+        }
+    }
+}
+
+impl StickConfig {
+    /// Settings for the `controller`th joystick (0-based), from `[joystick.N]`.
+    pub fn from_config(config: &Config, controller: usize) -> Self {
+        let section = format!("joystick.{}", controller + 1);
+        let default = StickConfig::default();
+        StickConfig {
+            dead_zone: config
+                .get_f32(&section, "dead_zone", default.dead_zone)
+                .clamp(0.0, 0.95),
+            diagonal_bias: config
+                .get_f32(&section, "diagonal_bias", default.diagonal_bias)
+                .clamp(-1.0, 1.0),
+        }
+    }
+
+    /// D-pad directions for raw SDL axis values (y grows downwards).
+    pub fn dpad(&self, x: i16, y: i16) -> JoypadButton {
+        let x = x as f32 / i16::MAX as f32;
+        let y = -(y as f32) / i16::MAX as f32;
+        if x.hypot(y) <= self.dead_zone {
+            return JoypadButton::empty();
+        }
+
+        // Angle folded into the first octant pair: 0° is horizontal, 90° vertical
+        let angle = y.abs().atan2(x.abs()).to_degrees();
+        let diagonal_half_width = 22.5 * (1.0 + self.diagonal_bias);
+        let horizontal = angle < 45.0 + diagonal_half_width;
+        let vertical = angle > 45.0 - diagonal_half_width;
+
+        let mut buttons = JoypadButton::empty();
+        // Exactly on a four-way boundary, horizontal wins
+        if horizontal || !vertical {
+            buttons |= if x < 0.0 {
+                JoypadButton::LEFT
+            } else {
+                JoypadButton::RIGHT
+            };
+        }
+        if vertical {
+            buttons |= if y < 0.0 {
+                JoypadButton::DOWN
+            } else {
+                JoypadButton::UP
+            };
+        }
+        buttons
+    }
+}
+
+/// Replaces the d-pad bits of `buttons`, leaving the face buttons alone.
+pub fn with_dpad(buttons: JoypadButton, dpad: JoypadButton) -> JoypadButton {
+    (buttons - DPAD) | (dpad & DPAD)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dead_zone_and_diagonals() {
+        let even = StickConfig::default();
+        assert_eq!(even.dpad(5000, -3000), JoypadButton::empty());
+        assert_eq!(even.dpad(i16::MAX, 0), JoypadButton::RIGHT);
+        assert_eq!(even.dpad(0, i16::MAX), JoypadButton::DOWN);
+        assert_eq!(
+            even.dpad(-20000, -20000),
+            JoypadButton::LEFT | JoypadButton::UP
+        );
+        // 15° off horizontal: right only with even sectors...
+        assert_eq!(even.dpad(30000, -8000), JoypadButton::RIGHT);
+
+        // ...a diagonal when diagonals are favoured
+        let loose = StickConfig {
+            diagonal_bias: 0.5,
+            ..even
+        };
+        assert_eq!(
+            loose.dpad(30000, -8000),
+            JoypadButton::RIGHT | JoypadButton::UP
+        );
+
+        let four_way = StickConfig {
+            dead_zone: 0.5,
+            diagonal_bias: -1.0,
+        };
+        assert_eq!(four_way.dpad(10000, 10000), JoypadButton::empty());
+        assert_eq!(four_way.dpad(20000, 21000), JoypadButton::DOWN);
+    }
+
+    #[test]
+    fn test_config_per_controller() {
+        let config = Config::parse("[joystick.2]\ndead_zone = 2\ndiagonal_bias = -0.5\n").unwrap();
+        assert_eq!(StickConfig::from_config(&config, 0), StickConfig::default());
+        assert_eq!(
+            StickConfig::from_config(&config, 1),
+            StickConfig {
+                dead_zone: 0.95,
+                diagonal_bias: -0.5
+            }
+        );
+
+        let buttons = JoypadButton::BUTTON_A | JoypadButton::LEFT;
+        assert_eq!(
+            with_dpad(buttons, JoypadButton::UP),
+            JoypadButton::BUTTON_A | JoypadButton::UP
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//
+// Strict mode (--strict): conditions the core tolerates silently in normal play,
+// because real hardware or common games do them too, are reported with details so
+// that core bugs and homebrew mistakes show up instead of being papered over.
+//
+// Components call `report`, which costs one atomic load when strict mode is off;
+// the main loop collects the reports between instructions and prints them with
+// the CPU state.
+//
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref PENDING: Mutex<Vec<Diagnostic>> = Mutex::new(Vec::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Violation {
+    /// A palette RAM write above $3F; only the low 6 bits are kept
+    PaletteValue,
+    /// OAM writes running past $FF and wrapping to the first sprite
+    OamOverflow,
+    /// A PRG bank number past the end of the ROM, wrapped around
+    PrgBankOutOfRange,
+    /// A CHR bank number past the end of CHR memory, wrapped around
+    ChrBankOutOfRange,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Violation::PaletteValue => "palette value out of range",
+            Violation::OamOverflow => "OAM overflow",
+            Violation::PrgBankOutOfRange => "PRG bank out of range",
+            Violation::ChrBankOutOfRange => "CHR bank out of range",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub violation: Violation,
+    pub detail: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.violation, self.detail)
+    }
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Records a violation; `detail` is only built in strict mode.
+pub fn report<F: FnOnce() -> String>(violation: Violation, detail: F) {
+    if enabled() {
+        PENDING.lock().unwrap().push(Diagnostic {
+            violation,
+            detail: detail(),
+        });
+    }
+}
+
+/// The violations reported since the last call.
+pub fn take() -> Vec<Diagnostic> {
+    if !enabled() {
+        return vec![];
+    }
+    std::mem::take(&mut *PENDING.lock().unwrap())
+}
+
+/// Bank `bank` of `count`, wrapped the way the unconnected address lines of a
+/// smaller chip do, reported when it did not fit.
+pub fn wrap_bank(bank: usize, count: usize, violation: Violation) -> usize {
+    if bank >= count {
+        report(violation, || format!("bank {} of {}", bank, count));
+    }
+    bank % count
+}
+
+/// Offset `offset` into a chip of `len` bytes, wrapped like `wrap_bank`.
+pub fn wrap_offset(offset: usize, len: usize, violation: Violation) -> usize {
+    if offset >= len {
+        report(violation, || {
+            format!("offset ${:X} in {}KB", offset, len / 1024)
+        });
+    }
+    offset % len
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Strict mode for the length of a test, switched back off (and the reports
+    /// left over dropped) even if the test fails.
+    struct Enabled;
+
+    impl Enabled {
+        fn new() -> Self {
+            enable();
+            Enabled
+        }
+    }
+
+    impl Drop for Enabled {
+        fn drop(&mut self) {
+            ENABLED.store(false, Ordering::Relaxed);
+            PENDING.lock().unwrap().clear();
+        }
+    }
+
+    #[test]
+    fn test_reports_only_when_enabled() {
+        // Strict mode is global and this is the only test turning it on; reports
+        // from tests running alongside may land in `take`, so only look for ours
+        assert!(!enabled());
+        assert_eq!(wrap_bank(5, 4, Violation::PrgBankOutOfRange), 1);
+        assert!(take().is_empty());
+
+        let _strict = Enabled::new();
+        assert_eq!(wrap_bank(3, 4, Violation::PrgBankOutOfRange), 3);
+        report(Violation::OamOverflow, || "wrapped".to_string());
+        let reports = take();
+        assert!(reports.contains(&Diagnostic {
+            violation: Violation::OamOverflow,
+            detail: "wrapped".to_string(),
+        }));
+        assert!(!reports
+            .iter()
+            .any(|d| d.violation == Violation::PrgBankOutOfRange && d.detail == "bank 3 of 4"));
+    }
+}
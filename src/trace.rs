@@ -1,36 +1,41 @@
-use crate::cpu::AddressingMode;
-use crate::cpu::Mem;
-use crate::cpu::CPU;
-use crate::opcodes;
-use std::collections::HashMap;
+use crate::cpu::{AddressingMode, CpuBus, CPU};
+use crate::disasm;
+use std::fmt;
 
-pub fn trace(cpu: &mut CPU) -> String {
-    let ref opscodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
-
-    let code = cpu.mem_read(cpu.program_counter);
-    let ops = opscodes.get(&code).unwrap();
+/// Address the operand at `addr` points to, read through `peek` so that tracing
+/// an instruction does not touch the registers it would read.
+fn operand_address<B: CpuBus>(cpu: &CPU<B>, mode: &AddressingMode, addr: u16) -> u16 {
+    let peek = |addr: u16| cpu.bus.peek(addr);
+    let peek_u16 = |addr: u16| u16::from_le_bytes([peek(addr), peek(addr.wrapping_add(1))]);
+    let zero_page_u16 =
+        |ptr: u8| u16::from_le_bytes([peek(ptr as u16), peek(ptr.wrapping_add(1) as u16)]);
+    match mode {
+        AddressingMode::ZeroPage => peek(addr) as u16,
+        AddressingMode::ZeroPageX => peek(addr).wrapping_add(cpu.register_x) as u16,
+        AddressingMode::ZeroPageY => peek(addr).wrapping_add(cpu.register_y) as u16,
+        AddressingMode::Absolute => peek_u16(addr),
+        AddressingMode::AbsoluteX => peek_u16(addr).wrapping_add(cpu.register_x as u16),
+        AddressingMode::AbsoluteY => peek_u16(addr).wrapping_add(cpu.register_y as u16),
+        AddressingMode::IndirectX => zero_page_u16(peek(addr).wrapping_add(cpu.register_x)),
+        AddressingMode::IndirectY => zero_page_u16(peek(addr)).wrapping_add(cpu.register_y as u16),
+        _ => 0,
+    }
+}
 
+pub fn trace<B: CpuBus>(cpu: &CPU<B>) -> String {
     let begin = cpu.program_counter;
-    let mut hex_dump = vec![];
-    hex_dump.push(code);
-
-    let (mem_addr, stored_value) = match ops.mode {
-        AddressingMode::Immediate | AddressingMode::Implied => (0, 0),
-        _ => {
-            let (addr, _) = cpu.get_absolute_address(&ops.mode, begin + 1);
-            (addr, cpu.mem_read(addr))
-        }
-    };
+    let ops = disasm::decode(&mut |addr| cpu.bus.peek(addr), begin);
 
-    let tmp = match ops.len {
-        1 => match ops.code {
-            0x0a | 0x4a | 0x2a | 0x6a => format!("A "),
+    let mem_addr = operand_address(cpu, &ops.mode, begin.wrapping_add(1));
+    let stored_value = cpu.bus.peek(mem_addr);
+
+    let tmp = match ops.bytes.len() {
+        1 => match ops.mode {
+            AddressingMode::Accumulator => "A ".to_string(),
             _ => String::from(""),
         },
         2 => {
-            let address: u8 = cpu.mem_read(begin + 1);
-            // let value = cpu.mem_read(address));
-            hex_dump.push(address);
+            let address = ops.bytes[1];
 
             match ops.mode {
                 AddressingMode::Immediate => format!("#${:02x}", address),
@@ -57,42 +62,23 @@ pub fn trace(cpu: &mut CPU) -> String {
                     mem_addr,
                     stored_value
                 ),
-                AddressingMode::Relative => {
-                    // assuming local jumps: BNE, BVS, etc....
-                    let address: usize =
-                        (begin as usize + 2).wrapping_add((address as i8) as usize);
-                    format!("${:04x}", address)
-                }
+                AddressingMode::Relative => format!("${:04x}", ops.target.unwrap()),
 
                 _ => panic!(
                     "unexpected addressing mode {:?} has ops-len 2. code {:02x}",
-                    ops.mode, ops.code
+                    ops.mode, ops.bytes[0]
                 ),
             }
         }
         3 => {
-            let address_lo = cpu.mem_read(begin + 1);
-            let address_hi = cpu.mem_read(begin + 2);
-            hex_dump.push(address_lo);
-            hex_dump.push(address_hi);
-
-            let address = cpu.mem_read_u16(begin + 1);
+            let address = ops.operand.unwrap();
 
             match ops.mode {
                 AddressingMode::Implied => {
                     format!("${:04x}", address)
                 }
                 AddressingMode::Indirect => {
-                    //jmp indirect
-                    let jmp_addr = if address & 0x00FF == 0x00FF {
-                        let lo = cpu.mem_read(address);
-                        let hi = cpu.mem_read(address & 0xFF00);
-                        (hi as u16) << 8 | (lo as u16)
-                    } else {
-                        cpu.mem_read_u16(address)
-                    };
-
-                    format!("(${:04x}) = {:04x}", address, jmp_addr)
+                    format!("(${:04x}) = {:04x}", address, ops.target.unwrap())
                 }
                 AddressingMode::Absolute => format!("${:04x} = {:02x}", mem_addr, stored_value),
                 AddressingMode::AbsoluteX => format!(
@@ -105,14 +91,15 @@ pub fn trace(cpu: &mut CPU) -> String {
                 ),
                 _ => panic!(
                     "unexpected addressing mode {:?} has ops-len 3. code {:02x}",
-                    ops.mode, ops.code
+                    ops.mode, ops.bytes[0]
                 ),
             }
         }
         _ => String::from(""),
     };
 
-    let hex_str = hex_dump
+    let hex_str = ops
+        .bytes
         .iter()
         .map(|z| format!("{:02x}", z))
         .collect::<Vec<String>>()
@@ -128,11 +115,72 @@ pub fn trace(cpu: &mut CPU) -> String {
     .to_ascii_uppercase()
 }
 
+/// First line where a run departs from a reference log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// Counted from 1, like an editor does
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "diverged at line {}\n  expected: {}\n  actual:   {}",
+            self.line, self.expected, self.actual
+        )
+    }
+}
+
+/// Runs one instruction per line of `log`, a trace in the layout of nestest.log,
+/// comparing the disassembly, the registers, the `PPU:` position (on a bus with a
+/// PPU) and the `CYC:` counter before each. Returns how many lines matched.
+pub fn compare_log<B: CpuBus>(cpu: &mut CPU<B>, log: &str) -> Result<usize, Divergence> {
+    let mut matched = 0;
+    for (index, expected) in log.lines().filter(|line| !line.is_empty()).enumerate() {
+        let registers = expected
+            .find(" PPU:")
+            .map_or(expected, |end| &expected[..end])
+            .trim_end();
+        let column = |name: &str| {
+            let start = expected.find(name)? + name.len();
+            let rest = &expected[start..];
+            Some(
+                rest.find(" CYC:")
+                    .map_or(rest, |end| &rest[..end])
+                    .trim_end(),
+            )
+        };
+
+        let actual = trace(cpu);
+        let position = cpu.ppu_position().map(|position| position.to_string());
+        let same_position = match (column("PPU:"), &position) {
+            (Some(expected), Some(actual)) => expected == actual,
+            _ => true,
+        };
+        let same_cycles = column("CYC:").is_none_or(|cycles| cycles == cpu.cycles.to_string());
+        if actual != registers || !same_position || !same_cycles {
+            let position = position.map_or(String::new(), |position| format!(" PPU:{}", position));
+            return Err(Divergence {
+                line: index + 1,
+                expected: expected.to_string(),
+                actual: format!("{}{} CYC:{}", actual, position, cpu.cycles),
+            });
+        }
+        cpu.step();
+        matched += 1;
+    }
+    Ok(matched)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::bus::Bus;
     use crate::cartridge::test::test_rom;
+    use crate::cpu::Mem;
 
     #[test]
     fn test_format_trace() {
@@ -166,6 +214,56 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_compare_log() {
+        let log = "\
+0064  A2 01     LDX #$01                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+0066  CA        DEX                             A:00 X:01 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9
+0067  88        DEY                             A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 33 CYC:11
+";
+        // Power on takes 7 cycles, 21 dots
+        let powered_on = || {
+            let mut bus = Bus::new(test_rom(), 44100.0, |_ppu, _joypad| {});
+            for (addr, code) in [0xa2, 0x01, 0xca, 0x88].into_iter().enumerate() {
+                bus.mem_write(0x64 + addr as u16, code);
+            }
+            let mut cpu = CPU::new(bus);
+            cpu.power_on();
+            cpu.program_counter = 0x64;
+            cpu
+        };
+        let mut cpu = powered_on();
+        // DEX set the zero flag, which the last line leaves out
+        let divergence = compare_log(&mut cpu, log).unwrap_err();
+        assert_eq!(divergence.line, 3);
+        assert_eq!(
+            divergence.actual,
+            "0067  88        DEY                             A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 33 CYC:11"
+        );
+
+        let mut cpu = powered_on();
+        let fixed = log.replace("Y:00 P:24 SP:FD PPU:  0, 33", "Y:00 P:26 SP:FD PPU:  0, 33");
+        assert_eq!(compare_log(&mut cpu, &fixed), Ok(3));
+    }
+
+    /// nestest.nes in automation mode, from $C000 with no PPU needed. Neither the ROM
+    /// nor its log ship with the emulator: copy both into tests/ and run
+    /// `cargo test -- --ignored`.
+    #[test]
+    #[ignore = "needs tests/nestest.nes and tests/nestest.log"]
+    fn test_nestest_log() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let rom = crate::cartridge::Rom::from_path(dir.join("nestest.nes")).unwrap();
+        let log = std::fs::read_to_string(dir.join("nestest.log")).unwrap();
+
+        let mut cpu = CPU::new(Bus::new(rom, 44100.0, |_ppu, _joypad| {}));
+        cpu.power_on();
+        cpu.program_counter = 0xC000;
+        if let Err(divergence) = compare_log(&mut cpu, &log) {
+            panic!("nestest.log {}", divergence);
+        }
+    }
+
     #[test]
     fn test_format_mem_access() {
         let mut bus = Bus::new(test_rom(), 44100.0, |_ppu, _joypad| {});
@@ -174,8 +272,8 @@ mod test {
         bus.mem_write(101, 0x33);
 
         //data
-        bus.mem_write(0x33, 00);
-        bus.mem_write(0x34, 04);
+        bus.mem_write(0x33, 0x00);
+        bus.mem_write(0x34, 0x04);
 
         //target cell
         bus.mem_write(0x400, 0xAA);
@@ -192,4 +290,23 @@ mod test {
             result[0]
         );
     }
+
+    #[test]
+    fn test_trace_leaves_bus_alone() {
+        let mut bus = Bus::new(test_rom(), 44100.0, |_ppu, _joypad| {});
+        // LDA ($10),Y through $1234
+        for (addr, code) in [0xb1, 0x10].into_iter().enumerate() {
+            bus.mem_write(0x64 + addr as u16, code);
+        }
+        bus.mem_write(0x10, 0x34);
+        bus.mem_write(0x11, 0x12);
+        bus.mem_write(0x20, 0x77);
+        bus.mem_read(0x20);
+
+        let mut cpu = CPU::new(bus);
+        cpu.program_counter = 0x64;
+        assert!(trace(&cpu).starts_with("0064  B1 10     LDA ($10),Y = 1234 @ 1234 = "));
+        // Nothing was read: the last value on the data bus is still there
+        assert_eq!(cpu.bus.peek(0x4018), 0x77);
+    }
 }
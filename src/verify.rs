@@ -0,0 +1,212 @@
+//
+// Headless movie playback: replays a movie as fast as possible and hashes the
+// emulator state so that a sync can be checked without looking at the screen.
+//
+
+use crate::bus::Bus;
+use crate::cartridge::Rom;
+use crate::cpu::CPU;
+use crate::hash::crc32;
+use crate::input::{ControllerPorts, DeviceKind};
+use crate::movie::Movie;
+use crate::ppu::NesPPU;
+use crate::render;
+use crate::render::frame::Frame;
+use std::fmt;
+
+/// A checkpoint is recorded every this many frames, plus one after the last frame.
+pub const CHECKPOINT_INTERVAL: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    /// Number of movie frames played when the hashes were taken
+    pub frame: usize,
+    /// CRC32 of the 2KB of CPU RAM
+    pub ram_hash: u32,
+    /// CRC32 of the rendered RGB frame
+    pub frame_hash: u32,
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "frame {}: ram={:08x} frame={:08x}",
+            self.frame, self.ram_hash, self.frame_hash
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct VerifyReport {
+    /// Periodic checkpoints, the last one being the final state
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+impl VerifyReport {
+    pub fn final_state(&self) -> &Checkpoint {
+        self.checkpoints.last().unwrap()
+    }
+}
+
+/// Plays `movie` from power-on.
+///
+/// Frame `i` of the movie is latched into the joypads when the PPU reaches the
+/// vblank line for the (i+1)th time, NMI or not, so the NMI handler of that frame
+/// sees it. Playback stops at the vblank following the last frame, or fails when
+/// a frame runs far longer than the region's.
+pub fn verify_movie(rom: Rom, movie: &Movie) -> Result<VerifyReport, String> {
+    play_movie(rom, movie, CHECKPOINT_INTERVAL)
+}
+
+fn play_movie(rom: Rom, movie: &Movie, interval: usize) -> Result<VerifyReport, String> {
+    let bus = Bus::new(
+        rom,
+        crate::AUDIO_SAMPLE_RATE,
+        |_: &NesPPU, _: &mut ControllerPorts| {},
+    );
+    let mut cpu = CPU::new(bus);
+    if movie.four_score {
+        for port in 0..2 {
+            cpu.bus.ports_mut().connect(port, DeviceKind::FourScore);
+        }
+    }
+    if let Some(region) = movie.region {
+        cpu.bus.override_region(region);
+    }
+    cpu.power_on();
+
+    let region = cpu.bus.region();
+    let vblank = region.vblank_line();
+    let max_cycles = 2 * region.cycles_per_frame() as u64;
+    let mut frame = Frame::new();
+    let mut checkpoints = Vec::new();
+    let mut scanline = cpu.bus.ppu().scanline;
+    let mut frame_start = cpu.cycles;
+
+    for played in 0..=movie.len() {
+        loop {
+            cpu.step();
+            // Nobody listens, but draining keeps the APU sample clock bounded.
+            cpu.collect_audio_sample();
+
+            let now = cpu.bus.ppu().scanline;
+            let entered_vblank = now == vblank && scanline != vblank;
+            scanline = now;
+            if entered_vblank {
+                break;
+            }
+            if cpu.cycles.saturating_sub(frame_start) > max_cycles {
+                return Err(format!(
+                    "frame {} did not end within {} CPU cycles",
+                    played + 1,
+                    max_cycles
+                ));
+            }
+        }
+
+        if played == movie.len() || (played > 0 && played.is_multiple_of(interval)) {
+            render::render(cpu.bus.ppu(), &mut frame);
+            checkpoints.push(Checkpoint {
+                frame: played,
+                ram_hash: crc32(cpu.bus.ram()),
+                frame_hash: crc32(&frame.data),
+            });
+        }
+
+        if let Some(input) = movie.frames.get(played) {
+            for player in 0..4 {
+                if let Some(joypad) = cpu.bus.ports_mut().player_joypad(player) {
+                    joypad.set_buttons(input.joypad_buttons(player));
+                }
+            }
+            if input.power {
+                cpu.power_on();
+            } else if input.reset {
+                cpu.reset();
+            }
+            scanline = cpu.bus.ppu().scanline;
+        }
+        frame_start = cpu.cycles;
+    }
+
+    Ok(VerifyReport { checkpoints })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::program_rom;
+    use crate::movie::MovieFrame;
+
+    // Enables NMI and spins; the NMI handler stores the A button in $00 and counts
+    // frames in $01.
+    fn movie_test_rom() -> Rom {
+        let program = vec![
+            0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0x80, // reset
+            0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, // strobe
+            0xAD, 0x16, 0x40, 0x85, 0x00, 0xE6, 0x01, 0x40, // read A, count, RTI
+        ];
+        program_rom(program, [0x8008, 0x8000, 0x8000])
+    }
+
+    fn movie(buttons: &[u8]) -> Movie {
+        Movie {
+            frames: buttons
+                .iter()
+                .map(|&buttons| MovieFrame {
+                    buttons: [buttons, 0, 0, 0],
+                    ..MovieFrame::default()
+                })
+                .collect(),
+            ..Movie::default()
+        }
+    }
+
+    #[test]
+    fn test_verify_movie_plays_inputs() {
+        let pressed = verify_movie(movie_test_rom(), &movie(&[0x00, 0x00, 0x01])).unwrap();
+        let released = verify_movie(movie_test_rom(), &movie(&[0x00, 0x00, 0x00])).unwrap();
+
+        assert_eq!(pressed.checkpoints.len(), 1);
+        assert_eq!(pressed.final_state().frame, 3);
+        assert_ne!(
+            pressed.final_state().ram_hash,
+            released.final_state().ram_hash
+        );
+        assert_eq!(
+            pressed.final_state().frame_hash,
+            released.final_state().frame_hash
+        );
+    }
+
+    #[test]
+    fn test_verify_movie_without_nmi() {
+        // NMI stays off; the main loop polls the A button into $00
+        let program = vec![
+            0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, // strobe
+            0xAD, 0x16, 0x40, 0x85, 0x00, 0x4C, 0x00, 0x80, // read A, loop
+        ];
+        let rom = || program_rom(program.clone(), [0x8000; 3]);
+
+        let pressed = verify_movie(rom(), &movie(&[0x00, 0x01])).unwrap();
+        let released = verify_movie(rom(), &movie(&[0x00, 0x00])).unwrap();
+        assert_eq!(pressed.final_state().frame, 2);
+        assert_ne!(
+            pressed.final_state().ram_hash,
+            released.final_state().ram_hash
+        );
+    }
+
+    #[test]
+    fn test_verify_movie_is_deterministic() {
+        let inputs: Vec<u8> = (0..21).map(|i| (i % 3) as u8).collect();
+
+        let first = play_movie(movie_test_rom(), &movie(&inputs), 10).unwrap();
+        let second = play_movie(movie_test_rom(), &movie(&inputs), 10).unwrap();
+
+        assert_eq!(first, second);
+        let frames: Vec<usize> = first.checkpoints.iter().map(|c| c.frame).collect();
+        assert_eq!(frames, vec![10, 20, 21]);
+    }
+}
@@ -0,0 +1,120 @@
+//
+// Memory write log: every write the CPU makes to a chosen set of addresses, with
+// the frame and cycle it happened on, as CSV. Tracking a game variable (player X,
+// hit points, RNG state) over a play session is then a matter of loading the file
+// in a spreadsheet and plotting one address.
+//
+//   nes_emulator game.nes --log-writes 0x86,0x3B5-0x3B7 --log-writes-out writes.csv
+//
+// Addresses are hexadecimal, with or without a `0x` or `$` prefix. Writes to the
+// mirrors of internal RAM are logged under the $0000-$07FF address they land on,
+// and those to the mirrors of the PPU registers under $2000-$2007.
+//
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub const DEFAULT_OUT: &str = "writes.csv";
+
+/// Parses a comma-separated list of addresses and `first-last` ranges.
+pub fn parse_addresses(list: &str) -> Result<HashSet<u16>, String> {
+    let address = |text: &str| {
+        let digits = text.trim();
+        let digits = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix('$'))
+            .unwrap_or(digits);
+        u16::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not an address", text))
+    };
+    let mut addresses = HashSet::new();
+    for item in list.split(',').filter(|item| !item.trim().is_empty()) {
+        match item.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (address(first)?, address(last)?);
+                if first > last {
+                    return Err(format!("'{}' is an empty range", item));
+                }
+                addresses.extend(first..=last);
+            }
+            None => {
+                addresses.insert(address(item)?);
+            }
+        }
+    }
+    if addresses.is_empty() {
+        return Err("no address to log".to_string());
+    }
+    Ok(addresses)
+}
+
+pub struct WriteLog {
+    addresses: HashSet<u16>,
+    out: BufWriter<File>,
+    /// First write error, reported by `finish` rather than on every write
+    error: Option<io::Error>,
+}
+
+impl WriteLog {
+    pub fn create<P: AsRef<Path>>(path: P, addresses: HashSet<u16>) -> io::Result<WriteLog> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "frame,cycle,address,value")?;
+        Ok(WriteLog {
+            addresses,
+            out,
+            error: None,
+        })
+    }
+
+    /// Logs the write if its address is watched. `addr` is the CPU address.
+    pub fn record(&mut self, frame: u64, cycle: usize, addr: u16, data: u8) {
+        let addr = if addr < 0x2000 { addr & 0x07FF } else { addr };
+        if self.error.is_some() || !self.addresses.contains(&addr) {
+            return;
+        }
+        if let Err(e) = writeln!(self.out, "{},{},{:04X},{}", frame, cycle, addr, data) {
+            self.error = Some(e);
+        }
+    }
+
+    /// Flushes the file, returning the first error met while logging.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_addresses() {
+        let addresses = parse_addresses("0x86, $3B5-3b7,10").unwrap();
+        let mut sorted: Vec<u16> = addresses.into_iter().collect();
+        sorted.sort();
+        assert_eq!(sorted, [0x10, 0x86, 0x3B5, 0x3B6, 0x3B7]);
+        assert!(parse_addresses("0x90-0x80").is_err());
+        assert!(parse_addresses("player").is_err());
+        assert!(parse_addresses("").is_err());
+    }
+
+    #[test]
+    fn test_log_csv() {
+        let path = std::env::temp_dir().join(format!("nes_watch_test_{}.csv", std::process::id()));
+        let mut log = WriteLog::create(&path, parse_addresses("86").unwrap()).unwrap();
+        log.record(1, 100, 0x0086, 5);
+        log.record(1, 120, 0x0087, 6);
+        // Mirror of $0086
+        log.record(2, 30000, 0x0886, 7);
+        log.finish().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "frame,cycle,address,value\n1,100,0086,5\n2,30000,0086,7\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}